@@ -0,0 +1,72 @@
+//! compiled_check compares `CompiledSpf::check` with linear scan over directives of flattened record.
+//!
+//! Run it with `cargo bench --bench compiled_check`.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use spf::{CompiledSpf, Ipv4Net, Ipv6Net, SpfAction, SpfDirective, SpfMechanism, SpfRecord};
+
+/// flattened_record builds record with given number of ip4 and ip6 terms followed by `-all`.
+fn flattened_record(terms: u32) -> SpfRecord<'static> {
+    (0..terms)
+        .map(|i| {
+            let mechanism = if i % 4 == 3 {
+                SpfMechanism::ip6(Ipv6Net::new(Ipv6Addr::new(0x2001, 0xdb8, i as u16, 0, 0, 0, 0, 0), Some(48)).unwrap())
+            } else {
+                SpfMechanism::ip4(Ipv4Net::new(Ipv4Addr::from(0x0a00_0000 + (i << 8)), Some(24)).unwrap())
+            };
+            SpfDirective::from_mechanism(mechanism)
+        })
+        .chain(std::iter::once(SpfDirective::new(SpfAction::Fail, SpfMechanism::all()).unwrap()))
+        .collect()
+}
+
+fn linear_check(record: &SpfRecord, ip: IpAddr) -> SpfAction {
+    for d in record.directives.iter() {
+        let matched = match &d.mechanism {
+            SpfMechanism::Ipv4(net) => net.matches(ip),
+            SpfMechanism::Ipv6(net) => net.matches(ip),
+            SpfMechanism::All => true,
+            _ => false,
+        };
+        if matched {
+            return d.qualifier;
+        }
+    }
+    SpfAction::Neutral
+}
+
+fn addresses(terms: u32) -> Vec<IpAddr> {
+    (0..1024u32)
+        .map(|i| {
+            let i = i.wrapping_mul(2654435761) % (terms * 2);
+            if i % 5 == 4 {
+                IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, i as u16, 0, 0, 0, 0, 1))
+            } else {
+                IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + (i << 8) + 1))
+            }
+        })
+        .collect()
+}
+
+fn bench_check(c: &mut Criterion) {
+    let mut group = c.benchmark_group("check");
+    for terms in [16, 128, 512].iter().copied() {
+        let record = flattened_record(terms);
+        let compiled = CompiledSpf::compile(&record).unwrap();
+        let ips = addresses(terms);
+
+        group.bench_with_input(BenchmarkId::new("linear", terms), &ips, |b, ips| {
+            b.iter(|| ips.iter().map(|ip| linear_check(&record, black_box(*ip)) as u32).sum::<u32>())
+        });
+        group.bench_with_input(BenchmarkId::new("compiled", terms), &ips, |b, ips| {
+            b.iter(|| ips.iter().map(|ip| compiled.check(black_box(*ip)) as u32).sum::<u32>())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_check);
+criterion_main!(benches);
@@ -0,0 +1,70 @@
+//! directive_allocations counts heap allocations made while building corpus of 10k records.
+//!
+//! Run it with and without `smallvec` feature to compare:
+//! `cargo bench --bench directive_allocations` and `cargo bench --bench directive_allocations --features smallvec`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use spf::{DualCidr, Ipv4Net, SpfAction, SpfDirective, SpfMechanism, SpfRecord};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const RECORDS: usize = 10_000;
+
+/// median_record builds record with 5 to 7 directives, none of which allocates by itself.
+fn median_record(i: usize) -> SpfRecord<'static> {
+    let len = 5 + i % 3;
+    (0..len)
+        .map(|j| {
+            let mechanism = match j {
+                0 => SpfMechanism::MX(None, DualCidr::default()),
+                1 => SpfMechanism::A(None, DualCidr::default()),
+                j if j + 1 == len => SpfMechanism::All,
+                j => SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::from((i * 8 + j) as u32), Some(24)).unwrap()),
+            };
+            SpfDirective {
+                qualifier: if j + 1 == len { SpfAction::SoftFail } else { SpfAction::Pass },
+                explicit_qualifier: j + 1 == len,
+                mechanism,
+            }
+        })
+        .collect()
+}
+
+fn main() {
+    let mut corpus = Vec::with_capacity(RECORDS);
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for i in 0..RECORDS {
+        corpus.push(median_record(i));
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    let directives: usize = corpus.iter().map(|r| r.directives.len()).sum();
+    println!(
+        "built {} records with {} directives: {} allocations ({:.2} per record, smallvec: {})",
+        corpus.len(),
+        directives,
+        allocations,
+        allocations as f64 / RECORDS as f64,
+        cfg!(feature = "smallvec"),
+    );
+}
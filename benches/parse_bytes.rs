@@ -0,0 +1,44 @@
+//! parse_bytes compares parsing TXT record bytes with `SpfRecord::parse_bytes`, which checks that input is ASCII
+//! while splitting it into terms, with `str::from_utf8` followed by `SpfRecord::parse_str`.
+//! `SpfRecord::iter_directives` shows cost of parsing without collecting directives.
+//!
+//! Run it with `cargo bench --bench parse_bytes`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use spf::SpfRecord;
+
+const RECORDS: &[(&str, &str)] = &[
+    ("short", "v=spf1 mx -all"),
+    ("typical", "v=spf1 ip4:192.0.2.0/24 ip6:2001:db8::/32 a mx include:_spf.example.com ~all"),
+    (
+        "long",
+        "v=spf1 ip4:192.0.2.0/24 ip4:198.51.100.0/22 ip4:203.0.113.17 ip6:2001:db8::/32 ip6:2001:db8:1234::1 \
+         a mx:mail.example.com/28//64 include:_spf.google.com include:spf.protection.outlook.com \
+         include:servers.mcsv.net include:mailgun.org exists:%{i}._spf.example.net \
+         redirect=_spf.example.com exp=explain._spf.%{d}",
+    ),
+];
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, text) in RECORDS.iter() {
+        let bytes = text.as_bytes();
+        assert!(SpfRecord::parse_bytes(bytes).is_ok());
+
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("from_utf8+parse_str", name), bytes, |b, bytes| {
+            b.iter(|| SpfRecord::parse_str(std::str::from_utf8(black_box(bytes)).unwrap()).unwrap().directives.len())
+        });
+        group.bench_with_input(BenchmarkId::new("parse_bytes", name), bytes, |b, bytes| {
+            b.iter(|| SpfRecord::parse_bytes(black_box(bytes)).unwrap().directives.len())
+        });
+        group.bench_with_input(BenchmarkId::new("iter_directives", name), text, |b, text| {
+            b.iter(|| SpfRecord::iter_directives(black_box(text)).map(Result::unwrap).count())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
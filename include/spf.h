@@ -0,0 +1,160 @@
+#ifndef SPF_H
+#define SPF_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// SpfErrorCode is returned by all functions of C API.
+typedef enum SpfErrorCode {
+  SPF_ERROR_CODE_OK = 0,
+  // NullPointer is returned when required pointer argument is null.
+  SPF_ERROR_CODE_NULL_POINTER,
+  // InvalidUtf8 is returned when string argument is not valid UTF-8.
+  SPF_ERROR_CODE_INVALID_UTF8,
+  // ParseFailed is returned when record can't be parsed.
+  SPF_ERROR_CODE_PARSE_FAILED,
+  // NoRecord is returned when handle holds no record, since parsing failed.
+  SPF_ERROR_CODE_NO_RECORD,
+  // InvalidIp is returned when IP address can't be parsed.
+  SPF_ERROR_CODE_INVALID_IP,
+  // EvaluationFailed is returned when record can't be evaluated.
+  SPF_ERROR_CODE_EVALUATION_FAILED,
+  // Panic is returned when function panicked. It's a bug.
+  SPF_ERROR_CODE_PANIC,
+} SpfErrorCode;
+
+// SpfLintSeverity tells whether problem reported by `spf_lint` makes record invalid.
+typedef enum SpfLintSeverity {
+  // Error is reported for record, which can't be published, like one with two `redirect` modifiers.
+  SPF_LINT_SEVERITY_ERROR = 0,
+  // Warning is reported for valid record, which most likely does not do what its author meant.
+  SPF_LINT_SEVERITY_WARNING,
+} SpfLintSeverity;
+
+// SpfResultCode is result of `spf_check`.
+//
+// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-2.6) section `2.6`
+typedef enum SpfResultCode {
+  SPF_RESULT_CODE_NONE = 0,
+  SPF_RESULT_CODE_NEUTRAL,
+  SPF_RESULT_CODE_PASS,
+  SPF_RESULT_CODE_FAIL,
+  SPF_RESULT_CODE_SOFT_FAIL,
+  SPF_RESULT_CODE_TEMP_ERROR,
+  SPF_RESULT_CODE_PERM_ERROR,
+} SpfResultCode;
+
+// SpfAnswer collects answer of DNS query performed by resolver callback.
+typedef struct SpfAnswer SpfAnswer;
+
+// SpfHandle holds parsed record together with buffers of strings returned to caller.
+typedef struct SpfHandle SpfHandle;
+
+// SpfLintCallback is called by `spf_lint` once for each problem found in record.
+// `index` is index of directive, which caused the problem, `message` is valid only during the call.
+//
+// It's nullable, so that null given by caller is rejected with `SPF_ERROR_CODE_NULL_POINTER` rather than called.
+typedef void (*SpfLintCallback)(void *user_data,
+                                enum SpfLintSeverity severity,
+                                size_t index,
+                                const char *message);
+
+// SpfLookupCallback performs DNS query of given name and adds values from the answer to `answer`
+// with `spf_answer_push`. It returns 0 on success, also when name does not exist, and anything else when
+// query fails, for instance because of timeout.
+//
+// It's nullable, so that null given by caller is rejected with `SPF_ERROR_CODE_NULL_POINTER` rather than called.
+typedef int (*SpfLookupCallback)(void *user_data, const char *name, struct SpfAnswer *answer);
+
+// SpfResolverCallbacks performs DNS queries required by `spf_check`.
+//
+// Names given to callbacks have no trailing dot. Callbacks must not unwind and none of them may be null.
+typedef struct SpfResolverCallbacks {
+  // user_data is passed as first argument of each callback.
+  void *user_data;
+  // lookup_txt answers with texts of TXT records. Strings of each record have to be joined.
+  SpfLookupCallback lookup_txt;
+  // lookup_a answers with addresses of both A and AAAA records, like `192.0.2.1` or `2001:db8::1`.
+  SpfLookupCallback lookup_a;
+  // lookup_mx answers with hosts of MX records.
+  SpfLookupCallback lookup_mx;
+  // lookup_ptr answers with names of PTR records of given address, which is given in its text form,
+  // like `192.0.2.1`, rather than as `in-addr.arpa` name.
+  SpfLookupCallback lookup_ptr;
+} SpfResolverCallbacks;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// spf_parse parses SPF record and stores handle of it in `out`. Handle has to be freed with `spf_record_free`.
+//
+// When parsing fails `SPF_ERROR_CODE_PARSE_FAILED` is returned, but handle without record is stored anyway,
+// so that `spf_last_error_message` can tell what's wrong with record.
+//
+// # Safety
+// `text` has to be NUL-terminated string and `out` has to be valid pointer.
+enum SpfErrorCode spf_parse(const char *text,
+                            struct SpfHandle **out);
+
+// spf_record_free frees handle returned by `spf_parse`. Null handle is ignored.
+//
+// # Safety
+// `handle` has to be null or handle returned by `spf_parse`, which was not freed yet.
+void spf_record_free(struct SpfHandle *handle);
+
+// spf_record_to_string stores text of record in `out`. Text is owned by handle and it's valid until
+// next call of this function with the same handle or until handle is freed.
+//
+// # Safety
+// `handle` has to be valid handle and `out` has to be valid pointer.
+enum SpfErrorCode spf_record_to_string(struct SpfHandle *handle, const char **out);
+
+// spf_lint calls `callback` for each error and warning of record. Errors are reported first.
+//
+// # Safety
+// `handle` has to be valid handle and `callback` can't be null. `user_data` is passed to callback as is.
+enum SpfErrorCode spf_lint(struct SpfHandle *handle,
+                           SpfLintCallback callback,
+                           void *user_data);
+
+// spf_answer_push adds value to answer of DNS query. It may be called only by resolver callbacks.
+//
+// # Safety
+// `answer` has to be answer given to callback and `value` has to be NUL-terminated string.
+enum SpfErrorCode spf_answer_push(struct SpfAnswer *answer, const char *value);
+
+// spf_check checks whether host with address `ip` is authorized to send mail from `sender`, which
+// introduced itself with `helo`, by SPF record of `domain`. Result is stored in `out`.
+//
+// `domain` is domain of sender or `helo` when sender is empty. Failed DNS queries result in `TempError`.
+//
+// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4) section `4`
+//
+// # Safety
+// Strings have to be NUL-terminated, `resolver` and `out` have to be valid pointers and callbacks
+// of `resolver` can't be null.
+enum SpfErrorCode spf_check(const char *domain,
+                            const char *ip,
+                            const char *sender,
+                            const char *helo,
+                            const struct SpfResolverCallbacks *resolver,
+                            enum SpfResultCode *out);
+
+// spf_last_error_message returns message of last error returned for handle or null when there was none.
+// Message is owned by handle and it's valid until next failed call with the same handle or until handle is freed.
+//
+// # Safety
+// `handle` has to be null or valid handle.
+const char *spf_last_error_message(const struct SpfHandle *handle);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SPF_H */
@@ -0,0 +1,377 @@
+//! Module with C API of parser, linter and `check_host`, which is enabled with `ffi` feature.
+//!
+//! Header of this API is `include/spf.h`, it's generated with `cbindgen --config cbindgen.toml --output include/spf.h`.
+//! Build crate with `cargo rustc --release --features ffi --crate-type cdylib` (or `staticlib`) in order to link it.
+//!
+//! All strings are NUL-terminated UTF-8. Functions return `SpfErrorCode`, values are returned through out pointers.
+//! When function taking handle fails, message describing the error can be read with `spf_last_error_message`.
+//! Panics never cross the boundary, they are turned into `SPF_ERROR_CODE_PANIC`.
+//!
+//! DNS queries of `spf_check` are performed by caller through `SpfResolverCallbacks`, so C side keeps control of I/O.
+
+use std::ffi::{CStr, CString};
+use std::net::IpAddr;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use crate::spf::{check_host, MacroContext, SpfEvaluationResult, SpfRecord, SpfResolver};
+
+/// SpfErrorCode is returned by all functions of C API.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpfErrorCode {
+    Ok = 0,
+
+    /// NullPointer is returned when required pointer argument is null.
+    NullPointer,
+
+    /// InvalidUtf8 is returned when string argument is not valid UTF-8.
+    InvalidUtf8,
+
+    /// ParseFailed is returned when record can't be parsed.
+    ParseFailed,
+
+    /// NoRecord is returned when handle holds no record, since parsing failed.
+    NoRecord,
+
+    /// InvalidIp is returned when IP address can't be parsed.
+    InvalidIp,
+
+    /// EvaluationFailed is returned when record can't be evaluated.
+    EvaluationFailed,
+
+    /// Panic is returned when function panicked. It's a bug.
+    Panic,
+}
+
+/// SpfResultCode is result of `spf_check`.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-2.6) section `2.6`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpfResultCode {
+    None = 0,
+    Neutral,
+    Pass,
+    Fail,
+    SoftFail,
+    TempError,
+    PermError,
+}
+
+impl From<SpfEvaluationResult> for SpfResultCode {
+    fn from(res: SpfEvaluationResult) -> Self {
+        match res {
+            SpfEvaluationResult::None => SpfResultCode::None,
+            SpfEvaluationResult::Neutral => SpfResultCode::Neutral,
+            SpfEvaluationResult::Pass => SpfResultCode::Pass,
+            SpfEvaluationResult::Fail => SpfResultCode::Fail,
+            SpfEvaluationResult::SoftFail => SpfResultCode::SoftFail,
+            SpfEvaluationResult::TempError => SpfResultCode::TempError,
+            SpfEvaluationResult::PermError | SpfEvaluationResult::LimitExceeded(_) => SpfResultCode::PermError,
+        }
+    }
+}
+
+/// SpfLintSeverity tells whether problem reported by `spf_lint` makes record invalid.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpfLintSeverity {
+    /// Error is reported for record, which can't be published, like one with two `redirect` modifiers.
+    Error = 0,
+
+    /// Warning is reported for valid record, which most likely does not do what its author meant.
+    Warning,
+}
+
+/// SpfHandle holds parsed record together with buffers of strings returned to caller.
+pub struct SpfHandle {
+    record: Option<SpfRecord<'static>>,
+    text: Option<CString>,
+    last_error: Option<CString>,
+}
+
+impl SpfHandle {
+    fn set_error(&mut self, code: SpfErrorCode, message: impl ToString) -> SpfErrorCode {
+        // messages come from valid C strings, so they have no NUL bytes
+        self.last_error = CString::new(message.to_string()).ok();
+        code
+    }
+}
+
+/// SpfAnswer collects answer of DNS query performed by resolver callback.
+pub struct SpfAnswer {
+    values: Vec<String>,
+}
+
+/// SpfLintCallback is called by `spf_lint` once for each problem found in record.
+/// `index` is index of directive, which caused the problem, `message` is valid only during the call.
+///
+/// It's nullable, so that null given by caller is rejected with `SPF_ERROR_CODE_NULL_POINTER` rather than called.
+pub type SpfLintCallback = Option<extern "C" fn(user_data: *mut c_void, severity: SpfLintSeverity, index: usize, message: *const c_char)>;
+
+/// SpfLookupCallback performs DNS query of given name and adds values from the answer to `answer`
+/// with `spf_answer_push`. It returns 0 on success, also when name does not exist, and anything else when
+/// query fails, for instance because of timeout.
+///
+/// It's nullable, so that null given by caller is rejected with `SPF_ERROR_CODE_NULL_POINTER` rather than called.
+pub type SpfLookupCallback = Option<extern "C" fn(user_data: *mut c_void, name: *const c_char, answer: *mut SpfAnswer) -> c_int>;
+
+/// SpfResolverCallbacks performs DNS queries required by `spf_check`.
+///
+/// Names given to callbacks have no trailing dot. Callbacks must not unwind and none of them may be null.
+#[repr(C)]
+pub struct SpfResolverCallbacks {
+    /// user_data is passed as first argument of each callback.
+    pub user_data: *mut c_void,
+
+    /// lookup_txt answers with texts of TXT records. Strings of each record have to be joined.
+    pub lookup_txt: SpfLookupCallback,
+
+    /// lookup_a answers with addresses of both A and AAAA records, like `192.0.2.1` or `2001:db8::1`.
+    pub lookup_a: SpfLookupCallback,
+
+    /// lookup_mx answers with hosts of MX records.
+    pub lookup_mx: SpfLookupCallback,
+
+    /// lookup_ptr answers with names of PTR records of given address, which is given in its text form,
+    /// like `192.0.2.1`, rather than as `in-addr.arpa` name.
+    pub lookup_ptr: SpfLookupCallback,
+}
+
+impl SpfResolverCallbacks {
+    /// has_null_callback checks if any callback is null. `spf_check` rejects such resolver up front.
+    fn has_null_callback(&self) -> bool {
+        [self.lookup_txt, self.lookup_a, self.lookup_mx, self.lookup_ptr].iter().any(Option::is_none)
+    }
+
+    fn lookup(&self, callback: SpfLookupCallback, name: &str) -> Result<Vec<String>, ()> {
+        let callback = callback.ok_or(())?;
+        let name = CString::new(name).map_err(|_| ())?;
+        let mut answer = SpfAnswer {
+            values: Vec::new(),
+        };
+        match callback(self.user_data, name.as_ptr(), &mut answer) {
+            0 => Ok(answer.values),
+            _ => Err(()),
+        }
+    }
+}
+
+impl SpfResolver for SpfResolverCallbacks {
+    type Error = ();
+
+    fn lookup_spf(&self, domain: &str) -> Result<Vec<String>, ()> {
+        self.lookup(self.lookup_txt, domain)
+    }
+
+    fn lookup_a(&self, domain: &str) -> Result<Vec<IpAddr>, ()> {
+        // addresses which can't be parsed are skipped, just like records of other types would be
+        Ok(self.lookup(self.lookup_a, domain)?.iter().filter_map(|a| a.parse().ok()).collect())
+    }
+
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<String>, ()> {
+        self.lookup(self.lookup_mx, domain)
+    }
+
+    fn domain_exists(&self, domain: &str) -> Result<bool, ()> {
+        // exists mechanism always queries A records, even for IPv6 clients
+        Ok(SpfResolver::lookup_a(self, domain)?.iter().any(IpAddr::is_ipv4))
+    }
+
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, ()> {
+        self.lookup(self.lookup_ptr, &ip.to_string())
+    }
+}
+
+/// guard runs function and turns its panic into error code.
+fn guard<F>(f: F) -> SpfErrorCode
+    where F: FnOnce() -> SpfErrorCode
+{
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(SpfErrorCode::Panic)
+}
+
+/// str_arg borrows string argument.
+///
+/// # Safety
+/// Pointer has to be null or point at NUL-terminated string, which outlives returned one.
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, SpfErrorCode> {
+    if s.is_null() {
+        return Err(SpfErrorCode::NullPointer);
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| SpfErrorCode::InvalidUtf8)
+}
+
+/// spf_parse parses SPF record and stores handle of it in `out`. Handle has to be freed with `spf_record_free`.
+///
+/// When parsing fails `SPF_ERROR_CODE_PARSE_FAILED` is returned, but handle without record is stored anyway,
+/// so that `spf_last_error_message` can tell what's wrong with record.
+///
+/// # Safety
+/// `text` has to be NUL-terminated string and `out` has to be valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn spf_parse(text: *const c_char, out: *mut *mut SpfHandle) -> SpfErrorCode {
+    guard(|| {
+        if out.is_null() {
+            return SpfErrorCode::NullPointer;
+        }
+        *out = ptr::null_mut();
+        let text = match str_arg(text) {
+            Ok(text) => text,
+            Err(code) => return code,
+        };
+        let mut handle = Box::new(SpfHandle {
+            record: None,
+            text: None,
+            last_error: None,
+        });
+        let code = match SpfRecord::parse_str(text) {
+            Ok(record) => {
+                handle.record = Some(record.into_owned());
+                SpfErrorCode::Ok
+            }
+            Err(e) => handle.set_error(SpfErrorCode::ParseFailed, e),
+        };
+        *out = Box::into_raw(handle);
+        code
+    })
+}
+
+/// spf_record_free frees handle returned by `spf_parse`. Null handle is ignored.
+///
+/// # Safety
+/// `handle` has to be null or handle returned by `spf_parse`, which was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn spf_record_free(handle: *mut SpfHandle) {
+    if !handle.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(handle))));
+    }
+}
+
+/// spf_record_to_string stores text of record in `out`. Text is owned by handle and it's valid until
+/// next call of this function with the same handle or until handle is freed.
+///
+/// # Safety
+/// `handle` has to be valid handle and `out` has to be valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn spf_record_to_string(handle: *mut SpfHandle, out: *mut *const c_char) -> SpfErrorCode {
+    guard(|| {
+        let handle = match handle.as_mut() {
+            Some(handle) if !out.is_null() => handle,
+            _ => return SpfErrorCode::NullPointer,
+        };
+        *out = ptr::null();
+        let text = match &handle.record {
+            Some(record) => record.to_string(),
+            None => return handle.set_error(SpfErrorCode::NoRecord, "handle holds no record"),
+        };
+        // record text can't contain NUL bytes, since it was parsed from C string
+        let text = handle.text.insert(CString::new(text).expect("record text has no NUL bytes"));
+        *out = text.as_ptr();
+        SpfErrorCode::Ok
+    })
+}
+
+/// spf_lint calls `callback` for each error and warning of record. Errors are reported first.
+///
+/// # Safety
+/// `handle` has to be valid handle and `callback` can't be null. `user_data` is passed to callback as is.
+#[no_mangle]
+pub unsafe extern "C" fn spf_lint(handle: *mut SpfHandle, callback: SpfLintCallback, user_data: *mut c_void) -> SpfErrorCode {
+    guard(|| {
+        let (handle, callback) = match (handle.as_mut(), callback) {
+            (Some(handle), Some(callback)) => (handle, callback),
+            _ => return SpfErrorCode::NullPointer,
+        };
+        let record = match &handle.record {
+            Some(record) => record,
+            None => return handle.set_error(SpfErrorCode::NoRecord, "handle holds no record"),
+        };
+        let errors = record.validate().err().unwrap_or_default().into_iter()
+            .map(|e| (SpfLintSeverity::Error, e.index(), e.to_string()));
+        let warnings = record.warnings().into_iter()
+            .map(|w| (SpfLintSeverity::Warning, w.index(), w.to_string()));
+        for (severity, index, message) in errors.chain(warnings) {
+            let message = CString::new(message).unwrap_or_default();
+            callback(user_data, severity, index, message.as_ptr());
+        }
+        SpfErrorCode::Ok
+    })
+}
+
+/// spf_answer_push adds value to answer of DNS query. It may be called only by resolver callbacks.
+///
+/// # Safety
+/// `answer` has to be answer given to callback and `value` has to be NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn spf_answer_push(answer: *mut SpfAnswer, value: *const c_char) -> SpfErrorCode {
+    guard(|| {
+        let answer = match answer.as_mut() {
+            Some(answer) => answer,
+            None => return SpfErrorCode::NullPointer,
+        };
+        match str_arg(value) {
+            Ok(value) => {
+                answer.values.push(value.to_string());
+                SpfErrorCode::Ok
+            }
+            Err(code) => code,
+        }
+    })
+}
+
+/// spf_check checks whether host with address `ip` is authorized to send mail from `sender`, which
+/// introduced itself with `helo`, by SPF record of `domain`. Result is stored in `out`.
+///
+/// `domain` is domain of sender or `helo` when sender is empty. Failed DNS queries result in `TempError`.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4) section `4`
+///
+/// # Safety
+/// Strings have to be NUL-terminated, `resolver` and `out` have to be valid pointers and callbacks
+/// of `resolver` can't be null.
+#[no_mangle]
+pub unsafe extern "C" fn spf_check(
+    domain: *const c_char,
+    ip: *const c_char,
+    sender: *const c_char,
+    helo: *const c_char,
+    resolver: *const SpfResolverCallbacks,
+    out: *mut SpfResultCode,
+) -> SpfErrorCode {
+    guard(|| {
+        let resolver = match resolver.as_ref() {
+            Some(resolver) if !out.is_null() && !resolver.has_null_callback() => resolver,
+            _ => return SpfErrorCode::NullPointer,
+        };
+        let (domain, ip, sender, helo) = match (str_arg(domain), str_arg(ip), str_arg(sender), str_arg(helo)) {
+            (Ok(domain), Ok(ip), Ok(sender), Ok(helo)) => (domain, ip, sender, helo),
+            (Err(code), _, _, _) | (_, Err(code), _, _) | (_, _, Err(code), _) | (_, _, _, Err(code)) => return code,
+        };
+        let ip = match ip.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => return SpfErrorCode::InvalidIp,
+        };
+        match check_host(resolver, &MacroContext::new(sender, domain, ip, helo)) {
+            Ok(res) => {
+                *out = SpfResultCode::from(res.result);
+                SpfErrorCode::Ok
+            }
+            Err(_) => SpfErrorCode::EvaluationFailed,
+        }
+    })
+}
+
+/// spf_last_error_message returns message of last error returned for handle or null when there was none.
+/// Message is owned by handle and it's valid until next failed call with the same handle or until handle is freed.
+///
+/// # Safety
+/// `handle` has to be null or valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn spf_last_error_message(handle: *const SpfHandle) -> *const c_char {
+    match handle.as_ref().and_then(|h| h.last_error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
@@ -1,9 +1,21 @@
 use std::collections::HashMap;
+#[cfg(feature = "arbitrary")]
+use std::cell::Cell;
+#[cfg(feature = "arbitrary")]
+use std::convert::Infallible;
+#[cfg(feature = "arbitrary")]
+use std::net::IpAddr;
 
+#[cfg(feature = "arbitrary")]
+use arbitrary::Unstructured;
 use lazy_static::lazy_static;
 
 use crate::spf::evaluate_macro;
+use crate::spf::MacroEvaluationError;
 use crate::spf::MacroVariable;
+use crate::spf::SpfRecord;
+#[cfg(feature = "arbitrary")]
+use crate::spf::{DomainSpec, InMemoryResolver, MacroContext, SpfEvaluationError, SpfMechanism, SpfResolver};
 
 lazy_static! {
     static ref DEFAULT_OPTIONS_MAP: HashMap<MacroVariable, &'static str> = {
@@ -14,8 +26,278 @@ lazy_static! {
     };
 }
 
+/// fuzz_evaluate_macro evaluates input with fixed context. It's fast smoke target for macro parser.
+/// Syntax errors have to point at char boundary inside of input.
 pub fn fuzz_evaluate_macro(data: &[u8]) {
     if let Ok(text) = std::str::from_utf8(data) {
-        let _ = evaluate_macro(&*DEFAULT_OPTIONS_MAP, text);
+        if let Err(MacroEvaluationError::ParsingSyntaxError { offset }) = evaluate_macro(&*DEFAULT_OPTIONS_MAP, text) {
+            assert!(text.is_char_boundary(offset), "{:?} has syntax error at offset {}, which is not char boundary", text, offset);
+        }
+    }
+}
+
+/// fuzz_parse_record parses input as record text. Every successfully parsed record has to be valid.
+pub fn fuzz_parse_record(data: &[u8]) {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(record) = SpfRecord::parse_str(text) {
+            assert!(record.validate().is_ok(), "{:?} parsed into invalid record: {:?}", text, record.validate());
+        }
+    }
+}
+
+/// fuzz_parse_bytes parses raw bytes, which may not be valid UTF-8. Since parser builds strings from them
+/// without UTF-8 validation, only ASCII input may be parsed successfully.
+pub fn fuzz_parse_bytes(data: &[u8]) {
+    if let Ok(record) = SpfRecord::parse_bytes(data) {
+        assert!(data.is_ascii(), "non ASCII input was parsed: {:?}", data);
+        assert!(record.validate().is_ok(), "{:?} parsed into invalid record: {:?}", data, record.validate());
+    }
+}
+
+/// fuzz_roundtrip checks that every successfully parsed record is displayed as text which parses back
+/// into equal record, and that normalization is idempotent and keeps records valid.
+pub fn fuzz_roundtrip(data: &[u8]) {
+    let record = match SpfRecord::parse_bytes(data) {
+        Ok(record) => record,
+        Err(_) => return,
+    };
+    let text = record.to_string();
+    let reparsed = SpfRecord::parse_str(&text)
+        .unwrap_or_else(|e| panic!("displayed record {:?} can't be parsed: {:?}", text, e));
+    assert_eq!(reparsed.to_string(), text);
+    assert_eq!(reparsed.into_owned(), record.clone().into_owned());
+
+    let normalized = record.normalize();
+    assert_eq!(normalized.normalize(), normalized);
+    let text = normalized.to_string();
+    assert_eq!(SpfRecord::parse_str(&text).map(SpfRecord::into_owned).ok(), Some(normalized));
+}
+
+#[cfg(feature = "arbitrary")]
+pub use self::context::fuzz_evaluate_macro_with_context;
+
+#[cfg(feature = "arbitrary")]
+mod context {
+    use std::collections::HashMap;
+
+    use arbitrary::{Result, Unstructured};
+
+    use crate::spf::{evaluate_macro, validate_macro, DomainSpec, MacroEvaluationError, MacroVariable, MAX_DOMAIN_LENGTH};
+
+    /// MAX_VALUE_LENGTH is maximum length of single generated variable value.
+    const MAX_VALUE_LENGTH: usize = 8 * 1024;
+
+    const VALUE_PARTS: &[&str] = &[
+        ".", "-", "+", ",", "/", "_", "=", "%", "%%", "%{", "}", "example", "com", "192.0.2.1", "2001:db8::1",
+        "ż", "日本", "\u{0}", "\u{7f}", "\u{80}", "\u{fffd}", " ",
+    ];
+
+    fn arbitrary_value(u: &mut Unstructured) -> Result<String> {
+        let mut res = String::new();
+        match u.int_in_range(0..=3)? {
+            0 => {}
+            1 => {
+                res.push_str(u.arbitrary()?);
+            }
+            2 => {
+                let part = *u.choose(VALUE_PARTS)?;
+                let count = u.int_in_range(1..=MAX_VALUE_LENGTH / part.len())?;
+                res = part.repeat(count);
+            }
+            _ => {
+                let parts = u.int_in_range(1..=64)?;
+                for _ in 0..parts {
+                    res.push_str(u.choose(VALUE_PARTS)?);
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    fn arbitrary_context(u: &mut Unstructured) -> Result<HashMap<MacroVariable, String>> {
+        let mut res = HashMap::new();
+        for v in MacroVariable::VARIANTS.iter() {
+            // missing variables have to be reported as errors as well
+            if u.ratio(1, 8)? {
+                continue;
+            }
+            res.insert(*v, arbitrary_value(u)?);
+        }
+        Ok(res)
+    }
+
+    /// fuzz_evaluate_macro_with_context splits input into macro text and values of variables,
+    /// then checks that validation agrees with evaluation and that expanded domains are not too long.
+    pub fn fuzz_evaluate_macro_with_context(data: &[u8]) {
+        let mut u = Unstructured::new(data);
+        let text: &str = match u.arbitrary() {
+            Ok(text) => text,
+            Err(_) => return,
+        };
+        let ctx = match arbitrary_context(&mut u) {
+            Ok(ctx) => ctx,
+            Err(_) => return,
+        };
+
+        let validated = validate_macro(text);
+        let evaluated = evaluate_macro(&ctx, text);
+        match (&validated, &evaluated) {
+            (Ok(()), Err(MacroEvaluationError::UnknownVariable(_))) |
+            (Ok(()), Err(MacroEvaluationError::ExpOnlyVariable(_))) |
+            (Ok(()), Ok(_)) |
+            (Err(_), Err(_)) => {}
+            _ => panic!("validate_macro returned {:?} but evaluate_macro returned {:?}", validated, evaluated),
+        }
+
+        if validated.is_ok() {
+            let d = DomainSpec::from_raw_unchecked(text.into());
+            if let Ok(expanded) = d.expand(&ctx, "example.com") {
+                assert!(d.is_literal() || expanded.len() <= MAX_DOMAIN_LENGTH);
+            }
+        }
+    }
+}
+
+/// fuzz_parse_txt_chunks parses input as TXT record rdata, which is sequence of character-strings
+/// prefixed with their lengths. Record parsed from chunks has to be the same as record parsed from their concatenation.
+pub fn fuzz_parse_txt_chunks(data: &[u8]) {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while let Some((len, tail)) = rest.split_first() {
+        let len = *len as usize;
+        if tail.len() < len {
+            return;
+        }
+        match std::str::from_utf8(&tail[..len]) {
+            Ok(chunk) => chunks.push(chunk),
+            Err(_) => return,
+        }
+        rest = &tail[len..];
+    }
+
+    let text = chunks.concat();
+    let expected = SpfRecord::parse_str(&text).map(SpfRecord::into_owned);
+    assert_eq!(SpfRecord::parse_txt_chunks(chunks), expected, "{:?}", text);
+}
+
+/// UNIVERSE contains domains, which generated records point at, so that includes and redirects
+/// form chains and loops.
+#[cfg(feature = "arbitrary")]
+const UNIVERSE: &[&str] = &["example.com", "a.example.com", "b.example.com", "example.org"];
+
+/// MAX_QUERIES bounds number of queries made during single check: record of checked domain, one query
+/// for each of 10 terms which cause DNS lookups, which may be followed by queries of at most 10 MX hosts
+/// or PTR names, and validation of PTR names required by `%{p}`.
+#[cfg(feature = "arbitrary")]
+const MAX_QUERIES: usize = 1 + 10 * (1 + 10) + (1 + 10);
+
+/// CountingResolver fails fuzz target as soon as check makes more queries than `MAX_QUERIES`.
+#[cfg(feature = "arbitrary")]
+struct CountingResolver {
+    inner: InMemoryResolver,
+    queries: Cell<usize>,
+}
+
+#[cfg(feature = "arbitrary")]
+impl CountingResolver {
+    fn count(&self) -> &InMemoryResolver {
+        let queries = self.queries.get() + 1;
+        assert!(queries <= MAX_QUERIES, "check made more than {} queries", MAX_QUERIES);
+        self.queries.set(queries);
+        &self.inner
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl SpfResolver for CountingResolver {
+    type Error = Infallible;
+
+    fn lookup_spf(&self, domain: &str) -> Result<Vec<String>, Self::Error> {
+        self.count().lookup_spf(domain)
+    }
+
+    fn lookup_a(&self, domain: &str) -> Result<Vec<IpAddr>, Self::Error> {
+        self.count().lookup_a(domain)
+    }
+
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<String>, Self::Error> {
+        self.count().lookup_mx(domain)
+    }
+
+    fn domain_exists(&self, domain: &str) -> Result<bool, Self::Error> {
+        self.count().domain_exists(domain)
+    }
+
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, Self::Error> {
+        self.count().lookup_ptr(ip)
+    }
+}
+
+/// retarget points domain-spec of mechanism at given domain. Mechanisms without domain-spec are returned as they are.
+#[cfg(feature = "arbitrary")]
+fn retarget(mechanism: SpfMechanism<'static>, domain: &'static str) -> SpfMechanism<'static> {
+    let spec = DomainSpec::new(domain).unwrap();
+    match mechanism {
+        SpfMechanism::A(Some(_), cidr) => SpfMechanism::A(Some(spec), cidr),
+        SpfMechanism::AAAA(Some(_), cidr) => SpfMechanism::AAAA(Some(spec), cidr),
+        SpfMechanism::MX(Some(_), cidr) => SpfMechanism::MX(Some(spec), cidr),
+        SpfMechanism::Ptr(Some(_)) => SpfMechanism::Ptr(Some(spec)),
+        SpfMechanism::Include(_) => SpfMechanism::Include(spec),
+        SpfMechanism::Exists(_) => SpfMechanism::Exists(spec),
+        SpfMechanism::Redirect(_) => SpfMechanism::Redirect(spec),
+        m => m,
+    }
+}
+
+/// arbitrary_universe generates resolver, which knows records of all domains of `UNIVERSE`, and source IP.
+/// Most domain-specs of generated records point at domains of universe.
+#[cfg(feature = "arbitrary")]
+fn arbitrary_universe(u: &mut Unstructured) -> arbitrary::Result<(InMemoryResolver, IpAddr)> {
+    let mut resolver = InMemoryResolver::new();
+    for domain in UNIVERSE.iter() {
+        let mut record: SpfRecord<'static> = u.arbitrary()?;
+        for d in record.directives.iter_mut() {
+            if u.ratio(3, 4)? {
+                let target = UNIVERSE[u.choose_index(UNIVERSE.len())?];
+                d.mechanism = retarget(std::mem::replace(&mut d.mechanism, SpfMechanism::All), target);
+            }
+        }
+        resolver.add_txt(domain, &record.to_string());
+        if u.arbitrary()? {
+            resolver.add_address(domain, u.arbitrary()?);
+        }
+        if u.arbitrary()? {
+            resolver.add_mx(domain, UNIVERSE[u.choose_index(UNIVERSE.len())?]);
+        }
+    }
+    let ip: IpAddr = u.arbitrary()?;
+    if u.arbitrary()? {
+        let name = UNIVERSE[u.choose_index(UNIVERSE.len())?];
+        resolver.add_ptr(ip, name).add_address(name, ip);
+    }
+    Ok((resolver, ip))
+}
+
+/// fuzz_evaluate_with_resolver checks record of first domain of generated universe of domains with
+/// arbitrary records, which include and redirect to each other.
+/// Check has to finish within `MAX_QUERIES` queries, regardless of loops, and may fail only because of macros.
+#[cfg(feature = "arbitrary")]
+pub fn fuzz_evaluate_with_resolver(data: &[u8]) {
+    let mut u = Unstructured::new(data);
+    let (resolver, ip) = match arbitrary_universe(&mut u) {
+        Ok(universe) => universe,
+        Err(_) => return,
+    };
+    let texts = resolver.lookup_spf(UNIVERSE[0]).unwrap();
+    let record = SpfRecord::parse_str(&texts[0]).unwrap();
+
+    let ctx = MacroContext::new("user@example.org", UNIVERSE[0], ip, UNIVERSE[1]);
+    let resolver = CountingResolver {
+        inner: resolver,
+        queries: Cell::new(0),
+    };
+    match record.evaluate_with_resolver(&resolver, ip, &ctx) {
+        Ok(_) | Err(SpfEvaluationError::Macro(_)) => {}
+        Err(e) => panic!("{} evaluated with error {:?}", record, e),
     }
 }
@@ -1,3 +1,14 @@
+//! spf crate implements parsing and evaluation of SPF records.
+//!
+//! # Thread safety
+//! All public types with `'static` lifetime(records, directives, resource bags and errors) are `Send` and `Sync`,
+//! so parsed records may be cached and shared between threads, for instance in `Arc`.
+//! `DomainSpec` caches parsed macro string in atomic pointer, so it's safe to expand it from many threads at once.
+//!
+//! Traits meant to be implemented by users, like `EvaluationContext` and `SpfResolver`, are object safe, so they
+//! may be used as `&dyn EvaluationContext`. `AsyncSpfResolver` is an exception, since its methods return
+//! `impl Future`, but its futures are `Send`, so async evaluation may be spawned on multi-threaded executors.
+
 #[macro_use]
 extern crate derive_more;
 #[macro_use]
@@ -6,5 +17,9 @@ extern crate serde_derive;
 pub use spf::*;
 
 mod spf;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 #[cfg(fuzzing)]
-pub mod fuzz;
\ No newline at end of file
+pub mod fuzz;
@@ -0,0 +1,409 @@
+//! Module with `Arbitrary` implementations for SPF types, used by structured fuzzing.
+//!
+//! Only valid values are generated: CIDR lengths are in range, domains use constrained label alphabet and
+//! macro strings are built from valid tokens. Generated records consist of mechanisms followed by at most one
+//! `redirect` and at most one `exp` modifier.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use ::arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::spf::{
+    DomainSpec, DualCidr, Ipv4Net, Ipv6Net, MAX_IPV4_PREFIX_LENGTH, MAX_IPV6_PREFIX_LENGTH, MacroVariable,
+    SpfAction, SpfDirective, SpfMechanism, SpfRecord, UnknownModifier,
+};
+
+/// MAX_DIRECTIVES is maximum number of mechanisms in generated record.
+const MAX_DIRECTIVES: usize = 12;
+
+const LABEL_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789-_";
+const TOP_LABELS: &[&str] = &["com", "org", "net", "example", "test", "co-uk", "x1"];
+const MODIFIER_NAME_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789-_.";
+const MACRO_DELIMITERS: &[char] = &['.', '-', '+', ',', '/', '_', '='];
+
+fn arbitrary_label(u: &mut Unstructured) -> Result<String> {
+    let len = u.int_in_range(1..=12)?;
+    let mut res = String::with_capacity(len);
+    for _ in 0..len {
+        res.push(*u.choose(LABEL_CHARS)? as char);
+    }
+    Ok(res)
+}
+
+fn arbitrary_macro_expand(u: &mut Unstructured, res: &mut String) -> Result<()> {
+    let variable: MacroVariable = u.arbitrary()?;
+    let mut letter = variable.into_num() as char;
+    if u.ratio(1, 4)? {
+        letter = letter.to_ascii_uppercase();
+    }
+    if u.ratio(1, 3)? {
+        res.push('%');
+        res.push(letter);
+        return Ok(());
+    }
+    res.push_str("%{");
+    res.push(letter);
+    if u.ratio(1, 3)? {
+        res.push_str(&u.int_in_range(1..=128u32)?.to_string());
+    }
+    if u.arbitrary()? {
+        res.push('r');
+    }
+    let delimiters = u.int_in_range(0..=2)?;
+    for _ in 0..delimiters {
+        res.push(*u.choose(MACRO_DELIMITERS)?);
+    }
+    res.push('}');
+    Ok(())
+}
+
+/// arbitrary_domain_spec_text generates text which matches `domain-spec` grammar.
+fn arbitrary_domain_spec_text(u: &mut Unstructured, allow_macros: bool) -> Result<String> {
+    let mut res = String::new();
+    let parts = u.int_in_range(1..=4)?;
+    for i in 0..parts {
+        if i > 0 {
+            res.push('.');
+        }
+        if allow_macros && u.ratio(1, 3)? {
+            arbitrary_macro_expand(u, &mut res)?;
+        } else {
+            res.push_str(&arbitrary_label(u)?);
+            if allow_macros && u.ratio(1, 8)? {
+                res.push_str(u.choose(&["%%", "%_", "%-"])?);
+            }
+        }
+    }
+    if !(allow_macros && res.ends_with('}') && u.arbitrary()?) {
+        res.push('.');
+        res.push_str(u.choose(TOP_LABELS)?);
+    }
+    Ok(res)
+}
+
+fn arbitrary_domain_spec(u: &mut Unstructured) -> Result<DomainSpec<'static>> {
+    let allow_macros = u.ratio(1, 4)?;
+    let text = arbitrary_domain_spec_text(u, allow_macros)?;
+    Ok(DomainSpec::new(text).expect("generated domain-spec is valid"))
+}
+
+fn arbitrary_optional_domain_spec(u: &mut Unstructured) -> Result<Option<DomainSpec<'static>>> {
+    if u.arbitrary()? {
+        Ok(Some(arbitrary_domain_spec(u)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn arbitrary_prefix(u: &mut Unstructured, max: u8) -> Result<Option<u8>> {
+    if u.arbitrary()? {
+        Ok(Some(u.int_in_range(0..=max)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn arbitrary_modifier_name(u: &mut Unstructured) -> Result<String> {
+    loop {
+        let mut res = String::new();
+        res.push(*u.choose(b"abcdefghijklmnopqrstuvwxyz")? as char);
+        let len = u.int_in_range(0..=8)?;
+        for _ in 0..len {
+            res.push(*u.choose(MODIFIER_NAME_CHARS)? as char);
+        }
+        if res != "redirect" && res != "exp" {
+            return Ok(res);
+        }
+    }
+}
+
+/// arbitrary_mechanism generates mechanism(not modifier).
+fn arbitrary_mechanism(u: &mut Unstructured) -> Result<SpfMechanism<'static>> {
+    Ok(match u.int_in_range(0..=8u8)? {
+        0 => SpfMechanism::A(arbitrary_optional_domain_spec(u)?, u.arbitrary()?),
+        1 => SpfMechanism::AAAA(arbitrary_optional_domain_spec(u)?, u.arbitrary()?),
+        2 => SpfMechanism::MX(arbitrary_optional_domain_spec(u)?, u.arbitrary()?),
+        3 => SpfMechanism::Ipv4(u.arbitrary()?),
+        4 => SpfMechanism::Ipv6(u.arbitrary()?),
+        5 => SpfMechanism::Include(arbitrary_domain_spec(u)?),
+        6 => SpfMechanism::Exists(arbitrary_domain_spec(u)?),
+        7 => SpfMechanism::Ptr(arbitrary_optional_domain_spec(u)?),
+        _ => SpfMechanism::All,
+    })
+}
+
+impl<'a> Arbitrary<'a> for SpfAction {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[SpfAction::Pass, SpfAction::Fail, SpfAction::SoftFail, SpfAction::Neutral])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for MacroVariable {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(MacroVariable::VARIANTS)?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for DualCidr {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let v4 = arbitrary_prefix(u, MAX_IPV4_PREFIX_LENGTH)?;
+        let v6 = arbitrary_prefix(u, MAX_IPV6_PREFIX_LENGTH)?;
+        Ok(DualCidr::new(v4, v6).expect("generated prefix lengths are in range"))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Ipv4Net {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let addr = Ipv4Addr::from(u.arbitrary::<u32>()?);
+        let prefix = arbitrary_prefix(u, MAX_IPV4_PREFIX_LENGTH)?;
+        Ok(Ipv4Net::new(addr, prefix).expect("generated prefix length is in range"))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Ipv6Net {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let addr = Ipv6Addr::from(u.arbitrary::<u128>()?);
+        let prefix = arbitrary_prefix(u, MAX_IPV6_PREFIX_LENGTH)?;
+        Ok(Ipv6Net::new(addr, prefix).expect("generated prefix length is in range"))
+    }
+}
+
+impl<'a> Arbitrary<'a> for DomainSpec<'static> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        arbitrary_domain_spec(u)
+    }
+}
+
+impl<'a> Arbitrary<'a> for SpfMechanism<'static> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=10u8)? {
+            0 => SpfMechanism::Redirect(arbitrary_domain_spec(u)?),
+            1 => SpfMechanism::Exp(arbitrary_domain_spec(u)?),
+            2 => {
+                let value = if u.arbitrary()? {
+                    arbitrary_domain_spec_text(u, true)?
+                } else {
+                    String::new()
+                };
+                SpfMechanism::from(UnknownModifier::new(arbitrary_modifier_name(u)?, value))
+            }
+            _ => arbitrary_mechanism(u)?,
+        })
+    }
+}
+
+/// arbitrary_directive generates directive holding given mechanism(not modifier).
+/// `Pass` qualifier is written explicitly or left implicit, other qualifiers are always explicit.
+fn arbitrary_directive(u: &mut Unstructured, mechanism: SpfMechanism<'static>) -> Result<SpfDirective<'static>> {
+    let qualifier: SpfAction = u.arbitrary()?;
+    Ok(SpfDirective {
+        qualifier,
+        explicit_qualifier: qualifier != SpfAction::Pass || u.arbitrary()?,
+        mechanism,
+    })
+}
+
+impl<'a> Arbitrary<'a> for SpfDirective<'static> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mechanism: SpfMechanism<'static> = u.arbitrary()?;
+        // modifiers have no qualifier
+        if mechanism.is_modifier() {
+            Ok(SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism,
+            })
+        } else {
+            arbitrary_directive(u, mechanism)
+        }
+    }
+}
+
+impl<'a> Arbitrary<'a> for SpfRecord<'static> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut directives = Vec::new();
+        let len = u.int_in_range(0..=MAX_DIRECTIVES)?;
+        for _ in 0..len {
+            let mechanism = arbitrary_mechanism(u)?;
+            directives.push(arbitrary_directive(u, mechanism)?);
+        }
+        if u.ratio(1, 4)? {
+            directives.push(SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism: SpfMechanism::Redirect(arbitrary_domain_spec(u)?),
+            });
+        }
+        if u.ratio(1, 8)? {
+            directives.push(SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism: SpfMechanism::Exp(arbitrary_domain_spec(u)?),
+            });
+        }
+        Ok(SpfRecord::from(directives))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    /// CORPUS_TARGETS are fuzz targets, which take record text as input.
+    const CORPUS_TARGETS: &[&str] = &["parse_record", "parse_bytes", "roundtrip"];
+
+    /// TXT_CORPUS_TARGET is fuzz target, which takes TXT record rdata as input.
+    const TXT_CORPUS_TARGET: &str = "parse_txt_chunks";
+
+    /// CORPUS_SIZE is number of valid records in generated corpus.
+    const CORPUS_SIZE: u64 = 300;
+
+    /// MUTATION_POINTS are characters after which (or at which) mutants are most likely to hit error paths.
+    const MUTATION_POINTS: &[u8] = b":/=%{} +-~?";
+    const MUTATION_CHARS: &[u8] = b":/=%{}.+-~?a0\x7f";
+
+    /// seeded_bytes returns deterministic pseudo random bytes.
+    fn seeded_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_generated_records_are_valid() {
+        for seed in 0..300 {
+            let bytes = seeded_bytes(seed, 4096);
+            let mut u = Unstructured::new(&bytes);
+            let record: SpfRecord<'static> = u.arbitrary().unwrap();
+
+            let text = record.to_string();
+            assert_eq!(SpfRecord::parse_str(&text).unwrap().into_owned(), record, "{}", text);
+            for d in record.directives.iter() {
+                if let Some(domain) = d.mechanism.target() {
+                    domain.validate().unwrap_or_else(|e| panic!("{} is not valid: {}", domain, e));
+                }
+                if d.mechanism.is_modifier() {
+                    assert_eq!(d.qualifier, SpfAction::Pass);
+                    assert!(!d.explicit_qualifier);
+                }
+                if d.qualifier != SpfAction::Pass {
+                    assert!(d.explicit_qualifier);
+                }
+            }
+            assert!(record.directives.iter().filter(|d| d.mechanism.is_redirect()).count() <= 1);
+        }
+    }
+
+    /// seed_record generates record for seed corpus. Generated records have no unknown modifiers,
+    /// so some are appended here.
+    fn seed_record(seed: u64) -> SpfRecord<'static> {
+        let bytes = seeded_bytes(seed, 4096);
+        let mut u = Unstructured::new(&bytes);
+        let mut record: SpfRecord<'static> = u.arbitrary().unwrap();
+        for _ in 0..u.int_in_range(0..=2).unwrap() {
+            let d: SpfDirective<'static> = u.arbitrary().unwrap();
+            if let SpfMechanism::UnknownModifier(_) = d.mechanism {
+                record.directives.push(d);
+            }
+        }
+        record
+    }
+
+    /// near_valid_mutants returns copies of text with single byte deleted or replaced
+    /// at position chosen from ones next to `MUTATION_POINTS`.
+    fn near_valid_mutants(text: &str, seed: u64) -> Vec<Vec<u8>> {
+        let text = text.as_bytes();
+        let points: Vec<usize> = (0..text.len())
+            .filter(|i| MUTATION_POINTS.contains(&text[*i]))
+            .flat_map(|i| vec![i, i + 1])
+            .filter(|i| *i < text.len())
+            .collect();
+        if points.is_empty() {
+            return Vec::new();
+        }
+        let random = seeded_bytes(seed, 3);
+        let pos = points[random[0] as usize % points.len()];
+
+        let mut deleted = text.to_vec();
+        deleted.remove(pos);
+        let mut flipped = text.to_vec();
+        flipped[pos] = MUTATION_CHARS[random[1] as usize % MUTATION_CHARS.len()];
+        vec![deleted, flipped]
+    }
+
+    /// txt_chunks splits text into character-strings of pseudo random lengths, so that terms are split across them.
+    fn txt_chunks(text: &[u8], seed: u64) -> Vec<&[u8]> {
+        let mut chunks = Vec::new();
+        let mut rest = text;
+        for len in seeded_bytes(seed, text.len()) {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at((len as usize % 32 + 1).min(rest.len()));
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+    }
+
+    /// txt_rdata encodes character-strings as TXT record rdata, each one prefixed with its length.
+    fn txt_rdata(chunks: &[&[u8]]) -> Vec<u8> {
+        chunks.iter().flat_map(|c| std::iter::once(c.len() as u8).chain(c.iter().copied())).collect()
+    }
+
+    /// test_seed_corpus generates seed corpus for record fuzz targets from fixed seeds.
+    /// Corpus is written into `fuzz/corpus` only when `SPF_WRITE_CORPUS` environment variable is set:
+    /// `SPF_WRITE_CORPUS=1 cargo test --features arbitrary test_seed_corpus`
+    #[test]
+    fn test_seed_corpus() {
+        let mut corpus = Vec::new();
+        for seed in 0..CORPUS_SIZE {
+            let record = seed_record(seed);
+            let text = record.to_string();
+            assert_eq!(SpfRecord::parse_str(&text).unwrap().into_owned(), record, "{}", text);
+
+            corpus.push((format!("gen-{:04}", seed), text.clone().into_bytes()));
+            for (i, mutant) in near_valid_mutants(&text, seed).into_iter().enumerate() {
+                corpus.push((format!("gen-{:04}-mut{}", seed, i), mutant));
+            }
+        }
+
+        let kinds = corpus.iter()
+            .filter_map(|(_, text)| SpfRecord::parse_bytes(text).ok())
+            .flat_map(|r| r.directives.into_iter().map(|d| std::mem::discriminant(&d.mechanism)))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(kinds.len(), 12, "seed corpus does not contain every mechanism kind");
+
+        for (seed, (_, text)) in corpus.iter().enumerate() {
+            let chunks = txt_chunks(text, seed as u64)
+                .into_iter()
+                .map(|c| std::str::from_utf8(c).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(SpfRecord::parse_txt_chunks(chunks).ok(), SpfRecord::parse_bytes(text).map(SpfRecord::into_owned).ok());
+        }
+
+        if std::env::var_os("SPF_WRITE_CORPUS").is_none() {
+            return;
+        }
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("corpus");
+        for target in CORPUS_TARGETS.iter() {
+            let dir = root.join(target);
+            fs::create_dir_all(&dir).unwrap();
+            for (name, text) in corpus.iter() {
+                fs::write(dir.join(name), text).unwrap();
+            }
+        }
+        let dir = root.join(TXT_CORPUS_TARGET);
+        fs::create_dir_all(&dir).unwrap();
+        for (seed, (name, text)) in corpus.iter().enumerate() {
+            fs::write(dir.join(name), txt_rdata(&txt_chunks(text, seed as u64))).unwrap();
+        }
+    }
+}
@@ -0,0 +1,587 @@
+//! Module containing types describing CIDR prefix lengths used by SPF mechanisms.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// MAX_IPV4_PREFIX_LENGTH is number of bits in IPv4 address.
+pub const MAX_IPV4_PREFIX_LENGTH: u8 = 32;
+
+/// MAX_IPV6_PREFIX_LENGTH is number of bits in IPv6 address.
+pub const MAX_IPV6_PREFIX_LENGTH: u8 = 128;
+
+/// CidrError is returned when CIDR prefix length is out of range or can't be parsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CidrError {
+    /// InvalidIpv4Length is returned when IPv4 prefix length is greater than 32.
+    InvalidIpv4Length(u8),
+
+    /// InvalidIpv6Length is returned when IPv6 prefix length is greater than 128.
+    InvalidIpv6Length(u8),
+
+    /// InvalidFormat is returned when text does not match CIDR length grammar.
+    InvalidFormat,
+
+    /// InvalidAddress is returned when IP address of network can't be parsed.
+    InvalidAddress,
+}
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CidrError::InvalidIpv4Length(l) => write!(f, "IPv4 prefix length {} is greater than {}", l, MAX_IPV4_PREFIX_LENGTH),
+            CidrError::InvalidIpv6Length(l) => write!(f, "IPv6 prefix length {} is greater than {}", l, MAX_IPV6_PREFIX_LENGTH),
+            CidrError::InvalidFormat => write!(f, "invalid CIDR length format"),
+            CidrError::InvalidAddress => write!(f, "invalid IP address"),
+        }
+    }
+}
+
+impl std::error::Error for CidrError {}
+
+/// DualCidr contains optional IPv4 and IPv6 prefix lengths of `a`, `aaaa` and `mx` mechanisms.
+///
+/// It's always valid: IPv4 length is at most 32 and IPv6 length is at most 128.
+///
+/// # Format
+/// It's formatted and parsed just like in SPF record: `/24`, `//64`, `/24//64` or empty string
+/// when neither of lengths is given.
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-5.6) section `5.6`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(try_from = "RawDualCidr"))]
+pub struct DualCidr {
+    v4: Option<u8>,
+    v6: Option<u8>,
+}
+
+#[cfg(feature = "serialize")]
+#[derive(Deserialize)]
+struct RawDualCidr {
+    v4: Option<u8>,
+    v6: Option<u8>,
+}
+
+#[cfg(feature = "serialize")]
+impl TryFrom<RawDualCidr> for DualCidr {
+    type Error = CidrError;
+
+    fn try_from(raw: RawDualCidr) -> Result<Self, Self::Error> {
+        Self::new(raw.v4, raw.v6)
+    }
+}
+
+impl DualCidr {
+    /// new creates DualCidr from given lengths and checks if they are in valid range.
+    pub fn new(v4: Option<u8>, v6: Option<u8>) -> Result<Self, CidrError> {
+        match (v4, v6) {
+            (Some(l), _) if l > MAX_IPV4_PREFIX_LENGTH => Err(CidrError::InvalidIpv4Length(l)),
+            (_, Some(l)) if l > MAX_IPV6_PREFIX_LENGTH => Err(CidrError::InvalidIpv6Length(l)),
+            _ => Ok(Self {
+                v4,
+                v6,
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn v4(&self) -> Option<u8> {
+        self.v4
+    }
+
+    #[inline]
+    pub fn v6(&self) -> Option<u8> {
+        self.v6
+    }
+
+    /// effective_v4 returns IPv4 prefix length which should be used during evaluation.
+    /// When it's not given it defaults to 32.
+    #[inline]
+    pub fn effective_v4(&self) -> u8 {
+        self.v4.unwrap_or(MAX_IPV4_PREFIX_LENGTH)
+    }
+
+    /// effective_v6 returns IPv6 prefix length which should be used during evaluation.
+    /// When it's not given it defaults to 128.
+    #[inline]
+    pub fn effective_v6(&self) -> u8 {
+        self.v6.unwrap_or(MAX_IPV6_PREFIX_LENGTH)
+    }
+
+    /// is_default returns true if neither of lengths is given.
+    #[inline]
+    pub fn is_default(&self) -> bool {
+        self.v4.is_none() && self.v6.is_none()
+    }
+}
+
+/// parse_prefix_length parses decimal number without leading zeros. Range is not checked.
+pub(crate) fn parse_prefix_length(text: &str) -> Result<u8, CidrError> {
+    let b = text.as_bytes();
+    if b.is_empty() || b.len() > 3 || !b.iter().all(|c| c.is_ascii_digit()) || (b.len() > 1 && b[0] == b'0') {
+        return Err(CidrError::InvalidFormat);
+    }
+    u8::from_str(text).map_err(|_| CidrError::InvalidFormat)
+}
+
+impl FromStr for DualCidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (v4, v6) = match s.find("//") {
+            Some(idx) => (&s[..idx], Some(&s[idx + 2..])),
+            None => (s, None),
+        };
+
+        let v4 = if v4.is_empty() {
+            None
+        } else if let Some(v4) = v4.strip_prefix('/') {
+            Some(parse_prefix_length(v4)?)
+        } else {
+            return Err(CidrError::InvalidFormat);
+        };
+        let v6 = match v6 {
+            Some(v6) => Some(parse_prefix_length(v6)?),
+            None => None,
+        };
+        Self::new(v4, v6)
+    }
+}
+
+impl fmt::Display for DualCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(v4) = self.v4 {
+            write!(f, "/{}", v4)?;
+        }
+        if let Some(v6) = self.v6 {
+            write!(f, "//{}", v6)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ipv4Net is IPv4 network used by `ip4` mechanism: address and optional prefix length.
+///
+/// Prefix length is always valid(at most 32). When it's not given whole address is matched,
+/// just like with prefix length of 32.
+/// Address is kept as given, so it may have bits set after prefix. They are ignored during matching.
+///
+/// Nets are ordered by address then by prefix length.
+///
+/// # Serde
+/// By default net is serialized as string in slash notation, like `192.0.2.0/24`.
+/// Fields serialized with `serde_cidr::structured` module are serialized as structure with `addr` and `prefix` fields.
+/// Both forms are accepted during deserialization and prefix length is validated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv4Net {
+    addr: Ipv4Addr,
+    prefix: Option<u8>,
+}
+
+/// Ipv6Net is IPv6 network used by `ip6` mechanism: address and optional prefix length.
+///
+/// It works just like `Ipv4Net` but prefix length is at most 128.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv6Net {
+    addr: Ipv6Addr,
+    prefix: Option<u8>,
+}
+
+impl Ipv4Net {
+    /// new creates network from given address and prefix length and checks if length is in valid range.
+    pub fn new(addr: Ipv4Addr, prefix: Option<u8>) -> Result<Self, CidrError> {
+        match prefix {
+            Some(l) if l > MAX_IPV4_PREFIX_LENGTH => Err(CidrError::InvalidIpv4Length(l)),
+            _ => Ok(Self {
+                addr,
+                prefix,
+            })
+        }
+    }
+
+    /// addr returns address exactly as it was given.
+    #[inline]
+    pub fn addr(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    /// prefix returns prefix length if it was given.
+    #[inline]
+    pub fn prefix(&self) -> Option<u8> {
+        self.prefix
+    }
+
+    /// prefix_len returns prefix length used for matching. It defaults to 32.
+    #[inline]
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix.unwrap_or(MAX_IPV4_PREFIX_LENGTH)
+    }
+
+    #[inline]
+    pub(crate) fn mask(&self) -> u32 {
+        match self.prefix_len() {
+            0 => 0,
+            l => u32::MAX << (MAX_IPV4_PREFIX_LENGTH - l),
+        }
+    }
+
+    /// network returns address with all bits after prefix cleared.
+    #[inline]
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) & self.mask())
+    }
+
+    /// contains checks if given address belongs to this network.
+    #[inline]
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        (u32::from(ip) ^ u32::from(self.addr)) & self.mask() == 0
+    }
+
+    /// matches checks if given address of either family belongs to this network.
+    ///
+    /// IPv4-mapped IPv6 addresses(`::ffff:192.0.2.1`) are treated as IPv4 addresses they map,
+    /// other IPv6 addresses never match.
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.contains(ip),
+            IpAddr::V6(ip) => match ipv4_mapped(ip) {
+                Some(ip) => self.contains(ip),
+                None => false,
+            }
+        }
+    }
+}
+
+impl Ipv6Net {
+    /// new creates network from given address and prefix length and checks if length is in valid range.
+    pub fn new(addr: Ipv6Addr, prefix: Option<u8>) -> Result<Self, CidrError> {
+        match prefix {
+            Some(l) if l > MAX_IPV6_PREFIX_LENGTH => Err(CidrError::InvalidIpv6Length(l)),
+            _ => Ok(Self {
+                addr,
+                prefix,
+            })
+        }
+    }
+
+    /// addr returns address exactly as it was given.
+    #[inline]
+    pub fn addr(&self) -> Ipv6Addr {
+        self.addr
+    }
+
+    /// prefix returns prefix length if it was given.
+    #[inline]
+    pub fn prefix(&self) -> Option<u8> {
+        self.prefix
+    }
+
+    /// prefix_len returns prefix length used for matching. It defaults to 128.
+    #[inline]
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix.unwrap_or(MAX_IPV6_PREFIX_LENGTH)
+    }
+
+    #[inline]
+    pub(crate) fn mask(&self) -> u128 {
+        match self.prefix_len() {
+            0 => 0,
+            l => u128::MAX << (MAX_IPV6_PREFIX_LENGTH - l),
+        }
+    }
+
+    /// network returns address with all bits after prefix cleared.
+    #[inline]
+    pub fn network(&self) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.addr) & self.mask())
+    }
+
+    /// contains checks if given address belongs to this network.
+    #[inline]
+    pub fn contains(&self, ip: Ipv6Addr) -> bool {
+        (u128::from(ip) ^ u128::from(self.addr)) & self.mask() == 0
+    }
+
+    /// matches checks if given address of either family belongs to this network.
+    ///
+    /// IPv4 addresses and IPv4-mapped IPv6 addresses never match, since they are treated as IPv4 ones.
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(_) => false,
+            IpAddr::V6(ip) => ipv4_mapped(ip).is_none() && self.contains(ip),
+        }
+    }
+}
+
+/// ipv4_mapped returns IPv4 address mapped by given `::ffff:0:0/96` address.
+pub(crate) fn ipv4_mapped(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, _, _] => {
+            let o = ip.octets();
+            Some(Ipv4Addr::new(o[12], o[13], o[14], o[15]))
+        }
+        _ => None,
+    }
+}
+
+impl From<Ipv4Addr> for Ipv4Net {
+    /// Creates network matching single address without explicit prefix length.
+    fn from(addr: Ipv4Addr) -> Self {
+        Self {
+            addr,
+            prefix: None,
+        }
+    }
+}
+
+impl From<Ipv6Addr> for Ipv6Net {
+    /// Creates network matching single address without explicit prefix length.
+    fn from(addr: Ipv6Addr) -> Self {
+        Self {
+            addr,
+            prefix: None,
+        }
+    }
+}
+
+impl TryFrom<(Ipv4Addr, Option<u8>)> for Ipv4Net {
+    type Error = CidrError;
+
+    #[inline]
+    fn try_from((addr, prefix): (Ipv4Addr, Option<u8>)) -> Result<Self, Self::Error> {
+        Self::new(addr, prefix)
+    }
+}
+
+impl TryFrom<(Ipv6Addr, Option<u8>)> for Ipv6Net {
+    type Error = CidrError;
+
+    #[inline]
+    fn try_from((addr, prefix): (Ipv6Addr, Option<u8>)) -> Result<Self, Self::Error> {
+        Self::new(addr, prefix)
+    }
+}
+
+impl From<Ipv4Net> for (Ipv4Addr, Option<u8>) {
+    #[inline]
+    fn from(net: Ipv4Net) -> Self {
+        (net.addr, net.prefix)
+    }
+}
+
+impl From<Ipv6Net> for (Ipv6Addr, Option<u8>) {
+    #[inline]
+    fn from(net: Ipv6Net) -> Self {
+        (net.addr, net.prefix)
+    }
+}
+
+/// split_net splits `addr/len` text into address and optional prefix length.
+fn split_net(s: &str) -> Result<(&str, Option<u8>), CidrError> {
+    match s.find('/') {
+        Some(idx) => Ok((&s[..idx], Some(parse_prefix_length(&s[idx + 1..])?))),
+        None => Ok((s, None)),
+    }
+}
+
+impl FromStr for Ipv4Net {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = split_net(s)?;
+        let addr = Ipv4Addr::from_str(addr).map_err(|_| CidrError::InvalidAddress)?;
+        Self::new(addr, prefix)
+    }
+}
+
+impl FromStr for Ipv6Net {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = split_net(s)?;
+        let addr = Ipv6Addr::from_str(addr).map_err(|_| CidrError::InvalidAddress)?;
+        Self::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.prefix {
+            Some(l) => write!(f, "{}/{}", self.addr, l),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+impl fmt::Display for Ipv6Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.prefix {
+            Some(l) => write!(f, "{}/{}", self.addr, l),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validated_constructor() {
+        assert!(DualCidr::new(Some(32), Some(128)).is_ok());
+        assert!(DualCidr::new(Some(0), Some(0)).is_ok());
+        assert_eq!(DualCidr::new(Some(33), None), Err(CidrError::InvalidIpv4Length(33)));
+        assert_eq!(DualCidr::new(None, Some(129)), Err(CidrError::InvalidIpv6Length(129)));
+    }
+
+    #[test]
+    fn test_effective_lengths() {
+        let c = DualCidr::default();
+        assert!(c.is_default());
+        assert_eq!((c.effective_v4(), c.effective_v6()), (32, 128));
+
+        let c = DualCidr::new(Some(24), None).unwrap();
+        assert_eq!((c.effective_v4(), c.effective_v6()), (24, 128));
+    }
+
+    #[test]
+    fn test_display_and_parse() {
+        for (text, v4, v6) in [
+            ("", None, None),
+            ("/24", Some(24), None),
+            ("//64", None, Some(64)),
+            ("/24//64", Some(24), Some(64)),
+            ("/0//0", Some(0), Some(0)),
+            ("/32//128", Some(32), Some(128)),
+        ].iter() {
+            let c = DualCidr::from_str(text).unwrap();
+            assert_eq!(c, DualCidr::new(*v4, *v6).unwrap());
+            assert_eq!(&c.to_string(), text);
+        }
+
+        assert_eq!(DualCidr::from_str("/33"), Err(CidrError::InvalidIpv4Length(33)));
+        assert_eq!(DualCidr::from_str("/24//129"), Err(CidrError::InvalidIpv6Length(129)));
+        for text in ["/", "//", "/256", "//1280", "24", "/024", "/24/", "/24//", "/-1", "/2a", "/24//64//64", "///64"].iter() {
+            assert!(DualCidr::from_str(text).is_err(), "{} should not parse", text);
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_serde_validates_lengths() {
+        let c = DualCidr::new(Some(24), Some(64)).unwrap();
+        let json = serde_json::to_string(&c).unwrap();
+        assert_eq!(json, r#"{"v4":24,"v6":64}"#);
+        assert_eq!(serde_json::from_str::<DualCidr>(&json).unwrap(), c);
+
+        assert!(serde_json::from_str::<DualCidr>(r#"{"v4":33,"v6":null}"#).is_err());
+        assert!(serde_json::from_str::<DualCidr>(r#"{"v4":null,"v6":129}"#).is_err());
+    }
+
+    #[test]
+    fn test_net_constructors() {
+        assert!(Ipv4Net::new(Ipv4Addr::UNSPECIFIED, Some(32)).is_ok());
+        assert_eq!(Ipv4Net::new(Ipv4Addr::UNSPECIFIED, Some(33)), Err(CidrError::InvalidIpv4Length(33)));
+        assert!(Ipv6Net::new(Ipv6Addr::UNSPECIFIED, Some(128)).is_ok());
+        assert_eq!(Ipv6Net::new(Ipv6Addr::UNSPECIFIED, Some(129)), Err(CidrError::InvalidIpv6Length(129)));
+
+        let net = Ipv4Net::try_from((Ipv4Addr::new(192, 0, 2, 0), Some(24))).unwrap();
+        assert_eq!(<(Ipv4Addr, Option<u8>)>::from(net), (Ipv4Addr::new(192, 0, 2, 0), Some(24)));
+        assert!(Ipv4Net::try_from((Ipv4Addr::new(192, 0, 2, 0), Some(99))).is_err());
+        assert_eq!(Ipv6Net::from(Ipv6Addr::LOCALHOST).prefix_len(), 128);
+    }
+
+    #[test]
+    fn test_ipv4_net_contains() {
+        let net = Ipv4Net::from_str("192.0.2.77/24").unwrap();
+        assert_eq!(net.network(), Ipv4Addr::new(192, 0, 2, 0));
+        assert!(net.contains(Ipv4Addr::new(192, 0, 2, 0)));
+        assert!(net.contains(Ipv4Addr::new(192, 0, 2, 255)));
+        assert!(!net.contains(Ipv4Addr::new(192, 0, 3, 0)));
+
+        // non-octet-aligned prefix: 10.0.0.0/13 spans 10.0.0.0 - 10.7.255.255
+        let net = Ipv4Net::from_str("10.0.0.0/13").unwrap();
+        assert!(net.contains(Ipv4Addr::new(10, 7, 255, 255)));
+        assert!(!net.contains(Ipv4Addr::new(10, 8, 0, 0)));
+
+        let net = Ipv4Net::from_str("192.0.2.1").unwrap();
+        assert!(net.contains(Ipv4Addr::new(192, 0, 2, 1)));
+        assert!(!net.contains(Ipv4Addr::new(192, 0, 2, 2)));
+
+        let net = Ipv4Net::from_str("192.0.2.1/0").unwrap();
+        assert!(net.contains(Ipv4Addr::new(255, 255, 255, 255)));
+        assert!(net.contains(Ipv4Addr::new(0, 0, 0, 0)));
+    }
+
+    #[test]
+    fn test_ipv6_net_contains() {
+        let net = Ipv6Net::from_str("2001:db8::/32").unwrap();
+        assert!(net.contains(Ipv6Addr::from_str("2001:db8:ffff::1").unwrap()));
+        assert!(!net.contains(Ipv6Addr::from_str("2001:db9::").unwrap()));
+
+        // non-octet-aligned prefix: 2001:db8::/57
+        let net = Ipv6Net::from_str("2001:db8:0:80::/57").unwrap();
+        assert_eq!(net.network(), Ipv6Addr::from_str("2001:db8:0:80::").unwrap());
+        assert!(net.contains(Ipv6Addr::from_str("2001:db8:0:ff::1").unwrap()));
+        assert!(!net.contains(Ipv6Addr::from_str("2001:db8:0:7f::1").unwrap()));
+
+        let net = Ipv6Net::from_str("::1/0").unwrap();
+        assert!(net.contains(Ipv6Addr::from_str("ffff::").unwrap()));
+    }
+
+    #[test]
+    fn test_net_matches_mapped_addresses() {
+        let v4 = Ipv4Net::from_str("192.0.2.0/24").unwrap();
+        let v6 = Ipv6Net::from_str("::/0").unwrap();
+        let mapped = IpAddr::from_str("::ffff:192.0.2.10").unwrap();
+
+        assert!(v4.matches(mapped));
+        assert!(v4.matches(IpAddr::from_str("192.0.2.10").unwrap()));
+        assert!(!v4.matches(IpAddr::from_str("2001:db8::1").unwrap()));
+
+        assert!(!v6.matches(mapped));
+        assert!(!v6.matches(IpAddr::from_str("192.0.2.10").unwrap()));
+        assert!(v6.matches(IpAddr::from_str("2001:db8::1").unwrap()));
+    }
+
+    #[test]
+    fn test_net_display_and_parse() {
+        for text in ["192.0.2.0/24", "192.0.2.1", "0.0.0.0/0"].iter() {
+            assert_eq!(&Ipv4Net::from_str(text).unwrap().to_string(), text);
+        }
+        for text in ["2001:db8::/32", "::1", "::/0"].iter() {
+            assert_eq!(&Ipv6Net::from_str(text).unwrap().to_string(), text);
+        }
+        assert_eq!(Ipv4Net::from_str("192.0.2.0/33"), Err(CidrError::InvalidIpv4Length(33)));
+        assert_eq!(Ipv6Net::from_str("::/129"), Err(CidrError::InvalidIpv6Length(129)));
+        assert_eq!(Ipv4Net::from_str("192.0.2/24"), Err(CidrError::InvalidAddress));
+        assert_eq!(Ipv4Net::from_str("192.0.2.0/"), Err(CidrError::InvalidFormat));
+        assert_eq!(Ipv6Net::from_str("192.0.2.0/24"), Err(CidrError::InvalidAddress));
+    }
+
+    #[test]
+    fn test_net_ordering() {
+        let mut nets = [
+            Ipv4Net::from_str("192.0.2.0/24").unwrap(),
+            Ipv4Net::from_str("10.0.0.0/8").unwrap(),
+            Ipv4Net::from_str("192.0.2.0/25").unwrap(),
+            Ipv4Net::from_str("192.0.2.0").unwrap(),
+        ];
+        nets.sort();
+        assert_eq!(nets.iter().map(|n| n.to_string()).collect::<Vec<_>>(), vec![
+            "10.0.0.0/8", "192.0.2.0", "192.0.2.0/24", "192.0.2.0/25",
+        ]);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_serde_validates_net_prefix() {
+        let net = Ipv4Net::from_str("192.0.2.0/24").unwrap();
+        let json = serde_json::to_string(&net).unwrap();
+        assert_eq!(serde_json::from_str::<Ipv4Net>(&json).unwrap(), net);
+        assert!(serde_json::from_str::<Ipv4Net>(r#"{"addr":"192.0.2.0","prefix":33}"#).is_err());
+        assert!(serde_json::from_str::<Ipv6Net>(r#"{"addr":"::","prefix":129}"#).is_err());
+    }
+}
@@ -0,0 +1,347 @@
+//! Module with `CompiledSpf` - matcher of records, which can be evaluated without any DNS queries.
+//!
+//! It's meant for large, flattened records consisting of hundreds of `ip4` and `ip6` terms,
+//! where linear scan over directives for each connection is too slow.
+
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::spf::cidr::ipv4_mapped;
+use crate::spf::{SpfAction, SpfDirectiveKind, SpfMechanism, SpfRecord};
+
+/// CompileError is returned when record can't be compiled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CompileError {
+    /// DynamicDirective is returned when directive at given index requires DNS query or macro expansion
+    /// in order to be evaluated, like `include`, `a` or `redirect`.
+    DynamicDirective {
+        index: usize,
+        kind: SpfDirectiveKind,
+    },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::DynamicDirective { index, kind } => {
+                write!(f, "directive {} of kind {:?} can't be evaluated statically", index, kind)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Addr is integer form of IP address of single family.
+trait Addr: Copy + Ord {
+    const MIN: Self;
+
+    /// succ returns next address or `None` if it's last one.
+    fn succ(self) -> Option<Self>;
+}
+
+impl Addr for u32 {
+    const MIN: Self = 0;
+
+    #[inline]
+    fn succ(self) -> Option<Self> {
+        self.checked_add(1)
+    }
+}
+
+impl Addr for u128 {
+    const MIN: Self = 0;
+
+    #[inline]
+    fn succ(self) -> Option<Self> {
+        self.checked_add(1)
+    }
+}
+
+/// RangeTable maps disjoint, sorted ranges of addresses to qualifier of first directive which matches them.
+///
+/// Range `i` starts at `starts[i]` and ends just before `starts[i + 1]`. First range always starts at `A::MIN`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RangeTable<A> {
+    starts: Vec<A>,
+    actions: Vec<Option<SpfAction>>,
+}
+
+impl<A: Addr> RangeTable<A> {
+    /// build creates table from inclusive ranges given in order of their priority.
+    /// Each address gets qualifier of first range containing it.
+    fn build(ranges: &[(A, A, SpfAction)]) -> Self {
+        // split address space into elementary intervals, so that each range is union of consecutive ones
+        let mut bounds = Vec::with_capacity(ranges.len() * 2 + 1);
+        bounds.push(A::MIN);
+        for (start, end, _) in ranges.iter() {
+            bounds.push(*start);
+            bounds.extend(end.succ());
+        }
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        // next[i] points to some interval at or after i, which may be unassigned.
+        // Assigned intervals are skipped, so each one is visited once.
+        let mut next = (0..=bounds.len()).collect::<Vec<_>>();
+        let mut assigned = vec![None; bounds.len()];
+        for (start, end, action) in ranges.iter() {
+            let lo = bounds.binary_search(start).expect("start is a bound");
+            let hi = match end.succ() {
+                Some(e) => bounds.binary_search(&e).expect("end is a bound"),
+                None => bounds.len(),
+            };
+            let mut i = find_unassigned(&mut next, lo);
+            while i < hi {
+                assigned[i] = Some(*action);
+                next[i] = i + 1;
+                i = find_unassigned(&mut next, i + 1);
+            }
+        }
+
+        let mut table = Self {
+            starts: Vec::new(),
+            actions: Vec::new(),
+        };
+        for (start, action) in bounds.into_iter().zip(assigned) {
+            if table.actions.last() != Some(&action) {
+                table.starts.push(start);
+                table.actions.push(action);
+            }
+        }
+        table
+    }
+
+    #[inline]
+    fn lookup(&self, addr: A) -> Option<SpfAction> {
+        let idx = self.starts.partition_point(|s| *s <= addr);
+        self.actions[idx - 1]
+    }
+}
+
+fn find_unassigned(next: &mut [usize], mut i: usize) -> usize {
+    while next[i] != i {
+        next[i] = next[next[i]];
+        i = next[i];
+    }
+    i
+}
+
+/// CompiledSpf is record compiled into lookup tables, which checks address in `O(log n)` time.
+///
+/// Only records with fully static behavior can be compiled: ones consisting of `ip4`, `ip6` and `all`
+/// mechanisms with any qualifiers. `exp` and unknown modifiers don't affect matching, so they are ignored.
+///
+/// # Ordering
+/// Just like during normal evaluation, first matching directive wins. When networks overlap and have different
+/// qualifiers, address space is split into ranges and each range gets qualifier of first directive covering it.
+/// For instance in `-ip4:192.0.2.1 ip4:192.0.2.0/24` address `192.0.2.1` fails and rest of network passes.
+/// Directives after `all` are never reached, so they are dropped.
+///
+/// # Example
+/// ```
+/// use std::net::{IpAddr, Ipv4Addr};
+/// use spf::{CompiledSpf, Ipv4Net, SpfAction, SpfDirective, SpfMechanism, SpfRecord};
+///
+/// let record: SpfRecord = vec![
+///     SpfDirective::new(SpfAction::Fail, SpfMechanism::ip4(Ipv4Net::from(Ipv4Addr::new(192, 0, 2, 1)))).unwrap(),
+///     SpfDirective::from_mechanism(SpfMechanism::ip4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap())),
+///     SpfDirective::new(SpfAction::SoftFail, SpfMechanism::all()).unwrap(),
+/// ].into_iter().collect();
+///
+/// let compiled = CompiledSpf::compile(&record).unwrap();
+/// assert_eq!(compiled.check(IpAddr::from([192, 0, 2, 1])), SpfAction::Fail);
+/// assert_eq!(compiled.check(IpAddr::from([192, 0, 2, 2])), SpfAction::Pass);
+/// assert_eq!(compiled.check(IpAddr::from([198, 51, 100, 1])), SpfAction::SoftFail);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledSpf {
+    v4: RangeTable<u32>,
+    v6: RangeTable<u128>,
+    default: SpfAction,
+}
+
+impl CompiledSpf {
+    /// compile creates matcher from given record.
+    /// It fails when record contains directives which can't be evaluated without DNS queries.
+    pub fn compile(record: &SpfRecord) -> Result<Self, CompileError> {
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        // RFC 7208 section 4.7: when nothing matches and there is no redirect, result is neutral
+        let mut default = SpfAction::Neutral;
+        for (index, d) in record.directives.iter().enumerate() {
+            match &d.mechanism {
+                SpfMechanism::Ipv4(net) => {
+                    let start = u32::from(net.network());
+                    v4.push((start, start | !net.mask(), d.qualifier));
+                }
+                SpfMechanism::Ipv6(net) => {
+                    let start = u128::from(net.network());
+                    v6.push((start, start | !net.mask(), d.qualifier));
+                }
+                SpfMechanism::All => {
+                    default = d.qualifier;
+                    break;
+                }
+                SpfMechanism::Exp(_) | SpfMechanism::UnknownModifier(_) => {}
+                m => return Err(CompileError::DynamicDirective {
+                    index,
+                    kind: m.kind(),
+                }),
+            }
+        }
+        Ok(Self {
+            v4: RangeTable::build(&v4),
+            v6: RangeTable::build(&v6),
+            default,
+        })
+    }
+
+    /// check returns qualifier of first directive which matches given address.
+    /// When no directive matches, it returns `Neutral`.
+    ///
+    /// IPv4-mapped IPv6 addresses(`::ffff:192.0.2.1`) are treated as IPv4 addresses they map.
+    pub fn check(&self, ip: IpAddr) -> SpfAction {
+        let action = match ip {
+            IpAddr::V4(ip) => self.v4.lookup(u32::from(ip)),
+            IpAddr::V6(ip) => match ipv4_mapped(ip) {
+                Some(ip) => self.v4.lookup(u32::from(ip)),
+                None => self.v6.lookup(u128::from(ip)),
+            },
+        };
+        action.unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::spf::{DomainSpec, Ipv4Net, Ipv6Net, SpfDirective, UnknownModifier};
+
+    use super::*;
+
+    /// naive_check evaluates static record by scanning directives in order.
+    fn naive_check(record: &SpfRecord, ip: IpAddr) -> SpfAction {
+        for d in record.directives.iter() {
+            let matched = match &d.mechanism {
+                SpfMechanism::Ipv4(net) => net.matches(ip),
+                SpfMechanism::Ipv6(net) => net.matches(ip),
+                SpfMechanism::All => true,
+                _ => false,
+            };
+            if matched {
+                return d.qualifier;
+            }
+        }
+        SpfAction::Neutral
+    }
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            self.0 >> 11
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn action(&mut self) -> SpfAction {
+            [SpfAction::Pass, SpfAction::Fail, SpfAction::SoftFail, SpfAction::Neutral][self.below(4) as usize]
+        }
+
+        /// ipv4 returns address from small space, so generated networks overlap often.
+        fn ipv4(&mut self) -> Ipv4Addr {
+            Ipv4Addr::new(192, 0, self.below(4) as u8, self.below(256) as u8)
+        }
+
+        fn ipv6(&mut self) -> Ipv6Addr {
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, self.below(4) as u16, self.below(256) as u16)
+        }
+
+        fn ip(&mut self) -> IpAddr {
+            match self.below(6) {
+                0 => IpAddr::V6(self.ipv4().to_ipv6_mapped()),
+                1 => IpAddr::V4(Ipv4Addr::from(self.next() as u32)),
+                2 | 3 => IpAddr::V6(self.ipv6()),
+                _ => IpAddr::V4(self.ipv4()),
+            }
+        }
+
+        fn record(&mut self) -> SpfRecord<'static> {
+            let len = self.below(40);
+            let mut directives = (0..len)
+                .map(|_| {
+                    let mechanism = match self.below(10) {
+                        0 => SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::from(self.next() as u32), Some(self.below(33) as u8)).unwrap()),
+                        1..=5 => SpfMechanism::Ipv4(Ipv4Net::new(self.ipv4(), Some(16 + self.below(17) as u8)).unwrap()),
+                        6 => SpfMechanism::Ipv4(Ipv4Net::from(self.ipv4())),
+                        7 => SpfMechanism::Ipv6(Ipv6Net::new(self.ipv6(), Some(self.below(129) as u8)).unwrap()),
+                        8 => SpfMechanism::Ipv6(Ipv6Net::new(self.ipv6(), Some(112 + self.below(17) as u8)).unwrap()),
+                        _ => SpfMechanism::Ipv6(Ipv6Net::new(Ipv6Addr::UNSPECIFIED, Some(self.below(97) as u8)).unwrap()),
+                    };
+                    SpfDirective::new(self.action(), mechanism).unwrap()
+                })
+                .collect::<Vec<_>>();
+            if self.below(2) == 0 {
+                let at = self.below(directives.len() as u64 + 1) as usize;
+                directives.insert(at, SpfDirective::new(self.action(), SpfMechanism::All).unwrap());
+            }
+            SpfRecord::from(directives)
+        }
+    }
+
+    #[test]
+    fn test_compiled_matches_naive_evaluation() {
+        let mut rng = Rng(7);
+        for _ in 0..500 {
+            let record = rng.record();
+            let compiled = CompiledSpf::compile(&record).unwrap();
+            for _ in 0..200 {
+                let ip = rng.ip();
+                assert_eq!(compiled.check(ip), naive_check(&record, ip), "{} in {:?}", ip, record);
+            }
+        }
+    }
+
+    #[test]
+    fn test_edge_networks() {
+        let record: SpfRecord = vec![
+            SpfDirective::new(SpfAction::Fail, SpfMechanism::ip4(Ipv4Net::from(Ipv4Addr::BROADCAST))).unwrap(),
+            SpfDirective::new(SpfAction::SoftFail, SpfMechanism::ip6(Ipv6Net::new(Ipv6Addr::UNSPECIFIED, Some(0)).unwrap())).unwrap(),
+            SpfDirective::from_mechanism(SpfMechanism::ip4(Ipv4Net::new(Ipv4Addr::UNSPECIFIED, Some(0)).unwrap())),
+        ].into_iter().collect();
+        let compiled = CompiledSpf::compile(&record).unwrap();
+        for ip in [IpAddr::from(Ipv4Addr::BROADCAST), IpAddr::from(Ipv4Addr::UNSPECIFIED), IpAddr::from(Ipv6Addr::LOCALHOST),
+            IpAddr::from([0xffffu16; 8]), IpAddr::from(Ipv4Addr::BROADCAST.to_ipv6_mapped())].iter() {
+            assert_eq!(compiled.check(*ip), naive_check(&record, *ip), "{}", ip);
+        }
+        assert_eq!(compiled.check(IpAddr::from(Ipv4Addr::BROADCAST)), SpfAction::Fail);
+        assert_eq!(compiled.check(IpAddr::from(Ipv6Addr::LOCALHOST)), SpfAction::SoftFail);
+        assert_eq!(CompiledSpf::compile(&SpfRecord::new()).unwrap().check(IpAddr::from(Ipv6Addr::LOCALHOST)), SpfAction::Neutral);
+    }
+
+    #[test]
+    fn test_dynamic_records_are_rejected() {
+        let record: SpfRecord = vec![
+            SpfDirective::from_mechanism(SpfMechanism::Exp(DomainSpec::new("exp.example.com").unwrap())),
+            SpfDirective::from_mechanism(SpfMechanism::from(UnknownModifier::new("foo", "bar"))),
+            SpfDirective::from_mechanism(SpfMechanism::include("_spf.example.com").unwrap()),
+        ].into_iter().collect();
+        assert_eq!(CompiledSpf::compile(&record), Err(CompileError::DynamicDirective {
+            index: 2,
+            kind: SpfDirectiveKind::Include,
+        }));
+
+        // nothing after all is ever evaluated
+        let record: SpfRecord = vec![
+            SpfDirective::new(SpfAction::Fail, SpfMechanism::all()).unwrap(),
+            SpfDirective::from_mechanism(SpfMechanism::redirect("example.com").unwrap()),
+        ].into_iter().collect();
+        assert_eq!(CompiledSpf::compile(&record).unwrap().check(IpAddr::from(Ipv4Addr::LOCALHOST)), SpfAction::Fail);
+    }
+}
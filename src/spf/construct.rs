@@ -0,0 +1,559 @@
+//! Module with constructors of `SpfRecord`, `SpfDirective` and `SpfMechanism`.
+//!
+//! These are preferred way of creating values, since they check everything that can be checked
+//! without DNS: domain-spec syntax, CIDR ranges and qualifiers of modifiers.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::spf::{
+    CidrError, DomainSpec, DomainSpecError, DualCidr, Ipv4Net, Ipv6Net, SpfAction, SpfDirective, SpfDirectiveKind,
+    SpfMechanism, SpfRecord,
+};
+
+/// SpfBuildError is returned when constructed value would not be valid.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SpfBuildError {
+    /// InvalidDomain is returned when domain-spec does not match `domain-spec` grammar.
+    InvalidDomain(DomainSpecError),
+
+    /// InvalidCidr is returned when CIDR prefix length is out of range.
+    InvalidCidr(CidrError),
+
+    /// QualifiedModifier is returned when qualifier other than `Pass` is given to modifier, like `redirect`.
+    /// Modifiers can't have qualifiers.
+    QualifiedModifier,
+
+    /// DuplicateModifier is returned when `redirect` or `exp` is added to record, which already has one.
+    DuplicateModifier {
+        kind: SpfDirectiveKind,
+    },
+}
+
+impl From<DomainSpecError> for SpfBuildError {
+    #[inline]
+    fn from(e: DomainSpecError) -> Self {
+        SpfBuildError::InvalidDomain(e)
+    }
+}
+
+impl From<CidrError> for SpfBuildError {
+    #[inline]
+    fn from(e: CidrError) -> Self {
+        SpfBuildError::InvalidCidr(e)
+    }
+}
+
+impl fmt::Display for SpfBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfBuildError::InvalidDomain(e) => write!(f, "invalid domain: {}", e),
+            SpfBuildError::InvalidCidr(e) => write!(f, "invalid CIDR: {}", e),
+            SpfBuildError::QualifiedModifier => write!(f, "modifier can't have qualifier"),
+            SpfBuildError::DuplicateModifier { kind } => write!(f, "modifier {:?} is already set", kind),
+        }
+    }
+}
+
+impl std::error::Error for SpfBuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpfBuildError::InvalidDomain(e) => Some(e),
+            SpfBuildError::InvalidCidr(e) => Some(e),
+            SpfBuildError::QualifiedModifier | SpfBuildError::DuplicateModifier { .. } => None,
+        }
+    }
+}
+
+/// domain_spec creates domain-spec and checks it against whole `domain-spec` grammar.
+fn domain_spec<'a, T>(domain: T) -> Result<DomainSpec<'a>, DomainSpecError>
+    where T: Into<Cow<'a, str>>
+{
+    let domain = DomainSpec::new(domain)?;
+    domain.validate()?;
+    Ok(domain)
+}
+
+impl<'a> SpfRecord<'a> {
+    /// new creates record without any directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// empty creates record without any directives. It's same as `new`.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> SpfDirective<'a> {
+    /// new creates directive with given qualifier. Qualifier other than `Pass` is marked as explicit.
+    ///
+    /// It fails when mechanism is modifier and qualifier is not `Pass`.
+    ///
+    /// # Example
+    /// ```
+    /// use spf::{SpfAction, SpfBuildError, SpfDirective, SpfMechanism};
+    ///
+    /// let d = SpfDirective::new(SpfAction::Fail, SpfMechanism::all()).unwrap();
+    /// assert!(d.explicit_qualifier);
+    ///
+    /// let redirect = SpfMechanism::redirect("_spf.example.com").unwrap();
+    /// assert!(matches!(SpfDirective::new(SpfAction::Fail, redirect), Err(SpfBuildError::QualifiedModifier)));
+    /// ```
+    pub fn new(qualifier: SpfAction, mechanism: SpfMechanism<'a>) -> Result<Self, SpfBuildError> {
+        if mechanism.is_modifier() && qualifier != SpfAction::Pass {
+            return Err(SpfBuildError::QualifiedModifier);
+        }
+        Ok(Self {
+            qualifier,
+            explicit_qualifier: qualifier != SpfAction::Pass,
+            mechanism,
+        })
+    }
+
+    /// from_mechanism creates directive with implicit `Pass` qualifier, like `mx` or `redirect=example.com`.
+    pub fn from_mechanism(mechanism: SpfMechanism<'a>) -> Self {
+        Self {
+            qualifier: SpfAction::Pass,
+            explicit_qualifier: false,
+            mechanism,
+        }
+    }
+}
+
+impl<'a> From<SpfMechanism<'a>> for SpfDirective<'a> {
+    #[inline]
+    fn from(mechanism: SpfMechanism<'a>) -> Self {
+        Self::from_mechanism(mechanism)
+    }
+}
+
+impl<'a> SpfMechanism<'a> {
+    /// a returns builder of `a` mechanism.
+    ///
+    /// # Example
+    /// ```
+    /// use spf::{DualCidr, SpfMechanism};
+    ///
+    /// let m = SpfMechanism::a().domain("example.com").ip4_prefix(24).build().unwrap();
+    /// assert_eq!(m.as_a().map(|(d, cidr)| (d.unwrap().as_str(), cidr)), Some(("example.com", DualCidr::new(Some(24), None).unwrap())));
+    ///
+    /// assert!(SpfMechanism::a().ip4_prefix(33).build().is_err());
+    /// assert!(SpfMechanism::a().domain("example.123").build().is_err());
+    /// ```
+    pub fn a() -> HostMechanismBuilder<'a> {
+        HostMechanismBuilder::new(HostMechanismKind::A)
+    }
+
+    /// aaaa returns builder of `aaaa` mechanism. Take a look at `a` for example.
+    pub fn aaaa() -> HostMechanismBuilder<'a> {
+        HostMechanismBuilder::new(HostMechanismKind::Aaaa)
+    }
+
+    /// mx returns builder of `mx` mechanism. Take a look at `a` for example.
+    pub fn mx() -> HostMechanismBuilder<'a> {
+        HostMechanismBuilder::new(HostMechanismKind::Mx)
+    }
+
+    /// ip4 creates `ip4` mechanism. Network is always valid, so it can't fail.
+    pub fn ip4(net: Ipv4Net) -> Self {
+        SpfMechanism::Ipv4(net)
+    }
+
+    /// ip6 creates `ip6` mechanism. Network is always valid, so it can't fail.
+    pub fn ip6(net: Ipv6Net) -> Self {
+        SpfMechanism::Ipv6(net)
+    }
+
+    /// ip4_net creates `ip4` mechanism matching network of given address and prefix length.
+    /// It fails if prefix length is greater than 32.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::Ipv4Addr;
+    /// use spf::SpfMechanism;
+    ///
+    /// let m = SpfMechanism::ip4_net(Ipv4Addr::new(192, 0, 2, 0), 24).unwrap();
+    /// assert_eq!(m.to_string(), "ip4:192.0.2.0/24");
+    /// assert!(SpfMechanism::ip4_net(Ipv4Addr::new(192, 0, 2, 0), 33).is_err());
+    /// ```
+    pub fn ip4_net(addr: Ipv4Addr, prefix: u8) -> Result<Self, CidrError> {
+        Ok(SpfMechanism::Ipv4(Ipv4Net::new(addr, Some(prefix))?))
+    }
+
+    /// ip6_net creates `ip6` mechanism matching network of given address and prefix length.
+    /// It fails if prefix length is greater than 128.
+    pub fn ip6_net(addr: Ipv6Addr, prefix: u8) -> Result<Self, CidrError> {
+        Ok(SpfMechanism::Ipv6(Ipv6Net::new(addr, Some(prefix))?))
+    }
+
+    /// include creates `include` mechanism. It fails if domain is not valid domain-spec.
+    ///
+    /// # Example
+    /// ```
+    /// use spf::SpfMechanism;
+    ///
+    /// let m = SpfMechanism::include("_spf.example.com").unwrap();
+    /// assert_eq!(m.as_include().map(|d| d.as_str()), Some("_spf.example.com"));
+    ///
+    /// assert!(SpfMechanism::include("").is_err());
+    /// assert!(SpfMechanism::include("%{z}.example.com").is_err());
+    /// ```
+    pub fn include<T>(domain: T) -> Result<Self, DomainSpecError>
+        where T: Into<Cow<'a, str>>
+    {
+        Ok(SpfMechanism::Include(domain_spec(domain)?))
+    }
+
+    /// exists creates `exists` mechanism. It fails if domain is not valid domain-spec.
+    pub fn exists<T>(domain: T) -> Result<Self, DomainSpecError>
+        where T: Into<Cow<'a, str>>
+    {
+        Ok(SpfMechanism::Exists(domain_spec(domain)?))
+    }
+
+    /// redirect creates `redirect` modifier. It fails if domain is not valid domain-spec.
+    pub fn redirect<T>(domain: T) -> Result<Self, DomainSpecError>
+        where T: Into<Cow<'a, str>>
+    {
+        Ok(SpfMechanism::Redirect(domain_spec(domain)?))
+    }
+
+    /// exp creates `exp` modifier. It fails if domain is not valid domain-spec.
+    pub fn exp<T>(domain: T) -> Result<Self, DomainSpecError>
+        where T: Into<Cow<'a, str>>
+    {
+        Ok(SpfMechanism::Exp(domain_spec(domain)?))
+    }
+
+    /// ptr creates `ptr` mechanism. Without domain current domain is checked.
+    /// It fails if domain is not valid domain-spec.
+    pub fn ptr<T>(domain: Option<T>) -> Result<Self, DomainSpecError>
+        where T: Into<Cow<'a, str>>
+    {
+        Ok(SpfMechanism::Ptr(domain.map(domain_spec).transpose()?))
+    }
+
+    /// all creates `all` mechanism.
+    pub fn all() -> Self {
+        SpfMechanism::All
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum HostMechanismKind {
+    A,
+    Aaaa,
+    Mx,
+}
+
+/// HostMechanismBuilder builds `a`, `aaaa` and `mx` mechanisms, which take optional domain and CIDR lengths.
+///
+/// Values are checked once `build` is called.
+#[derive(Debug, Clone)]
+pub struct HostMechanismBuilder<'a> {
+    kind: HostMechanismKind,
+    domain: Option<Cow<'a, str>>,
+    v4: Option<u8>,
+    v6: Option<u8>,
+}
+
+impl<'a> HostMechanismBuilder<'a> {
+    fn new(kind: HostMechanismKind) -> Self {
+        Self {
+            kind,
+            domain: None,
+            v4: None,
+            v6: None,
+        }
+    }
+
+    /// domain sets domain-spec of mechanism. When it's not set, current domain is used during evaluation.
+    pub fn domain<T>(mut self, domain: T) -> Self
+        where T: Into<Cow<'a, str>>
+    {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// cidr sets both CIDR lengths of mechanism.
+    pub fn cidr(mut self, cidr: DualCidr) -> Self {
+        self.v4 = cidr.v4();
+        self.v6 = cidr.v6();
+        self
+    }
+
+    /// ip4_prefix sets IPv4 CIDR length of mechanism.
+    pub fn ip4_prefix(mut self, prefix: u8) -> Self {
+        self.v4 = Some(prefix);
+        self
+    }
+
+    /// ip6_prefix sets IPv6 CIDR length of mechanism.
+    pub fn ip6_prefix(mut self, prefix: u8) -> Self {
+        self.v6 = Some(prefix);
+        self
+    }
+
+    /// build creates mechanism. It fails if domain is not valid domain-spec or if CIDR lengths are out of range.
+    pub fn build(self) -> Result<SpfMechanism<'a>, SpfBuildError> {
+        let domain = match self.domain {
+            Some(d) => Some(domain_spec(d)?),
+            None => None,
+        };
+        let cidr = DualCidr::new(self.v4, self.v6)?;
+        Ok(match self.kind {
+            HostMechanismKind::A => SpfMechanism::A(domain, cidr),
+            HostMechanismKind::Aaaa => SpfMechanism::AAAA(domain, cidr),
+            HostMechanismKind::Mx => SpfMechanism::MX(domain, cidr),
+        })
+    }
+}
+
+/// SpfRecordBuilder builds record directive by directive. Each directive is checked when it's added,
+/// so mistakes are reported by call which made them.
+///
+/// Built record owns its data, so it may be formatted with `Display` or stored for later.
+///
+/// # Example
+/// ```
+/// use spf::{SpfAction, SpfRecordBuilder};
+///
+/// let record = SpfRecordBuilder::new()
+///     .ip4("192.0.2.0/24")?
+///     .include("_spf.example.com")?
+///     .mx_with_prefix(None, 24)?
+///     .a(Some("mail.example.org"))?
+///     .all(SpfAction::Fail)
+///     .build();
+/// assert_eq!(record.to_string(), "v=spf1 ip4:192.0.2.0/24 include:_spf.example.com mx/24 a:mail.example.org -all");
+/// # Ok::<(), spf::SpfBuildError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SpfRecordBuilder {
+    directives: Vec<SpfDirective<'static>>,
+}
+
+impl SpfRecordBuilder {
+    /// new creates builder of record without any directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// directive adds mechanism or modifier with given qualifier. Qualifier other than `Pass` is marked as explicit.
+    ///
+    /// It fails when modifier is given qualifier other than `Pass` or when `redirect` or `exp` is already set.
+    pub fn directive(mut self, qualifier: SpfAction, mechanism: SpfMechanism<'static>) -> Result<Self, SpfBuildError> {
+        let kind = mechanism.kind();
+        if (kind == SpfDirectiveKind::Redirect || kind == SpfDirectiveKind::Exp) && self.directives.iter().any(|d| d.mechanism.kind() == kind) {
+            return Err(SpfBuildError::DuplicateModifier { kind });
+        }
+        self.directives.push(SpfDirective::new(qualifier, mechanism)?);
+        Ok(self)
+    }
+
+    #[inline]
+    fn pass(self, mechanism: SpfMechanism<'static>) -> Result<Self, SpfBuildError> {
+        self.directive(SpfAction::Pass, mechanism)
+    }
+
+    /// ip4 adds `ip4` mechanism with network given as text, like `192.0.2.0/24`.
+    pub fn ip4(self, net: &str) -> Result<Self, SpfBuildError> {
+        self.pass(SpfMechanism::Ipv4(Ipv4Net::from_str(net)?))
+    }
+
+    /// ip6 adds `ip6` mechanism with network given as text, like `2001:db8::/32`.
+    pub fn ip6(self, net: &str) -> Result<Self, SpfBuildError> {
+        self.pass(SpfMechanism::Ipv6(Ipv6Net::from_str(net)?))
+    }
+
+    /// a adds `a` mechanism. Without domain current domain is used.
+    pub fn a(self, domain: Option<&str>) -> Result<Self, SpfBuildError> {
+        self.host(SpfMechanism::a(), domain, None)
+    }
+
+    /// a_with_prefix adds `a` mechanism with IPv4 CIDR length.
+    pub fn a_with_prefix(self, domain: Option<&str>, prefix: u8) -> Result<Self, SpfBuildError> {
+        self.host(SpfMechanism::a(), domain, Some(prefix))
+    }
+
+    /// mx adds `mx` mechanism. Without domain current domain is used.
+    pub fn mx(self, domain: Option<&str>) -> Result<Self, SpfBuildError> {
+        self.host(SpfMechanism::mx(), domain, None)
+    }
+
+    /// mx_with_prefix adds `mx` mechanism with IPv4 CIDR length.
+    pub fn mx_with_prefix(self, domain: Option<&str>, prefix: u8) -> Result<Self, SpfBuildError> {
+        self.host(SpfMechanism::mx(), domain, Some(prefix))
+    }
+
+    fn host(self, mut builder: HostMechanismBuilder<'static>, domain: Option<&str>, prefix: Option<u8>) -> Result<Self, SpfBuildError> {
+        if let Some(domain) = domain {
+            builder = builder.domain(domain.to_string());
+        }
+        if let Some(prefix) = prefix {
+            builder = builder.ip4_prefix(prefix);
+        }
+        self.pass(builder.build()?)
+    }
+
+    /// include adds `include` mechanism.
+    pub fn include(self, domain: &str) -> Result<Self, SpfBuildError> {
+        self.pass(SpfMechanism::include(domain.to_string())?)
+    }
+
+    /// exists adds `exists` mechanism.
+    pub fn exists(self, domain: &str) -> Result<Self, SpfBuildError> {
+        self.pass(SpfMechanism::exists(domain.to_string())?)
+    }
+
+    /// ptr adds `ptr` mechanism. Without domain current domain is used.
+    pub fn ptr(self, domain: Option<&str>) -> Result<Self, SpfBuildError> {
+        self.pass(SpfMechanism::ptr(domain.map(str::to_string))?)
+    }
+
+    /// all adds `all` mechanism with given qualifier.
+    pub fn all(mut self, qualifier: SpfAction) -> Self {
+        self.directives.push(SpfDirective::new(qualifier, SpfMechanism::All).expect("all is not modifier"));
+        self
+    }
+
+    /// redirect sets `redirect` modifier. It fails if record already has one.
+    pub fn redirect(self, domain: &str) -> Result<Self, SpfBuildError> {
+        self.pass(SpfMechanism::redirect(domain.to_string())?)
+    }
+
+    /// exp sets `exp` modifier. It fails if record already has one.
+    pub fn exp(self, domain: &str) -> Result<Self, SpfBuildError> {
+        self.pass(SpfMechanism::exp(domain.to_string())?)
+    }
+
+    /// build creates record from added directives.
+    pub fn build(self) -> SpfRecord<'static> {
+        self.directives.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_constructors() {
+        assert!(SpfRecord::new().directives.is_empty());
+        assert_eq!(SpfRecord::empty(), SpfRecord::default());
+
+        let net = Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap();
+        let record: SpfRecord = vec![
+            SpfDirective::from_mechanism(SpfMechanism::mx().build().unwrap()),
+            SpfDirective::new(SpfAction::SoftFail, SpfMechanism::ip4(net)).unwrap(),
+            SpfDirective::new(SpfAction::Fail, SpfMechanism::all()).unwrap(),
+            SpfMechanism::redirect("_spf.example.com").unwrap().into(),
+        ].into_iter().collect();
+
+        assert_eq!(record.directives[0].mechanism, SpfMechanism::MX(None, DualCidr::default()));
+        assert!(!record.directives[0].explicit_qualifier);
+        assert!(record.directives[1].explicit_qualifier);
+        assert_eq!(record.directives[3].qualifier, SpfAction::Pass);
+
+        let d = SpfDirective::new(SpfAction::Pass, SpfMechanism::all()).unwrap();
+        assert!(!d.explicit_qualifier);
+    }
+
+    #[test]
+    fn test_directive_validation() {
+        for modifier in [SpfMechanism::redirect("example.com").unwrap(), SpfMechanism::exp("exp.example.com").unwrap()].iter() {
+            assert!(SpfDirective::new(SpfAction::Pass, modifier.clone()).is_ok());
+            assert!(matches!(SpfDirective::new(SpfAction::Neutral, modifier.clone()), Err(SpfBuildError::QualifiedModifier)));
+        }
+    }
+
+    #[test]
+    fn test_domain_validation() {
+        type Constructor = fn(&'static str) -> Result<SpfMechanism<'static>, DomainSpecError>;
+
+        let constructors: &[Constructor] = &[
+            SpfMechanism::include,
+            SpfMechanism::exists,
+            SpfMechanism::redirect,
+            SpfMechanism::exp,
+        ];
+        for c in constructors {
+            assert!(c("%{i}.example.com").is_ok());
+            assert!(matches!(c(""), Err(DomainSpecError::Empty)));
+            assert!(matches!(c("exa mple.com"), Err(DomainSpecError::InvalidCharFound)));
+            assert!(matches!(c("%{q}.example.com"), Err(DomainSpecError::InvalidMacro(_))));
+            assert!(matches!(c("example.123"), Err(DomainSpecError::InvalidDomainEnd)));
+        }
+    }
+
+    #[test]
+    fn test_host_mechanism_builder() {
+        let m = SpfMechanism::aaaa()
+            .domain("example.com")
+            .cidr(DualCidr::new(Some(24), Some(64)).unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(m, SpfMechanism::AAAA(Some(DomainSpec::new("example.com").unwrap()), DualCidr::new(Some(24), Some(64)).unwrap()));
+        assert_eq!(SpfMechanism::a().build().unwrap(), SpfMechanism::A(None, DualCidr::default()));
+
+        assert!(matches!(SpfMechanism::mx().ip4_prefix(33).build(), Err(SpfBuildError::InvalidCidr(CidrError::InvalidIpv4Length(33)))));
+        assert!(matches!(SpfMechanism::mx().ip6_prefix(129).build(), Err(SpfBuildError::InvalidCidr(CidrError::InvalidIpv6Length(129)))));
+        assert!(matches!(SpfMechanism::a().domain("").build(), Err(SpfBuildError::InvalidDomain(DomainSpecError::Empty))));
+        assert!(matches!(SpfMechanism::a().domain("example.").build(), Err(SpfBuildError::InvalidDomain(_))));
+    }
+
+    #[test]
+    fn test_record_builder() -> Result<(), SpfBuildError> {
+        let record = SpfRecordBuilder::new()
+            .ip4("192.0.2.0/24")?
+            .ip6("2001:db8::/32")?
+            .a_with_prefix(Some("%{d}"), 28)?
+            .mx(None)?
+            .ptr(None)?
+            .exists("%{ir}.%{v}._spf.%{d}")?
+            .directive(SpfAction::SoftFail, SpfMechanism::include("_spf.example.com")?)?
+            .all(SpfAction::Neutral)
+            .redirect("_spf.example.org")?
+            .exp("exp.%{d}")?
+            .build();
+        assert!(record.validate().is_ok());
+        let text = record.to_string();
+        assert_eq!(text, "v=spf1 ip4:192.0.2.0/24 ip6:2001:db8::/32 a:%{d}/28 mx ptr exists:%{ir}.%{v}._spf.%{d} ~include:_spf.example.com ?all redirect=_spf.example.org exp=exp.%{d}");
+        assert_eq!(SpfRecord::parse_str(&text).unwrap().into_owned(), record);
+
+        assert!(matches!(SpfRecordBuilder::new().ip4("192.0.2.0/33"), Err(SpfBuildError::InvalidCidr(CidrError::InvalidIpv4Length(33)))));
+        assert!(matches!(SpfRecordBuilder::new().ip6("192.0.2.0"), Err(SpfBuildError::InvalidCidr(CidrError::InvalidAddress))));
+        assert!(matches!(SpfRecordBuilder::new().mx_with_prefix(None, 33), Err(SpfBuildError::InvalidCidr(_))));
+        assert!(matches!(SpfRecordBuilder::new().include(""), Err(SpfBuildError::InvalidDomain(DomainSpecError::Empty))));
+        assert!(matches!(SpfRecordBuilder::new().a(Some("example.123")), Err(SpfBuildError::InvalidDomain(_))));
+        assert!(matches!(
+            SpfRecordBuilder::new().directive(SpfAction::Fail, SpfMechanism::exp("exp.example.com")?),
+            Err(SpfBuildError::QualifiedModifier)
+        ));
+        assert!(matches!(
+            SpfRecordBuilder::new().redirect("example.com")?.redirect("example.org"),
+            Err(SpfBuildError::DuplicateModifier { kind: SpfDirectiveKind::Redirect })
+        ));
+        assert!(matches!(
+            SpfRecordBuilder::new().exp("example.com")?.mx(None)?.exp("example.org"),
+            Err(SpfBuildError::DuplicateModifier { kind: SpfDirectiveKind::Exp })
+        ));
+        assert!(SpfRecordBuilder::new().build().directives.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_constructors() {
+        let m = SpfMechanism::ip6_net("2001:db8::".parse().unwrap(), 32).unwrap();
+        assert_eq!(m, SpfMechanism::Ipv6(Ipv6Net::from_str("2001:db8::/32").unwrap()));
+        assert_eq!(SpfMechanism::ip6_net("2001:db8::".parse().unwrap(), 129), Err(CidrError::InvalidIpv6Length(129)));
+        assert_eq!(SpfMechanism::ip4_net(Ipv4Addr::new(192, 0, 2, 1), 32).unwrap().as_ip4().map(|n| n.prefix_len()), Some(32));
+    }
+}
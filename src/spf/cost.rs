@@ -0,0 +1,228 @@
+//! Module responsible for estimating how many DNS lookups given SPF record costs.
+//!
+//! RFC 7208 limits number of DNS querying terms to 10 per check.
+//! Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4.6.4) section `4.6.4`
+
+use std::collections::{HashMap, HashSet};
+
+use crate::spf::{DomainSpec, SpfMechanism, SpfRecord};
+
+/// DNS_LOOKUP_LIMIT is maximum number of DNS querying terms allowed during single check.
+pub const DNS_LOOKUP_LIMIT: u32 = 10;
+
+/// DirectiveCost describes how many DNS lookups single directive of record consumes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct DirectiveCost {
+    /// local is number of lookups directive itself performs. It's either 0 or 1.
+    pub local: u8,
+
+    /// transitive is number of lookups performed by directive including ones performed by records
+    /// it points to(`include` and `redirect`).
+    ///
+    /// It's `None` when no resolutions were given or when cost couldn't be computed, because target record
+    /// is missing, target contains macros or includes form a cycle.
+    pub transitive: Option<u32>,
+
+    /// over_budget_here is true for first directive at which running total of lookups exceeds `DNS_LOOKUP_LIMIT`.
+    pub over_budget_here: bool,
+}
+
+impl<'a> SpfMechanism<'a> {
+    /// lookup_cost returns number of DNS lookups required by this mechanism itself,
+    /// not counting lookups done by included records.
+    pub fn lookup_cost(&self) -> u8 {
+        match self {
+            SpfMechanism::A(_, _) |
+            SpfMechanism::AAAA(_, _) |
+            SpfMechanism::MX(_, _) |
+            SpfMechanism::Include(_) |
+            SpfMechanism::Exists(_) |
+            SpfMechanism::Redirect(_) |
+            SpfMechanism::Ptr(_) => 1,
+
+            SpfMechanism::Ipv4(_) |
+            SpfMechanism::Ipv6(_) |
+            SpfMechanism::UnknownModifier(_) |
+            SpfMechanism::Exp(_) |
+            SpfMechanism::All => 0,
+        }
+    }
+
+    /// referenced_record returns domain of record which is evaluated by this mechanism
+    /// (target of `include` or `redirect`) if any.
+    pub(crate) fn referenced_record(&self) -> Option<&DomainSpec<'a>> {
+        match self {
+            SpfMechanism::Include(d) | SpfMechanism::Redirect(d) => Some(d),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn find_record<'r, 'a>(resolved: &'r HashMap<String, SpfRecord<'a>>, domain: &str) -> Option<&'r SpfRecord<'a>> {
+    resolved.get(domain).or_else(|| {
+        let domain = domain.to_ascii_lowercase();
+        resolved.get(domain.trim_end_matches('.'))
+    })
+}
+
+fn transitive_record_cost(record: &SpfRecord, resolved: &HashMap<String, SpfRecord>, visiting: &mut HashSet<String>) -> Option<u32> {
+    let mut total = 0u32;
+    for d in record.directives.iter() {
+        total = total.saturating_add(transitive_mechanism_cost(&d.mechanism, resolved, visiting)?);
+    }
+    Some(total)
+}
+
+fn transitive_mechanism_cost(mechanism: &SpfMechanism, resolved: &HashMap<String, SpfRecord>, visiting: &mut HashSet<String>) -> Option<u32> {
+    let local = mechanism.lookup_cost() as u32;
+    let domain = match mechanism.referenced_record() {
+        // macro has to be expanded before lookup so there is no way to tell which record is used
+        Some(domain) => domain.as_literal()?,
+        None => return Some(local),
+    };
+
+    let key = domain.to_ascii_lowercase();
+    if !visiting.insert(key.clone()) {
+        // include loop, cost is unbounded
+        return None;
+    }
+    let res = find_record(resolved, domain)
+        .and_then(|r| transitive_record_cost(r, resolved, visiting))
+        .map(|c| c.saturating_add(local));
+    visiting.remove(&key);
+    res
+}
+
+impl<'a> SpfRecord<'a> {
+    /// annotate_costs computes lookup cost of each directive of this record.
+    /// Returned vector is aligned by index with `directives`.
+    ///
+    /// When `resolved` records are given(keyed by domain) costs of `include` and `redirect` targets are
+    /// computed recursively and directive at which limit of `DNS_LOOKUP_LIMIT` is exceeded is marked.
+    /// Otherwise only local costs are used to compute running total.
+    pub fn annotate_costs(&self, resolved: Option<&HashMap<String, SpfRecord>>) -> Vec<DirectiveCost> {
+        let mut res = Vec::with_capacity(self.directives.len());
+        let mut visiting = HashSet::new();
+        let mut running_total = 0u32;
+        let mut overflow_found = false;
+
+        for d in self.directives.iter() {
+            let local = d.mechanism.lookup_cost();
+            let transitive = resolved
+                .and_then(|resolved| transitive_mechanism_cost(&d.mechanism, resolved, &mut visiting));
+
+            running_total = running_total.saturating_add(transitive.unwrap_or(local as u32));
+            let over_budget_here = !overflow_found && running_total > DNS_LOOKUP_LIMIT;
+            overflow_found |= over_budget_here;
+
+            res.push(DirectiveCost {
+                local,
+                transitive,
+                over_budget_here,
+            });
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use crate::spf::{DualCidr, Ipv4Net, SpfAction, SpfDirective};
+
+    use super::*;
+
+    fn directive(mechanism: SpfMechanism<'static>) -> SpfDirective<'static> {
+        SpfDirective {
+            qualifier: SpfAction::Pass,
+            explicit_qualifier: false,
+            mechanism,
+        }
+    }
+
+    fn include(domain: &'static str) -> SpfDirective<'static> {
+        directive(SpfMechanism::Include(DomainSpec::new(domain).unwrap()))
+    }
+
+    /// eleven_lookups_record returns record which performs 11 lookups: `a mx include:i0 ... include:i8 -all`
+    fn eleven_lookups_record() -> SpfRecord<'static> {
+        let mut directives = vec![
+            directive(SpfMechanism::A(None, DualCidr::default())),
+            directive(SpfMechanism::MX(None, DualCidr::default())),
+        ];
+        for d in ["i0.example.com", "i1.example.com", "i2.example.com", "i3.example.com",
+            "i4.example.com", "i5.example.com", "i6.example.com", "i7.example.com", "i8.example.com"].iter() {
+            directives.push(include(d));
+        }
+        directives.push(SpfDirective {
+            qualifier: SpfAction::Fail,
+            explicit_qualifier: true,
+            mechanism: SpfMechanism::All,
+        });
+        SpfRecord::from(directives)
+    }
+
+    #[test]
+    fn test_local_costs_of_eleven_lookups_record() {
+        let record = eleven_lookups_record();
+        let costs = record.annotate_costs(None);
+        assert_eq!(costs.len(), record.directives.len());
+
+        assert!(costs[..11].iter().all(|c| c.local == 1 && c.transitive.is_none()));
+        assert_eq!(costs[11].local, 0);
+
+        let flagged = costs.iter().enumerate()
+            .filter(|(_, c)| c.over_budget_here)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        assert_eq!(flagged, vec![10]);
+    }
+
+    #[test]
+    fn test_ip_mechanisms_are_free() {
+        let record = SpfRecord::from(vec![
+            directive(SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap())),
+            directive(SpfMechanism::All),
+        ]);
+        let costs = record.annotate_costs(None);
+        assert!(costs.iter().all(|c| c.local == 0 && !c.over_budget_here));
+    }
+
+    #[test]
+    fn test_transitive_costs_flag_first_overflowing_include() {
+        let mut resolved = HashMap::new();
+        resolved.insert("_spf.example.com".to_string(), SpfRecord::from(vec![
+            directive(SpfMechanism::A(None, DualCidr::default())),
+            directive(SpfMechanism::MX(None, DualCidr::default())),
+            include("_nested.example.com"),
+        ]));
+        resolved.insert("_nested.example.com".to_string(), SpfRecord::from(vec![
+            directive(SpfMechanism::Exists(DomainSpec::new("example.net").unwrap())),
+            directive(SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap())),
+        ]));
+
+        let record = SpfRecord::from(vec![
+            include("_spf.example.com"),
+            include("_SPF.example.com"),
+            include("_spf.example.com"),
+            include("missing.example.com"),
+        ]);
+        let costs = record.annotate_costs(Some(&resolved));
+        assert_eq!(costs.iter().map(|c| c.transitive).collect::<Vec<_>>(), vec![Some(5), Some(5), Some(5), None]);
+        assert_eq!(costs.iter().map(|c| c.over_budget_here).collect::<Vec<_>>(), vec![false, false, true, false]);
+    }
+
+    #[test]
+    fn test_include_cycle_has_no_transitive_cost() {
+        let mut resolved = HashMap::new();
+        resolved.insert("a.example.com".to_string(), SpfRecord::from(vec![include("b.example.com")]));
+        resolved.insert("b.example.com".to_string(), SpfRecord::from(vec![include("a.example.com")]));
+
+        let record = SpfRecord::from(vec![include("a.example.com")]);
+        let costs = record.annotate_costs(Some(&resolved));
+        assert_eq!(costs[0].transitive, None);
+        assert_eq!(costs[0].local, 1);
+    }
+}
@@ -0,0 +1,112 @@
+//! Module with `Display` implementations, which render records, directives and mechanisms as RFC 7208 text.
+//!
+//! Output of parsed values parses back to equal values. Parsed text is reproduced exactly, except for
+//! spacing between terms and case of names, which are not kept by parser.
+
+use std::fmt;
+
+use crate::spf::{SpfAction, SpfDirective, SpfMechanism, SpfRecord};
+
+impl<'a> fmt::Display for SpfMechanism<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, domain, cidr) = match self {
+            SpfMechanism::A(d, cidr) => ("a", d, cidr),
+            SpfMechanism::AAAA(d, cidr) => ("aaaa", d, cidr),
+            SpfMechanism::MX(d, cidr) => ("mx", d, cidr),
+            SpfMechanism::Ptr(Some(d)) => return write!(f, "ptr:{}", d),
+            SpfMechanism::Ptr(None) => return f.write_str("ptr"),
+            SpfMechanism::Ipv4(net) => return write!(f, "ip4:{}", net),
+            SpfMechanism::Ipv6(net) => return write!(f, "ip6:{}", net),
+            SpfMechanism::Include(d) => return write!(f, "include:{}", d),
+            SpfMechanism::Exists(d) => return write!(f, "exists:{}", d),
+            SpfMechanism::Redirect(d) => return write!(f, "redirect={}", d),
+            SpfMechanism::Exp(d) => return write!(f, "exp={}", d),
+            SpfMechanism::UnknownModifier(m) => return write!(f, "{}={}", m.name, m.value),
+            SpfMechanism::All => return f.write_str("all"),
+        };
+        f.write_str(name)?;
+        if let Some(d) = domain {
+            write!(f, ":{}", d)?;
+        }
+        write!(f, "{}", cidr)
+    }
+}
+
+/// Qualifier is written when it was explicit in parsed text or when it's not `Pass`.
+impl<'a> fmt::Display for SpfDirective<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.explicit_qualifier || self.qualifier != SpfAction::Pass {
+            write!(f, "{}", char::from(self.qualifier))?;
+        }
+        write!(f, "{}", self.mechanism)
+    }
+}
+
+/// Record is rendered as `v=spf1` followed by directives separated with single space.
+///
+/// Records built by hand are rendered as they are, use `SpfRecord::validate` to check them before publishing.
+///
+/// # Example
+/// ```
+/// use spf::SpfRecord;
+///
+/// let record = SpfRecord::parse_str("v=spf1  +mx a:%{d}/24//64 ~all").unwrap();
+/// assert_eq!(record.to_string(), "v=spf1 +mx a:%{d}/24//64 ~all");
+/// ```
+impl<'a> fmt::Display for SpfRecord<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("v=spf1")?;
+        for d in self.directives.iter() {
+            write!(f, " {}", d)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// RECORDS contains records in the form they are published in, so they have to be rendered byte for byte.
+    const RECORDS: &[&str] = &[
+        "v=spf1",
+        "v=spf1 -all",
+        "v=spf1 mx include:_spf.google.com ~all",
+        "v=spf1 +mx +a -all",
+        "v=spf1 a mx:mail.example.com a/24 mx//64 aaaa:example.com/24//64 ?all",
+        "v=spf1 ptr ~ptr:%{d}.example.com -all",
+        "v=spf1 ip4:192.0.2.0/24 ip4:198.51.100.17 ip6:2001:db8::/32 ip6:2001:db8::cb01 ip6:::ffff:192.0.2.1 -all",
+        "v=spf1 exists:%{ir}.%{v}._spf.%{d} exists:%{l1r-}.%{O}.lp._spf.%{d2} -all",
+        "v=spf1 include:spf.protection.outlook.com redirect=_spf.example.com exp=explain._spf.%{d}",
+        "v=spf1 -all foo=bar x-ext.1= note=%{S}-%%-%_",
+    ];
+
+    #[test]
+    fn test_records_round_trip() {
+        for text in RECORDS.iter() {
+            let record = SpfRecord::parse_str(text).unwrap();
+            let rendered = record.to_string();
+            assert_eq!(rendered, *text);
+            assert_eq!(SpfRecord::parse_str(&rendered).unwrap().into_owned(), record.clone().into_owned());
+        }
+    }
+
+    #[test]
+    fn test_display_mechanisms() {
+        assert_eq!(SpfMechanism::a().build().unwrap().to_string(), "a");
+        assert_eq!(SpfMechanism::mx().ip6_prefix(64).build().unwrap().to_string(), "mx//64");
+        assert_eq!(SpfMechanism::aaaa().domain("example.com").ip4_prefix(24).build().unwrap().to_string(), "aaaa:example.com/24");
+        assert_eq!(SpfMechanism::all().to_string(), "all");
+        assert_eq!(SpfMechanism::include("_spf.example.com").unwrap().to_string(), "include:_spf.example.com");
+        assert_eq!(SpfMechanism::redirect("%{d}.example.com").unwrap().to_string(), "redirect=%{d}.example.com");
+    }
+
+    #[test]
+    fn test_display_qualifiers() {
+        let d = SpfDirective::new(SpfAction::Fail, SpfMechanism::all()).unwrap();
+        assert_eq!(d.to_string(), "-all");
+        assert_eq!(SpfDirective::from_mechanism(SpfMechanism::all()).to_string(), "all");
+        assert_eq!(SpfDirective::parse_str("+all").unwrap().to_string(), "+all");
+        assert_eq!(SpfDirective::parse_str("+all").unwrap().normalize().to_string(), "all");
+    }
+}
@@ -0,0 +1,496 @@
+//! Module containing `DomainSpec` - domain argument of SPF mechanisms and modifiers, which may contain macros.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::spf::{EvaluationContext, MacroEvaluationError, MacroString, MacroVariable};
+
+/// MAX_DOMAIN_LENGTH is maximum length of domain created by macro expansion.
+/// Longer domains are truncated from the left.
+pub const MAX_DOMAIN_LENGTH: usize = 253;
+
+/// DomainSpecError is returned when domain-spec is not valid.
+#[derive(Debug, From)]
+#[non_exhaustive]
+pub enum DomainSpecError {
+    /// Empty is returned when domain-spec has no chars.
+    Empty,
+
+    /// InvalidCharFound is returned when domain-spec contains char which is not visible ASCII char.
+    InvalidCharFound,
+
+    /// InvalidMacro is returned when macro string syntax is not valid.
+    InvalidMacro(MacroEvaluationError),
+
+    /// InvalidDomainEnd is returned when domain-spec neither ends with macro nor with top level label.
+    InvalidDomainEnd,
+}
+
+impl fmt::Display for DomainSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainSpecError::Empty => write!(f, "domain-spec is empty"),
+            DomainSpecError::InvalidCharFound => write!(f, "domain-spec contains invalid char"),
+            DomainSpecError::InvalidMacro(e) => write!(f, "domain-spec contains invalid macro: {}", e),
+            DomainSpecError::InvalidDomainEnd => write!(f, "domain-spec does not end with top level label or macro"),
+        }
+    }
+}
+
+impl std::error::Error for DomainSpecError {}
+
+/// DomainSpec is domain-spec argument of SPF mechanisms and modifiers like `include`, `a` or `redirect`.
+/// It's either literal domain or macro string which evaluates to domain.
+///
+/// Raw text is kept as is, so `Display` outputs it exactly like it was given.
+/// Parsed macro string is computed lazily and cached.
+///
+/// `PartialEq`, `Eq`, `Hash` and `Ord` use raw text only.
+///
+/// # Docs
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-7.1) section `7.1`
+pub struct DomainSpec<'a> {
+    raw: Cow<'a, str>,
+    parsed: MacroCache,
+}
+
+impl<'a> DomainSpec<'a> {
+    /// new creates domain-spec from given text. It fails if text is empty, contains
+    /// chars other than visible ASCII ones or if macro string syntax is invalid.
+    ///
+    /// It does not check whole domain-spec grammar. Use `validate` for that.
+    pub fn new<T>(raw: T) -> Result<Self, DomainSpecError>
+        where T: Into<Cow<'a, str>>
+    {
+        let raw = raw.into();
+        check_chars(&raw)?;
+        let parsed = if raw.contains('%') {
+            MacroCache::with(MacroString::parse(&raw)?)
+        } else {
+            MacroCache::empty()
+        };
+        Ok(Self {
+            raw,
+            parsed,
+        })
+    }
+
+    /// from_raw_unchecked creates domain-spec without any validation.
+    /// Invalid macro strings are reported once domain-spec is expanded.
+    pub(crate) fn from_raw_unchecked(raw: Cow<'a, str>) -> Self {
+        Self {
+            raw,
+            parsed: MacroCache::empty(),
+        }
+    }
+
+    /// as_str returns raw text of this domain-spec.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// is_literal returns true if this domain-spec contains no macros(or escapes) and
+    /// may be used as domain without expansion.
+    #[inline]
+    pub fn is_literal(&self) -> bool {
+        !self.raw.contains('%')
+    }
+
+    /// as_literal returns domain if this domain-spec contains no macros.
+    #[inline]
+    pub fn as_literal(&self) -> Option<&str> {
+        if self.is_literal() {
+            Some(&self.raw)
+        } else {
+            None
+        }
+    }
+
+    /// macro_string returns parsed macro string of this domain-spec.
+    pub fn macro_string(&self) -> Result<&MacroString, MacroEvaluationError> {
+        if let Some(parsed) = self.parsed.get() {
+            return Ok(parsed);
+        }
+        let parsed = MacroString::parse(&self.raw)?;
+        Ok(self.parsed.get_or_init(parsed))
+    }
+
+    /// expand evaluates macros of this domain-spec.
+    ///
+    /// `current_domain` is used as value of `%{d}` when evaluation context does not provide it.
+    /// If result is longer than 253 chars, labels are removed from the left until it fits.
+    pub fn expand<E>(&self, ctx: E, current_domain: &str) -> Result<Cow<'_, str>, MacroEvaluationError>
+        where E: EvaluationContext
+    {
+        self.expand_with_context(CurrentDomainContext {
+            inner: ctx,
+            current_domain,
+        })
+    }
+
+    /// expand_with_context evaluates macros of this domain-spec taking all variables, including `%{d}`, from context.
+    pub(crate) fn expand_with_context<E>(&self, ctx: E) -> Result<Cow<'_, str>, MacroEvaluationError>
+        where E: EvaluationContext
+    {
+        if self.is_literal() {
+            return Ok(Cow::Borrowed(&self.raw));
+        }
+        let res = self.macro_string()?.evaluate(ctx)?;
+        Ok(Cow::Owned(truncate_domain(&res).to_string()))
+    }
+
+    /// validate checks if this domain-spec matches `domain-spec` grammar of RFC 7208.
+    /// In particular it has to end either with macro or with top level label, like `.com`.
+    pub fn validate(&self) -> Result<(), DomainSpecError> {
+        check_chars(&self.raw)?;
+        let macro_string = self.macro_string()?;
+        let raw: &str = &self.raw;
+
+        // domain-end = ( "." toplabel [ "." ] ) / macro-expand
+        if raw.ends_with('}') || ends_with_short_macro(raw) {
+            // no need to check, there is macro at the end
+            if let Some(crate::spf::MacroToken::Expansion(_)) = macro_string.tokens().last() {
+                return Ok(());
+            }
+        }
+
+        let trimmed = raw.strip_suffix('.').unwrap_or(raw);
+        let (rest, top_label) = match trimmed.rfind('.') {
+            Some(idx) => (&trimmed[..idx], &trimmed[idx + 1..]),
+            None => return Err(DomainSpecError::InvalidDomainEnd),
+        };
+        if rest.is_empty() && !raw.contains('%') {
+            return Err(DomainSpecError::InvalidDomainEnd);
+        }
+        if is_top_label(top_label) {
+            Ok(())
+        } else {
+            Err(DomainSpecError::InvalidDomainEnd)
+        }
+    }
+
+    /// into_owned converts this domain-spec into one which does not borrow any data.
+    pub fn into_owned(self) -> DomainSpec<'static> {
+        let mut raw = self.raw.into_owned();
+        raw.shrink_to_fit();
+        DomainSpec {
+            raw: Cow::Owned(raw),
+            parsed: self.parsed,
+        }
+    }
+
+    /// as_borrowed returns view of this domain-spec which borrows raw text from `self`.
+    /// Parsed macro string is not copied, it's computed again when needed.
+    pub fn as_borrowed(&self) -> DomainSpec<'_> {
+        DomainSpec::from_raw_unchecked(Cow::Borrowed(&self.raw))
+    }
+
+    /// into_raw returns raw text of this domain-spec.
+    #[inline]
+    pub fn into_raw(self) -> Cow<'a, str> {
+        self.raw
+    }
+}
+
+/// check_chars checks if text is not empty and consists of visible ASCII chars only.
+fn check_chars(text: &str) -> Result<(), DomainSpecError> {
+    if text.is_empty() {
+        return Err(DomainSpecError::Empty);
+    }
+    if !text.bytes().all(|c| (0x21..=0x7e).contains(&c)) {
+        return Err(DomainSpecError::InvalidCharFound);
+    }
+    Ok(())
+}
+
+/// ends_with_short_macro checks if text ends with non-braced macro like `%d`.
+fn ends_with_short_macro(text: &str) -> bool {
+    let b = text.as_bytes();
+    b.len() >= 2 && b[b.len() - 2] == b'%' && MacroVariable::get_valid_lowercase_symbols().contains(&b[b.len() - 1].to_ascii_lowercase())
+}
+
+/// is_top_label checks `toplabel` rule of RFC 7208:
+/// `( *alphanum ALPHA *alphanum ) / ( 1*alphanum "-" *( alphanum / "-" ) alphanum )`
+fn is_top_label(label: &str) -> bool {
+    let b = label.as_bytes();
+    if b.is_empty() || !b.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'-') {
+        return false;
+    }
+    if b.contains(&b'-') {
+        b[0] != b'-' && b[b.len() - 1] != b'-'
+    } else {
+        b.iter().any(|c| c.is_ascii_alphabetic())
+    }
+}
+
+/// truncate_domain removes labels from the left until domain is not longer than `MAX_DOMAIN_LENGTH`.
+fn truncate_domain(domain: &str) -> &str {
+    let mut domain = domain;
+    while domain.len() > MAX_DOMAIN_LENGTH {
+        domain = match domain.find('.') {
+            Some(idx) => &domain[idx + 1..],
+            None => {
+                let mut start = domain.len() - MAX_DOMAIN_LENGTH;
+                while !domain.is_char_boundary(start) {
+                    start += 1;
+                }
+                &domain[start..]
+            }
+        };
+    }
+    domain
+}
+
+struct CurrentDomainContext<'d, E> {
+    inner: E,
+    current_domain: &'d str,
+}
+
+impl<'d, E> EvaluationContext for CurrentDomainContext<'d, E>
+    where E: EvaluationContext
+{
+    fn provide_data(&self, v: MacroVariable) -> Result<Cow<'_, str>, MacroEvaluationError> {
+        match self.inner.provide_data(v) {
+            Err(MacroEvaluationError::UnknownVariable(_)) if v == MacroVariable::Domain => Ok(Cow::Borrowed(self.current_domain)),
+            res => res,
+        }
+    }
+}
+
+/// MacroCache is lazily initialized box holding parsed macro string. Unlike `OnceLock<Box<_>>` it's single
+/// pointer wide, which keeps `DomainSpec` and so `SpfMechanism` small.
+///
+/// When two threads initialize it at once, value of one of them is dropped.
+struct MacroCache(AtomicPtr<MacroString>);
+
+impl MacroCache {
+    #[inline]
+    fn empty() -> Self {
+        Self(AtomicPtr::new(ptr::null_mut()))
+    }
+
+    #[inline]
+    fn with(value: MacroString) -> Self {
+        Self(AtomicPtr::new(Box::into_raw(Box::new(value))))
+    }
+
+    #[inline]
+    fn get(&self) -> Option<&MacroString> {
+        // safety: pointer is either null or comes from Box::into_raw and lives as long as self
+        unsafe { self.0.load(Ordering::Acquire).as_ref() }
+    }
+
+    fn get_or_init(&self, value: MacroString) -> &MacroString {
+        let new = Box::into_raw(Box::new(value));
+        match self.0.compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+            // safety: new was just set and is dropped only together with self
+            Ok(_) => unsafe { &*new },
+            Err(current) => {
+                // safety: new was never shared, current is valid just like in get
+                unsafe {
+                    drop(Box::from_raw(new));
+                    &*current
+                }
+            }
+        }
+    }
+}
+
+impl Clone for MacroCache {
+    fn clone(&self) -> Self {
+        match self.get() {
+            Some(v) => Self::with(v.clone()),
+            None => Self::empty(),
+        }
+    }
+}
+
+impl Drop for MacroCache {
+    fn drop(&mut self) {
+        let p = *self.0.get_mut();
+        if !p.is_null() {
+            // safety: pointer comes from Box::into_raw and is not used after drop
+            drop(unsafe { Box::from_raw(p) });
+        }
+    }
+}
+
+impl<'a> Clone for DomainSpec<'a> {
+    fn clone(&self) -> Self {
+        Self {
+            raw: self.raw.clone(),
+            parsed: self.parsed.clone(),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for DomainSpec<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DomainSpec").field(&self.raw).finish()
+    }
+}
+
+impl<'a> fmt::Display for DomainSpec<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl<'a, 'b> PartialEq<DomainSpec<'b>> for DomainSpec<'a> {
+    #[inline]
+    fn eq(&self, other: &DomainSpec<'b>) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<'a> Eq for DomainSpec<'a> {}
+
+impl<'a> PartialOrd for DomainSpec<'a> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Ord for DomainSpec<'a> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.raw.cmp(&other.raw)
+    }
+}
+
+impl<'a> Hash for DomainSpec<'a> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.raw.hash(state)
+    }
+}
+
+impl<'a> AsRef<str> for DomainSpec<'a> {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        &self.raw
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'a> serde::Serialize for DomainSpec<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        serializer.serialize_str(&self.raw)
+    }
+}
+
+/// Domain-spec is deserialized into owned string, so `DomainSpec<'static>` implements `DeserializeOwned`.
+/// Use `Borrowed` in order to borrow it from input.
+#[cfg(feature = "serialize")]
+impl<'de, 'a> serde::Deserialize<'de> for DomainSpec<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        let raw = String::deserialize(deserializer)?;
+        DomainSpec::new(raw).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn ctx() -> HashMap<MacroVariable, &'static str> {
+        let mut m = HashMap::new();
+        m.insert(MacroVariable::Ip, "192.0.2.3");
+        m.insert(MacroVariable::Sender, "user@example.com");
+        m
+    }
+
+    #[test]
+    fn test_literal_domain_spec() {
+        let d = DomainSpec::new("_spf.example.com").unwrap();
+        assert!(d.is_literal());
+        assert_eq!(d.as_literal(), Some("_spf.example.com"));
+        assert!(d.validate().is_ok());
+        assert_eq!(d.to_string(), "_spf.example.com");
+
+        let expanded = d.expand(ctx(), "example.org").unwrap();
+        assert!(matches!(expanded, Cow::Borrowed("_spf.example.com")));
+    }
+
+    #[test]
+    fn test_macro_domain_spec() {
+        let d = DomainSpec::new("%{ir}.%{d}.rbl.example.com").unwrap();
+        assert!(!d.is_literal());
+        assert_eq!(d.as_literal(), None);
+        assert!(d.validate().is_ok());
+        assert_eq!(d.to_string(), "%{ir}.%{d}.rbl.example.com");
+        assert_eq!(d.expand(ctx(), "example.org").unwrap(), "3.2.0.192.example.org.rbl.example.com");
+
+        let d = DomainSpec::new("%{d}").unwrap();
+        assert!(d.validate().is_ok());
+        assert_eq!(d.expand(ctx(), "example.org").unwrap(), "example.org");
+    }
+
+    #[test]
+    fn test_invalid_domain_spec() {
+        assert!(matches!(DomainSpec::new("%{q}.example.com"), Err(DomainSpecError::InvalidMacro(_))));
+        assert!(matches!(DomainSpec::new("%{d"), Err(DomainSpecError::InvalidMacro(_))));
+        assert!(matches!(DomainSpec::new(""), Err(DomainSpecError::Empty)));
+        assert!(matches!(DomainSpec::new("exa mple.com"), Err(DomainSpecError::InvalidCharFound)));
+
+        for text in ["example", "example.-com", "example.123", "example.com-", "example.com..", ".com"].iter() {
+            let d = DomainSpec::new(*text).unwrap();
+            assert!(matches!(d.validate(), Err(DomainSpecError::InvalidDomainEnd)), "{} should not be valid", text);
+        }
+        for text in ["example.com.", "a.b-c", "a.x1", "%{d}.com", "foo.%{d}", "%{i}.%d"].iter() {
+            assert!(DomainSpec::new(*text).unwrap().validate().is_ok(), "{} should be valid", text);
+        }
+    }
+
+    #[test]
+    fn test_long_expansion_is_truncated() {
+        let mut m = HashMap::new();
+        let long = vec!["abcdefghi"; 40].join(".");
+        m.insert(MacroVariable::Sender, long.as_str());
+
+        let d = DomainSpec::new("%{s}.example.com").unwrap();
+        let expanded = d.expand(&m, "example.org").unwrap();
+        assert!(expanded.len() <= MAX_DOMAIN_LENGTH);
+        assert!(expanded.ends_with(".example.com"));
+        assert!(expanded.starts_with("abcdefghi."));
+
+        // single label made of multi byte chars is cut at char boundary
+        let long = "ż".repeat(200);
+        m.insert(MacroVariable::Sender, long.as_str());
+        let expanded = DomainSpec::new("%{s}").unwrap().expand(&m, "example.org").unwrap().into_owned();
+        assert_eq!(expanded, "ż".repeat(126));
+    }
+
+    #[test]
+    fn test_equality_uses_raw_text() {
+        let a = DomainSpec::new("%{d}.example.com").unwrap();
+        let b = DomainSpec::from_raw_unchecked(Cow::Owned("%{d}.example.com".to_string()));
+        assert_eq!(a, b);
+        assert_eq!(a.clone().into_owned(), b.as_borrowed());
+    }
+
+    #[test]
+    fn test_macro_string_is_cached_once_across_threads() {
+        let d = DomainSpec::from_raw_unchecked(Cow::Borrowed("%{ir}.%{v}._spf.%{d}"));
+        let parsed = std::thread::scope(|s| {
+            let handles = (0..8).map(|_| s.spawn(|| d.macro_string().unwrap() as *const MacroString as usize)).collect::<Vec<_>>();
+            handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>()
+        });
+        assert!(parsed.iter().all(|p| *p == parsed[0]));
+
+        let cloned = d.clone();
+        assert!(!std::ptr::eq(cloned.macro_string().unwrap(), d.macro_string().unwrap()));
+        assert_eq!(cloned.macro_string().unwrap(), d.macro_string().unwrap());
+    }
+}
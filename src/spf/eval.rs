@@ -0,0 +1,871 @@
+//! Module responsible for evaluating SPF records, which is `check_host` function of RFC 7208.
+//!
+//! Evaluation performs no DNS queries. Resources it needs are taken from `ExternalResourceBag` and when one of them
+//! is missing evaluation stops with `SpfEvaluationError::MissingResource`, so caller can fetch it and try again.
+//!
+//! Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4) section `4`
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::spf::{
+    evaluate_exp_macro, DomainSpec, DualCidr, EvaluationContext, DNS_LOOKUP_LIMIT, ExternalResourceBag, ExternalResourceIdentifier,
+    InternedDomain, Ipv4Net, Ipv6Net, MacroEvaluationError, MacroToken, MacroVariable, SpfAction, SpfMechanism, SpfRecord,
+};
+
+/// EvaluationLimits are processing limits of single check. Check which exceeds any of them results
+/// in `SpfEvaluationResult::LimitExceeded`. Default limits are ones recommended by RFC.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4.6.4) section `4.6.4`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct EvaluationLimits {
+    /// max_dns_mechanisms is maximum number of `include`, `a`, `aaaa`, `mx`, `ptr`, `exists` and `redirect` terms
+    /// in all records evaluated during check.
+    ///
+    /// Terms are counted when record is entered rather than when they are evaluated, so record which has
+    /// too many of them fails even if its first mechanism matches.
+    pub max_dns_mechanisms: u8,
+
+    /// max_void_lookups is maximum number of lookups, which returned no data: domains which do not exist
+    /// and domains without addresses, MX hosts or validated names.
+    pub max_void_lookups: u8,
+
+    /// max_include_depth is maximum number of nested `include` and `redirect` evaluations.
+    pub max_include_depth: u8,
+}
+
+impl Default for EvaluationLimits {
+    fn default() -> Self {
+        Self {
+            max_dns_mechanisms: DNS_LOOKUP_LIMIT as u8,
+            max_void_lookups: 2,
+            max_include_depth: 10,
+        }
+    }
+}
+
+/// EvaluationLimit is single limit of `EvaluationLimits`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum EvaluationLimit {
+    /// DnsMechanisms is limit given by `EvaluationLimits::max_dns_mechanisms`.
+    DnsMechanisms,
+
+    /// VoidLookups is limit given by `EvaluationLimits::max_void_lookups`.
+    VoidLookups,
+
+    /// IncludeDepth is limit given by `EvaluationLimits::max_include_depth`.
+    IncludeDepth,
+}
+
+const VOID_LOOKUPS_EXCEEDED: SpfEvaluationResult = SpfEvaluationResult::LimitExceeded(EvaluationLimit::VoidLookups);
+
+/// MAX_MX_HOSTS is maximum number of hosts returned by MX query of `mx` mechanism.
+/// Mechanism evaluates to `PermError` if there are more of them.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4.6.4) section `4.6.4`
+const MAX_MX_HOSTS: usize = 10;
+
+/// SpfEvaluationResult is result of SPF check.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-2.6) section `2.6`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum SpfEvaluationResult {
+    /// None means that domain has no SPF record.
+    None,
+
+    /// Neutral means that record states nothing about sender. It's also result of record in which nothing matched.
+    Neutral,
+
+    /// Pass means that sender is authorized to use domain.
+    Pass,
+
+    /// Fail means that sender is not authorized to use domain.
+    Fail,
+
+    /// SoftFail means that sender is probably not authorized to use domain.
+    SoftFail,
+
+    /// TempError means that transient error, like DNS timeout, occurred during check.
+    TempError,
+
+    /// PermError means that record could not be interpreted correctly, for instance because it's invalid.
+    PermError,
+
+    /// LimitExceeded is `PermError`, which was caused by exceeding given processing limit.
+    /// Include and redirect loops end with it as well.
+    LimitExceeded(EvaluationLimit),
+}
+
+impl From<SpfAction> for SpfEvaluationResult {
+    #[inline]
+    fn from(action: SpfAction) -> Self {
+        match action {
+            SpfAction::Pass => SpfEvaluationResult::Pass,
+            SpfAction::Fail => SpfEvaluationResult::Fail,
+            SpfAction::SoftFail => SpfEvaluationResult::SoftFail,
+            SpfAction::Neutral => SpfEvaluationResult::Neutral,
+        }
+    }
+}
+
+/// SpfCheckResult is result of check together with explanation, which is given when check results in `Fail`
+/// and record has `exp` modifier. MTAs may put explanation in SMTP reject message.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-6.2) section `6.2`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SpfCheckResult {
+    /// result is result of check.
+    pub result: SpfEvaluationResult,
+
+    /// explanation is expanded explanation string. It's `None` when explanation is missing or can't be expanded.
+    pub explanation: Option<String>,
+}
+
+impl From<SpfEvaluationResult> for SpfCheckResult {
+    #[inline]
+    fn from(result: SpfEvaluationResult) -> Self {
+        Self {
+            result,
+            explanation: None,
+        }
+    }
+}
+
+/// SpfEvaluationError is returned when check could not be completed.
+/// Unlike `SpfEvaluationResult::PermError` it's not problem with record, but with data given to evaluator.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SpfEvaluationError {
+    /// MissingResource is returned when resource required to continue evaluation is not in resource bag.
+    /// Once it's put there record may be evaluated again.
+    MissingResource(ExternalResourceIdentifier<'static>),
+
+    /// Macro is returned when macro could not be expanded, because evaluation context does not provide
+    /// value of some variable.
+    Macro(MacroEvaluationError),
+}
+
+impl From<MacroEvaluationError> for SpfEvaluationError {
+    #[inline]
+    fn from(e: MacroEvaluationError) -> Self {
+        SpfEvaluationError::Macro(e)
+    }
+}
+
+impl fmt::Display for SpfEvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfEvaluationError::MissingResource(r) => write!(f, "resource {:?} is required to evaluate record", r),
+            SpfEvaluationError::Macro(e) => write!(f, "macro can't be expanded: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SpfEvaluationError {}
+
+/// ScopedContext provides domain of currently evaluated record as `%{d}` and, when it's given,
+/// validated name of source IP as `%{p}`. Other variables come from inner context.
+pub(crate) struct ScopedContext<'d, E> {
+    pub(crate) inner: E,
+    pub(crate) domain: Option<&'d str>,
+    pub(crate) validated_name: Option<&'d str>,
+}
+
+impl<'d, E> EvaluationContext for ScopedContext<'d, E>
+    where E: EvaluationContext
+{
+    fn provide_data(&self, v: MacroVariable) -> Result<Cow<'_, str>, MacroEvaluationError> {
+        match (v, self.domain, self.validated_name) {
+            (MacroVariable::Domain, Some(domain), _) => Ok(Cow::Borrowed(domain)),
+            (MacroVariable::ValidatedDomainNameOrIp, _, Some(name)) => Ok(Cow::Borrowed(name)),
+            _ => self.inner.provide_data(v),
+        }
+    }
+}
+
+/// needs_validated_name checks if domain-spec uses `%{p}` macro, which given context does not provide.
+fn needs_validated_name<E>(spec: &DomainSpec, ctx: E) -> bool
+    where E: EvaluationContext
+{
+    if spec.is_literal() {
+        return false;
+    }
+    let uses = match spec.macro_string() {
+        Ok(m) => m.tokens().iter().any(|t| matches!(t, MacroToken::Expansion(e) if e.variable == MacroVariable::ValidatedDomainNameOrIp)),
+        Err(_) => false,
+    };
+    uses && ctx.provide_data(MacroVariable::ValidatedDomainNameOrIp).is_err()
+}
+
+/// is_in_domain checks if name is given domain or its subdomain. Comparison is case insensitive.
+fn is_in_domain(name: &str, domain: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    if name.len() == domain.len() {
+        return name.eq_ignore_ascii_case(domain);
+    }
+    name.len() > domain.len() &&
+        name[name.len() - domain.len()..].eq_ignore_ascii_case(domain) &&
+        name.as_bytes()[name.len() - domain.len() - 1] == b'.'
+}
+
+/// pick_validated_name chooses value of `%{p}` from validated names of source IP. Name equal to domain is
+/// preferred over its subdomains, which are preferred over other names. Without names it's `unknown`.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-7.3) section `7.3`
+fn pick_validated_name<'n>(names: &'n [InternedDomain], domain: &str) -> &'n str {
+    names.iter().find(|n| n.trim_end_matches('.').eq_ignore_ascii_case(domain))
+        .or_else(|| names.iter().find(|n| is_in_domain(n, domain)))
+        .or_else(|| names.first())
+        .map(|n| n.as_str())
+        .unwrap_or("unknown")
+}
+
+/// exists_identifier returns identifier of existence of given domain, split at first dot.
+fn exists_identifier(domain: String) -> ExternalResourceIdentifier<'static> {
+    match domain.find('.') {
+        Some(idx) => ExternalResourceIdentifier::DomainExists(Cow::Owned(domain[..idx].to_string()), Cow::Owned(domain[idx + 1..].to_string())),
+        None => ExternalResourceIdentifier::DomainExists(Cow::Owned(domain), Cow::Borrowed("")),
+    }
+}
+
+/// matches_any checks if source IP is in network of any of given addresses with prefix lengths of `cidr`.
+fn matches_any(addresses: &[IpAddr], cidr: DualCidr, source_ip: IpAddr) -> bool {
+    addresses.iter().any(|addr| match addr {
+        IpAddr::V4(addr) => Ipv4Net::new(*addr, cidr.v4())
+            .expect("DualCidr holds valid prefix length")
+            .matches(source_ip),
+        IpAddr::V6(addr) => Ipv6Net::new(*addr, cidr.v6())
+            .expect("DualCidr holds valid prefix length")
+            .matches(source_ip),
+    })
+}
+
+struct Evaluator<'b, 'r, E> {
+    bag: &'b ExternalResourceBag<'r>,
+    source_ip: IpAddr,
+    ctx: E,
+    limits: EvaluationLimits,
+    dns_mechanisms: u32,
+    void_lookups: u32,
+
+    /// includes is number of `include` mechanisms, which are being evaluated.
+    includes: u32,
+
+    /// redirected_to is domain of last record reached with `redirect` outside of any `include`.
+    /// It's record, which `exp` is used, if check results in `Fail`.
+    redirected_to: Option<String>,
+}
+
+impl<'b, 'r, E> Evaluator<'b, 'r, E>
+    where E: EvaluationContext
+{
+    /// check_host evaluates record of given domain. Domain is `None` for top level record, in which case
+    /// `%{d}` is taken from evaluation context.
+    fn check_host(&mut self, record: &SpfRecord, domain: Option<&str>, depth: u8) -> Result<SpfEvaluationResult, SpfEvaluationError> {
+        if record.validate().is_err() {
+            return Ok(SpfEvaluationResult::PermError);
+        }
+        self.dns_mechanisms += record.directives.iter().map(|d| d.mechanism.lookup_cost() as u32).sum::<u32>();
+        if self.dns_mechanisms > self.limits.max_dns_mechanisms as u32 {
+            return Ok(SpfEvaluationResult::LimitExceeded(EvaluationLimit::DnsMechanisms));
+        }
+
+        for d in record.directives.iter() {
+            let matched = match &d.mechanism {
+                SpfMechanism::Ipv4(net) => net.matches(self.source_ip),
+                SpfMechanism::Ipv6(net) => net.matches(self.source_ip),
+                SpfMechanism::All => true,
+                SpfMechanism::Include(target) => match self.check_include(target, domain, depth)? {
+                    SpfEvaluationResult::Pass => true,
+                    SpfEvaluationResult::Fail | SpfEvaluationResult::SoftFail | SpfEvaluationResult::Neutral => false,
+                    SpfEvaluationResult::TempError => return Ok(SpfEvaluationResult::TempError),
+                    SpfEvaluationResult::PermError | SpfEvaluationResult::None => return Ok(SpfEvaluationResult::PermError),
+                    res @ SpfEvaluationResult::LimitExceeded(_) => return Ok(res),
+                },
+                SpfMechanism::Exists(target) => {
+                    let name = self.expand(target, domain)?;
+                    match self.bag.domain_exists(&name) {
+                        Some(exists) => {
+                            if !exists && self.void_lookup() {
+                                return Ok(VOID_LOOKUPS_EXCEEDED);
+                            }
+                            exists
+                        }
+                        None => return Err(SpfEvaluationError::MissingResource(exists_identifier(name))),
+                    }
+                }
+                SpfMechanism::A(spec, cidr) | SpfMechanism::AAAA(spec, cidr) => {
+                    let name = self.target_domain(spec.as_ref(), domain)?;
+                    let addresses = self.addresses(name)?;
+                    if addresses.is_empty() && self.void_lookup() {
+                        return Ok(VOID_LOOKUPS_EXCEEDED);
+                    }
+                    matches_any(addresses, *cidr, self.source_ip)
+                }
+                SpfMechanism::MX(spec, cidr) => {
+                    let name = self.target_domain(spec.as_ref(), domain)?;
+                    let bag = self.bag;
+                    let hosts = match bag.mx_hosts(&name) {
+                        Some(hosts) => hosts,
+                        None => return Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::MxHosts(Cow::Owned(name)))),
+                    };
+                    if hosts.len() > MAX_MX_HOSTS {
+                        return Ok(SpfEvaluationResult::PermError);
+                    }
+                    if hosts.is_empty() && self.void_lookup() {
+                        return Ok(VOID_LOOKUPS_EXCEEDED);
+                    }
+                    let mut matched = false;
+                    for host in hosts.iter() {
+                        let addresses = self.addresses(host.to_string())?;
+                        if addresses.is_empty() && self.void_lookup() {
+                            return Ok(VOID_LOOKUPS_EXCEEDED);
+                        }
+                        if matches_any(addresses, *cidr, self.source_ip) {
+                            matched = true;
+                            break;
+                        }
+                    }
+                    matched
+                }
+                SpfMechanism::Ptr(spec) => {
+                    let name = self.target_domain(spec.as_ref(), domain)?;
+                    match self.bag.validated_ptr_names(&name) {
+                        Some(names) => {
+                            if names.is_empty() && self.void_lookup() {
+                                return Ok(VOID_LOOKUPS_EXCEEDED);
+                            }
+                            names.iter().any(|n| is_in_domain(n, &name))
+                        }
+                        None => return Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::ValidatedPtrDomain(Cow::Owned(name)))),
+                    }
+                }
+                SpfMechanism::Redirect(_) | SpfMechanism::Exp(_) | SpfMechanism::UnknownModifier(_) => continue,
+            };
+            if matched {
+                return Ok(d.qualifier.into());
+            }
+        }
+
+        match record.redirect() {
+            Some(target) => {
+                if self.includes == 0 {
+                    self.redirected_to = Some(self.expand(target, domain)?);
+                }
+                match self.check_target(target, domain, depth)? {
+                    SpfEvaluationResult::None => Ok(SpfEvaluationResult::PermError),
+                    res => Ok(res),
+                }
+            }
+            None => Ok(SpfEvaluationResult::Neutral),
+        }
+    }
+
+    /// check_include evaluates record of domain pointed by `include`. Explanations of included records are never used.
+    fn check_include(&mut self, target: &DomainSpec, domain: Option<&str>, depth: u8) -> Result<SpfEvaluationResult, SpfEvaluationError> {
+        self.includes += 1;
+        let res = self.check_target(target, domain, depth);
+        self.includes -= 1;
+        res
+    }
+
+    /// check_target evaluates record of domain pointed by `include` or `redirect`.
+    fn check_target(&mut self, target: &DomainSpec, domain: Option<&str>, depth: u8) -> Result<SpfEvaluationResult, SpfEvaluationError> {
+        if depth >= self.limits.max_include_depth {
+            return Ok(SpfEvaluationResult::LimitExceeded(EvaluationLimit::IncludeDepth));
+        }
+        let target = self.expand(target, domain)?;
+        let bag = self.bag;
+        match (bag.record(&target), bag.record_failure(&target)) {
+            (Some(record), _) => self.check_host(record, Some(&target), depth + 1),
+            (None, Some(res)) => Ok(res),
+            (None, None) => Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::SPFFromDomain(Cow::Owned(target)))),
+        }
+    }
+
+    /// void_lookup counts lookup which returned no data and checks if there were too many of them.
+    fn void_lookup(&mut self) -> bool {
+        self.void_lookups += 1;
+        self.void_lookups > self.limits.max_void_lookups as u32
+    }
+
+    /// addresses returns addresses of given domain from resource bag.
+    fn addresses(&self, name: String) -> Result<&'b [IpAddr], SpfEvaluationError> {
+        match self.bag.addresses(&name) {
+            Some(addresses) => Ok(addresses),
+            None => Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::DomainAddresses(Cow::Owned(name)))),
+        }
+    }
+
+    /// target_domain expands domain-spec found in record of given domain. Without domain-spec it returns
+    /// domain of record, like `a`, `aaaa` and `mx` without domain use.
+    fn target_domain(&self, spec: Option<&DomainSpec>, domain: Option<&str>) -> Result<String, SpfEvaluationError> {
+        let validated_name = match spec {
+            Some(spec) if needs_validated_name(spec, &self.ctx) => Some(self.validated_name(domain)?),
+            _ => None,
+        };
+        let ctx = ScopedContext {
+            inner: &self.ctx,
+            domain,
+            validated_name,
+        };
+        Ok(expand_target(spec, ctx)?.into_owned())
+    }
+
+    /// validated_name returns value of `%{p}` in record of given domain.
+    fn validated_name(&self, domain: Option<&str>) -> Result<&'b str, SpfEvaluationError> {
+        let ctx = ScopedContext {
+            inner: &self.ctx,
+            domain,
+            validated_name: None,
+        };
+        let domain = expand_target(None, ctx)?;
+        match self.bag.validated_ptr_names(&domain) {
+            Some(names) => Ok(pick_validated_name(names, &domain)),
+            None => Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::ValidatedPtrDomain(Cow::Owned(domain.into_owned())))),
+        }
+    }
+
+    #[inline]
+    fn expand(&self, spec: &DomainSpec, domain: Option<&str>) -> Result<String, SpfEvaluationError> {
+        self.target_domain(Some(spec), domain)
+    }
+}
+
+impl<'a> SpfRecord<'a> {
+    /// evaluate checks whether `source_ip` is authorized to send mail by this record.
+    /// Records of included domains and existence of domains are taken from `bag`.
+    ///
+    /// Macros can't be expanded without evaluation context, so records using them have to be evaluated
+    /// with `evaluate_with_context`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// use spf::{ExternalResourceBag, SpfEvaluationResult, SpfRecord};
+    ///
+    /// let bag = ExternalResourceBag::default();
+    /// let record = SpfRecord::parse_str("v=spf1 ip4:192.0.2.0/24 -all").unwrap();
+    /// let res = record.evaluate(&bag, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))).unwrap();
+    /// assert_eq!(res, SpfEvaluationResult::Pass);
+    /// ```
+    pub fn evaluate(&self, bag: &ExternalResourceBag, source_ip: IpAddr) -> Result<SpfEvaluationResult, SpfEvaluationError> {
+        self.evaluate_with_context(bag, source_ip, HashMap::<MacroVariable, &str>::new())
+    }
+
+    /// evaluate_with_context checks whether `source_ip` is authorized to send mail by this record
+    /// and takes values of macro variables from `ctx`.
+    ///
+    /// Value of `%{d}` given by context is used only in this record. In included records it's
+    /// domain of included record, as RFC 7208 requires.
+    pub fn evaluate_with_context<E>(&self, bag: &ExternalResourceBag, source_ip: IpAddr, ctx: E) -> Result<SpfEvaluationResult, SpfEvaluationError>
+        where E: EvaluationContext
+    {
+        self.evaluate_with_limits(bag, source_ip, ctx, EvaluationLimits::default())
+    }
+
+    /// evaluate_with_limits works just like `evaluate_with_context` but uses given processing limits
+    /// instead of ones recommended by RFC.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// use spf::{EvaluationLimit, EvaluationLimits, ExternalResourceBag, MacroVariable, SpfEvaluationResult, SpfRecord};
+    ///
+    /// let record = SpfRecord::parse_str("v=spf1 a mx -all").unwrap();
+    /// let limits = EvaluationLimits {
+    ///     max_dns_mechanisms: 1,
+    ///     ..EvaluationLimits::default()
+    /// };
+    /// let ctx: HashMap<_, _> = vec![(MacroVariable::Domain, "example.com")].into_iter().collect();
+    /// let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    /// let res = record.evaluate_with_limits(&ExternalResourceBag::default(), ip, &ctx, limits).unwrap();
+    /// assert_eq!(res, SpfEvaluationResult::LimitExceeded(EvaluationLimit::DnsMechanisms));
+    /// ```
+    pub fn evaluate_with_limits<E>(&self, bag: &ExternalResourceBag, source_ip: IpAddr, ctx: E, limits: EvaluationLimits) -> Result<SpfEvaluationResult, SpfEvaluationError>
+        where E: EvaluationContext
+    {
+        self.evaluate_tracking_redirects(bag, source_ip, ctx, limits).map(|(res, _)| res)
+    }
+
+    /// evaluate_tracking_redirects works like `evaluate_with_limits`, but also returns domain of record reached with
+    /// last `redirect` outside of `include`. It's `None` when result comes from this record.
+    pub(crate) fn evaluate_tracking_redirects<E>(&self, bag: &ExternalResourceBag, source_ip: IpAddr, ctx: E, limits: EvaluationLimits) -> Result<(SpfEvaluationResult, Option<String>), SpfEvaluationError>
+        where E: EvaluationContext
+    {
+        let mut evaluator = Evaluator {
+            bag,
+            source_ip,
+            ctx,
+            limits,
+            dns_mechanisms: 0,
+            void_lookups: 0,
+            includes: 0,
+            redirected_to: None,
+        };
+        let res = evaluator.check_host(self, None, 0)?;
+        Ok((res, evaluator.redirected_to))
+    }
+
+    /// explanation_domain returns expanded domain-spec of `exp` modifier of this record or `None` if there is no `exp`.
+    /// TXT record of that domain is explanation string, which should be expanded with `evaluate_explanation`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use spf::{MacroVariable, SpfRecord};
+    ///
+    /// let record = SpfRecord::parse_str("v=spf1 -all exp=explain._spf.%{d}").unwrap();
+    /// let ctx: HashMap<_, _> = vec![(MacroVariable::Domain, "example.com")].into_iter().collect();
+    /// assert_eq!(record.explanation_domain(&ctx).unwrap().unwrap(), "explain._spf.example.com");
+    /// ```
+    pub fn explanation_domain<E>(&self, ctx: E) -> Option<Result<String, MacroEvaluationError>>
+        where E: EvaluationContext
+    {
+        let spec = self.exp()?;
+        Some(expand_target(Some(spec), ctx).map(Cow::into_owned))
+    }
+
+    /// required_resources returns resources, which evaluation of this record may need, in order of directives
+    /// which need them. Each resource is returned once.
+    ///
+    /// Domain-specs are expanded with `ctx`, which also has to provide `%{d}` when record contains `a`, `aaaa`
+    /// or `mx` without domain. Only resources needed by this record itself are returned. Ones needed by records
+    /// pointed by `include` and `redirect` and addresses of MX hosts are known once these are fetched.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use spf::{ExternalResourceIdentifier, MacroVariable, SpfRecord};
+    ///
+    /// let record = SpfRecord::parse_str("v=spf1 a include:_spf.example.org -all").unwrap();
+    /// let ctx: HashMap<_, _> = vec![(MacroVariable::Domain, "example.com")].into_iter().collect();
+    /// let resources = record.required_resources(&ctx).unwrap();
+    /// assert_eq!(resources, vec![
+    ///     ExternalResourceIdentifier::DomainAddresses("example.com".into()),
+    ///     ExternalResourceIdentifier::SPFFromDomain("_spf.example.org".into()),
+    /// ]);
+    /// ```
+    pub fn required_resources<E>(&self, ctx: E) -> Result<Vec<ExternalResourceIdentifier<'_>>, MacroEvaluationError>
+        where E: EvaluationContext
+    {
+        let mut res = Vec::new();
+        for d in self.directives.iter() {
+            let needs_validated_name = match &d.mechanism {
+                SpfMechanism::Exp(_) => false,
+                m => m.target().is_some_and(|spec| needs_validated_name(spec, &ctx)),
+            };
+            let resource = match &d.mechanism {
+                // domain-spec using %{p} can't be expanded before validated name is known
+                _ if needs_validated_name => ExternalResourceIdentifier::ValidatedPtrDomain(expand_target(None, &ctx)?),
+                SpfMechanism::A(spec, _) | SpfMechanism::AAAA(spec, _) => {
+                    ExternalResourceIdentifier::DomainAddresses(expand_target(spec.as_ref(), &ctx)?)
+                }
+                SpfMechanism::MX(spec, _) => ExternalResourceIdentifier::MxHosts(expand_target(spec.as_ref(), &ctx)?),
+                SpfMechanism::Include(spec) | SpfMechanism::Redirect(spec) => {
+                    ExternalResourceIdentifier::SPFFromDomain(expand_target(Some(spec), &ctx)?)
+                }
+                SpfMechanism::Exists(spec) => exists_identifier(expand_target(Some(spec), &ctx)?.into_owned()),
+                SpfMechanism::Ptr(spec) => ExternalResourceIdentifier::ValidatedPtrDomain(expand_target(spec.as_ref(), &ctx)?),
+                SpfMechanism::Ipv4(_) | SpfMechanism::Ipv6(_) | SpfMechanism::Exp(_) | SpfMechanism::UnknownModifier(_) |
+                SpfMechanism::All => continue,
+            };
+            if !res.contains(&resource) {
+                res.push(resource);
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// evaluate_explanation expands text of TXT record pointed by `exp` modifier. Unlike domain-specs
+/// explanation string may use `c`, `r` and `t` variables.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-6.2) section `6.2`
+#[inline]
+pub fn evaluate_explanation<E>(exp_txt: &str, ctx: E) -> Result<String, MacroEvaluationError>
+    where E: EvaluationContext
+{
+    evaluate_exp_macro(ctx, exp_txt)
+}
+
+/// expand_target expands domain-spec or returns value of `%{d}` if there is none. Trailing dot is removed.
+fn expand_target<'s, E>(spec: Option<&'s DomainSpec>, ctx: E) -> Result<Cow<'s, str>, MacroEvaluationError>
+    where E: EvaluationContext
+{
+    match spec {
+        Some(spec) => Ok(match spec.expand_with_context(ctx)? {
+            Cow::Borrowed(name) => Cow::Borrowed(name.trim_end_matches('.')),
+            Cow::Owned(name) => Cow::Owned(name.trim_end_matches('.').to_string()),
+        }),
+        None => Ok(Cow::Owned(ctx.provide_data(MacroVariable::Domain)?.trim_end_matches('.').to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::spf::{DomainInterner, InternedDomain, SpfDirective};
+
+    use super::*;
+
+    fn bag(records: &[(&str, &'static str)]) -> ExternalResourceBag<'static> {
+        ExternalResourceBag {
+            domain_record_map: records.iter()
+                .map(|(domain, text)| (InternedDomain::new(domain), SpfRecord::parse_str(text).unwrap()))
+                .collect(),
+            ..ExternalResourceBag::default()
+        }
+    }
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    #[test]
+    fn test_ip4_pass_and_fail() {
+        let bag = bag(&[]);
+        let record = SpfRecord::parse_str("v=spf1 ip4:192.0.2.0/24 -all").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 255)).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 3, 1)).unwrap(), SpfEvaluationResult::Fail);
+        assert_eq!(record.evaluate(&bag, IpAddr::V6(Ipv6Addr::LOCALHOST)).unwrap(), SpfEvaluationResult::Fail);
+
+        let record = SpfRecord::parse_str("v=spf1 ip6:2001:db8::/32").unwrap();
+        assert_eq!(record.evaluate(&bag, "2001:db8::1".parse().unwrap()).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::Neutral);
+    }
+
+    #[test]
+    fn test_include_which_fails() {
+        let mut bag = bag(&[("_spf.example.org", "v=spf1 ip4:198.51.100.0/24 -all")]);
+        // parser rejects second redirect, so invalid record is built by hand
+        let invalid = SpfRecord::from(vec![
+            SpfDirective::from_mechanism(SpfMechanism::redirect("a.example.org").unwrap()),
+            SpfDirective::from_mechanism(SpfMechanism::redirect("b.example.org").unwrap()),
+        ]);
+        bag.domain_record_map.insert(InternedDomain::new("invalid.example.org"), invalid);
+        let record = SpfRecord::parse_str("v=spf1 include:_spf.example.org ~all").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(198, 51, 100, 7)).unwrap(), SpfEvaluationResult::Pass);
+        // fail of included record only means that include did not match
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::SoftFail);
+
+        let record = SpfRecord::parse_str("v=spf1 include:invalid.example.org ~all").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::PermError);
+    }
+
+    #[test]
+    fn test_missing_resources() {
+        let mut bag = bag(&[]);
+        let record = SpfRecord::parse_str("v=spf1 include:_spf.Example.org. exists:%{d}.list.example -all").unwrap();
+        match record.evaluate(&bag, v4(192, 0, 2, 1)) {
+            Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::SPFFromDomain(d))) => assert_eq!(d, "_spf.Example.org"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        bag.domain_record_map.insert(InternedDomain::new("_spf.example.org"), SpfRecord::parse_str("v=spf1 -all").unwrap());
+        let ctx = vec![(MacroVariable::Domain, "example.com")].into_iter().collect::<HashMap<_, _>>();
+        match record.evaluate_with_context(&bag, v4(192, 0, 2, 1), &ctx) {
+            Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::DomainExists(p1, p2))) => {
+                assert_eq!((p1.as_ref(), p2.as_ref()), ("example", "com.list.example"));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(matches!(record.evaluate(&bag, v4(192, 0, 2, 1)), Err(SpfEvaluationError::Macro(_))));
+
+        bag.existence_map.insert(InternedDomain::new("example.com.list.example"), true);
+        assert_eq!(record.evaluate_with_context(&bag, v4(192, 0, 2, 1), &ctx).unwrap(), SpfEvaluationResult::Pass);
+    }
+
+    #[test]
+    fn test_redirect() {
+        let mut bag = bag(&[
+            ("_spf.example.com", "v=spf1 ip4:192.0.2.0/24 exists:%{d} -all"),
+            ("loop.example.com", "v=spf1 include:loop.example.com"),
+        ]);
+        // %{d} of redirected record is its own domain, not domain of record which redirects
+        bag.existence_map.insert(InternedDomain::new("_spf.example.com"), false);
+
+        let record = SpfRecord::parse_str("v=spf1 redirect=_spf.example.com").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(record.evaluate(&bag, v4(198, 51, 100, 1)).unwrap(), SpfEvaluationResult::Fail);
+
+        // redirect is ignored when any mechanism matched
+        let record = SpfRecord::parse_str("v=spf1 ?ip4:198.51.100.1 redirect=_spf.example.com").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(198, 51, 100, 1)).unwrap(), SpfEvaluationResult::Neutral);
+
+        let record = SpfRecord::parse_str("v=spf1 redirect=loop.example.com").unwrap();
+        assert!(matches!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::LimitExceeded(_)));
+    }
+
+    #[test]
+    fn test_mutual_includes_terminate() {
+        let bag = bag(&[
+            ("example.com", "v=spf1 include:example.org -all"),
+            ("example.org", "v=spf1 ip4:192.0.2.0/24 include:example.com ~all"),
+        ]);
+        let record = SpfRecord::parse_str("v=spf1 include:example.com").unwrap();
+        assert_eq!(
+            record.evaluate(&bag, v4(198, 51, 100, 1)).unwrap(),
+            SpfEvaluationResult::LimitExceeded(EvaluationLimit::DnsMechanisms)
+        );
+
+        let limits = EvaluationLimits {
+            max_dns_mechanisms: u8::MAX,
+            ..EvaluationLimits::default()
+        };
+        assert_eq!(
+            record.evaluate_with_limits(&bag, v4(198, 51, 100, 1), HashMap::<MacroVariable, &str>::new(), limits).unwrap(),
+            SpfEvaluationResult::LimitExceeded(EvaluationLimit::IncludeDepth)
+        );
+        // limit is hit only if evaluation gets that deep
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::Pass);
+    }
+
+    #[test]
+    fn test_dns_mechanisms_limit() {
+        let mut bag = bag(&[]);
+        let text = format!("v=spf1 {} -all", (0..11).map(|i| format!("include:_spf{}.example.com", i)).collect::<Vec<_>>().join(" "));
+        for i in 0..11 {
+            bag.domain_record_map.insert(InternedDomain::new(&format!("_spf{}.example.com", i)), SpfRecord::parse_str("v=spf1 +all").unwrap());
+        }
+        let record = SpfRecord::parse_str(&text).unwrap();
+        assert_eq!(
+            record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(),
+            SpfEvaluationResult::LimitExceeded(EvaluationLimit::DnsMechanisms)
+        );
+
+        // ten of them are fine, but lookups of included records count as well
+        let text = text.replacen(" include:_spf10.example.com", "", 1);
+        let record = SpfRecord::parse_str(&text).unwrap();
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::Pass);
+        bag.domain_record_map.insert(InternedDomain::new("_spf0.example.com"), SpfRecord::parse_str("v=spf1 a:example.com +all").unwrap());
+        assert_eq!(
+            record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(),
+            SpfEvaluationResult::LimitExceeded(EvaluationLimit::DnsMechanisms)
+        );
+    }
+
+    #[test]
+    fn test_void_lookups_limit() {
+        let interner = DomainInterner::new();
+        let mut bag = bag(&[]);
+        bag.insert_existence(&interner, "a.example.com", false);
+        bag.insert_existence(&interner, "b.example.com", false);
+        bag.insert_addresses(&interner, "example.com", vec![]);
+
+        let record = SpfRecord::parse_str("v=spf1 exists:a.example.com exists:b.example.com -all").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::Fail);
+        let record = SpfRecord::parse_str("v=spf1 exists:a.example.com exists:b.example.com a:example.com -all").unwrap();
+        assert_eq!(
+            record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(),
+            SpfEvaluationResult::LimitExceeded(EvaluationLimit::VoidLookups)
+        );
+    }
+
+    #[test]
+    fn test_address_mechanisms() {
+        let interner = DomainInterner::new();
+        let mut bag = bag(&[]);
+        bag.insert_addresses(&interner, "example.com", vec![v4(192, 0, 2, 10), "2001:db8::10".parse().unwrap()]);
+        bag.insert_addresses(&interner, "mail.example.org", vec![v4(198, 51, 100, 1)]);
+        bag.insert_mx_hosts(&interner, "example.org", vec!["mail.example.org", "backup.example.org"]);
+        let ctx = vec![(MacroVariable::Domain, "example.com")].into_iter().collect::<HashMap<_, _>>();
+
+        let record = SpfRecord::parse_str("v=spf1 a/24//64 -all").unwrap();
+        assert_eq!(record.evaluate_with_context(&bag, v4(192, 0, 2, 200), &ctx).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(record.evaluate_with_context(&bag, "2001:db8::1:0".parse().unwrap(), &ctx).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(record.evaluate_with_context(&bag, v4(192, 0, 3, 10), &ctx).unwrap(), SpfEvaluationResult::Fail);
+        assert!(matches!(record.evaluate(&bag, v4(192, 0, 2, 10)), Err(SpfEvaluationError::Macro(_))));
+
+        // hosts are checked in order, so first one matching is enough
+        let record = SpfRecord::parse_str("v=spf1 mx:example.org -all").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(198, 51, 100, 1)).unwrap(), SpfEvaluationResult::Pass);
+        match record.evaluate(&bag, v4(192, 0, 2, 1)) {
+            Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::DomainAddresses(d))) => assert_eq!(d, "backup.example.org"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        bag.insert_addresses(&interner, "backup.example.org", vec![]);
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::Fail);
+
+        let hosts = (0..=MAX_MX_HOSTS).map(|i| format!("mx{}.example.net", i)).collect::<Vec<_>>();
+        bag.insert_mx_hosts(&interner, "example.net", hosts.iter().map(String::as_str));
+        let record = SpfRecord::parse_str("v=spf1 mx:example.net -all").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::PermError);
+    }
+
+    #[test]
+    fn test_ptr_and_validated_name() {
+        let interner = DomainInterner::new();
+        let mut bag = bag(&[]);
+        bag.insert_validated_ptr_names(&interner, "example.com", vec!["other.org", "mail.example.com"]);
+        bag.insert_validated_ptr_names(&interner, "example.net", vec![]);
+        bag.existence_map.insert(InternedDomain::new("mail.example.com.list.example"), true);
+        let ctx = vec![(MacroVariable::Domain, "example.com")].into_iter().collect::<HashMap<_, _>>();
+
+        let record = SpfRecord::parse_str("v=spf1 ptr -all").unwrap();
+        assert_eq!(record.evaluate_with_context(&bag, v4(192, 0, 2, 1), &ctx).unwrap(), SpfEvaluationResult::Pass);
+        let record = SpfRecord::parse_str("v=spf1 ptr:example.net -all").unwrap();
+        assert_eq!(record.evaluate(&bag, v4(192, 0, 2, 1)).unwrap(), SpfEvaluationResult::Fail);
+        let record = SpfRecord::parse_str("v=spf1 ptr:Other.org -all").unwrap();
+        match record.evaluate(&bag, v4(192, 0, 2, 1)) {
+            Err(SpfEvaluationError::MissingResource(ExternalResourceIdentifier::ValidatedPtrDomain(d))) => assert_eq!(d, "Other.org"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // name in domain of record is preferred over other names
+        let record = SpfRecord::parse_str("v=spf1 exists:%{p}.list.example -all").unwrap();
+        assert_eq!(record.evaluate_with_context(&bag, v4(192, 0, 2, 1), &ctx).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(
+            record.required_resources(&ctx).unwrap(),
+            vec![ExternalResourceIdentifier::ValidatedPtrDomain(Cow::Borrowed("example.com"))]
+        );
+
+        assert_eq!(pick_validated_name(&[], "example.com"), "unknown");
+        let names = vec![InternedDomain::new("a.example.org"), InternedDomain::new("example.com.")];
+        assert_eq!(pick_validated_name(&names, "example.com"), "example.com.");
+        assert_eq!(pick_validated_name(&names, "example.net"), "a.example.org");
+    }
+
+    #[test]
+    fn test_required_resources() {
+        let record = SpfRecord::parse_str("v=spf1 mx a:mail.example.org include:_spf.other.org exists:%{i}.rbl.example -all").unwrap();
+        let ctx = vec![
+            (MacroVariable::Domain, "example.com"),
+            (MacroVariable::Ip, "192.0.2.1"),
+        ].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(record.required_resources(&ctx).unwrap(), vec![
+            ExternalResourceIdentifier::MxHosts(Cow::Borrowed("example.com")),
+            ExternalResourceIdentifier::DomainAddresses(Cow::Borrowed("mail.example.org")),
+            ExternalResourceIdentifier::SPFFromDomain(Cow::Borrowed("_spf.other.org")),
+            ExternalResourceIdentifier::DomainExists(Cow::Borrowed("192"), Cow::Borrowed("0.2.1.rbl.example")),
+        ]);
+
+        // literal domains are borrowed from record
+        match &record.required_resources(&ctx).unwrap()[1] {
+            ExternalResourceIdentifier::DomainAddresses(Cow::Borrowed(_)) => {}
+            r => panic!("unexpected resource: {:?}", r),
+        }
+
+        // without %{d} neither mx nor macros can be expanded
+        assert!(record.required_resources(HashMap::<MacroVariable, &str>::new()).is_err());
+
+        let record = SpfRecord::parse_str("v=spf1 a a:Example.com. mx:example.com ip4:192.0.2.1 redirect=example.com exp=exp.example.com").unwrap();
+        assert_eq!(record.required_resources(&ctx).unwrap(), vec![
+            ExternalResourceIdentifier::DomainAddresses(Cow::Borrowed("example.com")),
+            ExternalResourceIdentifier::DomainAddresses(Cow::Borrowed("Example.com")),
+            ExternalResourceIdentifier::MxHosts(Cow::Borrowed("example.com")),
+            ExternalResourceIdentifier::SPFFromDomain(Cow::Borrowed("example.com")),
+        ]);
+    }
+}
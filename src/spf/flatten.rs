@@ -0,0 +1,391 @@
+//! Module with `SpfRecord::flatten`, which inlines included records, so that record needs fewer DNS lookups.
+//!
+//! Flattening keeps result of evaluation for every address as long as records in bag are up to date.
+//! Directives which can't be inlined without changing result, like `-ip4` inside included record, make it fail.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::spf::{
+    DomainSpec, ExternalResourceBag, MacroToken, MacroVariable, SpfAction, SpfDirective, SpfEvaluationResult,
+    SpfMechanism, SpfRecord,
+};
+
+/// FlattenError is returned when record can't be flattened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FlattenError {
+    /// MissingRecord is returned when bag contains no record of included or redirected domain.
+    MissingRecord {
+        domain: String,
+    },
+
+    /// FailedRecord is returned when record of included or redirected domain can't be evaluated,
+    /// for instance because there is no such record or it's invalid. Result is one evaluation would give.
+    FailedRecord {
+        domain: String,
+        result: SpfEvaluationResult,
+    },
+
+    /// Loop is returned when chain of `include` and `redirect` reaches domain, which is already being flattened.
+    Loop {
+        domain: String,
+    },
+
+    /// UnsupportedDirective is returned when directive at given index of record of given domain can't be inlined
+    /// without changing result: it's mechanism other than `all` with qualifier other than `Pass` in included record,
+    /// `include` or `redirect` target which has to be expanded or domain-spec which uses `%{d}` or `%{p}`.
+    /// Domain is empty for flattened record itself.
+    UnsupportedDirective {
+        domain: String,
+        index: usize,
+    },
+}
+
+impl fmt::Display for FlattenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlattenError::MissingRecord { domain } => write!(f, "record of {} is missing", domain),
+            FlattenError::FailedRecord { domain, result } => write!(f, "record of {} results in {:?}", domain, result),
+            FlattenError::Loop { domain } => write!(f, "{} includes itself", domain),
+            FlattenError::UnsupportedDirective { domain, index } => {
+                write!(f, "directive {} of record of {:?} can't be inlined", index, domain)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlattenError {}
+
+/// Mode describes how record reached during flattening is inlined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    /// Top is flattened record itself. Its directives are kept as they are.
+    Top,
+
+    /// Redirected is record reached with `redirect` from top record. Its result becomes result of top record.
+    Redirected,
+
+    /// Included is record reached with `include` with given qualifier. Its `Pass` mechanisms get that qualifier.
+    Included(SpfAction),
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// uses_domain checks if domain-spec uses `%{d}` or `%{p}`, which change their meaning once directive is moved to other record.
+fn uses_domain(spec: &DomainSpec) -> bool {
+    match spec.macro_string() {
+        Ok(m) => m.tokens().iter().any(|t| {
+            matches!(t, MacroToken::Expansion(e) if matches!(e.variable, MacroVariable::Domain | MacroVariable::ValidatedDomainNameOrIp))
+        }),
+        Err(_) => true,
+    }
+}
+
+struct Flattener<'b, 'r> {
+    bag: &'b ExternalResourceBag<'r>,
+    directives: Vec<SpfDirective<'static>>,
+    ips: HashSet<SpfMechanism<'static>>,
+
+    /// visiting contains domains of records, which are being flattened.
+    visiting: Vec<String>,
+
+    /// reached_all is true once `all` is inlined. Mechanisms after it are never evaluated, so they are dropped.
+    reached_all: bool,
+
+    /// exp is `exp` modifier of last record of redirect chain.
+    exp: Option<SpfDirective<'static>>,
+}
+
+impl<'b, 'r> Flattener<'b, 'r> {
+    fn unsupported(domain: Option<&str>, index: usize) -> FlattenError {
+        FlattenError::UnsupportedDirective {
+            domain: domain.unwrap_or_default().to_string(),
+            index,
+        }
+    }
+
+    /// relocate returns mechanism of record of given domain, which means the same in other record.
+    fn relocate(mechanism: &SpfMechanism, domain: Option<&str>, index: usize) -> Result<SpfMechanism<'static>, FlattenError> {
+        let domain = match domain {
+            Some(domain) => domain,
+            None => return Ok(mechanism.clone().into_owned()),
+        };
+        if mechanism.target().is_some_and(uses_domain) {
+            return Err(Self::unsupported(Some(domain), index));
+        }
+        let current = || DomainSpec::new(domain.to_string()).map_err(|_| Self::unsupported(Some(domain), index));
+        Ok(match mechanism {
+            SpfMechanism::A(None, cidr) => SpfMechanism::A(Some(current()?), *cidr),
+            SpfMechanism::AAAA(None, cidr) => SpfMechanism::AAAA(Some(current()?), *cidr),
+            SpfMechanism::MX(None, cidr) => SpfMechanism::MX(Some(current()?), *cidr),
+            SpfMechanism::Ptr(None) => SpfMechanism::Ptr(Some(current()?)),
+            m => m.clone().into_owned(),
+        })
+    }
+
+    fn push(&mut self, qualifier: SpfAction, mechanism: SpfMechanism<'static>) {
+        if self.reached_all {
+            return;
+        }
+        if let SpfMechanism::Ipv4(_) | SpfMechanism::Ipv6(_) = mechanism {
+            // later mechanism with same network is never reached, whatever its qualifier is
+            if !self.ips.insert(mechanism.clone()) {
+                return;
+            }
+        }
+        self.reached_all = mechanism.is_all();
+        self.directives.push(SpfDirective {
+            qualifier,
+            explicit_qualifier: qualifier != SpfAction::Pass,
+            mechanism,
+        });
+    }
+
+    /// enter flattens record of domain, which is target of `include` or `redirect` at given index.
+    fn enter(&mut self, target: &DomainSpec, domain: Option<&str>, index: usize, mode: Mode) -> Result<(), FlattenError> {
+        let target = target.as_literal().ok_or_else(|| Self::unsupported(domain, index))?;
+        let target = normalize_domain(target);
+        if self.visiting.contains(&target) {
+            return Err(FlattenError::Loop { domain: target });
+        }
+        let bag = self.bag;
+        let record = match (bag.record(&target), bag.record_failure(&target)) {
+            (Some(record), _) if record.validate().is_ok() => record,
+            (Some(_), _) => return Err(FlattenError::FailedRecord { domain: target, result: SpfEvaluationResult::PermError }),
+            (None, Some(result)) => return Err(FlattenError::FailedRecord { domain: target, result }),
+            (None, None) => return Err(FlattenError::MissingRecord { domain: target }),
+        };
+        self.visiting.push(target.clone());
+        let res = self.flatten_record(record, Some(&target), mode);
+        self.visiting.pop();
+        res
+    }
+
+    fn flatten_record(&mut self, record: &SpfRecord, domain: Option<&str>, mode: Mode) -> Result<(), FlattenError> {
+        let has_all = record.directives.iter().any(|d| d.mechanism.is_all());
+        let mut redirect = None;
+        for (index, d) in record.directives.iter().enumerate() {
+            let qualifier = match mode {
+                Mode::Included(_) if d.qualifier != SpfAction::Pass && !d.mechanism.is_all() => {
+                    return Err(Self::unsupported(domain, index));
+                }
+                Mode::Included(qualifier) => qualifier,
+                Mode::Top | Mode::Redirected => d.qualifier,
+            };
+            match &d.mechanism {
+                SpfMechanism::Include(target) => {
+                    if !self.reached_all {
+                        self.enter(target, domain, index, Mode::Included(qualifier))?;
+                    }
+                }
+                // redirect is ignored when record has `all`
+                SpfMechanism::Redirect(target) if !has_all => redirect = Some((index, target)),
+                SpfMechanism::Redirect(_) => {}
+                SpfMechanism::Exp(_) if matches!(mode, Mode::Included(_)) => {}
+                SpfMechanism::Exp(_) => self.exp = Some(Self::relocate(&d.mechanism, domain, index)?.into()),
+                SpfMechanism::UnknownModifier(_) => {
+                    if mode == Mode::Top {
+                        self.directives.push(d.clone().into_owned());
+                    }
+                }
+                // failing `all` of included record just makes include not match
+                SpfMechanism::All if d.qualifier != SpfAction::Pass && matches!(mode, Mode::Included(_)) => {
+                    return Ok(());
+                }
+                m => {
+                    let m = Self::relocate(m, domain, index)?;
+                    self.push(qualifier, m);
+                }
+            }
+        }
+
+        if let Some((index, target)) = redirect {
+            if !self.reached_all {
+                let mode = match mode {
+                    Mode::Top | Mode::Redirected => {
+                        // exp of record, which redirects, is never used
+                        self.exp = None;
+                        Mode::Redirected
+                    }
+                    mode => mode,
+                };
+                self.enter(target, domain, index, mode)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> SpfRecord<'a> {
+    /// flatten creates record, which gives same results as this one, but has `include` and `redirect` replaced
+    /// with directives of records they point at, which are taken from bag.
+    ///
+    /// Mechanisms of included records get qualifier of `include` and mechanisms without domain, like `mx`,
+    /// get domain of included record. Failing `all`, `exp` and `redirect` of included records are dropped
+    /// and mechanisms after `all` are dropped, since they are never evaluated. Repeated `ip4` and `ip6`
+    /// mechanisms are dropped too.
+    ///
+    /// # Example
+    /// ```
+    /// use spf::{DomainInterner, ExternalResourceBag, SpfRecord};
+    ///
+    /// let interner = DomainInterner::new();
+    /// let mut bag = ExternalResourceBag::default();
+    /// bag.insert_record(&interner, "_spf.example.com", SpfRecord::parse_str("v=spf1 ip4:192.0.2.0/24 ~all").unwrap());
+    ///
+    /// let record = SpfRecord::parse_str("v=spf1 mx include:_spf.example.com -all").unwrap();
+    /// assert_eq!(record.flatten(&bag).unwrap().to_string(), "v=spf1 mx ip4:192.0.2.0/24 -all");
+    /// ```
+    pub fn flatten(&self, bag: &ExternalResourceBag) -> Result<SpfRecord<'static>, FlattenError> {
+        let mut f = Flattener {
+            bag,
+            directives: Vec::new(),
+            ips: HashSet::new(),
+            visiting: Vec::new(),
+            reached_all: false,
+            exp: None,
+        };
+        f.flatten_record(self, None, Mode::Top)?;
+        let mut directives = f.directives;
+        directives.extend(f.exp);
+        Ok(directives.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::spf::DomainInterner;
+
+    use super::*;
+
+    fn bag(records: &[(&str, &'static str)]) -> ExternalResourceBag<'static> {
+        let interner = DomainInterner::new();
+        let mut bag = ExternalResourceBag::default();
+        for (domain, text) in records.iter() {
+            bag.insert_record(&interner, domain, SpfRecord::parse_str(text).unwrap());
+        }
+        bag
+    }
+
+    fn flatten(bag: &ExternalResourceBag, text: &str) -> Result<String, FlattenError> {
+        SpfRecord::parse_str(text).unwrap().flatten(bag).map(|r| r.to_string())
+    }
+
+    #[test]
+    fn test_nested_includes() {
+        let bag = bag(&[
+            ("_spf.example.com", "v=spf1 ip4:192.0.2.0/24 include:_spf1.example.net include:_spf2.example.net ~all"),
+            ("_spf1.example.net", "v=spf1 ip4:198.51.100.0/24 ip4:192.0.2.0/24 -all"),
+            ("_spf2.example.net", "v=spf1 ip4:203.0.113.0/24 ip6:2001:db8::/32 ?all exp=explain.example.net"),
+        ]);
+        let text = "v=spf1 include:_spf.example.com -all";
+        let flat = flatten(&bag, text).unwrap();
+        assert_eq!(flat, "v=spf1 ip4:192.0.2.0/24 ip4:198.51.100.0/24 ip4:203.0.113.0/24 ip6:2001:db8::/32 -all");
+
+        let record = SpfRecord::parse_str(text).unwrap();
+        let flat = SpfRecord::parse_str(&flat).unwrap();
+        for ip in [[192, 0, 2, 1], [198, 51, 100, 7], [203, 0, 113, 200], [10, 0, 0, 1]].iter() {
+            let ip = IpAddr::from(Ipv4Addr::from(*ip));
+            assert_eq!(record.evaluate(&bag, ip).unwrap(), flat.evaluate(&bag, ip).unwrap(), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_qualifiers_and_domains() {
+        let bag = bag(&[
+            ("a.example.com", "v=spf1 a mx/24 ptr ip4:192.0.2.1 -all"),
+            ("all.example.com", "v=spf1 +all"),
+            ("redirect.example.com", "v=spf1 ip4:192.0.2.2 redirect=a.example.com"),
+        ]);
+        assert_eq!(
+            flatten(&bag, "v=spf1 ~include:a.example.com -all exp=exp.%{d}").unwrap(),
+            "v=spf1 ~a:a.example.com ~mx:a.example.com/24 ~ptr:a.example.com ~ip4:192.0.2.1 -all exp=exp.%{d}"
+        );
+        // passing all of included record matches everything, so nothing after it is reached
+        assert_eq!(flatten(&bag, "v=spf1 -include:all.example.com ip4:192.0.2.3 ~all").unwrap(), "v=spf1 -all");
+        // redirect of included record is followed as part of that record
+        assert_eq!(
+            flatten(&bag, "v=spf1 include:redirect.example.com ip4:192.0.2.1 ~all").unwrap(),
+            "v=spf1 ip4:192.0.2.2 a:a.example.com mx:a.example.com/24 ptr:a.example.com ip4:192.0.2.1 ~all"
+        );
+    }
+
+    #[test]
+    fn test_redirect() {
+        let bag = bag(&[
+            ("_spf.example.com", "v=spf1 mx -ip4:192.0.2.0/24 redirect=_spf.example.org exp=exp.example.com"),
+            ("_spf.example.org", "v=spf1 ip4:192.0.2.0/24 ~all exp=exp.example.org"),
+            ("_spf.example.net", "v=spf1 ip4:198.51.100.0/24 ~all exp=exp.%{d}"),
+        ]);
+        // qualifiers of redirected records are kept and exp of target is used
+        assert_eq!(
+            flatten(&bag, "v=spf1 a redirect=_spf.example.com exp=top.example.com").unwrap(),
+            "v=spf1 a mx:_spf.example.com -ip4:192.0.2.0/24 ~all exp=exp.example.org"
+        );
+        // exp of target can't be moved when it depends on domain of target
+        assert_eq!(
+            flatten(&bag, "v=spf1 redirect=_spf.example.net exp=top.example.com"),
+            Err(FlattenError::UnsupportedDirective { domain: "_spf.example.net".to_string(), index: 2 })
+        );
+        assert_eq!(flatten(&bag, "v=spf1 -all exp=exp.%{d}").unwrap(), "v=spf1 -all exp=exp.%{d}");
+        // redirect is ignored when record has all
+        assert_eq!(flatten(&bag, "v=spf1 redirect=missing.example.com ?all").unwrap(), "v=spf1 ?all");
+    }
+
+    #[test]
+    fn test_errors() {
+        let interner = DomainInterner::new();
+        let mut bag = bag(&[
+            ("loop1.example.com", "v=spf1 include:loop2.example.com -all"),
+            ("loop2.example.com", "v=spf1 include:LOOP1.example.com. -all"),
+            ("self.example.com", "v=spf1 redirect=self.example.com"),
+            ("fail.example.com", "v=spf1 ip4:192.0.2.0/24 -ip4:192.0.2.1 -all"),
+            ("macro.example.com", "v=spf1 a:%{d}.example.net -all"),
+            ("ptr.example.com", "v=spf1 exists:%{p}.example.net -all"),
+        ]);
+        bag.insert_record_failure(&interner, "none.example.com", SpfEvaluationResult::None);
+        // parser rejects second redirect, so invalid record is built by hand
+        let invalid = SpfRecord::from(vec![
+            SpfDirective::from_mechanism(SpfMechanism::redirect("a.example.com").unwrap()),
+            SpfDirective::from_mechanism(SpfMechanism::redirect("b.example.com").unwrap()),
+        ]);
+        bag.insert_record(&interner, "invalid.example.com", invalid);
+
+        assert_eq!(flatten(&bag, "v=spf1 include:loop1.example.com -all"), Err(FlattenError::Loop { domain: "loop1.example.com".to_string() }));
+        assert_eq!(flatten(&bag, "v=spf1 redirect=self.example.com"), Err(FlattenError::Loop { domain: "self.example.com".to_string() }));
+        assert_eq!(flatten(&bag, "v=spf1 include:missing.example.com -all"), Err(FlattenError::MissingRecord { domain: "missing.example.com".to_string() }));
+        assert_eq!(
+            flatten(&bag, "v=spf1 include:none.example.com -all"),
+            Err(FlattenError::FailedRecord { domain: "none.example.com".to_string(), result: SpfEvaluationResult::None })
+        );
+        assert_eq!(
+            flatten(&bag, "v=spf1 include:invalid.example.com -all"),
+            Err(FlattenError::FailedRecord { domain: "invalid.example.com".to_string(), result: SpfEvaluationResult::PermError })
+        );
+        assert_eq!(
+            flatten(&bag, "v=spf1 include:fail.example.com -all"),
+            Err(FlattenError::UnsupportedDirective { domain: "fail.example.com".to_string(), index: 1 })
+        );
+        assert_eq!(
+            flatten(&bag, "v=spf1 include:macro.example.com -all"),
+            Err(FlattenError::UnsupportedDirective { domain: "macro.example.com".to_string(), index: 0 })
+        );
+        assert_eq!(
+            flatten(&bag, "v=spf1 include:ptr.example.com -all"),
+            Err(FlattenError::UnsupportedDirective { domain: "ptr.example.com".to_string(), index: 0 })
+        );
+        assert_eq!(
+            flatten(&bag, "v=spf1 mx include:%{d}._spf.example.com -all"),
+            Err(FlattenError::UnsupportedDirective { domain: String::new(), index: 1 })
+        );
+        assert_eq!(
+            flatten(&bag, "v=spf1 include:loop1.example.com").unwrap_err().to_string(),
+            "loop1.example.com includes itself"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Module responsible for exporting graph of `include` and `redirect` relations between SPF records
+//! in [graphviz DOT format](https://graphviz.org/doc/info/lang.html).
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::spf::{find_record, SpfMechanism, SpfRecord};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EdgeKind {
+    Include,
+    Redirect,
+}
+
+struct GraphNode<'r, 'a> {
+    domain: String,
+    record: Option<&'r SpfRecord<'a>>,
+}
+
+struct GraphEdge {
+    from: usize,
+    to: usize,
+    kind: EdgeKind,
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// escape_dot_string escapes text so it may be put between quotes in DOT file.
+fn escape_dot_string(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            '\r' => {}
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+fn terminal_qualifier(record: &SpfRecord) -> String {
+    let all = record.directives.iter()
+        .find(|d| d.mechanism == SpfMechanism::All);
+    match all {
+        Some(d) => {
+            let c: char = d.qualifier.into();
+            format!("{}all", c)
+        }
+        None if record.directives.iter().any(|d| matches!(d.mechanism, SpfMechanism::Redirect(_))) => {
+            "redirect".to_string()
+        }
+        None => "no all".to_string(),
+    }
+}
+
+struct GraphBuilder<'r, 'a> {
+    records: &'r HashMap<String, SpfRecord<'a>>,
+    nodes: Vec<GraphNode<'r, 'a>>,
+    node_indices: HashMap<String, usize>,
+    edges: Vec<GraphEdge>,
+}
+
+impl<'r, 'a> GraphBuilder<'r, 'a> {
+    /// visit adds node for given domain(if not added yet) and all nodes reachable from it.
+    /// Domains containing macros are never resolved.
+    fn visit(&mut self, domain: &str, literal: bool) -> usize {
+        let key = normalize_domain(domain);
+        if let Some(idx) = self.node_indices.get(&key) {
+            return *idx;
+        }
+
+        let idx = self.nodes.len();
+        let record = if literal {
+            find_record(self.records, domain)
+        } else {
+            None
+        };
+        self.nodes.push(GraphNode {
+            domain: key.clone(),
+            record,
+        });
+        self.node_indices.insert(key, idx);
+
+        if let Some(record) = record {
+            for d in record.directives.iter() {
+                let kind = match d.mechanism {
+                    SpfMechanism::Include(_) => EdgeKind::Include,
+                    SpfMechanism::Redirect(_) => EdgeKind::Redirect,
+                    _ => continue,
+                };
+                let target = d.mechanism.referenced_record().unwrap();
+                let to = self.visit(target.as_str(), target.is_literal());
+                self.edges.push(GraphEdge {
+                    from: idx,
+                    to,
+                    kind,
+                });
+            }
+        }
+        idx
+    }
+
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(n) = stack.pop() {
+            if n == to {
+                return true;
+            }
+            if !visited.insert(n) {
+                continue;
+            }
+            stack.extend(self.edges.iter().filter(|e| e.from == n).map(|e| e.to));
+        }
+        false
+    }
+
+    fn render(&self) -> String {
+        // edge is part of cycle when it's source is reachable from it's target
+        let cyclic_edges = self.edges.iter()
+            .map(|e| self.reaches(e.to, e.from))
+            .collect::<Vec<_>>();
+        let mut cyclic_nodes = HashSet::new();
+        for (e, cyclic) in self.edges.iter().zip(cyclic_edges.iter()) {
+            if *cyclic {
+                cyclic_nodes.insert(e.from);
+                cyclic_nodes.insert(e.to);
+            }
+        }
+
+        let mut res = String::new();
+        res.push_str("digraph spf {\n");
+        for (i, n) in self.nodes.iter().enumerate() {
+            let mut attrs = Vec::new();
+            match n.record {
+                Some(record) => {
+                    let lookups: u32 = record.directives.iter()
+                        .map(|d| d.mechanism.lookup_cost() as u32)
+                        .sum();
+                    let label = format!("{}\nlookups: {}\n{}", n.domain, lookups, terminal_qualifier(record));
+                    attrs.push(format!("label=\"{}\"", escape_dot_string(&label)));
+                }
+                None => {
+                    attrs.push(format!("label=\"{}\"", escape_dot_string(&format!("{}\nunresolved", n.domain))));
+                    attrs.push("style=dotted".to_string());
+                }
+            }
+            if cyclic_nodes.contains(&i) {
+                attrs.push("color=red".to_string());
+            }
+            writeln!(res, "    \"{}\" [{}];", escape_dot_string(&n.domain), attrs.join(", ")).unwrap();
+        }
+        for (e, cyclic) in self.edges.iter().zip(cyclic_edges.iter()) {
+            let mut attrs = Vec::new();
+            if e.kind == EdgeKind::Redirect {
+                attrs.push("style=dashed");
+            }
+            if *cyclic {
+                attrs.push("color=red");
+            }
+            let from = escape_dot_string(&self.nodes[e.from].domain);
+            let to = escape_dot_string(&self.nodes[e.to].domain);
+            if attrs.is_empty() {
+                writeln!(res, "    \"{}\" -> \"{}\";", from, to).unwrap();
+            } else {
+                writeln!(res, "    \"{}\" -> \"{}\" [{}];", from, to, attrs.join(", ")).unwrap();
+            }
+        }
+        res.push_str("}\n");
+        res
+    }
+}
+
+/// export_include_graph renders graph of `include` and `redirect` relations reachable from `root_domain` as DOT.
+///
+/// Each domain is single node labeled with number of lookups its record performs and its terminal qualifier(`all` mechanism).
+/// Includes are rendered as solid edges, redirects as dashed ones. Domains missing from `records`
+/// (or containing macros) are rendered as dotted nodes. Nodes and edges forming cycles are red.
+pub fn export_include_graph(root_domain: &str, records: &HashMap<String, SpfRecord>) -> String {
+    let mut builder = GraphBuilder {
+        records,
+        nodes: Vec::new(),
+        node_indices: HashMap::new(),
+        edges: Vec::new(),
+    };
+    builder.visit(root_domain, true);
+    builder.render()
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use crate::spf::{DomainSpec, DualCidr, Ipv4Net, SpfAction, SpfDirective};
+
+    use super::*;
+
+    fn directive(qualifier: SpfAction, mechanism: SpfMechanism<'static>) -> SpfDirective<'static> {
+        SpfDirective {
+            qualifier,
+            explicit_qualifier: qualifier != SpfAction::Pass,
+            mechanism,
+        }
+    }
+
+    fn three_domain_fixture() -> HashMap<String, SpfRecord<'static>> {
+        let mut records = HashMap::new();
+        records.insert("example.com".to_string(), SpfRecord::from(vec![
+            directive(SpfAction::Pass, SpfMechanism::MX(None, DualCidr::default())),
+            directive(SpfAction::Pass, SpfMechanism::Include(DomainSpec::new("_spf.example.net").unwrap())),
+            directive(SpfAction::Pass, SpfMechanism::Include(DomainSpec::new("missing.example.org").unwrap())),
+            directive(SpfAction::Pass, SpfMechanism::Redirect(DomainSpec::new("fallback.example.org").unwrap())),
+        ]));
+        records.insert("_spf.example.net".to_string(), SpfRecord::from(vec![
+            directive(SpfAction::Pass, SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap())),
+            directive(SpfAction::SoftFail, SpfMechanism::All),
+        ]));
+        records.insert("fallback.example.org".to_string(), SpfRecord::from(vec![
+            directive(SpfAction::Pass, SpfMechanism::Include(DomainSpec::new("example.com").unwrap())),
+            directive(SpfAction::Fail, SpfMechanism::All),
+        ]));
+        records
+    }
+
+    fn parse_edges(dot: &str) -> HashSet<(String, String)> {
+        dot.lines()
+            .filter(|l| l.contains("->"))
+            .map(|l| {
+                let parts = l.split('"').collect::<Vec<_>>();
+                (parts[1].to_string(), parts[3].to_string())
+            })
+            .collect()
+    }
+
+    fn parse_nodes(dot: &str) -> HashSet<String> {
+        dot.lines()
+            .filter(|l| !l.contains("->") && l.contains("[label="))
+            .map(|l| l.split('"').nth(1).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_graph_contains_nodes_and_edges() {
+        let dot = export_include_graph("example.com", &three_domain_fixture());
+        assert_eq!(parse_nodes(&dot), [
+            "example.com", "_spf.example.net", "missing.example.org", "fallback.example.org",
+        ].iter().map(|s| s.to_string()).collect());
+        assert_eq!(parse_edges(&dot), [
+            ("example.com", "_spf.example.net"),
+            ("example.com", "missing.example.org"),
+            ("example.com", "fallback.example.org"),
+            ("fallback.example.org", "example.com"),
+        ].iter().map(|(a, b)| (a.to_string(), b.to_string())).collect());
+    }
+
+    #[test]
+    fn test_graph_matches_golden_file() {
+        let dot = export_include_graph("example.com", &three_domain_fixture());
+        assert_eq!(dot, include_str!("testdata/include_graph.dot"));
+    }
+
+    #[test]
+    fn test_unresolved_root() {
+        let dot = export_include_graph("example.com", &HashMap::new());
+        assert_eq!(dot, "digraph spf {\n    \"example.com\" [label=\"example.com\\nunresolved\", style=dotted];\n}\n");
+    }
+
+    #[test]
+    fn test_domain_names_are_escaped() {
+        let mut records = HashMap::new();
+        records.insert("example.com".to_string(), SpfRecord::from(vec![
+            directive(SpfAction::Pass, SpfMechanism::Include(DomainSpec::new("a\"b\\c.example.com").unwrap())),
+        ]));
+        let dot = export_include_graph("example.com", &records);
+        assert!(dot.contains("    \"example.com\" -> \"a\\\"b\\\\c.example.com\";\n"));
+    }
+}
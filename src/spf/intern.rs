@@ -0,0 +1,317 @@
+//! Module with `DomainInterner`, which lets many resource bags share single allocation per domain name.
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use crate::spf::{ExternalResourceBag, SpfEvaluationResult, SpfRecord};
+
+/// InternedDomain is cheaply clonable, shared domain name.
+///
+/// Domains created by `DomainInterner` are lowercase and ones with same text share single allocation.
+/// It dereferences to `str` and implements `Borrow<str>`, so maps keyed by it may be queried with `&str`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InternedDomain(Arc<str>);
+
+impl InternedDomain {
+    /// new creates domain which is not shared with any other one. Text is used as is.
+    /// Use `DomainInterner::intern` to share allocations.
+    pub fn new(domain: &str) -> Self {
+        Self(Arc::from(domain))
+    }
+
+    /// as_str returns text of this domain.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// ptr_eq checks if both domains share same allocation.
+    #[inline]
+    pub fn ptr_eq(&self, other: &InternedDomain) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Hash for InternedDomain {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // has to be same as hash of str, since Borrow<str> is implemented
+        self.as_str().hash(state)
+    }
+}
+
+impl Deref for InternedDomain {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for InternedDomain {
+    #[inline]
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for InternedDomain {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for InternedDomain {
+    #[inline]
+    fn from(domain: &str) -> Self {
+        Self::new(domain)
+    }
+}
+
+impl fmt::Debug for InternedDomain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for InternedDomain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl serde::Serialize for InternedDomain {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de> serde::Deserialize<'de> for InternedDomain {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        let domain = String::deserialize(deserializer)?;
+        Ok(Self(Arc::from(domain)))
+    }
+}
+
+/// DomainInterner hands out shared `InternedDomain`s, so each distinct domain is allocated once.
+///
+/// Domains are lowercased before interning. Interner may be shared between threads.
+///
+/// # Memory
+/// Interner keeps every domain it has seen until `purge` or `clear` is called.
+/// `purge` removes domains which are not used anywhere else, so calling it from time to time
+/// keeps memory usage bounded by number of domains actually in use.
+#[derive(Debug, Default)]
+pub struct DomainInterner {
+    domains: Mutex<HashSet<Arc<str>>>,
+}
+
+impl DomainInterner {
+    /// new creates empty interner.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// intern returns shared lowercase version of given domain.
+    pub fn intern(&self, domain: &str) -> InternedDomain {
+        let mut domains = self.domains.lock().unwrap_or_else(|e| e.into_inner());
+        if domain.bytes().any(|b| b.is_ascii_uppercase()) {
+            Self::intern_lowercase(&mut domains, &domain.to_ascii_lowercase())
+        } else {
+            Self::intern_lowercase(&mut domains, domain)
+        }
+    }
+
+    fn intern_lowercase(domains: &mut HashSet<Arc<str>>, domain: &str) -> InternedDomain {
+        if let Some(d) = domains.get(domain) {
+            return InternedDomain(d.clone());
+        }
+        let d: Arc<str> = Arc::from(domain);
+        domains.insert(d.clone());
+        InternedDomain(d)
+    }
+
+    /// len returns number of distinct domains held by this interner.
+    pub fn len(&self) -> usize {
+        self.domains.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// is_empty checks if this interner holds no domains.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// purge removes domains which are referenced only by this interner.
+    pub fn purge(&self) {
+        self.domains.lock().unwrap_or_else(|e| e.into_inner())
+            .retain(|d| Arc::strong_count(d) > 1);
+    }
+
+    /// clear removes all domains from this interner. Domains already handed out stay valid,
+    /// but ones interned later won't share allocations with them.
+    pub fn clear(&self) {
+        self.domains.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+impl<'a> ExternalResourceBag<'a> {
+    /// insert_existence records whether given domain exists. Domain is interned.
+    pub fn insert_existence(&mut self, interner: &DomainInterner, domain: &str, exists: bool) {
+        self.existence_map.insert(interner.intern(domain), exists);
+    }
+
+    /// insert_record records SPF record of given domain. Domain is interned.
+    pub fn insert_record(&mut self, interner: &DomainInterner, domain: &str, record: SpfRecord<'a>) {
+        self.domain_record_map.insert(interner.intern(domain), record);
+    }
+
+    /// insert_addresses records addresses from A and AAAA records of given domain. Domain is interned.
+    pub fn insert_addresses(&mut self, interner: &DomainInterner, domain: &str, addresses: Vec<IpAddr>) {
+        self.address_map.insert(interner.intern(domain), addresses);
+    }
+
+    /// insert_mx_hosts records hosts from MX records of given domain. Domain and hosts are interned.
+    pub fn insert_mx_hosts<'h, I>(&mut self, interner: &DomainInterner, domain: &str, hosts: I)
+        where I: IntoIterator<Item=&'h str>
+    {
+        let hosts = hosts.into_iter().map(|h| interner.intern(h)).collect();
+        self.mx_map.insert(interner.intern(domain), hosts);
+    }
+
+    /// insert_validated_ptr_names records validated reverse DNS names of source IP found for given domain.
+    /// Domain and names are interned.
+    pub fn insert_validated_ptr_names<'h, I>(&mut self, interner: &DomainInterner, domain: &str, names: I)
+        where I: IntoIterator<Item=&'h str>
+    {
+        let names = names.into_iter().map(|n| interner.intern(n)).collect();
+        self.validated_ptr_map.insert(interner.intern(domain), names);
+    }
+
+    /// insert_record_failure records result of given domain, whose SPF record can't be evaluated,
+    /// like `None` when there is no record. Domain is interned.
+    pub fn insert_record_failure(&mut self, interner: &DomainInterner, domain: &str, result: SpfEvaluationResult) {
+        self.record_failure_map.insert(interner.intern(domain), result);
+    }
+
+    /// domain_exists returns whether given domain exists, if it's known. Lookup is case insensitive.
+    pub fn domain_exists(&self, domain: &str) -> Option<bool> {
+        lookup(&self.existence_map, domain).copied()
+    }
+
+    /// record returns SPF record of given domain, if it's known. Lookup is case insensitive.
+    pub fn record(&self, domain: &str) -> Option<&SpfRecord<'a>> {
+        lookup(&self.domain_record_map, domain)
+    }
+
+    /// addresses returns addresses of given domain, if they are known. Lookup is case insensitive.
+    pub fn addresses(&self, domain: &str) -> Option<&[IpAddr]> {
+        lookup(&self.address_map, domain).map(Vec::as_slice)
+    }
+
+    /// mx_hosts returns hosts from MX records of given domain, if they are known. Lookup is case insensitive.
+    pub fn mx_hosts(&self, domain: &str) -> Option<&[InternedDomain]> {
+        lookup(&self.mx_map, domain).map(Vec::as_slice)
+    }
+
+    /// validated_ptr_names returns validated reverse DNS names of source IP found for given domain,
+    /// if they are known. Lookup is case insensitive.
+    pub fn validated_ptr_names(&self, domain: &str) -> Option<&[InternedDomain]> {
+        lookup(&self.validated_ptr_map, domain).map(Vec::as_slice)
+    }
+
+    /// record_failure returns result of given domain, whose SPF record can't be evaluated, if it's known.
+    /// Lookup is case insensitive.
+    pub fn record_failure(&self, domain: &str) -> Option<SpfEvaluationResult> {
+        lookup(&self.record_failure_map, domain).copied()
+    }
+}
+
+fn lookup<'m, V>(map: &'m std::collections::HashMap<InternedDomain, V>, domain: &str) -> Option<&'m V> {
+    map.get(domain).or_else(|| map.get(domain.to_ascii_lowercase().as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_interner_shares_allocations() {
+        assert_send_sync::<DomainInterner>();
+        assert_send_sync::<InternedDomain>();
+
+        let interner = DomainInterner::new();
+        let a = interner.intern("_spf.Example.com");
+        let b = interner.intern("_spf.example.com");
+        assert_eq!(a.as_str(), "_spf.example.com");
+        assert!(a.ptr_eq(&b));
+        assert!(!a.ptr_eq(&InternedDomain::new("_spf.example.com")));
+        assert_eq!(a, InternedDomain::new("_spf.example.com"));
+        assert_eq!(interner.len(), 1);
+    }
+
+    #[test]
+    fn test_many_entries_over_few_domains() {
+        let interner = DomainInterner::new();
+        let domains = (0..200).map(|i| format!("mx{}.example.com", i)).collect::<Vec<_>>();
+
+        let mut bags = Vec::new();
+        for chunk in 0..1000 {
+            let mut bag = ExternalResourceBag {
+                source_ip: None,
+                existence_map: HashMap::new(),
+                domain_record_map: HashMap::new(),
+                address_map: HashMap::new(),
+                mx_map: HashMap::new(),
+                validated_ptr_map: HashMap::new(),
+                record_failure_map: HashMap::new(),
+            };
+            for i in 0..100 {
+                bag.insert_existence(&interner, &domains[(chunk + i * 7) % domains.len()], i % 2 == 0);
+            }
+            bags.push(bag);
+        }
+        let entries: usize = bags.iter().map(|b| b.existence_map.len()).sum();
+        assert_eq!(entries, 100_000);
+        assert_eq!(interner.len(), 200);
+
+        let first = bags[0].existence_map.keys().find(|d| d.as_str() == "mx0.example.com").unwrap();
+        for bag in bags.iter() {
+            if let Some((d, _)) = bag.existence_map.get_key_value("mx0.example.com") {
+                assert!(d.ptr_eq(first));
+            }
+        }
+        assert_eq!(bags[0].domain_exists("MX0.example.com"), Some(true));
+    }
+
+    #[test]
+    fn test_purge_removes_unused_domains() {
+        let interner = DomainInterner::new();
+        let kept = interner.intern("kept.example.com");
+        interner.intern("dropped.example.com");
+        assert_eq!(interner.len(), 2);
+
+        interner.purge();
+        assert_eq!(interner.len(), 1);
+        assert!(interner.intern("kept.example.com").ptr_eq(&kept));
+
+        interner.clear();
+        assert!(interner.is_empty());
+    }
+}
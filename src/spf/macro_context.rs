@@ -0,0 +1,183 @@
+//! Module containing `MacroContext`, which derives values of macro variables from SMTP session data.
+
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+use crate::spf::{AnyMacroVariable, EvaluationContext, MacroEvaluationError, MacroVariable};
+
+/// ExpVariables contains values of variables, which may be used only in `exp` text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ExpVariables {
+    client_ip: String,
+    receiver: String,
+    timestamp: String,
+}
+
+/// MacroContext is `EvaluationContext` built from data known during SMTP session,
+/// so that values of macro variables do not have to be computed by hand.
+///
+/// `p` is never provided, since it requires DNS lookups. Evaluator provides it on its own.
+/// `c`, `r` and `t` are provided only after `with_exp_variables` is called.
+///
+/// # Docs
+/// Take a look at [RFC7280](https://tools.ietf.org/html/rfc7208) section `7.3`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MacroContext {
+    ip: IpAddr,
+    sender: String,
+    local_part_len: usize,
+    domain: String,
+    ip_text: String,
+    helo: String,
+    exp: Option<ExpVariables>,
+}
+
+impl MacroContext {
+    /// new creates context for check of `domain` for message from `sender`, which was sent from `ip` by client,
+    /// which used `helo` in HELO/EHLO command.
+    ///
+    /// When sender has no local part `postmaster` is used. When sender is empty, `postmaster@<helo>` is used.
+    pub fn new(sender: &str, domain: &str, ip: IpAddr, helo: &str) -> Self {
+        let sender = if sender.is_empty() {
+            format!("postmaster@{}", helo)
+        } else {
+            match sender.rfind('@') {
+                Some(0) => format!("postmaster{}", sender),
+                Some(_) => sender.to_string(),
+                None => format!("postmaster@{}", sender),
+            }
+        };
+        // sender always contains '@' here
+        let local_part_len = sender.rfind('@').unwrap();
+
+        Self {
+            ip,
+            sender,
+            local_part_len,
+            domain: domain.to_string(),
+            ip_text: MacroVariable::format_ip(ip),
+            helo: helo.to_string(),
+            exp: None,
+        }
+    }
+
+    /// with_exp_variables sets values of `c`, `r` and `t` variables, which may be used only in `exp` text.
+    /// `receiver` is domain name of host performing the check and `timestamp` is number of seconds since UNIX epoch.
+    pub fn with_exp_variables(mut self, receiver: &str, timestamp: u64) -> Self {
+        self.exp = Some(ExpVariables {
+            client_ip: self.ip.to_string(),
+            receiver: receiver.to_string(),
+            timestamp: timestamp.to_string(),
+        });
+        self
+    }
+
+    /// ip returns address of SMTP client.
+    #[inline]
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// sender returns sender used as `s` variable, after `postmaster` was substituted, if needed.
+    #[inline]
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    /// domain returns domain, which is checked.
+    #[inline]
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+}
+
+impl EvaluationContext for MacroContext {
+    fn provide_data(&self, v: MacroVariable) -> Result<Cow<'_, str>, MacroEvaluationError> {
+        let value = match v {
+            MacroVariable::Sender => &self.sender,
+            MacroVariable::LocalPartOfSender => &self.sender[..self.local_part_len],
+            MacroVariable::DomainOfSender => &self.sender[self.local_part_len + 1..],
+            MacroVariable::Domain => &self.domain,
+            MacroVariable::Ip => &self.ip_text,
+            MacroVariable::InAddr => match self.ip {
+                IpAddr::V4(_) => "in-addr",
+                IpAddr::V6(_) => "ip6",
+            },
+            MacroVariable::HeloOrEhloDomain => &self.helo,
+            MacroVariable::SmtpClientIp => match &self.exp {
+                Some(exp) => &exp.client_ip,
+                None => return Err(MacroEvaluationError::UnknownVariable(AnyMacroVariable::from(v))),
+            },
+            MacroVariable::DomainNameOfHostPerformingTheCheck => match &self.exp {
+                Some(exp) => &exp.receiver,
+                None => return Err(MacroEvaluationError::UnknownVariable(AnyMacroVariable::from(v))),
+            },
+            MacroVariable::CurrentTimestamp => match &self.exp {
+                Some(exp) => &exp.timestamp,
+                None => return Err(MacroEvaluationError::UnknownVariable(AnyMacroVariable::from(v))),
+            },
+            MacroVariable::ValidatedDomainNameOrIp => {
+                return Err(MacroEvaluationError::UnknownVariable(AnyMacroVariable::from(v)));
+            }
+        };
+        Ok(Cow::Borrowed(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::spf::{evaluate_exp_macro, evaluate_macro};
+
+    use super::*;
+
+    #[test]
+    fn test_derives_variables() {
+        let ctx = MacroContext::new("strong-bad@email.example.com", "email.example.com", IpAddr::from(Ipv4Addr::new(192, 0, 2, 3)), "mx.example.org");
+
+        // examples from rfc 7208 section 7.4
+        assert_eq!(evaluate_macro(&ctx, "%{s}").unwrap(), "strong-bad@email.example.com");
+        assert_eq!(evaluate_macro(&ctx, "%{o}").unwrap(), "email.example.com");
+        assert_eq!(evaluate_macro(&ctx, "%{d4}").unwrap(), "email.example.com");
+        assert_eq!(evaluate_macro(&ctx, "%{d2}").unwrap(), "example.com");
+        assert_eq!(evaluate_macro(&ctx, "%{dr}").unwrap(), "com.example.email");
+        assert_eq!(evaluate_macro(&ctx, "%{l-}").unwrap(), "strong.bad");
+        assert_eq!(evaluate_macro(&ctx, "%{lr-}").unwrap(), "bad.strong");
+        assert_eq!(evaluate_macro(&ctx, "%{ir}.%{v}._spf.%{d2}").unwrap(), "3.2.0.192.in-addr._spf.example.com");
+        assert_eq!(evaluate_macro(&ctx, "%{h}").unwrap(), "mx.example.org");
+
+        let ctx = MacroContext::new("strong-bad@email.example.com", "email.example.com", IpAddr::from("2001:db8::cb01".parse::<Ipv6Addr>().unwrap()), "mx.example.org");
+        assert_eq!(
+            evaluate_macro(&ctx, "%{ir}.%{v}._spf.%{d2}").unwrap(),
+            "1.0.b.c.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6._spf.example.com"
+        );
+    }
+
+    #[test]
+    fn test_postmaster_is_used_without_local_part() {
+        let ip = IpAddr::from(Ipv4Addr::new(192, 0, 2, 3));
+        for (sender, expected) in [("example.com", "postmaster@example.com"), ("@example.com", "postmaster@example.com"), ("", "postmaster@mx.example.org"), ("a@b@example.com", "a@b@example.com")].iter() {
+            let ctx = MacroContext::new(sender, "example.com", ip, "mx.example.org");
+            assert_eq!(ctx.sender(), *expected);
+            assert_eq!(evaluate_macro(&ctx, "%{l}@%{o}").unwrap(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_exp_variables() {
+        let ip = IpAddr::from("2001:db8::cb01".parse::<Ipv6Addr>().unwrap());
+        let ctx = MacroContext::new("user@example.com", "example.com", ip, "mx.example.org");
+        assert!(matches!(evaluate_exp_macro(&ctx, "%{c}"), Err(MacroEvaluationError::UnknownVariable(_))));
+        assert!(matches!(evaluate_exp_macro(&ctx, "%{p}"), Err(MacroEvaluationError::UnknownVariable(_))));
+
+        let ctx = ctx.with_exp_variables("mail.example.net", 1_000_000_000);
+        assert_eq!(
+            evaluate_exp_macro(&ctx, "%{c} is not allowed to send mail for %{o}, checked by %{r} at %{t}").unwrap(),
+            "2001:db8::cb01 is not allowed to send mail for example.com, checked by mail.example.net at 1000000000"
+        );
+        assert_eq!(evaluate_macro(&ctx, "%{c}"), Err(MacroEvaluationError::ExpOnlyVariable(MacroVariable::SmtpClientIp)));
+        assert_eq!(ctx.ip(), ip);
+        assert_eq!(ctx.domain(), "example.com");
+    }
+}
@@ -3,34 +3,81 @@
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::fmt;
 use std::num::ParseIntError;
-use std::str::FromStr;
 
 use crate::spf::{AnyMacroVariable, MacroVariable};
 
-#[derive(Debug, From)]
+/// MacroEvaluationError is returned when macro string can't be parsed or evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum MacroEvaluationError {
-    ParsingSyntaxError,
+    /// ParsingSyntaxError is returned when macro string syntax is not valid.
+    /// Offset is byte offset of char in macro string, at which parser gave up. It's length of text
+    /// when text ends in the middle of macro.
+    ParsingSyntaxError {
+        offset: usize,
+    },
 
     /// UnknownVariable is returned when `EvaluationContext` was not able to find value for given variable.
     UnknownVariable(AnyMacroVariable),
 
+    /// ParseIntError was returned when number of labels to use did not fit in `usize`.
+    /// It's not returned anymore, since such numbers are saturated, but it's kept for compatibility.
     ParseIntError(ParseIntError),
+
+    /// ExpOnlyVariable is returned when variable, which may be used only in `exp` text(`c`, `r` or `t`),
+    /// is found in macro string evaluated as something else, for instance domain-spec.
+    ExpOnlyVariable(MacroVariable),
+}
+
+impl From<AnyMacroVariable> for MacroEvaluationError {
+    #[inline]
+    fn from(v: AnyMacroVariable) -> Self {
+        MacroEvaluationError::UnknownVariable(v)
+    }
+}
+
+impl From<ParseIntError> for MacroEvaluationError {
+    #[inline]
+    fn from(e: ParseIntError) -> Self {
+        MacroEvaluationError::ParseIntError(e)
+    }
+}
+
+impl fmt::Display for MacroEvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacroEvaluationError::ParsingSyntaxError { offset } => write!(f, "macro syntax error at offset {}", offset),
+            MacroEvaluationError::UnknownVariable(v) => write!(f, "value of macro variable {:?} is unknown", v),
+            MacroEvaluationError::ParseIntError(e) => write!(f, "invalid number of labels: {}", e),
+            MacroEvaluationError::ExpOnlyVariable(v) => write!(f, "macro variable {:?} may be used only in exp text", v),
+        }
+    }
+}
+
+impl std::error::Error for MacroEvaluationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MacroEvaluationError::ParseIntError(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 /// EvaluationContext provides variables required to format macro.
 pub trait EvaluationContext {
     /// according to rfc valid tokens are:
-    fn provide_data(&self, v: MacroVariable) -> Result<Cow<str>, MacroEvaluationError>;
+    fn provide_data(&self, v: MacroVariable) -> Result<Cow<'_, str>, MacroEvaluationError>;
 }
 
-impl<'a, S> EvaluationContext for HashMap<MacroVariable, S>
+impl<S> EvaluationContext for HashMap<MacroVariable, S>
     where S: AsRef<str>
 {
-    fn provide_data(&self, var: MacroVariable) -> Result<Cow<str>, MacroEvaluationError> {
+    fn provide_data(&self, var: MacroVariable) -> Result<Cow<'_, str>, MacroEvaluationError> {
         self.get(&var)
             .map(|val| val.as_ref())
-            .map(|val| Cow::Borrowed(val))
+            .map(Cow::Borrowed)
             .ok_or(MacroEvaluationError::UnknownVariable(AnyMacroVariable::from(var)))
     }
 }
@@ -49,12 +96,12 @@ pub struct VecEvaluationContext<'a, T>(bool, Cow<'a, [(MacroVariable, T)]>)
     where [(MacroVariable, T)]: Clone
 ;
 
-impl<'a, T> Into<Cow<'a, [(MacroVariable, T)]>> for VecEvaluationContext<'a, T>
+impl<'a, T> From<VecEvaluationContext<'a, T>> for Cow<'a, [(MacroVariable, T)]>
     where [(MacroVariable, T)]: Clone
 {
     #[inline]
-    fn into(self) -> Cow<'a, [(MacroVariable, T)]> {
-        self.1
+    fn from(ctx: VecEvaluationContext<'a, T>) -> Cow<'a, [(MacroVariable, T)]> {
+        ctx.1
     }
 }
 
@@ -77,7 +124,7 @@ impl<'a, T> EvaluationContext for VecEvaluationContext<'a, T>
         T: AsRef<str>,
         [(MacroVariable, T)]: Clone
 {
-    fn provide_data(&self, v: MacroVariable) -> Result<Cow<str>, MacroEvaluationError> {
+    fn provide_data(&self, v: MacroVariable) -> Result<Cow<'_, str>, MacroEvaluationError> {
         if self.0 {
             if let Ok(idx) = self.1.binary_search_by_key(&v, |k| k.0) {
                 Ok(Cow::Borrowed(self.1[idx].1.as_ref()))
@@ -94,81 +141,95 @@ impl<'a, T> EvaluationContext for VecEvaluationContext<'a, T>
     }
 }
 
-impl<'a, S> EvaluationContext for &HashMap<MacroVariable, S>
-    where S: AsRef<str>
+impl<E> EvaluationContext for &E
+    where E: EvaluationContext + ?Sized
 {
-    fn provide_data(&self, var: MacroVariable) -> Result<Cow<str>, MacroEvaluationError> {
-        self.get(&var)
-            .map(|val| val.as_ref())
-            .map(|val| Cow::Borrowed(val))
-            .ok_or(MacroEvaluationError::UnknownVariable(AnyMacroVariable::from(var)))
+    #[inline]
+    fn provide_data(&self, var: MacroVariable) -> Result<Cow<'_, str>, MacroEvaluationError> {
+        (**self).provide_data(var)
     }
 }
 
-struct MacroEvaluator<'a, E> {
-    ctx: E,
-    res: String,
+/// MacroExpansion describes single `%{...}` term of macro string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MacroExpansion {
+    /// variable which value is expanded
+    pub variable: MacroVariable,
+
+    /// url_encode is true when macro letter was uppercase
+    pub url_encode: bool,
+
+    /// label_count is number of rightmost labels of value to use(after reversal, if any), if given.
+    ///
+    /// Numbers which do not fit in `usize` are saturated, so they select all labels. Zero is accepted,
+    /// even though RFC requires nonzero number, and it selects no labels, so expansion is empty.
+    pub label_count: Option<usize>,
+
+    /// reverse is true if labels should be used in reverse order
+    pub reverse: bool,
+
+    /// delimiters contains chars which split value into labels. When empty `.` is used.
+    pub delimiters: Vec<char>,
+}
+
+/// MacroToken is single part of parsed macro string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MacroToken {
+    /// Literal contains text which is copied to output as is.
+    /// Escapes(`%%`, `%_` and `%-`) are already replaced with text they represent.
+    Literal(String),
+
+    /// Expansion is replaced with value of macro variable.
+    Expansion(MacroExpansion),
+}
+
+/// MacroString is parsed SPF macro string, which may be evaluated many times with different contexts.
+///
+/// # Docs
+/// Take a look at [RFC7280](https://tools.ietf.org/html/rfc7208) section `7. Macros`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct MacroString {
+    tokens: Vec<MacroToken>,
+}
+
+struct MacroParser<'a> {
+    tokens: Vec<MacroToken>,
+    literal: String,
     input: &'a str,
+
+    /// text is whole macro string, which `input` is suffix of.
+    text: &'a str,
 }
 
-impl<'a, E> MacroEvaluator<'a, E>
-    where E: EvaluationContext
-{
-    fn put_formatter(&mut self, letter: u8, reverse: bool, do_urlencode: bool, label_count: Option<usize>, delimiter: HashSet<char>) -> Result<(), MacroEvaluationError> {
-        let text = self.ctx.provide_data(
-            MacroVariable::try_from(letter)
-                .map_err(|_| AnyMacroVariable::from(letter))?
-        )?;
-        let i = text.split(|c| {
-            if delimiter.is_empty() {
-                c == '.'
-            } else {
-                delimiter.contains(&c)
-            }
-        });
-        let new_text = if reverse {
-            i
-                .rev()
-                .take(label_count.unwrap_or(std::usize::MAX))
-                .collect::<Vec<_>>()
-                .join(".")
-        } else {
-            i
-                .take(label_count.unwrap_or(std::usize::MAX))
-                .collect::<Vec<_>>()
-                .join(".")
-        };
-        if do_urlencode {
-            for c in url::form_urlencoded::byte_serialize(new_text.as_bytes()) {
-                self.res.push_str(c);
-            }
-        } else {
-            self.res.push_str(&new_text);
+impl<'a> MacroParser<'a> {
+    /// syntax_error returns error pointing at char, which is `consumed` bytes after start of remaining input.
+    fn syntax_error(&self, consumed: usize) -> MacroEvaluationError {
+        MacroEvaluationError::ParsingSyntaxError {
+            offset: self.text.len() - self.input.len() + consumed,
         }
-        Ok(())
     }
 
-    /// returns offset and number read. If there is no number offset is always zero.
-    fn read_number(mut input_data: &str) -> Result<(usize, Option<usize>), MacroEvaluationError> {
-        let mut offset = 0;
-        let original_data = input_data;
-        loop {
-            if input_data.len() == 0 {
-                return Err(MacroEvaluationError::ParsingSyntaxError);
-            }
-            let c = input_data.chars().nth(0).unwrap();
-            input_data = &input_data[c.len_utf8()..];
-            if c.is_ascii_digit() {
-                offset += c.len_utf8();
-            } else {
-                let data = &original_data[..offset];
-                return if data.len() == 0 {
-                    Ok((offset, None))
-                } else {
-                    let res = Ok((offset, Some(usize::from_str(data)?)));
-                    res
-                };
-            }
+    fn push_literal(&mut self, text: &str) {
+        self.literal.push_str(text);
+    }
+
+    fn push_expansion(&mut self, expansion: MacroExpansion) {
+        if !self.literal.is_empty() {
+            self.tokens.push(MacroToken::Literal(std::mem::take(&mut self.literal)));
+        }
+        self.tokens.push(MacroToken::Expansion(expansion));
+    }
+
+    /// returns byte offset just after digits and number read. If there is no number offset is always zero.
+    /// Digits are ASCII, so offset is always at char boundary. Number is saturated at `usize::MAX`.
+    fn read_number(input_data: &str) -> (usize, Option<usize>) {
+        let digits = input_data.bytes().take_while(u8::is_ascii_digit);
+        let offset = digits.clone().count();
+        if offset == 0 {
+            (0, None)
+        } else {
+            let number = digits.fold(0usize, |n, d| n.saturating_mul(10).saturating_add(usize::from(d - b'0')));
+            (offset, Some(number))
         }
     }
 
@@ -186,8 +247,8 @@ impl<'a, E> MacroEvaluator<'a, E>
 
         let mut data = self.input;
         loop {
-            if data.len() == 0 {
-                return Err(MacroEvaluationError::ParsingSyntaxError);
+            if data.is_empty() {
+                return Err(self.syntax_error(offset));
             }
             let c = data.chars().nth(0).unwrap();
             offset += c.len_utf8();
@@ -198,25 +259,25 @@ impl<'a, E> MacroEvaluator<'a, E>
                     state = 1;
                 }
                 (0, '_') => {
-                    self.res.push(' ');
+                    self.push_literal(" ");
                     break;
                 }
                 (0, '-') => {
-                    self.res.push_str("%20");
+                    self.push_literal("%20");
                     break;
                 }
                 (0, '%') => {
-                    self.res.push('%');
+                    self.push_literal("%");
                     break;
                 }
-                (0, l) if l <= std::u8::MAX as char && MacroVariable::get_valid_lowercase_symbols().contains(&(l.to_ascii_lowercase() as u8)) => {
+                (0, l) if l <= u8::MAX as char && MacroVariable::get_valid_lowercase_symbols().contains(&(l.to_ascii_lowercase() as u8)) => {
                     letter = Some(l.to_ascii_lowercase());
                     // uppercase macros are expanded like lowercase but are urlencoded
                     do_urlencode = l.is_ascii_uppercase();
                     break;
                 }
                 // if one of allowed modifiers
-                (1, l) if l <= std::u8::MAX as char && MacroVariable::get_valid_lowercase_symbols().contains(&(l.to_ascii_lowercase() as u8)) => {
+                (1, l) if l <= u8::MAX as char && MacroVariable::get_valid_lowercase_symbols().contains(&(l.to_ascii_lowercase() as u8)) => {
                     letter = Some(l.to_ascii_lowercase());
                     // uppercase macros are expanded like lowercase but are urlencoded
                     do_urlencode = l.is_ascii_uppercase();
@@ -224,7 +285,7 @@ impl<'a, E> MacroEvaluator<'a, E>
                     state = 2;
 
                     // read number as well here(if any)
-                    match Self::read_number(data)? {
+                    match Self::read_number(data) {
                         (number_offset, Some(number)) => {
                             offset += number_offset;
                             data = &data[number_offset..];
@@ -235,8 +296,8 @@ impl<'a, E> MacroEvaluator<'a, E>
                         }
                     }
                 }
-                // found reverse modifier
-                (2, 'r') => {
+                // found reverse modifier, which is case-insensitive just like rest of record
+                (2, 'r') | (2, 'R') => {
                     is_reverse = true;
                     state = 3;
                 }
@@ -249,19 +310,29 @@ impl<'a, E> MacroEvaluator<'a, E>
                     state = 0;
                     break;
                 }
-                (_, _) => {
-                    return Err(MacroEvaluationError::ParsingSyntaxError);
+                (_, c) => {
+                    return Err(self.syntax_error(offset - c.len_utf8()));
                 }
             }
         }
 
         if state != 0 {
-            return Err(MacroEvaluationError::ParsingSyntaxError);
+            return Err(self.syntax_error(offset));
         }
 
         if let Some(letter) = letter {
             debug_assert!(letter.is_ascii_alphabetic());
-            self.put_formatter(letter as u8, is_reverse, do_urlencode, number_data, delimiter)?;
+            let variable = MacroVariable::try_from(letter as u8)
+                .map_err(|_| AnyMacroVariable::from(letter as u8))?;
+            let mut delimiters = delimiter.into_iter().collect::<Vec<_>>();
+            delimiters.sort_unstable();
+            self.push_expansion(MacroExpansion {
+                variable,
+                url_encode: do_urlencode,
+                label_count: number_data,
+                reverse: is_reverse,
+                delimiters,
+            });
         }
 
         self.input = &self.input[offset..];
@@ -271,7 +342,7 @@ impl<'a, E> MacroEvaluator<'a, E>
     }
 
     fn consume_token(&mut self) -> Result<(), MacroEvaluationError> {
-        if self.input.len() == 0 {
+        if self.input.is_empty() {
             Ok(())
         } else {
             let c = self.input.chars().nth(0).unwrap();
@@ -279,25 +350,186 @@ impl<'a, E> MacroEvaluator<'a, E>
             if c == '%' {
                 self.consume_after_percentage_token()?;
             } else {
-                self.res.push(c);
+                self.literal.push(c);
             }
             Ok(())
         }
     }
 
     /// # Note
-    /// inc case of error state is corrupted and this evaluator must not be used anymore
-    fn consume_tokens(&mut self) -> Result<(), MacroEvaluationError> {
+    /// inc case of error state is corrupted and this parser must not be used anymore
+    fn consume_tokens(mut self) -> Result<Vec<MacroToken>, MacroEvaluationError> {
         loop {
-            if self.input.len() == 0 {
+            if self.input.is_empty() {
                 break;
             }
             self.consume_token()?;
         }
+        if !self.literal.is_empty() {
+            self.tokens.push(MacroToken::Literal(self.literal));
+        }
+        Ok(self.tokens)
+    }
+}
+
+impl MacroExpansion {
+    fn expand_into<E>(&self, ctx: &E, res: &mut String) -> Result<(), MacroEvaluationError>
+        where E: EvaluationContext
+    {
+        let text = ctx.provide_data(self.variable)?;
+        let mut labels = text.split(|c| {
+            if self.delimiters.is_empty() {
+                c == '.'
+            } else {
+                self.delimiters.contains(&c)
+            }
+        }).collect::<Vec<_>>();
+        if self.reverse {
+            labels.reverse();
+        }
+        // rfc 7208 section 7.3: digits select labels from the right hand side, after reversal
+        let skip = labels.len().saturating_sub(self.label_count.unwrap_or(usize::MAX));
+        let new_text = labels[skip..].join(".");
+        if self.url_encode {
+            res.extend(percent_encode(new_text.as_bytes(), is_unreserved));
+        } else {
+            res.push_str(&new_text);
+        }
         Ok(())
     }
 }
 
+/// is_unreserved checks if byte is `unreserved` char of [RFC3986](https://tools.ietf.org/html/rfc3986#section-2.3),
+/// so it does not have to be escaped.
+#[inline]
+pub fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_' || b == b'~'
+}
+
+const fn percent_encoded_table() -> [u8; 256 * 3] {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut res = [0; 256 * 3];
+    let mut i = 0;
+    while i < 256 {
+        res[i * 3] = b'%';
+        res[i * 3 + 1] = HEX[i >> 4];
+        res[i * 3 + 2] = HEX[i & 0xf];
+        i += 1;
+    }
+    res
+}
+
+/// PERCENT_ENCODED contains `%XX` escape of each byte, one after another.
+static PERCENT_ENCODED: [u8; 256 * 3] = percent_encoded_table();
+
+/// PercentEncode is iterator returned by `percent_encode`.
+#[derive(Debug, Clone)]
+pub struct PercentEncode<'a, P> {
+    bytes: &'a [u8],
+    keep: P,
+}
+
+impl<'a, P> Iterator for PercentEncode<'a, P>
+    where P: Fn(u8) -> bool
+{
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = *self.bytes.first()?;
+        if first.is_ascii() && (self.keep)(first) {
+            let len = self.bytes.iter()
+                .position(|b| !(b.is_ascii() && (self.keep)(*b)))
+                .unwrap_or(self.bytes.len());
+            let (run, rest) = self.bytes.split_at(len);
+            self.bytes = rest;
+            // run contains ASCII chars only
+            Some(std::str::from_utf8(run).unwrap())
+        } else {
+            self.bytes = &self.bytes[1..];
+            let idx = first as usize * 3;
+            Some(std::str::from_utf8(&PERCENT_ENCODED[idx..idx + 3]).unwrap())
+        }
+    }
+}
+
+/// percent_encode escapes each byte for which `keep` returns false as `%XX`, where `XX` is uppercase hex.
+/// Bytes for which `keep` returns true are copied as is, unless they are not ASCII.
+///
+/// Returned iterator yields runs of kept chars and escapes, so it does not allocate.
+/// Uppercase macros are encoded with `is_unreserved` as `keep`.
+pub fn percent_encode<P>(bytes: &[u8], keep: P) -> PercentEncode<'_, P>
+    where P: Fn(u8) -> bool
+{
+    PercentEncode {
+        bytes,
+        keep,
+    }
+}
+
+impl MacroString {
+    /// parse parses given macro string. It fails when macro string syntax is not valid.
+    pub fn parse(macro_text: &str) -> Result<Self, MacroEvaluationError> {
+        let p = MacroParser {
+            tokens: Vec::new(),
+            literal: String::new(),
+            input: macro_text,
+            text: macro_text,
+        };
+        Ok(Self {
+            tokens: p.consume_tokens()?,
+        })
+    }
+
+    /// tokens returns parts this macro string consists of.
+    #[inline]
+    pub fn tokens(&self) -> &[MacroToken] {
+        &self.tokens
+    }
+
+    /// is_literal returns true if there is no macro variable to expand in this string.
+    pub fn is_literal(&self) -> bool {
+        self.tokens.iter().all(|t| matches!(t, MacroToken::Literal(_)))
+    }
+
+    /// exp_only_variable returns first variable, which may be used only in `exp` text, if there is any.
+    pub fn exp_only_variable(&self) -> Option<MacroVariable> {
+        self.tokens.iter()
+            .filter_map(|t| match t {
+                MacroToken::Expansion(e) if e.variable.is_exp_only() => Some(e.variable),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// evaluate expands this macro string using variables from given evaluation context.
+    /// It fails with `ExpOnlyVariable` if string uses `c`, `r` or `t`, use `evaluate_exp` for `exp` text.
+    ///
+    /// # Note
+    /// It DOES NOT check validity of created data. So for instance generated domains MAY NOT BE VALID!
+    pub fn evaluate<E>(&self, evaluation_context: E) -> Result<String, MacroEvaluationError>
+        where E: EvaluationContext
+    {
+        if let Some(v) = self.exp_only_variable() {
+            return Err(MacroEvaluationError::ExpOnlyVariable(v));
+        }
+        self.evaluate_exp(evaluation_context)
+    }
+
+    /// evaluate_exp expands this macro string as `exp` text, so all variables are allowed.
+    pub fn evaluate_exp<E>(&self, evaluation_context: E) -> Result<String, MacroEvaluationError>
+        where E: EvaluationContext
+    {
+        let mut res = String::new();
+        for t in self.tokens.iter() {
+            match t {
+                MacroToken::Literal(text) => res.push_str(text),
+                MacroToken::Expansion(e) => e.expand_into(&evaluation_context, &mut res)?,
+            }
+        }
+        Ok(res)
+    }
+}
+
 /// evaluate_macro evaluates given SPF macro with given evaluation context
 ///
 /// # Docs
@@ -308,13 +540,26 @@ impl<'a, E> MacroEvaluator<'a, E>
 pub fn evaluate_macro<E>(evaluation_context: E, macro_text: &str) -> Result<String, MacroEvaluationError>
     where E: EvaluationContext
 {
-    let mut e = MacroEvaluator {
-        input: macro_text,
-        res: String::with_capacity(macro_text.len()),
-        ctx: evaluation_context,
-    };
-    e.consume_tokens()?;
-    Ok(e.res)
+    MacroString::parse(macro_text)?.evaluate(evaluation_context)
+}
+
+/// evaluate_exp_macro evaluates given SPF macro as explanation string, which unlike other macro strings
+/// may use `c`, `r` and `t` variables.
+///
+/// # Docs
+/// Take a look at [RFC7280](https://tools.ietf.org/html/rfc7208) section `7.3`
+pub fn evaluate_exp_macro<E>(evaluation_context: E, macro_text: &str) -> Result<String, MacroEvaluationError>
+    where E: EvaluationContext
+{
+    MacroString::parse(macro_text)?.evaluate_exp(evaluation_context)
+}
+
+/// validate_macro checks if given text is syntactically valid SPF macro string without evaluating it.
+///
+/// When it succeeds `evaluate_macro` for the same text may fail only because of missing variable.
+#[inline]
+pub fn validate_macro(macro_text: &str) -> Result<(), MacroEvaluationError> {
+    MacroString::parse(macro_text).map(|_| ())
 }
 
 #[cfg(test)]
@@ -338,9 +583,11 @@ mod test {
 
     #[test]
     fn test_can_evaluate_macro() {
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{r1}").unwrap(), "a");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r1}").unwrap(), "d");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r2}").unwrap(), "c.d");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r1r}").unwrap(), "a");
 
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{r10}").unwrap(), "a.b.c.d");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r10}").unwrap(), "a.b.c.d");
 
         assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "asdf").unwrap(), "asdf");
         assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%_").unwrap(), " ");
@@ -349,20 +596,139 @@ mod test {
         assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%s").unwrap(), "sender");
         assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{sr}").unwrap(), "sender");
 
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{r}").unwrap(), "a.b.c.d");
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{r0}").unwrap(), "");
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{rr}").unwrap(), "d.c.b.a");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r}").unwrap(), "a.b.c.d");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r0}").unwrap(), "");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{rr}").unwrap(), "d.c.b.a");
 
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{H}").unwrap(), "++");
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{Hr}").unwrap(), "++");
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%H").unwrap(), "++");
+        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{H}").unwrap(), "%20%20");
+        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{Hr}").unwrap(), "%20%20");
+        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%H").unwrap(), "%20%20");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{C}").unwrap(), "a.b-c%3Dd");
 
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{c.-=}").unwrap(), "a.b.c.d");
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{cr.-=}").unwrap(), "d.c.b.a");
-        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{c0r.-=}").unwrap(), "");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{c.-=}").unwrap(), "a.b.c.d");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{cr.-=}").unwrap(), "d.c.b.a");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{c0r.-=}").unwrap(), "");
 
         evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%").unwrap_err();
         evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%q").unwrap_err();
-        evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%t").unwrap_err();
+        evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%t").unwrap_err();
+    }
+
+    #[test]
+    fn test_exp_only_variables() {
+        for (text, v) in [("%{r}", MacroVariable::DomainNameOfHostPerformingTheCheck), ("%{d}.%{C}", MacroVariable::SmtpClientIp), ("%t", MacroVariable::CurrentTimestamp)].iter() {
+            assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, text), Err(MacroEvaluationError::ExpOnlyVariable(*v)), "{}", text);
+            assert_eq!(MacroString::parse(text).unwrap().exp_only_variable(), Some(*v));
+            // they are still syntactically valid
+            validate_macro(text).unwrap();
+        }
+        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{c}").unwrap_err().to_string(), "macro variable SmtpClientIp may be used only in exp text");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{s} via %{r}").unwrap(), "sender via a.b.c.d");
+        assert_eq!(MacroString::parse("%{s}.%{h}").unwrap().exp_only_variable(), None);
+    }
+
+    #[test]
+    fn test_validate_macro() {
+        validate_macro("%{ir}.%{v}._spf.%{d2}").unwrap();
+        validate_macro("%{q}").unwrap_err();
+        validate_macro("%{d").unwrap_err();
+
+        // missing variables are not syntax errors
+        validate_macro("%{p}").unwrap();
+        assert!(matches!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{p}"), Err(MacroEvaluationError::UnknownVariable(_))));
+    }
+
+    #[test]
+    fn test_syntax_error_offset() {
+        for (text, offset) in [("%{q}", 2), ("%{d", 3), ("%{d2", 4), ("ab%", 3), ("%{d2x}", 4), ("%{d}.%{ir}.%{s.q}", 15), ("ż%{ż}", 4)].iter() {
+            assert_eq!(validate_macro(text), Err(MacroEvaluationError::ParsingSyntaxError { offset: *offset }), "{}", text);
+        }
+        assert_eq!(validate_macro("%{q}").unwrap_err().to_string(), "macro syntax error at offset 2");
+    }
+
+    #[test]
+    fn test_label_count() {
+        // numbers which do not fit in usize select all labels
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r999999999999999999999}").unwrap(), "a.b.c.d");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r00000000000000000000000000002}").unwrap(), "c.d");
+        let m = MacroString::parse("%{d18446744073709551616r}").unwrap();
+        assert!(matches!(&m.tokens()[0], MacroToken::Expansion(e) if e.label_count == Some(usize::MAX) && e.reverse));
+
+        // zero is not allowed by RFC, but it's accepted and selects no labels
+        assert_eq!(evaluate_macro(&*DEFAULT_OPTIONS_MAP, "%{s0}").unwrap(), "");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "x%{r0}.y").unwrap(), "x.y");
+
+        // transformer is case-insensitive
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{r2R}").unwrap(), "b.a");
+        assert_eq!(evaluate_exp_macro(&*DEFAULT_OPTIONS_MAP, "%{RR}").unwrap(), "d.c.b.a");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_malformed_macros_do_not_panic() {
+        // inputs found with fuzzing, with offsets of chars at which parser gives up
+        for (text, offset) in [
+            ("%{s1\u{17c}}", 4),
+            ("%{s\u{17c}", 3),
+            ("%{s12", 5),
+            ("%{s1", 4),
+            ("%{s", 3),
+            ("%{", 2),
+            ("%{s99999999999999999999999999r", 30),
+            ("%{s1r\u{17c}}", 5),
+            ("%{s1.\u{1f600}}", 5),
+            ("%\u{17c}", 1),
+            ("\u{17c}%{s1}%", 8),
+        ].iter() {
+            assert_eq!(validate_macro(text), Err(MacroEvaluationError::ParsingSyntaxError { offset: *offset }), "{:?}", text);
+            assert!(text.is_char_boundary(*offset));
+        }
+    }
+
+    #[test]
+    fn test_macro_string_is_parsed_once() {
+        let m = MacroString::parse("%{ir}.%%.x").unwrap();
+        assert!(!m.is_literal());
+        assert_eq!(m.tokens().len(), 2);
+        assert_eq!(m.tokens()[1], MacroToken::Literal(".%.x".to_string()));
+        match &m.tokens()[0] {
+            MacroToken::Expansion(e) => {
+                assert_eq!(e.variable, MacroVariable::Ip);
+                assert!(e.reverse);
+                assert_eq!(e.label_count, None);
+            }
+            t => panic!("unexpected token {:?}", t),
+        }
+        assert!(matches!(m.evaluate(&*DEFAULT_OPTIONS_MAP), Err(MacroEvaluationError::UnknownVariable(_))));
+
+        assert!(MacroString::parse("example.com").unwrap().is_literal());
+        MacroString::parse("%{d").unwrap_err();
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        let encode = |bytes: &[u8]| percent_encode(bytes, is_unreserved).collect::<String>();
+
+        // unreserved chars are kept
+        assert_eq!(encode(b"AZaz09-._~"), "AZaz09-._~");
+        assert_eq!(encode(b" "), "%20");
+        assert_eq!(encode(b"%"), "%25");
+        assert_eq!(encode(b"+/=@:"), "%2B%2F%3D%40%3A");
+        assert_eq!(encode(b"\x00\x7f"), "%00%7F");
+        assert_eq!(encode(b"\x80\xff"), "%80%FF");
+        assert_eq!(encode("ż".as_bytes()), "%C5%BC");
+        assert_eq!(encode(b"a b%c"), "a%20b%25c");
+        assert_eq!(encode(b""), "");
+
+        for b in 0..=255u8 {
+            let encoded = encode(&[b]);
+            if is_unreserved(b) {
+                assert_eq!(encoded, (b as char).to_string());
+            } else {
+                assert_eq!(encoded, format!("%{:02X}", b));
+            }
+        }
+
+        // non ASCII bytes are escaped even if predicate keeps them
+        assert_eq!(percent_encode(&[b'a', 0xff], |_| true).collect::<String>(), "a%FF");
+    }
+}
@@ -6,128 +6,63 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-
+use std::net::IpAddr;
+
+pub use cidr::*;
+pub use compiled::*;
+pub use construct::*;
+pub use cost::*;
+pub use domain_spec::*;
+pub use eval::*;
+pub use flatten::*;
+pub use graph::*;
+pub use intern::*;
+pub use macro_context::*;
 pub use macro_eval::*;
 pub use parse::*;
-
+pub use resolver::*;
+#[cfg(feature = "serialize")]
+pub use serde_borrow::*;
+pub use validate::*;
+
+#[macro_use]
+mod util;
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+mod cidr;
+mod compiled;
+mod construct;
+mod cost;
+mod display;
+mod domain_spec;
 mod eval;
+mod flatten;
+mod graph;
+mod intern;
+mod macro_context;
 mod macro_eval;
+mod normalize;
+mod owned;
 mod parse;
-// TODO(teawithsand): rather than copy this macro from dnsie export it to some common place(?)
-/// flag_enum creates enum which may be either known or unknown(yet) flag.
-macro_rules! flag_enum {
-    (
-        $name:ident, $any_name:ident: $val_ty:ty {
-             $(
-                $variant_name:ident = $variant_val:tt
-             ),*
-        }
-
-    ) => {
-        #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-        #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
-        pub enum $name {
-            $(
-                $variant_name = ($variant_val) as isize
-            ),*
-        }
-
-        impl $name {
-            // deprecate this fn?
-            #[inline]
-            pub fn try_from_num(n: $val_ty) -> Result<Self, ()> {
-                Self::try_from(n)
-            }
-
-            #[inline]
-            pub fn into_num(self) -> $val_ty {
-                self.into()
-            }
-        }
-
-        impl Into<$val_ty> for $name {
-            #[inline]
-            fn into(self) -> $val_ty {
-                match self {
-                    $(
-                        Self::$variant_name => $variant_val
-                    ),*
-                }
-            }
-        }
-
-        impl TryFrom<$val_ty> for $name {
-            type Error = ();
-
-            #[inline]
-            fn try_from(val: $val_ty) -> Result<Self, Self::Error> {
-                match val {
-                    $(
-                        $variant_val => Ok(Self::$variant_name),
-                    )*
-                    _ => Err(()),
-                }
-            }
-        }
-
-        /*
-        impl Into<$any_name> for $name {
-            fn into(self) -> $any_name {
-                $any_name::Known(self)
-            }
-        }
-        */
-
-        impl From<$name> for $any_name {
-            fn from(data: $name) -> $any_name {
-                $any_name::Known(data)
-            }
-        }
-
-        #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-        #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
-        pub enum $any_name {
-            Known($name),
-            Unknown($val_ty)
-        }
-
-        impl $any_name {
-            pub fn into_canonical(self) -> Self {
-                match self {
-                    Self::Known(v) => Self::Known(v),
-                    Self::Unknown(v) => match $name::try_from(v) {
-                        Ok(new_v) => Self::Known(new_v),
-                        Err(_) => Self::Unknown(v),
-                    }
-                }
-            }
-        }
-
-        impl Into<$val_ty> for $any_name {
-            #[inline]
-            fn into(self) -> $val_ty {
-                match self {
-                    Self::Known(v) => v.into(),
-                    Self::Unknown(v) => v,
-                }
-            }
-        }
-
-        impl From<$val_ty> for $any_name {
-            #[inline]
-            fn from(val: $val_ty) -> Self {
-                Self::Unknown(val).into_canonical()
-            }
-        }
-    }
-}
+mod resolver;
+#[cfg(feature = "proptest")]
+pub mod proptest;
+mod record;
+#[cfg(feature = "serialize")]
+mod serde_borrow;
+#[cfg(feature = "serialize")]
+pub mod serde_cidr;
+#[cfg(feature = "serialize")]
+pub mod serde_str;
+mod validate;
 
 /// SPFAction decides what to do with message
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum SpfAction {
     // +
+    #[default]
     Pass = '+' as isize,
 
     // -
@@ -163,10 +98,10 @@ impl TryFrom<u8> for SpfAction {
     }
 }
 
-impl Into<char> for SpfAction {
+impl From<SpfAction> for char {
     #[inline]
-    fn into(self) -> char {
-        match self {
+    fn from(action: SpfAction) -> char {
+        match action {
             SpfAction::Pass => '+',
             SpfAction::Fail => '-',
             SpfAction::SoftFail => '~',
@@ -175,24 +110,21 @@ impl Into<char> for SpfAction {
     }
 }
 
-impl Into<u8> for SpfAction {
+impl From<SpfAction> for u8 {
     #[inline]
-    fn into(self) -> u8 {
-        let c: char = self.into();
+    fn from(action: SpfAction) -> u8 {
+        let c: char = action.into();
         c as u8
     }
 }
 
-impl Default for SpfAction {
-    fn default() -> Self {
-        Self::Pass
-    }
-}
-
 /// SpfDirectiveKind describes kind of directive that should be used
 /// It may be used to determine kin of contents of `SpfDirective`
+///
+/// It's returned by `SpfMechanism::kind`.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum SpfDirectiveKind {
     /// A points to A records of given domain
     A,
@@ -200,98 +132,428 @@ pub enum SpfDirectiveKind {
     /// AAAA points to AAAA records of given domain
     AAAA,
 
+    /// MX points to A/AAAA records of MX hosts of given domain
+    MX,
+
     /// Ipv4 describes either single IP address or range of ip addresses. for instance: `192.0.2.0/24`
     IPv4,
 
     /// Ipv6 describes either single IP address or range of ip addresses just like v4 type
     IPv6,
-}
 
-// TODO(teawithsand): Enforce Ipv4/Ipv6 restrictions of mask size during deserialization with serde
+    /// Include evaluates SPF record of other domain
+    Include,
+
+    /// Exists checks if given domain has any A record
+    Exists,
+
+    /// Redirect modifier
+    Redirect,
+
+    /// Exp(explanation) modifier
+    Exp,
+
+    /// Modifier which is not specified by RFC
+    UnknownModifier,
+
+    /// All always matches
+    All,
+
+    /// Ptr checks if validated reverse DNS name of source IP is in given domain
+    Ptr,
+}
 
 /// SpfRecord contains single full result of parsing DNS TXT record which contains spf policy.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// # Equality
+/// `PartialEq`, `Eq` and `Hash` of `SpfRecord`, `SpfDirective` and `SpfMechanism` are structural.
+/// Records which are equivalent but written differently(for instance `ip4:192.0.2.1` and `ip4:192.0.2.1/32`
+/// or domains differing only in case) are not equal and have different hashes.
+/// Any semantic comparison has to be provided by named method rather than by these traits.
+///
+/// # Serde
+/// Record, directives and mechanisms are serialized as structures mirroring their fields.
+/// Fields serialized with `serde_str` module are serialized as their text, like `"v=spf1 -ip4:192.0.2.0/24 mx"`,
+/// and deserialized with parser, which is handy for config files.
+///
+/// Deserialized records own their strings, so `SpfRecord<'static>` implements `DeserializeOwned`.
+/// Use `Borrowed` in order to borrow them from input instead.
+///
+/// # Construction
+/// Use `SpfRecord::new`, `FromIterator` or `From<Vec<SpfDirective>>` rather than struct literal.
+/// Constructing it directly is discouraged, since fields may stop being public in future.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct SpfRecord<'a> {
     /// list of directives contained by given spf dns.packet
-    pub directives: Vec<SpfDirective<'a>>,
+    pub directives: Directives<'a>,
 }
 
+/// Directives is container of directives used by `SpfRecord`.
+///
+/// It's `Vec` by default. With `smallvec` feature it's `SmallVec`, which keeps up to 8 directives inline,
+/// so typical record needs no heap allocation for them. Both support iteration, indexing, `FromIterator`
+/// and conversion from `Vec`, so code using only these does not depend on the feature.
+#[cfg(not(feature = "smallvec"))]
+pub type Directives<'a> = Vec<SpfDirective<'a>>;
+
+/// Directives is container of directives used by `SpfRecord`.
+///
+/// It's `Vec` by default. With `smallvec` feature it's `SmallVec`, which keeps up to 8 directives inline,
+/// so typical record needs no heap allocation for them. Both support iteration, indexing, `FromIterator`
+/// and conversion from `Vec`, so code using only these does not depend on the feature.
+///
+/// Note that `SmallVec` makes `SpfRecord` invariant over its lifetime, so `SpfRecord<'static>` does not coerce
+/// to shorter lifetime automatically. Use `into_owned` when records with different lifetimes have to be compared.
+#[cfg(feature = "smallvec")]
+pub type Directives<'a> = smallvec::SmallVec<[SpfDirective<'a>; 8]>;
+
 /// SpfDirective describe single directive. Many of them may be in single SpfRecord.
 ///
 /// It does not implement support for custom Spf directives.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// # Construction
+/// Use `SpfDirective::new` or `SpfDirective::from_mechanism` together with constructors of `SpfMechanism`,
+/// like `SpfMechanism::include` or `SpfMechanism::a`, which validate their arguments.
+/// Constructing it directly is discouraged, since fields may stop being public in future.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct SpfDirective<'a> {
     /// qualifier answers question: What to do when rule matched?
     pub qualifier: SpfAction,
 
+    /// explicit_qualifier is true when qualifier was written in record text, like `+` in `+mx`.
+    /// It's false for modifiers and for mechanisms with implicit `Pass` qualifier.
+    ///
+    /// It exists only to preserve original text, so `normalize` sets it only for qualifiers other than `Pass`
+    /// and `semantically_eq` ignores it.
+    #[cfg_attr(feature = "serialize", serde(default, skip_serializing_if = "std::ops::Not::not"))]
+    pub explicit_qualifier: bool,
+
     /// mechanism answers question: Should this qualifier be applied to this sender?
     pub mechanism: SpfMechanism<'a>,
 }
 
 /// SpfMechanism describes single rule which may or may not match given sender
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// # Matching
+/// New mechanisms and modifiers may be added to this enum in future, so it's marked as `#[non_exhaustive]`.
+/// For common tasks use accessors rather than `match` with wildcard arm:
+/// ```
+/// use std::net::Ipv4Addr;
+/// use spf::{DomainSpec, Ipv4Net, SpfDirectiveKind, SpfMechanism};
+///
+/// let m = SpfMechanism::Include(DomainSpec::new("_spf.example.com").unwrap());
+/// assert_eq!(m.kind(), SpfDirectiveKind::Include);
+/// assert!(m.is_include());
+/// assert_eq!(m.as_include().and_then(|d| d.as_literal()), Some("_spf.example.com"));
+/// assert_eq!(m.as_ip4(), None);
+///
+/// let net = Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap();
+/// let m = SpfMechanism::Ipv4(net);
+/// assert_eq!(m.as_ip4(), Some(net));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum SpfMechanism<'a> {
-    A(Option<Cow<'a, str>>, (Option<u8>, Option<u8>)),
-    AAAA(Option<Cow<'a, str>>, (Option<u8>, Option<u8>)),
-    MX(Option<Cow<'a, str>>, (Option<u8>, Option<u8>)),
+    A(Option<DomainSpec<'a>>, DualCidr),
+    AAAA(Option<DomainSpec<'a>>, DualCidr),
+    MX(Option<DomainSpec<'a>>, DualCidr),
 
     /// contains ipv4 address and length of address space(in bits) to check
     ///
-    /// length should be always less than or equal to `4 * 8 = 32` because there is no more bits in IPv4 addr
-    Ipv4(Ipv4Addr, Option<u8>),
+    /// length is always less than or equal to `4 * 8 = 32` because there is no more bits in IPv4 addr
+    Ipv4(Ipv4Net),
 
     /// contains ipv6 address and length of address space to check
     ///
-    ///  length should be always less than or equal to `8 * 16 = 128` because there is no more bits in IPv6 addr
-    Ipv6(Ipv6Addr, Option<u8>),
+    /// length is always less than or equal to `8 * 16 = 128` because there is no more bits in IPv6 addr
+    Ipv6(Ipv6Net),
 
-    Include(Cow<'a, str>),
+    Include(DomainSpec<'a>),
 
     // note: it contains specifier rather than string. It's kind of formatter string just like printf's first argument.
-    Exists(Cow<'a, str>),
+    Exists(DomainSpec<'a>),
 
     // note: it contains specifier rather than string. It's kind of formatter string just like printf's first argument.
-    Redirect(Cow<'a, str>),
+    Redirect(DomainSpec<'a>),
 
     /// UnknownModifier is modifier which is not specified by rfc7208(https://tools.ietf.org/html/rfc7208)
-    UnknownModifier(Cow<'a, str>, Cow<'a, str>),
+    ///
+    /// It's rare, so it's boxed in order to keep size of other mechanisms small.
+    UnknownModifier(Box<UnknownModifier<'a>>),
 
     /// Exp contains explanation message which may contain format parameters
-    Exp(Cow<'a, str>),
+    Exp(DomainSpec<'a>),
 
     All,
+
+    /// Ptr matches when validated reverse DNS name of source IP is given domain or its subdomain.
+    /// Current domain is used when domain is not given.
+    ///
+    /// RFC 7208 discourages its use, since it's slow, but it's still found in many records.
+    Ptr(Option<DomainSpec<'a>>),
+}
+
+// Many records may be kept in memory at once, so size of mechanisms is pinned here.
+// It used to be 64 bytes(72 for directive), before `DomainSpec` cache became single pointer
+// and unknown modifiers were boxed.
+#[cfg(target_pointer_width = "64")]
+const _: () = assert!(std::mem::size_of::<SpfMechanism>() == 40 && std::mem::size_of::<SpfDirective>() == 48);
+
+/// UnknownModifier is `name=value` modifier which is not specified by RFC 7208, like `foo=bar`.
+///
+/// It's serialized as structure, but sequence of name and value is accepted as well.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct UnknownModifier<'a> {
+    pub name: Cow<'a, str>,
+
+    /// value is macro string. It's kept as is.
+    pub value: Cow<'a, str>,
+}
+
+impl<'a> UnknownModifier<'a> {
+    /// new creates modifier with given name and value. Neither of them is validated.
+    pub fn new<N, V>(name: N, value: V) -> Self
+        where N: Into<Cow<'a, str>>, V: Into<Cow<'a, str>>
+    {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl<'a> From<UnknownModifier<'a>> for SpfMechanism<'a> {
+    #[inline]
+    fn from(modifier: UnknownModifier<'a>) -> Self {
+        SpfMechanism::UnknownModifier(Box::new(modifier))
+    }
 }
 
 /// ExternalResourceIdentifier describes which external resource is required to
 /// evaluate given directive or mechanism
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum ExternalResourceIdentifier<'a> {
     /// SourceIP is required in order to evaluate given directive
     SourceIP,
 
     /// SPF record(s) from given domain are required to evaluate this directive
     ///
-    /// Used to evaluate `include`
+    /// Used to evaluate `include` and `redirect`
     SPFFromDomain(Cow<'a, str>),
 
     /// True if domain created as `part_one + "." + part_two` exists or false otherwise.
     ///
     /// Used to evaluate `exists`
     DomainExists(Cow<'a, str>, Cow<'a, str>),
+
+    /// A and AAAA records of given domain are required to evaluate this directive
+    ///
+    /// Used to evaluate `a` and `aaaa` and for each host returned by MX query of `mx`
+    DomainAddresses(Cow<'a, str>),
+
+    /// MX records of given domain are required to evaluate this directive
+    ///
+    /// Used to evaluate `mx`
+    MxHosts(Cow<'a, str>),
+
+    /// Validated reverse DNS names of source IP, which are given domain or its subdomains, are required
+    /// to evaluate this directive
+    ///
+    /// Used to evaluate `ptr` and to expand `%{p}` macro, for which domain is domain of evaluated record
+    ValidatedPtrDomain(Cow<'a, str>),
+}
+
+impl<'a> SpfMechanism<'a> {
+    /// kind returns kind of this mechanism.
+    pub fn kind(&self) -> SpfDirectiveKind {
+        match self {
+            SpfMechanism::A(_, _) => SpfDirectiveKind::A,
+            SpfMechanism::AAAA(_, _) => SpfDirectiveKind::AAAA,
+            SpfMechanism::MX(_, _) => SpfDirectiveKind::MX,
+            SpfMechanism::Ipv4(_) => SpfDirectiveKind::IPv4,
+            SpfMechanism::Ipv6(_) => SpfDirectiveKind::IPv6,
+            SpfMechanism::Include(_) => SpfDirectiveKind::Include,
+            SpfMechanism::Exists(_) => SpfDirectiveKind::Exists,
+            SpfMechanism::Redirect(_) => SpfDirectiveKind::Redirect,
+            SpfMechanism::UnknownModifier(_) => SpfDirectiveKind::UnknownModifier,
+            SpfMechanism::Exp(_) => SpfDirectiveKind::Exp,
+            SpfMechanism::All => SpfDirectiveKind::All,
+            SpfMechanism::Ptr(_) => SpfDirectiveKind::Ptr,
+        }
+    }
+
+    /// is_modifier returns true if this is modifier(`name=value` term) rather than mechanism.
+    pub fn is_modifier(&self) -> bool {
+        matches!(self, SpfMechanism::Redirect(_) | SpfMechanism::Exp(_) | SpfMechanism::UnknownModifier(_))
+    }
+
+    #[inline]
+    pub fn is_include(&self) -> bool {
+        self.kind() == SpfDirectiveKind::Include
+    }
+
+    #[inline]
+    pub fn is_redirect(&self) -> bool {
+        self.kind() == SpfDirectiveKind::Redirect
+    }
+
+    #[inline]
+    pub fn is_all(&self) -> bool {
+        self.kind() == SpfDirectiveKind::All
+    }
+
+    /// as_a returns domain and dual CIDR length of `a` mechanism.
+    pub fn as_a(&self) -> Option<(Option<&DomainSpec<'a>>, DualCidr)> {
+        match self {
+            SpfMechanism::A(d, cidr) => Some((d.as_ref(), *cidr)),
+            _ => None,
+        }
+    }
+
+    /// as_aaaa returns domain and dual CIDR length of `aaaa` mechanism.
+    pub fn as_aaaa(&self) -> Option<(Option<&DomainSpec<'a>>, DualCidr)> {
+        match self {
+            SpfMechanism::AAAA(d, cidr) => Some((d.as_ref(), *cidr)),
+            _ => None,
+        }
+    }
+
+    /// as_mx returns domain and dual CIDR length of `mx` mechanism.
+    pub fn as_mx(&self) -> Option<(Option<&DomainSpec<'a>>, DualCidr)> {
+        match self {
+            SpfMechanism::MX(d, cidr) => Some((d.as_ref(), *cidr)),
+            _ => None,
+        }
+    }
+
+    /// as_ip4 returns network of `ip4` mechanism.
+    pub fn as_ip4(&self) -> Option<Ipv4Net> {
+        match self {
+            SpfMechanism::Ipv4(net) => Some(*net),
+            _ => None,
+        }
+    }
+
+    /// as_ip6 returns network of `ip6` mechanism.
+    pub fn as_ip6(&self) -> Option<Ipv6Net> {
+        match self {
+            SpfMechanism::Ipv6(net) => Some(*net),
+            _ => None,
+        }
+    }
+
+    /// as_include returns domain-spec of `include` mechanism.
+    pub fn as_include(&self) -> Option<&DomainSpec<'a>> {
+        match self {
+            SpfMechanism::Include(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// as_exists returns domain-spec of `exists` mechanism.
+    pub fn as_exists(&self) -> Option<&DomainSpec<'a>> {
+        match self {
+            SpfMechanism::Exists(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// as_redirect returns domain-spec of `redirect` modifier.
+    pub fn as_redirect(&self) -> Option<&DomainSpec<'a>> {
+        match self {
+            SpfMechanism::Redirect(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// as_exp returns domain-spec of `exp` modifier.
+    pub fn as_exp(&self) -> Option<&DomainSpec<'a>> {
+        match self {
+            SpfMechanism::Exp(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// as_ptr returns domain of `ptr` mechanism. It's `Some(None)` for `ptr` without domain.
+    pub fn as_ptr(&self) -> Option<Option<&DomainSpec<'a>>> {
+        match self {
+            SpfMechanism::Ptr(d) => Some(d.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// as_unknown_modifier returns name and value of unknown modifier.
+    pub fn as_unknown_modifier(&self) -> Option<(&str, &str)> {
+        match self {
+            SpfMechanism::UnknownModifier(m) => Some((m.name.as_ref(), m.value.as_ref())),
+            _ => None,
+        }
+    }
+
+    /// target returns domain-spec argument of this term: target of `include`, `exists`, `redirect` and `exp`
+    /// or domain of `a`, `aaaa`, `mx` and `ptr` if it's given.
+    ///
+    /// `ip4`, `ip6`, `all` and unknown modifiers have no target. Use `modifier_value` for value of unknown modifier.
+    pub fn target(&self) -> Option<&DomainSpec<'a>> {
+        match self {
+            SpfMechanism::A(d, _) | SpfMechanism::AAAA(d, _) | SpfMechanism::MX(d, _) | SpfMechanism::Ptr(d) => d.as_ref(),
+            SpfMechanism::Include(d) | SpfMechanism::Exists(d) | SpfMechanism::Redirect(d) | SpfMechanism::Exp(d) => Some(d),
+            SpfMechanism::Ipv4(_) | SpfMechanism::Ipv6(_) | SpfMechanism::UnknownModifier(_) | SpfMechanism::All => None,
+        }
+    }
+
+    /// target_mut returns mutable domain-spec argument of this term. It returns same target as `target`.
+    pub fn target_mut(&mut self) -> Option<&mut DomainSpec<'a>> {
+        match self {
+            SpfMechanism::A(d, _) | SpfMechanism::AAAA(d, _) | SpfMechanism::MX(d, _) | SpfMechanism::Ptr(d) => d.as_mut(),
+            SpfMechanism::Include(d) | SpfMechanism::Exists(d) | SpfMechanism::Redirect(d) | SpfMechanism::Exp(d) => Some(d),
+            SpfMechanism::Ipv4(_) | SpfMechanism::Ipv6(_) | SpfMechanism::UnknownModifier(_) | SpfMechanism::All => None,
+        }
+    }
+
+    /// modifier_value returns value of unknown modifier. It's raw macro string.
+    pub fn modifier_value(&self) -> Option<&str> {
+        match self {
+            SpfMechanism::UnknownModifier(m) => Some(&m.value),
+            _ => None,
+        }
+    }
 }
 
 /// ExternalResource contains external resources which may be used in order to evaluate
 /// SPF directive.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct ExternalResourceBag<'a> {
     pub source_ip: Option<IpAddr>,
-    pub existence_map: HashMap<Cow<'a, str>, bool>,
-    pub domain_record_map: HashMap<Cow<'a, str>, SpfRecord<'a>>,
+    pub existence_map: HashMap<InternedDomain, bool>,
+    pub domain_record_map: HashMap<InternedDomain, SpfRecord<'a>>,
+
+    /// address_map holds addresses from A and AAAA records of domains.
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub address_map: HashMap<InternedDomain, Vec<IpAddr>>,
+
+    /// mx_map holds hosts from MX records of domains.
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub mx_map: HashMap<InternedDomain, Vec<InternedDomain>>,
+
+    /// validated_ptr_map holds validated reverse DNS names of source IP, keyed by domain they were validated for.
+    /// Names are ones whose A or AAAA records contain source IP. Names outside of domain may be included.
+    ///
+    /// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-5.5) section `5.5`
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub validated_ptr_map: HashMap<InternedDomain, Vec<InternedDomain>>,
+
+    /// record_failure_map holds results of domains, whose SPF record can't be evaluated: `None` when domain
+    /// has no SPF record and `PermError` when it has more than one of them or its record can't be parsed.
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub record_failure_map: HashMap<InternedDomain, SpfEvaluationResult>,
 }
 
 flag_enum! {
@@ -312,7 +574,267 @@ flag_enum! {
 
 impl MacroVariable {
     /// get_valid_symbols returns reference to byte array of all valid formatter symbols
+    #[inline]
     pub fn get_valid_lowercase_symbols() -> &'static [u8] {
-        return &[b's', b'l', b'o', b'd', b'i', b'p', b'h', b'c', b'r', b't', b'v'];
+        Self::VALUES
+    }
+
+    /// is_exp_only returns true for variables, which may be used only in `exp` text: `c`, `r` and `t`.
+    #[inline]
+    pub fn is_exp_only(self) -> bool {
+        matches!(
+            self,
+            MacroVariable::SmtpClientIp | MacroVariable::DomainNameOfHostPerformingTheCheck | MacroVariable::CurrentTimestamp
+        )
+    }
+
+    /// format_ip formats address as value of `i` variable. IPv4 address is formatted in dotted-quad form.
+    /// IPv6 address is formatted as 32 dot separated hex nibbles, so that `%{ir}` reverses it like PTR name does.
+    pub fn format_ip(ip: IpAddr) -> String {
+        match ip {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => {
+                const HEX: &[u8; 16] = b"0123456789abcdef";
+                let mut res = String::with_capacity(63);
+                for b in ip.octets().iter() {
+                    if !res.is_empty() {
+                        res.push('.');
+                    }
+                    res.push(HEX[(b >> 4) as usize] as char);
+                    res.push('.');
+                    res.push(HEX[(b & 0xf) as usize] as char);
+                }
+                res
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    fn assert_object_safe<T: ?Sized>(_: Option<&T>) {}
+
+    #[test]
+    fn test_types_are_thread_safe() {
+        assert_send_sync::<SpfRecord<'static>>();
+        assert_send_sync::<SpfDirective<'static>>();
+        assert_send_sync::<SpfMechanism<'static>>();
+        assert_send_sync::<DomainSpec<'static>>();
+        assert_send_sync::<MacroString>();
+        assert_send_sync::<ExternalResourceBag<'static>>();
+        assert_send_sync::<ExternalResourceIdentifier<'static>>();
+        assert_send_sync::<DirectiveCost>();
+        assert_send_sync::<SpfEvaluationResult>();
+        assert_send_sync::<EvaluationLimits>();
+        assert_send_sync::<MacroContext>();
+        assert_send_sync::<SpfRecordBuilder>();
+        assert_send_sync::<FlattenError>();
+        assert_send_sync::<InMemoryResolver>();
+        assert_send_sync::<SpfCheckResult>();
+
+        assert_send_sync::<SpfParseError>();
+        assert_send_sync::<MacroEvaluationError>();
+        assert_send_sync::<CidrError>();
+        assert_send_sync::<DomainSpecError>();
+        assert_send_sync::<SpfEvaluationError>();
+
+        assert_object_safe::<dyn EvaluationContext>(None);
+        assert_object_safe::<dyn SpfResolver<Error = ()>>(None);
+    }
+
+    #[test]
+    fn test_evaluation_context_as_trait_object() {
+        let mut m = HashMap::new();
+        m.insert(MacroVariable::Domain, "example.com");
+        let ctx: &dyn EvaluationContext = &m;
+        assert_eq!(evaluate_macro(ctx, "%{d}").unwrap(), "example.com");
+        assert_eq!(DomainSpec::new("_spf.%{d}").unwrap().expand(ctx, "example.org").unwrap(), "_spf.example.com");
+    }
+
+    #[test]
+    fn test_format_ip() {
+        assert_eq!(MacroVariable::format_ip(IpAddr::from(Ipv4Addr::new(192, 0, 2, 3))), "192.0.2.3");
+        let ip = IpAddr::from("2001:db8::1".parse::<Ipv6Addr>().unwrap());
+        let formatted = MacroVariable::format_ip(ip);
+        assert!(formatted.starts_with("2.0.0.1.0.d.b.8.0.0.0.0"));
+        assert_eq!(formatted.split('.').count(), 32);
+
+        let ip = IpAddr::from("2001:db8::cb01".parse::<Ipv6Addr>().unwrap());
+        let mut m = HashMap::new();
+        m.insert(MacroVariable::Ip, MacroVariable::format_ip(ip));
+        m.insert(MacroVariable::InAddr, "ip6".to_string());
+        assert_eq!(evaluate_macro(&m, "%{ir}.%{v}").unwrap(), "1.0.b.c.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6");
+        m.insert(MacroVariable::Ip, MacroVariable::format_ip(IpAddr::from(Ipv4Addr::new(192, 0, 2, 3))));
+        m.insert(MacroVariable::InAddr, "in-addr".to_string());
+        assert_eq!(evaluate_macro(&m, "%{ir}.%{v}").unwrap(), "3.2.0.192.in-addr");
+    }
+
+    #[test]
+    fn test_records_hash_structurally() {
+        let record = |domain: &'static str, cidr: Option<u8>| SpfRecord::from(vec![
+            SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism: SpfMechanism::Include(DomainSpec::new(domain).unwrap()),
+            },
+            SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism: SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 1), cidr).unwrap()),
+            },
+        ]);
+
+        let mut set = HashSet::new();
+        assert!(set.insert(record("example.com", None)));
+        // equal record built from owned data is the same key
+        assert!(!set.insert(record("example.com", None).into_owned()));
+
+        // equivalent after normalization, but structurally different
+        assert!(set.insert(record("EXAMPLE.com", None)));
+        assert!(set.insert(record("example.com", Some(32))));
+        assert_eq!(set.len(), 3);
+
+        let mechanisms = set.iter()
+            .flat_map(|r| r.directives.iter().map(|d| &d.mechanism))
+            .collect::<HashSet<_>>();
+        assert_eq!(mechanisms.len(), 4);
+    }
+
+    #[test]
+    fn test_mechanism_accessors() {
+        let mechanisms = vec![
+            SpfMechanism::A(Some(DomainSpec::new("example.com").unwrap()), DualCidr::new(Some(24), None).unwrap()),
+            SpfMechanism::AAAA(None, DualCidr::new(None, Some(64)).unwrap()),
+            SpfMechanism::MX(None, DualCidr::default()),
+            SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap()),
+            SpfMechanism::Ipv6(Ipv6Net::from(Ipv6Addr::LOCALHOST)),
+            SpfMechanism::Include(DomainSpec::new("_spf.example.com").unwrap()),
+            SpfMechanism::Exists(DomainSpec::new("%{i}.example.com").unwrap()),
+            SpfMechanism::Redirect(DomainSpec::new("example.org").unwrap()),
+            SpfMechanism::from(UnknownModifier::new("foo", "bar")),
+            SpfMechanism::Exp(DomainSpec::new("exp.example.com").unwrap()),
+            SpfMechanism::All,
+            SpfMechanism::Ptr(None),
+        ];
+        let kinds = mechanisms.iter().map(|m| m.kind()).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![
+            SpfDirectiveKind::A,
+            SpfDirectiveKind::AAAA,
+            SpfDirectiveKind::MX,
+            SpfDirectiveKind::IPv4,
+            SpfDirectiveKind::IPv6,
+            SpfDirectiveKind::Include,
+            SpfDirectiveKind::Exists,
+            SpfDirectiveKind::Redirect,
+            SpfDirectiveKind::UnknownModifier,
+            SpfDirectiveKind::Exp,
+            SpfDirectiveKind::All,
+            SpfDirectiveKind::Ptr,
+        ]);
+        assert_eq!(mechanisms.iter().filter(|m| m.is_modifier()).count(), 3);
+
+        assert_eq!(mechanisms[0].as_a().map(|(d, cidr)| (d.map(DomainSpec::as_str), cidr)), Some((Some("example.com"), DualCidr::new(Some(24), None).unwrap())));
+        assert_eq!(mechanisms[1].as_aaaa(), Some((None, DualCidr::new(None, Some(64)).unwrap())));
+        assert_eq!(mechanisms[2].as_mx(), Some((None, DualCidr::default())));
+        assert_eq!(mechanisms[3].as_ip4(), Some(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap()));
+        assert_eq!(mechanisms[4].as_ip6(), Some(Ipv6Net::from(Ipv6Addr::LOCALHOST)));
+        assert_eq!(mechanisms[5].as_include().map(DomainSpec::as_str), Some("_spf.example.com"));
+        assert_eq!(mechanisms[6].as_exists().map(DomainSpec::as_str), Some("%{i}.example.com"));
+        assert_eq!(mechanisms[7].as_redirect().map(DomainSpec::as_str), Some("example.org"));
+        assert_eq!(mechanisms[8].as_unknown_modifier(), Some(("foo", "bar")));
+        assert_eq!(mechanisms[9].as_exp().map(DomainSpec::as_str), Some("exp.example.com"));
+        assert!(mechanisms[10].is_all());
+        assert_eq!(mechanisms[11].as_ptr(), Some(None));
+
+        // each accessor matches exactly one variant
+        assert_eq!(mechanisms.iter().filter(|m| m.as_a().is_some()).count(), 1);
+        assert_eq!(mechanisms.iter().filter(|m| m.as_ip4().is_some()).count(), 1);
+        assert_eq!(mechanisms.iter().filter(|m| m.as_ptr().is_some()).count(), 1);
+        assert_eq!(mechanisms.iter().filter(|m| m.as_include().is_some()).count(), 1);
+        assert_eq!(mechanisms.iter().filter(|m| m.is_include()).count(), 1);
+        assert_eq!(mechanisms.iter().filter(|m| m.is_redirect()).count(), 1);
+        assert_eq!(mechanisms.iter().filter(|m| m.as_unknown_modifier().is_some()).count(), 1);
+    }
+
+    #[test]
+    fn test_target_accessors() {
+        let domain = |d: &str| DomainSpec::new(d.to_string()).unwrap();
+        let net4 = Ipv4Net::from(Ipv4Addr::LOCALHOST);
+        let net6 = Ipv6Net::from(Ipv6Addr::LOCALHOST);
+        let cases: Vec<(SpfMechanism<'static>, Option<&str>, Option<&str>)> = vec![
+            (SpfMechanism::A(Some(domain("a.example.com")), DualCidr::default()), Some("a.example.com"), None),
+            (SpfMechanism::A(None, DualCidr::default()), None, None),
+            (SpfMechanism::AAAA(Some(domain("aaaa.example.com")), DualCidr::default()), Some("aaaa.example.com"), None),
+            (SpfMechanism::AAAA(None, DualCidr::default()), None, None),
+            (SpfMechanism::MX(Some(domain("mx.example.com")), DualCidr::default()), Some("mx.example.com"), None),
+            (SpfMechanism::MX(None, DualCidr::default()), None, None),
+            (SpfMechanism::Ipv4(net4), None, None),
+            (SpfMechanism::Ipv6(net6), None, None),
+            (SpfMechanism::Include(domain("_spf.example.com")), Some("_spf.example.com"), None),
+            (SpfMechanism::Exists(domain("%{i}.example.com")), Some("%{i}.example.com"), None),
+            (SpfMechanism::Redirect(domain("example.org")), Some("example.org"), None),
+            (SpfMechanism::from(UnknownModifier::new("foo", "bar.example.com")), None, Some("bar.example.com")),
+            (SpfMechanism::Exp(domain("exp.example.com")), Some("exp.example.com"), None),
+            (SpfMechanism::All, None, None),
+            (SpfMechanism::Ptr(Some(domain("ptr.example.com"))), Some("ptr.example.com"), None),
+            (SpfMechanism::Ptr(None), None, None),
+        ];
+
+        let mut covered = HashSet::new();
+        for (mut m, target, value) in cases {
+            // no wildcard on purpose: new variant has to be added to cases above
+            covered.insert(match &m {
+                SpfMechanism::A(_, _) => 0,
+                SpfMechanism::AAAA(_, _) => 1,
+                SpfMechanism::MX(_, _) => 2,
+                SpfMechanism::Ipv4(_) => 3,
+                SpfMechanism::Ipv6(_) => 4,
+                SpfMechanism::Include(_) => 5,
+                SpfMechanism::Exists(_) => 6,
+                SpfMechanism::Redirect(_) => 7,
+                SpfMechanism::UnknownModifier(_) => 8,
+                SpfMechanism::Exp(_) => 9,
+                SpfMechanism::All => 10,
+                SpfMechanism::Ptr(_) => 11,
+            });
+
+            assert_eq!(m.target().map(DomainSpec::as_str), target, "{:?}", m);
+            assert_eq!(m.modifier_value(), value, "{:?}", m);
+
+            if let Some(t) = m.target_mut() {
+                *t = domain("changed.example.com");
+            }
+            assert_eq!(m.target().map(DomainSpec::as_str), target.map(|_| "changed.example.com"), "{:?}", m);
+        }
+        assert_eq!(covered.len(), 12);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_explicit_qualifier_defaults_to_false() {
+        let implicit = SpfDirective {
+            qualifier: SpfAction::Pass,
+            explicit_qualifier: false,
+            mechanism: SpfMechanism::All,
+        };
+        let explicit = SpfDirective {
+            explicit_qualifier: true,
+            ..implicit.clone()
+        };
+
+        // data serialized before the flag existed looks just like implicit directive
+        let json = serde_json::to_string(&implicit).unwrap();
+        assert!(!json.contains("explicit_qualifier"));
+        assert_eq!(serde_json::from_str::<SpfDirective>(&json).unwrap(), implicit);
+
+        let json = serde_json::to_string(&explicit).unwrap();
+        assert_eq!(serde_json::from_str::<SpfDirective>(&json).unwrap(), explicit);
+    }
+}
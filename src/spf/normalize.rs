@@ -0,0 +1,155 @@
+//! Module responsible for normalizing SPF records, so records which have same meaning compare equal.
+//!
+//! Structural equality(`PartialEq`/`Hash`) is not affected by this module.
+//! Use `SpfRecord::semantically_eq` for normalization-aware comparison.
+
+use crate::spf::{
+    DomainSpec, DualCidr, Ipv4Net, Ipv6Net, MAX_IPV4_PREFIX_LENGTH, MAX_IPV6_PREFIX_LENGTH, SpfAction, SpfDirective,
+    SpfMechanism, SpfRecord, UnknownModifier,
+};
+
+/// normalize_domain_spec lowercases literal domain and removes trailing dot from it.
+/// Domain-specs containing macros are left as is, since case of macro letters is meaningful.
+fn normalize_domain_spec(d: &DomainSpec) -> DomainSpec<'static> {
+    match d.as_literal() {
+        Some(literal) => {
+            let normalized = literal.trim_end_matches('.').to_ascii_lowercase();
+            if normalized.is_empty() {
+                d.clone().into_owned()
+            } else {
+                DomainSpec::new(normalized).unwrap_or_else(|_| d.clone().into_owned())
+            }
+        }
+        None => d.clone().into_owned(),
+    }
+}
+
+fn normalize_dual_cidr(cidr: DualCidr) -> DualCidr {
+    DualCidr::new(
+        cidr.v4().filter(|l| *l != MAX_IPV4_PREFIX_LENGTH),
+        cidr.v6().filter(|l| *l != MAX_IPV6_PREFIX_LENGTH),
+    ).expect("normalized prefix lengths are in range")
+}
+
+impl<'a> SpfMechanism<'a> {
+    /// normalize returns mechanism with same meaning in canonical form:
+    /// literal domains are lowercased and have no trailing dot, default prefix lengths are removed
+    /// and unknown modifier names are lowercased.
+    pub fn normalize(&self) -> SpfMechanism<'static> {
+        match self {
+            SpfMechanism::A(d, cidr) => SpfMechanism::A(d.as_ref().map(normalize_domain_spec), normalize_dual_cidr(*cidr)),
+            SpfMechanism::AAAA(d, cidr) => SpfMechanism::AAAA(d.as_ref().map(normalize_domain_spec), normalize_dual_cidr(*cidr)),
+            SpfMechanism::MX(d, cidr) => SpfMechanism::MX(d.as_ref().map(normalize_domain_spec), normalize_dual_cidr(*cidr)),
+            SpfMechanism::Ipv4(net) => {
+                let prefix = net.prefix().filter(|l| *l != MAX_IPV4_PREFIX_LENGTH);
+                SpfMechanism::Ipv4(Ipv4Net::new(net.addr(), prefix).expect("normalized prefix length is in range"))
+            }
+            SpfMechanism::Ipv6(net) => {
+                let prefix = net.prefix().filter(|l| *l != MAX_IPV6_PREFIX_LENGTH);
+                SpfMechanism::Ipv6(Ipv6Net::new(net.addr(), prefix).expect("normalized prefix length is in range"))
+            }
+            SpfMechanism::Include(d) => SpfMechanism::Include(normalize_domain_spec(d)),
+            SpfMechanism::Exists(d) => SpfMechanism::Exists(normalize_domain_spec(d)),
+            SpfMechanism::Redirect(d) => SpfMechanism::Redirect(normalize_domain_spec(d)),
+            SpfMechanism::UnknownModifier(m) => {
+                SpfMechanism::from(UnknownModifier::new(m.name.to_ascii_lowercase(), m.value.to_string()))
+            }
+            // explanation text is shown to user, so it's case is kept
+            SpfMechanism::Exp(d) => SpfMechanism::Exp(d.clone().into_owned()),
+            SpfMechanism::All => SpfMechanism::All,
+            SpfMechanism::Ptr(d) => SpfMechanism::Ptr(d.as_ref().map(normalize_domain_spec)),
+        }
+    }
+}
+
+impl<'a> SpfDirective<'a> {
+    /// normalize returns directive with normalized mechanism. Information whether qualifier was explicit is dropped:
+    /// in canonical form only qualifiers other than `Pass` are written.
+    pub fn normalize(&self) -> SpfDirective<'static> {
+        SpfDirective {
+            qualifier: self.qualifier,
+            explicit_qualifier: self.qualifier != SpfAction::Pass,
+            mechanism: self.mechanism.normalize(),
+        }
+    }
+}
+
+impl<'a> SpfRecord<'a> {
+    /// normalize returns record with all directives normalized. Order of directives is preserved.
+    ///
+    /// Normalization is idempotent: `r.normalize().normalize() == r.normalize()`.
+    pub fn normalize(&self) -> SpfRecord<'static> {
+        self.directives.iter()
+            .map(SpfDirective::normalize)
+            .collect()
+    }
+
+    /// semantically_eq checks if both records are equal after normalization.
+    ///
+    /// Unlike `==` it treats records like `include:EXAMPLE.com` and `include:example.com.` as equal.
+    pub fn semantically_eq(&self, other: &SpfRecord) -> bool {
+        self.normalize() == other.normalize()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn record(mechanisms: Vec<SpfMechanism<'static>>) -> SpfRecord<'static> {
+        mechanisms.into_iter()
+            .map(|mechanism| SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_normalize_record() {
+        let r = record(vec![
+            SpfMechanism::Include(DomainSpec::new("_SPF.Example.COM.").unwrap()),
+            SpfMechanism::Exists(DomainSpec::new("%{I}.Example.com").unwrap()),
+            SpfMechanism::A(None, DualCidr::new(Some(32), Some(64)).unwrap()),
+            SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 1), Some(32)).unwrap()),
+            SpfMechanism::from(UnknownModifier::new("Foo", "Bar")),
+        ]);
+        let expected = record(vec![
+            SpfMechanism::Include(DomainSpec::new("_spf.example.com").unwrap()),
+            SpfMechanism::Exists(DomainSpec::new("%{I}.Example.com").unwrap()),
+            SpfMechanism::A(None, DualCidr::new(None, Some(64)).unwrap()),
+            SpfMechanism::Ipv4(Ipv4Net::from(Ipv4Addr::new(192, 0, 2, 1))),
+            SpfMechanism::from(UnknownModifier::new("foo", "Bar")),
+        ]);
+        assert_eq!(r.normalize(), expected);
+        assert_eq!(r.normalize().normalize(), expected);
+        assert_ne!(r, expected);
+        assert!(r.semantically_eq(&expected));
+    }
+
+    #[test]
+    fn test_explicit_qualifier_is_normalized_away() {
+        let implicit = record(vec![SpfMechanism::MX(None, DualCidr::default())]);
+        let mut explicit = implicit.clone();
+        explicit.directives[0].explicit_qualifier = true;
+
+        assert_ne!(implicit, explicit);
+        assert_eq!(explicit.normalize(), implicit);
+        assert!(explicit.semantically_eq(&implicit));
+
+        // qualifiers other than pass have to stay explicit, otherwise record would be invalid
+        let fail = SpfRecord::from(vec![SpfDirective::new(SpfAction::Fail, SpfMechanism::All).unwrap()]);
+        assert_eq!(fail.normalize(), fail);
+        assert!(fail.normalize().validate().is_ok());
+    }
+
+    #[test]
+    fn test_semantically_different_records() {
+        let a = record(vec![SpfMechanism::Include(DomainSpec::new("a.example.com").unwrap())]);
+        let b = record(vec![SpfMechanism::Include(DomainSpec::new("b.example.com").unwrap())]);
+        assert!(!a.semantically_eq(&b));
+    }
+}
@@ -0,0 +1,260 @@
+//! Module responsible for converting borrowing SPF types into ones with `'static` lifetime,
+//! so they may outlive text they were parsed from.
+
+use std::borrow::Cow;
+
+use crate::spf::{
+    Directives, DomainSpec, ExternalResourceBag, ExternalResourceIdentifier, SpfDirective, SpfMechanism, SpfRecord,
+    UnknownModifier,
+};
+
+/// owned_cow converts cow into owned one without excess capacity.
+#[inline]
+fn owned_cow(c: Cow<str>) -> Cow<'static, str> {
+    let mut s = c.into_owned();
+    s.shrink_to_fit();
+    Cow::Owned(s)
+}
+
+#[inline]
+fn borrowed_cow(c: &str) -> Cow<'_, str> {
+    Cow::Borrowed(c)
+}
+
+impl<'a> SpfMechanism<'a> {
+    /// into_owned converts this mechanism into one which does not borrow any data.
+    pub fn into_owned(self) -> SpfMechanism<'static> {
+        match self {
+            SpfMechanism::A(d, cidr) => SpfMechanism::A(d.map(DomainSpec::into_owned), cidr),
+            SpfMechanism::AAAA(d, cidr) => SpfMechanism::AAAA(d.map(DomainSpec::into_owned), cidr),
+            SpfMechanism::MX(d, cidr) => SpfMechanism::MX(d.map(DomainSpec::into_owned), cidr),
+            SpfMechanism::Ipv4(net) => SpfMechanism::Ipv4(net),
+            SpfMechanism::Ipv6(net) => SpfMechanism::Ipv6(net),
+            SpfMechanism::Include(d) => SpfMechanism::Include(d.into_owned()),
+            SpfMechanism::Exists(d) => SpfMechanism::Exists(d.into_owned()),
+            SpfMechanism::Redirect(d) => SpfMechanism::Redirect(d.into_owned()),
+            SpfMechanism::UnknownModifier(m) => SpfMechanism::from(UnknownModifier::new(owned_cow(m.name), owned_cow(m.value))),
+            SpfMechanism::Exp(d) => SpfMechanism::Exp(d.into_owned()),
+            SpfMechanism::All => SpfMechanism::All,
+            SpfMechanism::Ptr(d) => SpfMechanism::Ptr(d.map(DomainSpec::into_owned)),
+        }
+    }
+
+    /// as_borrowed returns view of this mechanism which borrows all it's data from `self`.
+    /// It does not allocate.
+    pub fn as_borrowed(&self) -> SpfMechanism<'_> {
+        match self {
+            SpfMechanism::A(d, cidr) => SpfMechanism::A(d.as_ref().map(DomainSpec::as_borrowed), *cidr),
+            SpfMechanism::AAAA(d, cidr) => SpfMechanism::AAAA(d.as_ref().map(DomainSpec::as_borrowed), *cidr),
+            SpfMechanism::MX(d, cidr) => SpfMechanism::MX(d.as_ref().map(DomainSpec::as_borrowed), *cidr),
+            SpfMechanism::Ipv4(net) => SpfMechanism::Ipv4(*net),
+            SpfMechanism::Ipv6(net) => SpfMechanism::Ipv6(*net),
+            SpfMechanism::Include(d) => SpfMechanism::Include(d.as_borrowed()),
+            SpfMechanism::Exists(d) => SpfMechanism::Exists(d.as_borrowed()),
+            SpfMechanism::Redirect(d) => SpfMechanism::Redirect(d.as_borrowed()),
+            SpfMechanism::UnknownModifier(m) => SpfMechanism::from(UnknownModifier::new(borrowed_cow(&m.name), borrowed_cow(&m.value))),
+            SpfMechanism::Exp(d) => SpfMechanism::Exp(d.as_borrowed()),
+            SpfMechanism::All => SpfMechanism::All,
+            SpfMechanism::Ptr(d) => SpfMechanism::Ptr(d.as_ref().map(DomainSpec::as_borrowed)),
+        }
+    }
+}
+
+impl<'a> SpfDirective<'a> {
+    /// into_owned converts this directive into one which does not borrow any data.
+    pub fn into_owned(self) -> SpfDirective<'static> {
+        SpfDirective {
+            qualifier: self.qualifier,
+            explicit_qualifier: self.explicit_qualifier,
+            mechanism: self.mechanism.into_owned(),
+        }
+    }
+
+    /// as_borrowed returns view of this directive which borrows all it's data from `self`.
+    /// It does not allocate.
+    pub fn as_borrowed(&self) -> SpfDirective<'_> {
+        SpfDirective {
+            qualifier: self.qualifier,
+            explicit_qualifier: self.explicit_qualifier,
+            mechanism: self.mechanism.as_borrowed(),
+        }
+    }
+}
+
+impl<'a> SpfRecord<'a> {
+    /// into_owned converts this record into one which does not borrow any data.
+    /// Owned record holds no excess capacity, so it's suitable for keeping in memory for long time.
+    pub fn into_owned(self) -> SpfRecord<'static> {
+        let mut directives: Directives<'static> = self.directives.into_iter()
+            .map(SpfDirective::into_owned)
+            .collect();
+        directives.shrink_to_fit();
+        SpfRecord {
+            directives,
+        }
+    }
+}
+
+impl<'a> ExternalResourceIdentifier<'a> {
+    /// into_owned converts this identifier into one which does not borrow any data.
+    pub fn into_owned(self) -> ExternalResourceIdentifier<'static> {
+        match self {
+            ExternalResourceIdentifier::SourceIP => ExternalResourceIdentifier::SourceIP,
+            ExternalResourceIdentifier::SPFFromDomain(d) => ExternalResourceIdentifier::SPFFromDomain(owned_cow(d)),
+            ExternalResourceIdentifier::DomainExists(p1, p2) => ExternalResourceIdentifier::DomainExists(owned_cow(p1), owned_cow(p2)),
+            ExternalResourceIdentifier::DomainAddresses(d) => ExternalResourceIdentifier::DomainAddresses(owned_cow(d)),
+            ExternalResourceIdentifier::MxHosts(d) => ExternalResourceIdentifier::MxHosts(owned_cow(d)),
+            ExternalResourceIdentifier::ValidatedPtrDomain(d) => ExternalResourceIdentifier::ValidatedPtrDomain(owned_cow(d)),
+        }
+    }
+}
+
+impl<'a> ExternalResourceBag<'a> {
+    /// into_owned converts this bag into one which does not borrow any data.
+    pub fn into_owned(self) -> ExternalResourceBag<'static> {
+        ExternalResourceBag {
+            source_ip: self.source_ip,
+            existence_map: self.existence_map,
+            domain_record_map: self.domain_record_map.into_iter()
+                .map(|(k, v)| (k, v.into_owned()))
+                .collect(),
+            address_map: self.address_map,
+            mx_map: self.mx_map,
+            validated_ptr_map: self.validated_ptr_map,
+            record_failure_map: self.record_failure_map,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::spf::{DomainInterner, DualCidr, Ipv4Net, SpfAction, SpfEvaluationResult};
+
+    use super::*;
+
+    fn borrowing_record(text: &str) -> SpfRecord<'_> {
+        let (include, mx) = text.split_at(text.find(' ').unwrap());
+        SpfRecord::from(vec![
+            SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism: SpfMechanism::Include(DomainSpec::new(include).unwrap()),
+            },
+            SpfDirective {
+                qualifier: SpfAction::SoftFail,
+                explicit_qualifier: true,
+                mechanism: SpfMechanism::MX(Some(DomainSpec::new(mx.trim()).unwrap()), DualCidr::new(Some(24), None).unwrap()),
+            },
+            SpfDirective {
+                qualifier: SpfAction::Fail,
+                explicit_qualifier: true,
+                mechanism: SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap()),
+            },
+        ])
+    }
+
+    /// record_from_short_lived_string is compile time proof that owned record does not borrow source text.
+    fn record_from_short_lived_string() -> SpfRecord<'static> {
+        let text = String::from("_spf.example.com mx.example.com");
+        borrowing_record(&text).into_owned()
+    }
+
+    #[test]
+    fn test_owned_record_outlives_source() {
+        let record = record_from_short_lived_string();
+
+        let text = String::from("_spf.example.com mx.example.com");
+        assert_eq!(record, borrowing_record(&text).into_owned());
+        drop(text);
+
+        for d in record.directives.iter() {
+            match &d.mechanism {
+                SpfMechanism::Include(d) | SpfMechanism::MX(Some(d), _) => assert!(matches!(d.clone().into_raw(), Cow::Owned(_))),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_as_borrowed_is_equal() {
+        let record = record_from_short_lived_string();
+        for d in record.directives.iter() {
+            assert_eq!(&d.as_borrowed(), d);
+        }
+    }
+
+    #[test]
+    fn test_owned_record_has_no_excess_capacity() {
+        let mut domain = String::with_capacity(256);
+        domain.push_str("_spf.example.com");
+        let mut directives = Vec::with_capacity(64);
+        directives.push(SpfDirective::from_mechanism(SpfMechanism::include(domain).unwrap()));
+        directives.push(SpfDirective::from_mechanism(SpfMechanism::from(UnknownModifier::new(String::with_capacity(64) + "foo", "bar"))));
+
+        let record = SpfRecord::from(directives).into_owned();
+        #[cfg(not(feature = "smallvec"))]
+        assert_eq!(record.directives.capacity(), 2);
+        match record.directives[0].mechanism.clone() {
+            SpfMechanism::Include(d) => match d.into_raw() {
+                Cow::Owned(s) => assert_eq!(s.capacity(), s.len()),
+                Cow::Borrowed(_) => panic!("owned record borrows domain"),
+            },
+            m => panic!("unexpected mechanism {:?}", m),
+        }
+        match &record.directives[1].mechanism {
+            SpfMechanism::UnknownModifier(m) => assert!(matches!(&m.name, Cow::Owned(s) if s.capacity() == s.len())),
+            m => panic!("unexpected mechanism {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_owned_bag() {
+        let text = String::from("example.com");
+        let mut bag = ExternalResourceBag {
+            source_ip: None,
+            existence_map: HashMap::new(),
+            domain_record_map: HashMap::new(),
+            address_map: HashMap::new(),
+            mx_map: HashMap::new(),
+            validated_ptr_map: HashMap::new(),
+            record_failure_map: HashMap::new(),
+        };
+        let interner = DomainInterner::new();
+        bag.insert_existence(&interner, &text, true);
+        bag.insert_record(&interner, &text, borrowing_record("a.example.com b.example.com"));
+
+        let identifier = ExternalResourceIdentifier::DomainExists(Cow::Borrowed(&text[..7]), Cow::Borrowed(&text[8..]));
+        let owned_identifier = identifier.clone().into_owned();
+        assert_eq!(identifier, owned_identifier);
+
+        let owned_bag = bag.clone().into_owned();
+        drop(bag);
+        drop(text);
+        assert_eq!(owned_bag.existence_map.get("example.com"), Some(&true));
+        assert_eq!(owned_bag.domain_record_map["example.com"].directives.len(), 3);
+    }
+
+    #[test]
+    fn test_bag_holds_records_from_dropped_buffers() {
+        let interner = DomainInterner::new();
+        let mut bag = ExternalResourceBag::default();
+
+        let outer_txt = b"v=spf1 include:_spf.example.org -all".to_vec();
+        let outer = SpfRecord::parse_bytes(&outer_txt).unwrap();
+        {
+            // included record is fetched after outer one was parsed, into buffer which is dropped right away
+            let txt = b"v=spf1 ip4:192.0.2.0/24 -all".to_vec();
+            let included = SpfRecord::parse_bytes(&txt).unwrap();
+            let owned = included.clone().into_owned();
+            assert!(owned.directives.iter().eq(included.directives.iter()));
+            bag.insert_record(&interner, "_spf.example.org", owned);
+        }
+
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(outer.evaluate(&bag, ip).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(outer.into_owned().evaluate(&bag, ip).unwrap(), SpfEvaluationResult::Pass);
+    }
+}
@@ -1,124 +1,853 @@
 use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
 
-use crate::{SpfAction, SpfDirective, SpfMechanism, SpfRecord};
+use crate::spf::{
+    CidrError, DomainSpec, DualCidr, Ipv4Net, Ipv6Net, MacroString, SpfAction, SpfDirective, SpfDirectiveKind, SpfMechanism,
+    SpfRecord, UnknownModifier,
+};
+use crate::spf::validate::is_modifier_name;
 
 /// SpfParseError is returned when parsing of given SPF record fails.
-#[derive(Debug, From)]
+///
+/// Offsets are byte offsets into parsed text. Invalid term is copied into error, so error does not borrow
+/// parsed text and can be returned as `Box<dyn Error>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum SpfParseError {
     /// InvalidRecordKind is returned when record does not start with `v=spf1`. Right now `1` is the only SPF version.
     InvalidRecordKind,
 
     /// Some non-ascii char(or some other illegal one) was found. This is against standard and such record should not be processed anymore.
-    InvalidCharFound,
+    InvalidCharFound {
+        offset: usize,
+    },
 
-    /// Format for some reason was illegal and text couldn't be parsed
-    InvalidFormat,
+    /// InvalidTerm is returned when term, which starts at given offset, is not valid mechanism or modifier.
+    InvalidTerm {
+        term: String,
+        offset: usize,
+        reason: TermParseErrorKind,
+    },
 }
 
-/*
-fn parse_addr_spec(text: &str) -> Result<(Option<u8>, Option<u8>), ()> {
-    let mut v4_addr_spec = None;
-    let mut v6_addr_spec = None;
-    if let Some(ipv4_spec) = text.split("/").nth(0) {
-        if ipv4_spec.len() > 0 {
-            v4_addr_spec = Some(u8::from_str(ipv4_spec)
-                .map_err(|_| ())?);
+impl SpfParseError {
+    /// offset returns byte offset of invalid char or of start of invalid term. For invalid record kind it's zero.
+    pub fn offset(&self) -> usize {
+        match self {
+            SpfParseError::InvalidRecordKind => 0,
+            SpfParseError::InvalidCharFound { offset } |
+            SpfParseError::InvalidTerm { offset, .. } => *offset,
         }
-    } else {
-        // if there is no '/' in string first result with is equal to text should be returned
-        // even if text is empty
-        unreachable!("This should not be reached!");
     }
-    if let Some(ipv6_spec) = text.split("/").nth(1) {
-        if ipv6_spec.len() == 0 {
-            return Err(());
+}
+
+impl fmt::Display for SpfParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfParseError::InvalidRecordKind => write!(f, "record does not start with {}", VERSION),
+            SpfParseError::InvalidCharFound { offset } => write!(f, "invalid char at offset {}", offset),
+            SpfParseError::InvalidTerm { term, offset, reason } => {
+                write!(f, "invalid term {:?} at offset {}: {}", term, offset, reason)
+            }
         }
-        v6_addr_spec = Some(u8::from_str(ipv6_spec)
-            .map_err(|_| ())?);
     }
-    Ok((v4_addr_spec, v6_addr_spec))
 }
 
-fn parse_domain_spec_with_double_cidr_length(text: &str) -> Result<Option<(&str, (Option<u8>, Option<u8>))>, ()> {
-    let mut domain = &text[..0];
-    if text.len() > 0 && text.as_bytes()[0] == b':' {
-        // TODO(teawithsand): implement this
+impl std::error::Error for SpfParseError {}
+
+/// SpfLookupError is returned when SPF record can't be selected from TXT records of domain.
+///
+/// Both `NoRecord` and `MultipleRecords` are not parse errors: first one is `None` result of check and
+/// second one is `PermError`.
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4.5) section `4.5`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpfLookupError {
+    /// NoRecord is returned when none of TXT records starts with `v=spf1`.
+    NoRecord,
+
+    /// MultipleRecords is returned when more than one TXT record starts with `v=spf1`.
+    MultipleRecords,
+
+    /// InvalidRecord is returned when the only SPF record can't be parsed.
+    InvalidRecord(SpfParseError),
+}
+
+impl From<SpfParseError> for SpfLookupError {
+    #[inline]
+    fn from(e: SpfParseError) -> Self {
+        SpfLookupError::InvalidRecord(e)
     }
-    if text.len() > 0 && text.as_bytes()[0] == b'/' {
-        // TODO(teawithsand): implement this
+}
+
+impl fmt::Display for SpfLookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfLookupError::NoRecord => write!(f, "no SPF record found"),
+            SpfLookupError::MultipleRecords => write!(f, "multiple SPF records found"),
+            SpfLookupError::InvalidRecord(e) => write!(f, "invalid SPF record: {}", e),
+        }
     }
-    todo!("Not implemented yet!");
 }
 
-impl<'a> SpfDirective<'a> {
-    pub fn parse_str(text: &'a str) -> Result<Self, SpfParseError> {
-        for c in orig_s.chars() {
-            if !c.is_ascii() {
-                return Err(SpfParseError::InvalidCharFound);
-            }
+impl std::error::Error for SpfLookupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpfLookupError::InvalidRecord(e) => Some(e),
+            SpfLookupError::NoRecord | SpfLookupError::MultipleRecords => None,
         }
+    }
+}
+
+/// TermParseErrorKind tells why term could not be parsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TermParseErrorKind {
+    /// UnknownName is returned when name is neither name of mechanism nor valid name of modifier.
+    UnknownName,
+
+    /// MissingArgument is returned when mechanism like `include` or `ip4` or modifier like `redirect`
+    /// has no argument, which it requires.
+    MissingArgument,
+
+    /// UnexpectedArgument is returned when mechanism like `all` has argument, which it does not take.
+    UnexpectedArgument,
+
+    /// InvalidCidrLength is returned when CIDR prefix length is out of range or malformed.
+    InvalidCidrLength,
+
+    /// InvalidIpAddress is returned when address of `ip4` or `ip6` mechanism can't be parsed.
+    InvalidIpAddress,
+
+    /// InvalidDomainSpec is returned when argument does not match `domain-spec` grammar.
+    InvalidDomainSpec,
 
-        if text.len() == 0 {
-            return Err(SpfParseError::InvalidFormat);
+    /// InvalidModifierValue is returned when value of unknown modifier is not valid macro string.
+    InvalidModifierValue,
+
+    /// QualifiedModifier is returned when modifier has qualifier. Modifiers have none.
+    QualifiedModifier,
+
+    /// DuplicateModifier is returned for second `redirect` or `exp` modifier of record.
+    DuplicateModifier,
+}
+
+impl fmt::Display for TermParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TermParseErrorKind::UnknownName => "unknown mechanism or invalid modifier name",
+            TermParseErrorKind::MissingArgument => "missing argument",
+            TermParseErrorKind::UnexpectedArgument => "unexpected argument",
+            TermParseErrorKind::InvalidCidrLength => "invalid CIDR length",
+            TermParseErrorKind::InvalidIpAddress => "invalid IP address",
+            TermParseErrorKind::InvalidDomainSpec => "invalid domain-spec",
+            TermParseErrorKind::InvalidModifierValue => "invalid modifier value",
+            TermParseErrorKind::QualifiedModifier => "modifier can't have qualifier",
+            TermParseErrorKind::DuplicateModifier => "modifier can appear only once",
+        })
+    }
+}
+
+impl From<CidrError> for TermParseErrorKind {
+    fn from(e: CidrError) -> Self {
+        match e {
+            CidrError::InvalidAddress => TermParseErrorKind::InvalidIpAddress,
+            _ => TermParseErrorKind::InvalidCidrLength,
+        }
+    }
+}
+
+/// TERM_NAMES maps names of mechanisms and modifiers(compared case-insensitively) to their kinds.
+const TERM_NAMES: &[(&str, SpfDirectiveKind)] = &[
+    ("a", SpfDirectiveKind::A),
+    ("aaaa", SpfDirectiveKind::AAAA),
+    ("mx", SpfDirectiveKind::MX),
+    ("ip4", SpfDirectiveKind::IPv4),
+    ("ip6", SpfDirectiveKind::IPv6),
+    ("include", SpfDirectiveKind::Include),
+    ("exists", SpfDirectiveKind::Exists),
+    ("ptr", SpfDirectiveKind::Ptr),
+    ("all", SpfDirectiveKind::All),
+    ("redirect", SpfDirectiveKind::Redirect),
+    ("exp", SpfDirectiveKind::Exp),
+];
+
+fn term_kind(name: &str) -> Option<SpfDirectiveKind> {
+    TERM_NAMES.iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, k)| *k)
+}
+
+/// parse_domain_spec borrows domain-spec from given text and checks if it matches `domain-spec` grammar.
+fn parse_domain_spec(text: &str) -> Result<DomainSpec<'_>, TermParseErrorKind> {
+    if text.is_empty() {
+        return Err(TermParseErrorKind::MissingArgument);
+    }
+    let d = DomainSpec::new(text).map_err(|_| TermParseErrorKind::InvalidDomainSpec)?;
+    d.validate().map_err(|_| TermParseErrorKind::InvalidDomainSpec)?;
+    Ok(d)
+}
+
+/// split_dual_cidr splits argument of `a` or `mx` mechanism, like `:example.com/24//64`,
+/// into domain-spec part(`:example.com`) and dual CIDR part(`/24//64`). Either of them may be empty.
+fn split_dual_cidr(text: &str) -> (&str, &str) {
+    let b = text.as_bytes();
+    let digits_start = |end: usize| {
+        let mut i = end;
+        while i > 0 && b[i - 1].is_ascii_digit() {
+            i -= 1;
         }
-        let mut text = text;
-        let action = match SpfAction::try_from(text.as_bytes()[0]) {
-            Ok(a) => {
-                text = &text[1..];
-                a
+        i
+    };
+
+    let mut end = b.len();
+    let start = digits_start(end);
+    if start < end && start >= 2 && &b[start - 2..start] == b"//" {
+        end = start - 2;
+    }
+    let start = digits_start(end);
+    if start < end && start >= 1 && b[start - 1] == b'/' && (start < 2 || b[start - 2] != b'/') {
+        end = start - 1;
+    }
+    text.split_at(end)
+}
+
+/// parse_domain_spec_with_dual_cidr parses optional `:domain-spec` followed by optional dual CIDR.
+fn parse_domain_spec_with_dual_cidr(text: &str) -> Result<(Option<DomainSpec<'_>>, DualCidr), TermParseErrorKind> {
+    let (domain, cidr) = split_dual_cidr(text);
+    let cidr = DualCidr::from_str(cidr)?;
+    let domain = if domain.is_empty() {
+        None
+    } else if let Some(domain) = domain.strip_prefix(':') {
+        Some(parse_domain_spec(domain)?)
+    } else {
+        // arguments start with `:` or `/`, so it's malformed CIDR like `a//`
+        return Err(TermParseErrorKind::InvalidCidrLength);
+    };
+    Ok((domain, cidr))
+}
+
+fn parse_mechanism<'a>(name: &str, args: &'a str) -> Result<SpfMechanism<'a>, TermParseErrorKind> {
+    let kind = match term_kind(name) {
+        Some(SpfDirectiveKind::Redirect) | Some(SpfDirectiveKind::Exp) | None => return Err(TermParseErrorKind::UnknownName),
+        Some(kind) => kind,
+    };
+    let m = match kind {
+        SpfDirectiveKind::All if args.is_empty() => SpfMechanism::All,
+        SpfDirectiveKind::A | SpfDirectiveKind::AAAA | SpfDirectiveKind::MX => {
+            let (domain, cidr) = parse_domain_spec_with_dual_cidr(args)?;
+            match kind {
+                SpfDirectiveKind::A => SpfMechanism::A(domain, cidr),
+                SpfDirectiveKind::AAAA => SpfMechanism::AAAA(domain, cidr),
+                _ => SpfMechanism::MX(domain, cidr),
             }
-            Err(_) => {
-                SpfAction::default()
+        }
+        SpfDirectiveKind::Ptr if args.is_empty() => SpfMechanism::Ptr(None),
+        SpfDirectiveKind::Ptr => {
+            let arg = args.strip_prefix(':').ok_or(TermParseErrorKind::UnexpectedArgument)?;
+            SpfMechanism::Ptr(Some(parse_domain_spec(arg)?))
+        }
+        SpfDirectiveKind::IPv4 | SpfDirectiveKind::IPv6 | SpfDirectiveKind::Include | SpfDirectiveKind::Exists => {
+            let arg = match args.strip_prefix(':') {
+                Some(arg) if !arg.is_empty() => arg,
+                _ => return Err(TermParseErrorKind::MissingArgument),
+            };
+            match kind {
+                SpfDirectiveKind::IPv4 => SpfMechanism::Ipv4(Ipv4Net::from_str(arg)?),
+                SpfDirectiveKind::IPv6 => SpfMechanism::Ipv6(Ipv6Net::from_str(arg)?),
+                SpfDirectiveKind::Include => SpfMechanism::Include(parse_domain_spec(arg)?),
+                _ => SpfMechanism::Exists(parse_domain_spec(arg)?),
             }
-        };
-        todo!("Here parse specific directives");
-        Ok(Self {
-            qualifier: action,
-            mechanism: SpfMechanism::All,
-        })
+        }
+        // `all` with arguments
+        _ => return Err(TermParseErrorKind::UnexpectedArgument),
+    };
+    Ok(m)
+}
+
+fn parse_modifier<'a>(name: &'a str, value: &'a str) -> Result<SpfMechanism<'a>, TermParseErrorKind> {
+    match term_kind(name) {
+        Some(SpfDirectiveKind::Redirect) => Ok(SpfMechanism::Redirect(parse_domain_spec(value)?)),
+        Some(SpfDirectiveKind::Exp) => Ok(SpfMechanism::Exp(parse_domain_spec(value)?)),
+        // names of mechanisms, like `a`, are valid names of unknown modifiers
+        _ if is_modifier_name(name) => {
+            MacroString::parse(value).map_err(|_| TermParseErrorKind::InvalidModifierValue)?;
+            Ok(SpfMechanism::from(UnknownModifier::new(name, value)))
+        }
+        _ => Err(TermParseErrorKind::UnknownName),
     }
 }
 
-impl<'a> SpfRecord<'a> {
-    pub fn parse_str(orig_s: &'a str) -> Result<Self, SpfParseError> {
-        // ensure that all chars are ascii chars
-        for c in orig_s.chars() {
-            if !c.is_ascii() {
-                return Err(SpfParseError::InvalidCharFound);
+impl<'a> SpfDirective<'a> {
+    /// parse_str parses single term of SPF record: mechanism with optional qualifier, like `-ip4:192.0.2.0/24`,
+    /// or modifier, like `redirect=_spf.example.com`.
+    ///
+    /// Names of mechanisms and modifiers are case-insensitive. Domain-specs and modifier values are borrowed
+    /// from given text as they are, so case of macro letters is preserved.
+    pub fn parse_str(text: &'a str) -> Result<Self, SpfParseError> {
+        if let Some(offset) = text.bytes().position(|c| !is_visible_ascii(c)) {
+            return Err(SpfParseError::InvalidCharFound { offset });
+        }
+        parse_term(text, 0)
+    }
+}
+
+#[inline]
+fn is_visible_ascii(c: u8) -> bool {
+    (0x21..=0x7e).contains(&c)
+}
+
+/// parse_term parses single term, which consists of visible ASCII chars only and starts at given offset of record.
+fn parse_term(text: &str, offset: usize) -> Result<SpfDirective<'_>, SpfParseError> {
+    parse_term_kind(text).map_err(|reason| SpfParseError::InvalidTerm {
+        term: text.to_string(),
+        offset,
+        reason,
+    })
+}
+
+fn parse_term_kind(text: &str) -> Result<SpfDirective<'_>, TermParseErrorKind> {
+    let (qualifier, explicit_qualifier, term) = match text.bytes().next().map(SpfAction::try_from) {
+        Some(Ok(q)) => (q, true, &text[1..]),
+        _ => (SpfAction::Pass, false, text),
+    };
+    let name_end = term.find([':', '/', '=']).unwrap_or(term.len());
+    let (name, args) = term.split_at(name_end);
+
+    let mechanism = match args.strip_prefix('=') {
+        // modifiers can't have qualifiers
+        Some(_) if explicit_qualifier => return Err(TermParseErrorKind::QualifiedModifier),
+        Some(value) => parse_modifier(name, value)?,
+        None => parse_mechanism(name, args)?,
+    };
+    Ok(SpfDirective {
+        qualifier,
+        explicit_qualifier,
+        mechanism,
+    })
+}
+
+/// VERSION is version tag which every SPF record starts with. It's compared case-insensitively.
+const VERSION: &str = "v=spf1";
+
+/// Terms is iterator over space separated terms of record text.
+///
+/// It checks that terms consist of visible ASCII chars while splitting them, so text is scanned only once
+/// and terms may be turned into `&str` without UTF-8 validation.
+#[derive(Debug, Clone)]
+struct Terms<'a> {
+    text: &'a [u8],
+
+    /// offset is offset of `text` in record text.
+    offset: usize,
+}
+
+impl<'a> Iterator for Terms<'a> {
+    /// Item is term together with its offset in record text.
+    type Item = Result<(&'a str, usize), SpfParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.text.iter().position(|c| *c != b' ')?;
+        let text = &self.text[start..];
+        let offset = self.offset + start;
+        let mut len = 0;
+        while len < text.len() && text[len] != b' ' {
+            if !is_visible_ascii(text[len]) {
+                self.text = &[];
+                return Some(Err(SpfParseError::InvalidCharFound { offset: offset + len }));
             }
+            len += 1;
         }
+        let (term, rest) = text.split_at(len);
+        self.text = rest;
+        self.offset = offset + len;
+
+        debug_assert!(std::str::from_utf8(term).is_ok());
+        // SAFETY: every byte of term was checked to be visible ASCII char, and ASCII text is valid UTF-8
+        Some(Ok((unsafe { std::str::from_utf8_unchecked(term) }, offset)))
+    }
+}
+
+/// SpfDirectives is iterator over directives of record text returned by `SpfRecord::iter_directives`.
+///
+/// Terms are parsed lazily and borrow from record text, so iterating does not allocate unless term is invalid.
+/// Iteration stops after first error.
+#[derive(Debug, Clone)]
+pub struct SpfDirectives<'a> {
+    terms: Terms<'a>,
+    error: Option<SpfParseError>,
+
+    /// has_redirect and has_exp are set once modifier is seen, since record may have at most one of each.
+    has_redirect: bool,
+    has_exp: bool,
+}
 
-        let s = orig_s.to_ascii_lowercase(); // all directives are case-insensitive
-        // now: All ascii chars are one byte
-        // so one may use indices from s to index orig_s and get reference to things like macro directives from there(using borrowing)
-        if !s.starts_with("v=spf1") {
-            return Err(SpfParseError::InvalidRecordKind);
+/// has_version_tag checks if text starts with `v=spf1` version tag, which is followed by space or end of text.
+pub(crate) fn has_version_tag(text: &[u8]) -> bool {
+    match text.get(..VERSION.len()) {
+        // there may be no more digits after version, like in `v=spf10`
+        Some(version) if version.eq_ignore_ascii_case(VERSION.as_bytes()) => matches!(text.get(VERSION.len()), None | Some(b' ')),
+        _ => false,
+    }
+}
+
+impl<'a> SpfDirectives<'a> {
+    fn new(text: &'a [u8]) -> Self {
+        if !has_version_tag(text) {
+            return Self::invalid_record_kind();
+        }
+        Self {
+            terms: Terms { text: &text[VERSION.len()..], offset: VERSION.len() },
+            error: None,
+            has_redirect: false,
+            has_exp: false,
         }
-        let s = s[..6].trim();
+    }
 
-        let mut directives = Vec::new();
-        for e in s.split(" ") {
-            let e = e.trim();
-            if e.len() == 0 {
-                continue;
+    fn invalid_record_kind() -> Self {
+        Self {
+            terms: Terms { text: &[], offset: 0 },
+            error: Some(SpfParseError::InvalidRecordKind),
+            has_redirect: false,
+            has_exp: false,
+        }
+    }
+}
+
+impl<'a> Iterator for SpfDirectives<'a> {
+    type Item = Result<SpfDirective<'a>, SpfParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        let res = self.terms.next()?.and_then(|(term, offset)| {
+            let directive = parse_term(term, offset)?;
+            let seen = match directive.mechanism {
+                SpfMechanism::Redirect(_) => &mut self.has_redirect,
+                SpfMechanism::Exp(_) => &mut self.has_exp,
+                _ => return Ok(directive),
+            };
+            if std::mem::replace(seen, true) {
+                return Err(SpfParseError::InvalidTerm {
+                    term: term.to_string(),
+                    offset,
+                    reason: TermParseErrorKind::DuplicateModifier,
+                });
             }
+            Ok(directive)
+        });
+        if res.is_err() {
+            self.terms.text = &[];
         }
+        Some(res)
+    }
+}
+
+impl<'a> SpfRecord<'a> {
+    /// parse_str parses SPF record text, like `v=spf1 mx include:_spf.example.com -all`.
+    ///
+    /// Text has to start with `v=spf1` version tag followed by terms separated with one or more spaces.
+    /// Domain-specs and modifier values are borrowed from given text.
+    ///
+    /// Only version tag and names of mechanisms and modifiers are matched case-insensitively. Arguments are kept
+    /// verbatim, since case of macro letters matters: `%{D}` is URL-escaped, while `%{d}` is not.
+    ///
+    /// # Example
+    /// Record borrows from text, so it can't outlive it:
+    /// ```compile_fail
+    /// use spf::SpfRecord;
+    ///
+    /// let record = {
+    ///     let text = String::from("v=spf1 exists:%{Ir}.%{V}.arpa -all");
+    ///     SpfRecord::parse_str(&text).unwrap()
+    /// };
+    /// println!("{}", record);
+    /// ```
+    pub fn parse_str(text: &'a str) -> Result<Self, SpfParseError> {
+        Self::parse_bytes(text.as_bytes())
+    }
+
+    /// parse_bytes works just like `parse_str` but takes raw bytes, like contents of TXT record.
+    ///
+    /// Bytes are checked to be ASCII while they are split into terms, so unlike `str::from_utf8`
+    /// followed by `parse_str` input is scanned once.
+    pub fn parse_bytes(text: &'a [u8]) -> Result<Self, SpfParseError> {
+        let directives = SpfDirectives::new(text).collect::<Result<_, _>>()?;
         Ok(Self {
             directives,
         })
     }
+
+    /// iter_directives parses directives of record text one by one, without collecting them into record.
+    /// Invalid version tag is returned as first and only item.
+    ///
+    /// # Example
+    /// ```
+    /// use spf::{SpfMechanism, SpfRecord};
+    ///
+    /// let includes = SpfRecord::iter_directives("v=spf1 include:_spf.example.com include:_spf.example.org -all")
+    ///     .filter_map(|d| match d.unwrap().mechanism {
+    ///         SpfMechanism::Include(d) => Some(d),
+    ///         _ => None,
+    ///     })
+    ///     .count();
+    /// assert_eq!(includes, 2);
+    /// ```
+    #[inline]
+    pub fn iter_directives(text: &'a str) -> SpfDirectives<'a> {
+        SpfDirectives::new(text.as_bytes())
+    }
+
+    /// find_and_parse selects SPF record from texts of all TXT records of domain and parses it.
+    /// Texts which do not start with `v=spf1` version tag are ignored, so that other TXT records
+    /// like domain verification tokens may be passed as well.
+    ///
+    /// Each text has to be already concatenated from its character-strings, take a look at `parse_txt_chunks`.
+    ///
+    /// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4.5) section `4.5`
+    pub fn find_and_parse(txt_records: impl IntoIterator<Item = &'a str>) -> Result<Self, SpfLookupError> {
+        let mut records = txt_records.into_iter().filter(|t| has_version_tag(t.as_bytes()));
+        match (records.next(), records.next()) {
+            (Some(text), None) => Ok(Self::parse_str(text)?),
+            (Some(_), Some(_)) => Err(SpfLookupError::MultipleRecords),
+            (None, _) => Err(SpfLookupError::NoRecord),
+        }
+    }
+}
+
+impl SpfRecord<'static> {
+    /// parse_txt_chunks parses record from character-strings of single TXT record.
+    /// They are concatenated without adding spaces between them, so term may be split across chunks.
+    /// Offsets of returned errors point into concatenated text.
+    ///
+    /// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-3.3) section `3.3`
+    pub fn parse_txt_chunks<'c, I>(chunks: I) -> Result<Self, SpfParseError>
+    where
+        I: IntoIterator<Item = &'c str>,
+    {
+        let text = chunks.into_iter().collect::<String>();
+        SpfRecord::parse_str(&text).map(SpfRecord::into_owned)
+    }
 }
-*/
 
 impl<'a> SpfRecord<'a> {
     /// join joins two SPF records into one. It's useful when parsing SPF directives
     /// contained in multiple DNS TXT records.
     pub fn join(self, other: SpfRecord<'a>) -> Self {
         let mut d = self.directives;
-        d.extend_from_slice(&other.directives);
+        d.extend(other.directives);
         Self {
             directives: d,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn domain(text: &'static str) -> DomainSpec<'static> {
+        DomainSpec::new(text).unwrap()
+    }
+
+    fn directive(qualifier: SpfAction, explicit_qualifier: bool, mechanism: SpfMechanism<'static>) -> SpfDirective<'static> {
+        SpfDirective {
+            qualifier,
+            explicit_qualifier,
+            mechanism,
+        }
+    }
+
+    #[test]
+    fn test_parse_directives() {
+        let cases = vec![
+            ("-ip4:192.0.2.0/24", directive(SpfAction::Fail, true,
+                SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 0), Some(24)).unwrap()))),
+            ("ip4:192.0.2.1", directive(SpfAction::Pass, false,
+                SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, 1), None).unwrap()))),
+            ("ip6:2001:db8::/32", directive(SpfAction::Pass, false,
+                SpfMechanism::Ipv6(Ipv6Net::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0), Some(32)).unwrap()))),
+            ("~include:_spf.example.com", directive(SpfAction::SoftFail, true,
+                SpfMechanism::Include(domain("_spf.example.com")))),
+            ("+a:%{d}/24//64", directive(SpfAction::Pass, true,
+                SpfMechanism::A(Some(domain("%{d}")), DualCidr::new(Some(24), Some(64)).unwrap()))),
+            ("a", directive(SpfAction::Pass, false, SpfMechanism::A(None, DualCidr::default()))),
+            ("a/24", directive(SpfAction::Pass, false, SpfMechanism::A(None, DualCidr::new(Some(24), None).unwrap()))),
+            ("mx//64", directive(SpfAction::Pass, false, SpfMechanism::MX(None, DualCidr::new(None, Some(64)).unwrap()))),
+            ("?mx:example.com", directive(SpfAction::Neutral, true, SpfMechanism::MX(Some(domain("example.com")), DualCidr::default()))),
+            ("aaaa:example.com//0", directive(SpfAction::Pass, false,
+                SpfMechanism::AAAA(Some(domain("example.com")), DualCidr::new(None, Some(0)).unwrap()))),
+            ("exists:%{ir}.%{v}._spf.%{d}", directive(SpfAction::Pass, false,
+                SpfMechanism::Exists(domain("%{ir}.%{v}._spf.%{d}")))),
+            ("ptr", directive(SpfAction::Pass, false, SpfMechanism::Ptr(None))),
+            ("?ptr:%{d}.example.com", directive(SpfAction::Neutral, true, SpfMechanism::Ptr(Some(domain("%{d}.example.com"))))),
+            ("-all", directive(SpfAction::Fail, true, SpfMechanism::All)),
+            ("redirect=_spf.example.com", directive(SpfAction::Pass, false, SpfMechanism::Redirect(domain("_spf.example.com")))),
+            ("exp=explain._spf.%{d}", directive(SpfAction::Pass, false, SpfMechanism::Exp(domain("explain._spf.%{d}")))),
+            ("foo=bar", directive(SpfAction::Pass, false, SpfMechanism::from(UnknownModifier::new("foo", "bar")))),
+            ("x-Ext.1=", directive(SpfAction::Pass, false, SpfMechanism::from(UnknownModifier::new("x-Ext.1", "")))),
+            ("all=x:y", directive(SpfAction::Pass, false, SpfMechanism::from(UnknownModifier::new("all", "x:y")))),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(SpfDirective::parse_str(text).unwrap(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_names_are_case_insensitive() {
+        assert_eq!(
+            SpfDirective::parse_str("-IP4:192.0.2.1").unwrap(),
+            SpfDirective::parse_str("-ip4:192.0.2.1").unwrap()
+        );
+        assert_eq!(SpfDirective::parse_str("Include:EXAMPLE.com").unwrap().mechanism,
+                   SpfMechanism::Include(domain("EXAMPLE.com")));
+        assert!(SpfDirective::parse_str("REDIRECT=example.com").unwrap().mechanism.is_redirect());
+        assert!(SpfDirective::parse_str("ALL").unwrap().mechanism.is_all());
+    }
+
+    #[test]
+    fn test_arguments_are_borrowed_verbatim() {
+        let text = String::from("exists:%{Ir}.%{V}.arpa");
+        let d = SpfDirective::parse_str(&text).unwrap();
+        let target = d.mechanism.target().unwrap();
+        assert_eq!(target.as_str(), "%{Ir}.%{V}.arpa");
+        assert!(matches!(target.clone().into_raw(), Cow::Borrowed(_)));
+
+        let d = SpfDirective::parse_str("Foo=%{S}-Denied").unwrap();
+        let (name, value) = d.mechanism.as_unknown_modifier().unwrap();
+        assert_eq!((name, value), ("Foo", "%{S}-Denied"));
+    }
+
+    #[test]
+    fn test_iter_directives() {
+        let text = String::from("V=SPF1 Include:_spf.%{D} exists:%{Ir}.%{V}.arpa Foo=%{S} -all");
+        let directives = SpfRecord::iter_directives(&text).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(directives.len(), 4);
+        for d in directives.iter().take(2) {
+            assert!(matches!(d.mechanism.target().unwrap().clone().into_raw(), Cow::Borrowed(_)));
+        }
+        assert_eq!(directives[1].mechanism.target().unwrap().as_str(), "%{Ir}.%{V}.arpa");
+        assert_eq!(directives[2].mechanism.as_unknown_modifier(), Some(("Foo", "%{S}")));
+        assert!(SpfRecord::parse_str(&text).unwrap().directives.iter().eq(directives.iter()));
+
+        // iteration stops at first error
+        let mut it = SpfRecord::iter_directives("v=spf1 mx foo -all");
+        assert!(it.next().unwrap().is_ok());
+        assert!(matches!(it.next(), Some(Err(SpfParseError::InvalidTerm { offset: 10, .. }))));
+        assert!(it.next().is_none());
+
+        for text in ["v=spf10 mx", "spf1", "", "v=spf2"].iter() {
+            let mut it = SpfRecord::iter_directives(text);
+            assert_eq!(it.next(), Some(Err(SpfParseError::InvalidRecordKind)), "{}", text);
+            assert!(it.next().is_none());
+        }
+        assert!(SpfRecord::iter_directives("v=spf1").next().is_none());
+        assert!(SpfRecord::iter_directives("v=spf1   ").next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_directives() {
+        for text in [
+            "", "+", "foo", "ip4", "ip4:", "ip4:192.0.2.0/33", "ip4:192.0.2.0/", "ip4:192.0.2.0/024", "ip4:2001:db8::",
+            "ip6:2001:db8::/129", "ip6:192.0.2.1", "a/33", "a//129", "mx/24/", "a:", "a:/24", "a//", "a///24", "ab",
+            "include", "include:", "include:example.123", "exists:%{q}.com", "ptr:", "ptr/24", "all:example.com", "all/24",
+            "?redirect=example.com", "-foo=bar", "redirect=", "exp=", "1foo=bar", "=bar", "foo=%{d", "a:b=c",
+            "ip4:192.0.2.1 ", "include:ex\u{e4}mple.com",
+        ].iter() {
+            assert!(SpfDirective::parse_str(text).is_err(), "{:?} should not be valid", text);
+        }
+        assert_eq!(SpfDirective::parse_str("a:ex\u{e4}mple.com"), Err(SpfParseError::InvalidCharFound { offset: 4 }));
+    }
+
+    #[test]
+    fn test_term_error_reasons() {
+        let cases = [
+            ("foo", TermParseErrorKind::UnknownName),
+            ("1foo=bar", TermParseErrorKind::UnknownName),
+            ("redirect:example.com", TermParseErrorKind::UnknownName),
+            ("include", TermParseErrorKind::MissingArgument),
+            ("exists:", TermParseErrorKind::MissingArgument),
+            ("ip4", TermParseErrorKind::MissingArgument),
+            ("a:/24", TermParseErrorKind::MissingArgument),
+            ("redirect=", TermParseErrorKind::MissingArgument),
+            ("all:example.com", TermParseErrorKind::UnexpectedArgument),
+            ("ptr/24", TermParseErrorKind::UnexpectedArgument),
+            ("ip4:192.0.2.0/33", TermParseErrorKind::InvalidCidrLength),
+            ("ip4:192.0.2.0/024", TermParseErrorKind::InvalidCidrLength),
+            ("a//129", TermParseErrorKind::InvalidCidrLength),
+            ("a//", TermParseErrorKind::InvalidCidrLength),
+            ("ip4:2001:db8::", TermParseErrorKind::InvalidIpAddress),
+            ("ip6:192.0.2.1", TermParseErrorKind::InvalidIpAddress),
+            ("include:example.123", TermParseErrorKind::InvalidDomainSpec),
+            ("exists:%{q}.com", TermParseErrorKind::InvalidDomainSpec),
+            ("foo=%{d", TermParseErrorKind::InvalidModifierValue),
+            ("-foo=bar", TermParseErrorKind::QualifiedModifier),
+        ];
+        for (text, expected) in cases.iter() {
+            match SpfDirective::parse_str(text) {
+                Err(SpfParseError::InvalidTerm { term, offset, reason }) => {
+                    assert_eq!((term.as_str(), offset, reason), (*text, 0, *expected), "{}", text);
+                }
+                res => panic!("unexpected result for {:?}: {:?}", text, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parsed_directives_are_valid() {
+        for text in ["a:%{d}/24//64", "exists:%{ir}.%{v}._spf.%{d}", "foo=%{l}", "redirect=%{d}", "-all"].iter() {
+            let record = SpfRecord::from(vec![SpfDirective::parse_str(text).unwrap()]);
+            assert!(record.validate().is_ok(), "{} is not valid: {:?}", text, record.validate());
+        }
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let record = SpfRecord::parse_str("v=spf1 mx include:_spf.google.com -all").unwrap();
+        assert_eq!(record.directives.len(), 3);
+        assert_eq!(record.directives[0], directive(SpfAction::Pass, false, SpfMechanism::MX(None, DualCidr::default())));
+        assert_eq!(record.directives[1], directive(SpfAction::Pass, false, SpfMechanism::Include(domain("_spf.google.com"))));
+        assert_eq!(record.directives[2], directive(SpfAction::Fail, true, SpfMechanism::All));
+
+        // terms may be separated with many spaces, trailing spaces are ignored
+        for text in ["v=spf1  mx   include:_spf.google.com -all", "v=spf1 mx include:_spf.google.com -all   ", "V=SPF1 mx include:_spf.google.com -all"].iter() {
+            assert_eq!(SpfRecord::parse_str(text).unwrap(), record, "{:?}", text);
+        }
+
+        assert_eq!(SpfRecord::parse_str("v=spf1").unwrap(), SpfRecord::empty());
+        assert_eq!(SpfRecord::parse_str("v=spf1 ").unwrap(), SpfRecord::empty());
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        for text in ["", "v=spf", "v=spf10", "v=spf2 -all", "v=spf1x -all", " v=spf1 -all", "spf1 -all", "v=spf1-all"].iter() {
+            assert!(matches!(SpfRecord::parse_str(text), Err(SpfParseError::InvalidRecordKind)), "{:?}", text);
+        }
+        for text in ["v=spf1 a\tmx", "v=spf1 -all\n", "v=spf1 include:ex\u{e4}mple.com"].iter() {
+            assert!(matches!(SpfRecord::parse_str(text), Err(SpfParseError::InvalidCharFound { .. })), "{:?}", text);
+        }
+        assert_eq!(SpfRecord::parse_bytes(b"v=spf1 a:\xffexample.com"), Err(SpfParseError::InvalidCharFound { offset: 9 }));
+        assert_eq!(SpfRecord::parse_str("v=spf1 -all\n").unwrap_err().offset(), 11);
+    }
+
+    #[test]
+    fn test_error_points_at_invalid_term() {
+        let text = "v=spf1 include:_spf.example.com  ip4:192.0.2.0/33 ~all";
+        let e = SpfRecord::parse_str(text).unwrap_err();
+        assert_eq!(e, SpfParseError::InvalidTerm {
+            term: "ip4:192.0.2.0/33".to_string(),
+            offset: 33,
+            reason: TermParseErrorKind::InvalidCidrLength,
+        });
+        assert_eq!(&text[e.offset()..e.offset() + 3], "ip4");
+        assert_eq!(e.to_string(), "invalid term \"ip4:192.0.2.0/33\" at offset 33: invalid CIDR length");
+
+        // errors compose with `?`
+        fn parse(text: &str) -> Result<usize, Box<dyn std::error::Error>> {
+            Ok(SpfRecord::parse_str(text)?.directives.len())
+        }
+        assert_eq!(parse("v=spf1 mx -all").unwrap(), 2);
+        assert_eq!(parse("v=spf2").unwrap_err().to_string(), "record does not start with v=spf1");
+    }
+
+    #[test]
+    fn test_duplicate_modifiers() {
+        let text = "v=spf1 redirect=a.example.com REDIRECT=b.example.com";
+        assert_eq!(SpfRecord::parse_str(text), Err(SpfParseError::InvalidTerm {
+            term: "REDIRECT=b.example.com".to_string(),
+            offset: 30,
+            reason: TermParseErrorKind::DuplicateModifier,
+        }));
+        assert!(matches!(SpfRecord::find_and_parse(vec![text]), Err(SpfLookupError::InvalidRecord(_))));
+
+        let text = "v=spf1 -all exp=a.example.com exp=b.example.com";
+        let mut directives = SpfRecord::iter_directives(text);
+        assert!(directives.next().unwrap().is_ok());
+        assert!(directives.next().unwrap().is_ok());
+        assert!(matches!(directives.next(), Some(Err(SpfParseError::InvalidTerm { offset: 30, reason: TermParseErrorKind::DuplicateModifier, .. }))));
+        assert!(directives.next().is_none());
+
+        // one of each is fine
+        let record = SpfRecord::parse_str("v=spf1 exp=a.example.com redirect=b.example.com").unwrap();
+        assert!(record.validate().is_ok());
+    }
+
+    #[test]
+    fn test_record_borrows_input() {
+        let text = String::from("v=spf1 exists:%{Ir}.%{V}.arpa EXP=%{S}-denied.example.com ~all");
+        let record = SpfRecord::parse_str(&text).unwrap();
+        assert_eq!(record.directives.len(), 3);
+        for d in record.directives.iter().take(2) {
+            let target = d.mechanism.target().unwrap();
+            assert!(matches!(target.clone().into_raw(), Cow::Borrowed(_)));
+            assert!(text.contains(target.as_str()));
+        }
+        assert_eq!(record.directives[0].mechanism.target().unwrap().as_str(), "%{Ir}.%{V}.arpa");
+        assert_eq!(record.directives[1].mechanism.target().unwrap().as_str(), "%{S}-denied.example.com");
+
+        let text = String::from("v=spf1 X-Note=%{S}-Denied EXP=%{S}.%{D} ~all");
+        let record = SpfRecord::parse_str(&text).unwrap();
+        match &record.directives[0].mechanism {
+            SpfMechanism::UnknownModifier(m) => {
+                assert!(matches!(m.name, Cow::Borrowed("X-Note")));
+                assert!(matches!(m.value, Cow::Borrowed("%{S}-Denied")));
+            }
+            m => panic!("unexpected mechanism {:?}", m),
+        }
+        assert!(matches!(record.exp().unwrap().clone().into_raw(), Cow::Borrowed("%{S}.%{D}")));
+
+        // domain-spec has to end with top label or macro, invalid term is reported with its original case
+        let e = SpfRecord::parse_str("v=spf1 exists:%{Ir}.%{V}.arpa EXP=%{S}-denied ~all").unwrap_err();
+        assert_eq!(e, SpfParseError::InvalidTerm {
+            term: "EXP=%{S}-denied".to_string(),
+            offset: 30,
+            reason: TermParseErrorKind::InvalidDomainSpec,
+        });
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let text = "v=spf1 ip4:192.0.2.0/24 a:%{d}/24//64 redirect=_spf.example.com";
+        assert_eq!(SpfRecord::parse_bytes(text.as_bytes()).unwrap(), SpfRecord::parse_str(text).unwrap());
+        assert_eq!(SpfRecord::parse_bytes(text.as_bytes()).unwrap().directives.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_txt_chunks() {
+        let text = "v=spf1 ip4:192.0.2.0/24 include:_spf.example.com -all";
+        let expected = SpfRecord::parse_str(text).unwrap().into_owned();
+        // split inside of version tag, mechanism name, argument and at separator
+        for at in [3, 10, 17, 23, 24, 33].iter() {
+            let (a, b) = text.split_at(*at);
+            assert_eq!(SpfRecord::parse_txt_chunks(vec![a, b]).unwrap(), expected, "{:?} {:?}", a, b);
+        }
+        assert_eq!(SpfRecord::parse_txt_chunks(text.split_inclusive(' ')).unwrap(), expected);
+        assert_eq!(SpfRecord::parse_txt_chunks(vec!["v=spf1 ", "", "-all"]).unwrap(), SpfRecord::parse_str("v=spf1 -all").unwrap());
+
+        // no space is inserted between chunks
+        assert_eq!(SpfRecord::parse_txt_chunks(vec!["v=spf1 mx", "-all"]).unwrap_err().offset(), 7);
+        assert_eq!(SpfRecord::parse_txt_chunks(vec!["v=spf", "1 ip4:192.0.2", ".300"]).unwrap_err().offset(), 7);
+        assert_eq!(SpfRecord::parse_txt_chunks(Vec::new()), Err(SpfParseError::InvalidRecordKind));
+    }
+
+    #[test]
+    fn test_find_and_parse() {
+        let texts = ["google-site-verification=abc", "v=spf1 mx -all", "v=spf10 -all", "v=spf2.0/pra -all"];
+        assert_eq!(SpfRecord::find_and_parse(texts.iter().copied()).unwrap(), SpfRecord::parse_str("v=spf1 mx -all").unwrap());
+        assert_eq!(SpfRecord::find_and_parse(vec!["V=SPF1"]).unwrap(), SpfRecord::empty());
+
+        assert_eq!(SpfRecord::find_and_parse(vec!["v=spf1 mx", "v=spf1 a"]), Err(SpfLookupError::MultipleRecords));
+        // invalid records are counted too
+        assert_eq!(SpfRecord::find_and_parse(vec!["v=spf1 mx", "v=spf1 a:"]), Err(SpfLookupError::MultipleRecords));
+        assert_eq!(SpfRecord::find_and_parse(vec!["spf1 mx", "v=spf1-all"]), Err(SpfLookupError::NoRecord));
+        assert_eq!(SpfRecord::find_and_parse(Vec::new()), Err(SpfLookupError::NoRecord));
+
+        let e = SpfRecord::find_and_parse(vec!["v=spf1 a:"]).unwrap_err();
+        assert!(matches!(e, SpfLookupError::InvalidRecord(SpfParseError::InvalidTerm { offset: 7, .. })));
+        assert!(std::error::Error::source(&e).is_some());
+    }
+}
+
@@ -0,0 +1,566 @@
+//! Module with [proptest](https://docs.rs/proptest) strategies generating valid SPF values,
+//! so downstream crates may write their own properties about SPF handling.
+//!
+//! Strategies are built from combinators over structured parts(labels, macro terms, addresses),
+//! rather than from raw strings, so failing cases shrink to small, readable records.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use ::proptest::collection::vec;
+use ::proptest::option;
+use ::proptest::prelude::*;
+use ::proptest::strategy::{BoxedStrategy, Union};
+
+use crate::spf::{
+    DomainSpec, DualCidr, Ipv4Net, Ipv6Net, MAX_IPV4_PREFIX_LENGTH, MAX_IPV6_PREFIX_LENGTH, MacroVariable,
+    SpfAction, SpfDirective, SpfDirectiveKind, SpfMechanism, SpfRecord, UnknownModifier,
+};
+
+/// StrategyConfig describes what kind of values strategies of this module generate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrategyConfig {
+    /// max_directives is maximum number of mechanisms in generated record, not counting modifiers.
+    pub max_directives: usize,
+
+    /// allowed_kinds contains kinds of mechanisms and modifiers which may be generated.
+    /// It must contain at least one mechanism kind.
+    pub allowed_kinds: Vec<SpfDirectiveKind>,
+
+    /// macros is true if domain-specs may contain macros.
+    pub macros: bool,
+}
+
+impl Default for StrategyConfig {
+    fn default() -> Self {
+        Self {
+            max_directives: 10,
+            allowed_kinds: vec![
+                SpfDirectiveKind::A,
+                SpfDirectiveKind::AAAA,
+                SpfDirectiveKind::MX,
+                SpfDirectiveKind::IPv4,
+                SpfDirectiveKind::IPv6,
+                SpfDirectiveKind::Include,
+                SpfDirectiveKind::Exists,
+                SpfDirectiveKind::Redirect,
+                SpfDirectiveKind::Exp,
+                SpfDirectiveKind::UnknownModifier,
+                SpfDirectiveKind::All,
+                SpfDirectiveKind::Ptr,
+            ],
+            macros: true,
+        }
+    }
+}
+
+impl StrategyConfig {
+    fn allows(&self, kind: SpfDirectiveKind) -> bool {
+        self.allowed_kinds.contains(&kind)
+    }
+}
+
+const TOP_LABELS: &[&str] = &["com", "org", "net", "example", "test"];
+
+/// action_strategy generates any qualifier.
+pub fn action_strategy() -> impl Strategy<Value=SpfAction> {
+    prop_oneof![
+        Just(SpfAction::Pass),
+        Just(SpfAction::Fail),
+        Just(SpfAction::SoftFail),
+        Just(SpfAction::Neutral),
+    ]
+}
+
+/// macro_variable_strategy generates any macro variable.
+pub fn macro_variable_strategy() -> impl Strategy<Value=MacroVariable> {
+    ::proptest::sample::select(MacroVariable::VARIANTS)
+}
+
+fn label_strategy() -> impl Strategy<Value=String> {
+    vec(::proptest::sample::select(&b"abcdefghijklmnopqrstuvwxyz0123456789-_"[..]), 1..12)
+        .prop_map(|chars| chars.into_iter().map(char::from).collect())
+}
+
+fn top_label_strategy() -> impl Strategy<Value=String> {
+    ::proptest::sample::select(TOP_LABELS).prop_map(str::to_string)
+}
+
+/// macro_expand_strategy generates single `%{...}` term.
+fn macro_expand_strategy() -> impl Strategy<Value=String> {
+    (
+        macro_variable_strategy(),
+        any::<bool>(),
+        option::of(1..=128u32),
+        any::<bool>(),
+        vec(::proptest::sample::select(&['.', '-', '+', ',', '/', '_', '='][..]), 0..=2),
+    ).prop_map(|(variable, url_encode, label_count, reverse, delimiters)| {
+        let letter = variable.into_num() as char;
+        let mut res = String::from("%{");
+        res.push(if url_encode { letter.to_ascii_uppercase() } else { letter });
+        if let Some(label_count) = label_count {
+            res.push_str(&label_count.to_string());
+        }
+        if reverse {
+            res.push('r');
+        }
+        res.extend(delimiters);
+        res.push('}');
+        res
+    })
+}
+
+/// macro_string_strategy generates valid macro string consisting of literal labels, `%{...}` terms and escapes.
+///
+/// When `macros` is false only literal text is generated.
+pub fn macro_string_strategy(macros: bool) -> impl Strategy<Value=String> {
+    let term = if macros {
+        prop_oneof![
+            3 => label_strategy(),
+            2 => macro_expand_strategy(),
+            1 => ::proptest::sample::select(&["%%", "%_", "%-"][..]).prop_map(str::to_string),
+        ].boxed()
+    } else {
+        label_strategy().boxed()
+    };
+    vec(term, 1..5).prop_map(|terms| terms.join("."))
+}
+
+/// domain_spec_strategy generates domain-spec which passes `DomainSpec::validate`.
+pub fn domain_spec_strategy(macros: bool) -> impl Strategy<Value=DomainSpec<'static>> {
+    let ends_with_macro = if macros {
+        any::<bool>().boxed()
+    } else {
+        Just(false).boxed()
+    };
+    (macro_string_strategy(macros), ends_with_macro, macro_expand_strategy(), top_label_strategy())
+        .prop_map(|(mut text, ends_with_macro, expand, top_label)| {
+            text.push('.');
+            if ends_with_macro {
+                text.push_str(&expand);
+            } else {
+                text.push_str(&top_label);
+            }
+            DomainSpec::new(text).expect("generated domain-spec is valid")
+        })
+}
+
+/// dual_cidr_strategy generates dual CIDR with prefix lengths in range.
+pub fn dual_cidr_strategy() -> impl Strategy<Value=DualCidr> {
+    (option::of(0..=MAX_IPV4_PREFIX_LENGTH), option::of(0..=MAX_IPV6_PREFIX_LENGTH))
+        .prop_map(|(v4, v6)| DualCidr::new(v4, v6).expect("generated prefix lengths are in range"))
+}
+
+/// ipv4_net_strategy generates IPv4 network with prefix length in range.
+pub fn ipv4_net_strategy() -> impl Strategy<Value=Ipv4Net> {
+    (any::<u32>(), option::of(0..=MAX_IPV4_PREFIX_LENGTH))
+        .prop_map(|(addr, prefix)| Ipv4Net::new(Ipv4Addr::from(addr), prefix).expect("generated prefix length is in range"))
+}
+
+/// ipv6_net_strategy generates IPv6 network with prefix length in range.
+pub fn ipv6_net_strategy() -> impl Strategy<Value=Ipv6Net> {
+    (any::<u128>(), option::of(0..=MAX_IPV6_PREFIX_LENGTH))
+        .prop_map(|(addr, prefix)| Ipv6Net::new(Ipv6Addr::from(addr), prefix).expect("generated prefix length is in range"))
+}
+
+fn modifier_name_strategy() -> impl Strategy<Value=String> {
+    (
+        ::proptest::sample::select(&b"abcdefghijklmnopqrstuvwxyz"[..]),
+        vec(::proptest::sample::select(&b"abcdefghijklmnopqrstuvwxyz0123456789-_."[..]), 0..8),
+    )
+        .prop_map(|(first, rest)| std::iter::once(first).chain(rest).map(char::from).collect::<String>())
+        .prop_filter("name of known modifier", |name| name != "redirect" && name != "exp")
+}
+
+fn kind_strategy(kind: SpfDirectiveKind, macros: bool) -> BoxedStrategy<SpfMechanism<'static>> {
+    match kind {
+        SpfDirectiveKind::A => (option::of(domain_spec_strategy(macros)), dual_cidr_strategy())
+            .prop_map(|(d, cidr)| SpfMechanism::A(d, cidr))
+            .boxed(),
+        SpfDirectiveKind::AAAA => (option::of(domain_spec_strategy(macros)), dual_cidr_strategy())
+            .prop_map(|(d, cidr)| SpfMechanism::AAAA(d, cidr))
+            .boxed(),
+        SpfDirectiveKind::MX => (option::of(domain_spec_strategy(macros)), dual_cidr_strategy())
+            .prop_map(|(d, cidr)| SpfMechanism::MX(d, cidr))
+            .boxed(),
+        SpfDirectiveKind::IPv4 => ipv4_net_strategy().prop_map(SpfMechanism::Ipv4).boxed(),
+        SpfDirectiveKind::IPv6 => ipv6_net_strategy().prop_map(SpfMechanism::Ipv6).boxed(),
+        SpfDirectiveKind::Include => domain_spec_strategy(macros).prop_map(SpfMechanism::Include).boxed(),
+        SpfDirectiveKind::Exists => domain_spec_strategy(macros).prop_map(SpfMechanism::Exists).boxed(),
+        SpfDirectiveKind::Ptr => option::of(domain_spec_strategy(macros)).prop_map(SpfMechanism::Ptr).boxed(),
+        SpfDirectiveKind::Redirect => domain_spec_strategy(macros).prop_map(SpfMechanism::Redirect).boxed(),
+        SpfDirectiveKind::Exp => domain_spec_strategy(macros).prop_map(SpfMechanism::Exp).boxed(),
+        SpfDirectiveKind::UnknownModifier => (modifier_name_strategy(), option::of(macro_string_strategy(macros)))
+            .prop_map(|(name, value)| SpfMechanism::from(UnknownModifier::new(name, value.unwrap_or_default())))
+            .boxed(),
+        _ => Just(SpfMechanism::All).boxed(),
+    }
+}
+
+/// mechanism_strategy generates mechanism or modifier of any kind allowed by config.
+///
+/// # Panics
+/// It panics when `config.allowed_kinds` is empty.
+pub fn mechanism_strategy(config: &StrategyConfig) -> BoxedStrategy<SpfMechanism<'static>> {
+    assert!(!config.allowed_kinds.is_empty(), "at least one kind has to be allowed");
+    Union::new(config.allowed_kinds.iter().map(|k| kind_strategy(*k, config.macros))).boxed()
+}
+
+/// directive_strategy generates directive holding mechanism(not modifier) of kind allowed by config.
+/// `Pass` qualifier is generated both explicit and implicit.
+///
+/// # Panics
+/// It panics when `config.allowed_kinds` contains no mechanism kinds.
+pub fn directive_strategy(config: &StrategyConfig) -> BoxedStrategy<SpfDirective<'static>> {
+    let mechanisms = config.allowed_kinds.iter()
+        .filter(|k| !matches!(k, SpfDirectiveKind::Redirect | SpfDirectiveKind::Exp | SpfDirectiveKind::UnknownModifier))
+        .map(|k| kind_strategy(*k, config.macros))
+        .collect::<Vec<_>>();
+    assert!(!mechanisms.is_empty(), "at least one mechanism kind has to be allowed");
+    (action_strategy(), any::<bool>(), Union::new(mechanisms))
+        .prop_map(|(qualifier, explicit, mechanism)| SpfDirective {
+            qualifier,
+            explicit_qualifier: explicit || qualifier != SpfAction::Pass,
+            mechanism,
+        })
+        .boxed()
+}
+
+/// spf_record_strategy generates record with up to `config.max_directives` mechanisms,
+/// followed by at most one `redirect`, at most one `exp` and some unknown modifiers(if allowed).
+///
+/// Modifiers always have `Pass` qualifier.
+pub fn spf_record_strategy(config: &StrategyConfig) -> impl Strategy<Value=SpfRecord<'static>> {
+    let modifier = |kind: SpfDirectiveKind, max: usize| if config.allows(kind) {
+        vec(kind_strategy(kind, config.macros), 0..=max).boxed()
+    } else {
+        Just(Vec::new()).boxed()
+    };
+    (
+        vec(directive_strategy(config), 0..=config.max_directives),
+        modifier(SpfDirectiveKind::Redirect, 1),
+        modifier(SpfDirectiveKind::Exp, 1),
+        modifier(SpfDirectiveKind::UnknownModifier, 2),
+    ).prop_map(|(mut directives, redirect, exp, unknown)| {
+        directives.extend(redirect.into_iter().chain(exp).chain(unknown).map(|mechanism| SpfDirective {
+            qualifier: SpfAction::Pass,
+            explicit_qualifier: false,
+            mechanism,
+        }));
+        SpfRecord::from(directives)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use crate::spf::{CompiledSpf, MacroContext, MacroEvaluationError, MacroString};
+
+    use super::*;
+
+    /// reference_contains_v4 checks if network contains address by comparing prefixes shifted down,
+    /// independently of masks used by `Ipv4Net`.
+    fn reference_contains_v4(net: u32, prefix: u8, ip: u32) -> bool {
+        prefix == 0 || net >> (32 - prefix as u32) == ip >> (32 - prefix as u32)
+    }
+
+    fn reference_contains_v6(net: u128, prefix: u8, ip: u128) -> bool {
+        prefix == 0 || net >> (128 - prefix as u32) == ip >> (128 - prefix as u32)
+    }
+
+    /// reference_check evaluates record made of `ip4`, `ip6` and `all` mechanisms, one directive after another.
+    fn reference_check(record: &SpfRecord, ip: IpAddr) -> SpfAction {
+        let (v4, v6) = match ip {
+            IpAddr::V4(ip) => (Some(u32::from(ip)), None),
+            IpAddr::V6(ip) => match ip.segments() {
+                [0, 0, 0, 0, 0, 0xffff, hi, lo] => (Some((hi as u32) << 16 | lo as u32), None),
+                _ => (None, Some(u128::from(ip))),
+            }
+        };
+        for d in record.directives.iter() {
+            let matched = match &d.mechanism {
+                SpfMechanism::Ipv4(net) => v4.is_some_and(|ip| reference_contains_v4(u32::from(net.addr()), net.prefix_len(), ip)),
+                SpfMechanism::Ipv6(net) => v6.is_some_and(|ip| reference_contains_v6(u128::from(net.addr()), net.prefix_len(), ip)),
+                SpfMechanism::All => true,
+                _ => false,
+            };
+            if matched {
+                return d.qualifier;
+            }
+        }
+        SpfAction::Neutral
+    }
+
+    /// candidate_v4 returns either random address or address of network with single bit flipped,
+    /// so candidates near prefix boundary are generated often.
+    fn candidate_v4(net: Ipv4Addr) -> impl Strategy<Value=u32> {
+        prop_oneof![
+            any::<u32>(),
+            (0..32u32).prop_map(move |bit| u32::from(net) ^ (1 << bit)),
+            Just(u32::from(net)),
+        ]
+    }
+
+    fn candidate_v6(net: Ipv6Addr) -> impl Strategy<Value=u128> {
+        prop_oneof![
+            any::<u128>(),
+            (0..128u32).prop_map(move |bit| u128::from(net) ^ (1 << bit)),
+            Just(u128::from(net)),
+        ]
+    }
+
+    /// small_v4_record_strategy generates `ip4` networks inside of `192.0.2.0/24`(or covering it).
+    fn small_v4_record_strategy() -> impl Strategy<Value=SpfRecord<'static>> {
+        let net = (any::<u8>(), prop_oneof![4 => 24..=32u8, 1 => 0..24u8])
+            .prop_map(|(host, prefix)| SpfMechanism::Ipv4(Ipv4Net::new(Ipv4Addr::new(192, 0, 2, host), Some(prefix)).unwrap()));
+        small_record_strategy(net.boxed())
+    }
+
+    /// small_v6_record_strategy generates `ip6` networks inside of `2001:db8::/120`(or covering it).
+    fn small_v6_record_strategy() -> impl Strategy<Value=SpfRecord<'static>> {
+        let net = (any::<u8>(), prop_oneof![4 => 120..=128u8, 1 => 0..120u8])
+            .prop_map(|(host, prefix)| {
+                let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, host as u16);
+                SpfMechanism::Ipv6(Ipv6Net::new(addr, Some(prefix)).unwrap())
+            });
+        small_record_strategy(net.boxed())
+    }
+
+    fn small_record_strategy(net: BoxedStrategy<SpfMechanism<'static>>) -> impl Strategy<Value=SpfRecord<'static>> {
+        (vec((action_strategy(), net), 0..8), option::of(action_strategy()))
+            .prop_map(|(nets, all)| {
+                nets.into_iter()
+                    .chain(all.map(|q| (q, SpfMechanism::All)))
+                    .map(|(qualifier, mechanism)| SpfDirective {
+                        qualifier,
+                        explicit_qualifier: qualifier != SpfAction::Pass,
+                        mechanism,
+                    })
+                    .collect()
+            })
+    }
+
+    /// restyle_domain_spec changes case of literal domain and adds trailing dot to it when asked to.
+    /// Domain-specs with macros are kept, since case of macro letters is meaningful.
+    fn restyle_domain_spec(d: &DomainSpec, upper: bool, dot: bool) -> DomainSpec<'static> {
+        let literal = match d.as_literal() {
+            Some(literal) if !literal.ends_with('.') => literal,
+            _ => return d.clone().into_owned(),
+        };
+        let mut raw = if upper { literal.to_ascii_uppercase() } else { literal.to_string() };
+        if dot {
+            raw.push('.');
+        }
+        DomainSpec::new(raw).unwrap_or_else(|_| d.clone().into_owned())
+    }
+
+    fn toggle_prefix(prefix: Option<u8>, max: u8) -> Option<u8> {
+        match prefix {
+            None => Some(max),
+            Some(l) if l == max => None,
+            l => l,
+        }
+    }
+
+    /// restyle returns record, which means the same as given one, but is written differently: each bit of style
+    /// enables one change of directive at the same index, like explicit `+`, upper case, trailing dot
+    /// or default prefix length.
+    fn restyle(record: &SpfRecord, styles: &[u8]) -> SpfRecord<'static> {
+        record.directives.iter().zip(styles.iter().chain(std::iter::repeat(&0)))
+            .map(|(d, style)| {
+                let (explicit, upper, dot, prefix) = (style & 1 != 0, style & 2 != 0, style & 4 != 0, style & 8 != 0);
+                let spec = |d: &DomainSpec| restyle_domain_spec(d, upper, dot);
+                let cidr = |c: DualCidr| if prefix {
+                    DualCidr::new(toggle_prefix(c.v4(), MAX_IPV4_PREFIX_LENGTH), toggle_prefix(c.v6(), MAX_IPV6_PREFIX_LENGTH)).unwrap()
+                } else {
+                    c
+                };
+                let mechanism = match &d.mechanism {
+                    SpfMechanism::A(t, c) => SpfMechanism::A(t.as_ref().map(spec), cidr(*c)),
+                    SpfMechanism::AAAA(t, c) => SpfMechanism::AAAA(t.as_ref().map(spec), cidr(*c)),
+                    SpfMechanism::MX(t, c) => SpfMechanism::MX(t.as_ref().map(spec), cidr(*c)),
+                    SpfMechanism::Ipv4(net) if prefix => {
+                        SpfMechanism::Ipv4(Ipv4Net::new(net.addr(), toggle_prefix(net.prefix(), MAX_IPV4_PREFIX_LENGTH)).unwrap())
+                    }
+                    SpfMechanism::Ipv6(net) if prefix => {
+                        SpfMechanism::Ipv6(Ipv6Net::new(net.addr(), toggle_prefix(net.prefix(), MAX_IPV6_PREFIX_LENGTH)).unwrap())
+                    }
+                    SpfMechanism::Include(t) => SpfMechanism::Include(spec(t)),
+                    SpfMechanism::Exists(t) => SpfMechanism::Exists(spec(t)),
+                    SpfMechanism::Redirect(t) => SpfMechanism::Redirect(spec(t)),
+                    SpfMechanism::Ptr(t) => SpfMechanism::Ptr(t.as_ref().map(spec)),
+                    SpfMechanism::UnknownModifier(m) if upper => {
+                        SpfMechanism::from(UnknownModifier::new(m.name.to_ascii_uppercase(), m.value.to_string()))
+                    }
+                    // explanation text is shown to user, so it's case matters
+                    m => m.clone().into_owned(),
+                };
+                SpfDirective {
+                    qualifier: d.qualifier,
+                    explicit_qualifier: if explicit && d.qualifier == SpfAction::Pass && !mechanism.is_modifier() {
+                        !d.explicit_qualifier
+                    } else {
+                        d.explicit_qualifier
+                    },
+                    mechanism,
+                }
+            })
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn ipv4_contains_matches_reference((net, ip) in ipv4_net_strategy().prop_flat_map(|net| (Just(net), candidate_v4(net.addr())))) {
+            let expected = reference_contains_v4(u32::from(net.addr()), net.prefix_len(), ip);
+            prop_assert_eq!(net.contains(Ipv4Addr::from(ip)), expected);
+            prop_assert_eq!(net.matches(IpAddr::V4(Ipv4Addr::from(ip))), expected);
+            // IPv4-mapped IPv6 addresses are matched by ip4 mechanisms as IPv4 ones
+            prop_assert_eq!(net.matches(IpAddr::V6(Ipv4Addr::from(ip).to_ipv6_mapped())), expected);
+            prop_assert!(net.contains(net.network()));
+            prop_assert!(reference_contains_v4(u32::from(net.network()), net.prefix_len(), u32::from(net.addr())));
+        }
+
+        #[test]
+        fn ipv6_contains_matches_reference((net, ip) in ipv6_net_strategy().prop_flat_map(|net| (Just(net), candidate_v6(net.addr())))) {
+            let expected = reference_contains_v6(u128::from(net.addr()), net.prefix_len(), ip);
+            let ip = Ipv6Addr::from(ip);
+            prop_assert_eq!(net.contains(ip), expected);
+            let mapped = ip.segments()[..6] == [0, 0, 0, 0, 0, 0xffff];
+            prop_assert_eq!(net.matches(IpAddr::V6(ip)), expected && !mapped);
+        }
+
+        #[test]
+        fn ipv6_never_matches_ipv4_sources(net in ipv6_net_strategy(), ip in any::<u32>()) {
+            let ip = Ipv4Addr::from(ip);
+            prop_assert!(!net.matches(IpAddr::V4(ip)));
+            prop_assert!(!net.matches(IpAddr::V6(ip.to_ipv6_mapped())));
+        }
+
+        #[test]
+        fn prefix_bounds(addr4 in any::<u32>(), ip4 in any::<u32>(), addr6 in any::<u128>(), ip6 in any::<u128>()) {
+            let (addr4, ip4) = (Ipv4Addr::from(addr4), Ipv4Addr::from(ip4));
+            let (addr6, ip6) = (Ipv6Addr::from(addr6), Ipv6Addr::from(ip6));
+
+            prop_assert!(Ipv4Net::new(addr4, Some(0)).unwrap().contains(ip4));
+            prop_assert!(Ipv6Net::new(addr6, Some(0)).unwrap().contains(ip6));
+
+            for net in [Ipv4Net::new(addr4, Some(MAX_IPV4_PREFIX_LENGTH)).unwrap(), Ipv4Net::from(addr4)].iter() {
+                prop_assert!(net.contains(addr4));
+                prop_assert_eq!(net.contains(ip4), ip4 == addr4);
+            }
+            for net in [Ipv6Net::new(addr6, Some(MAX_IPV6_PREFIX_LENGTH)).unwrap(), Ipv6Net::from(addr6)].iter() {
+                prop_assert!(net.contains(addr6));
+                prop_assert_eq!(net.contains(ip6), ip6 == addr6);
+            }
+        }
+
+        #[test]
+        fn compiled_v4_matches_enumeration(record in small_v4_record_strategy()) {
+            let compiled = CompiledSpf::compile(&record).unwrap();
+            for host in 0..=255u8 {
+                let ip = Ipv4Addr::new(192, 0, 2, host);
+                prop_assert_eq!(compiled.check(IpAddr::V4(ip)), reference_check(&record, IpAddr::V4(ip)), "{}", ip);
+                let mapped = IpAddr::V6(ip.to_ipv6_mapped());
+                prop_assert_eq!(compiled.check(mapped), reference_check(&record, mapped), "{}", mapped);
+            }
+            // addresses around enumerated range
+            for ip in [Ipv4Addr::new(192, 0, 1, 255), Ipv4Addr::new(192, 0, 3, 0), Ipv4Addr::new(0, 0, 0, 0)].iter() {
+                prop_assert_eq!(compiled.check(IpAddr::V4(*ip)), reference_check(&record, IpAddr::V4(*ip)), "{}", ip);
+            }
+        }
+
+        #[test]
+        fn compiled_v6_matches_enumeration(record in small_v6_record_strategy()) {
+            let compiled = CompiledSpf::compile(&record).unwrap();
+            for host in 0..=256u16 {
+                let ip = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, host));
+                prop_assert_eq!(compiled.check(ip), reference_check(&record, ip), "{}", ip);
+            }
+            let before = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb7, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff));
+            prop_assert_eq!(compiled.check(before), reference_check(&record, before));
+        }
+
+        #[test]
+        fn display_round_trips(record in spf_record_strategy(&StrategyConfig::default())) {
+            // generated records mix explicit and implicit qualifiers, so text has to be reproduced byte for byte
+            let text = record.to_string();
+            let parsed = SpfRecord::parse_str(&text).unwrap();
+            prop_assert_eq!(parsed.to_string(), text.as_str());
+            prop_assert_eq!(parsed.into_owned(), record);
+        }
+
+        #[test]
+        fn normalize_is_idempotent(record in spf_record_strategy(&StrategyConfig::default())) {
+            let normalized = record.normalize();
+            prop_assert_eq!(normalized.normalize(), normalized);
+        }
+
+        #[test]
+        fn semantic_equality_is_equivalence(
+            a in spf_record_strategy(&StrategyConfig::default()),
+            b_styles in vec(any::<u8>(), 0..12),
+            c_styles in vec(any::<u8>(), 0..12),
+            other in spf_record_strategy(&StrategyConfig::default()),
+        ) {
+            // b and c differ from a structurally, but mean the same
+            let b = restyle(&a, &b_styles);
+            let c = restyle(&b, &c_styles);
+
+            prop_assert!(a.semantically_eq(&a));
+            prop_assert!(a.semantically_eq(&b), "{} and {}", a, b);
+            prop_assert!(b.semantically_eq(&a));
+            prop_assert!(b.semantically_eq(&c), "{} and {}", b, c);
+            prop_assert!(a.semantically_eq(&c), "{} and {}", a, c);
+            prop_assert_eq!(a.semantically_eq(&other), other.semantically_eq(&a));
+            prop_assert_eq!(a.semantically_eq(&other), a.normalize() == other.normalize());
+        }
+
+        #[test]
+        fn explicit_qualifier_is_not_semantic(record in spf_record_strategy(&StrategyConfig::default())) {
+            let mut flipped = record.clone();
+            for d in flipped.directives.iter_mut().filter(|d| d.qualifier == SpfAction::Pass && !d.mechanism.is_modifier()) {
+                d.explicit_qualifier = !d.explicit_qualifier;
+            }
+            prop_assert!(record.semantically_eq(&flipped));
+            prop_assert!(record.normalize().directives.iter().all(|d| d.explicit_qualifier == (d.qualifier != SpfAction::Pass)));
+        }
+
+        #[test]
+        fn generated_domain_specs_are_valid(record in spf_record_strategy(&StrategyConfig::default())) {
+            for d in record.directives.iter() {
+                if let Some(domain) = d.mechanism.target() {
+                    prop_assert!(domain.validate().is_ok(), "{} is not valid", domain);
+                }
+            }
+        }
+
+        #[test]
+        fn config_limits_kinds(record in spf_record_strategy(&StrategyConfig {
+            max_directives: 3,
+            allowed_kinds: vec![SpfDirectiveKind::IPv4, SpfDirectiveKind::Include],
+            macros: false,
+        })) {
+            prop_assert!(record.directives.len() <= 3);
+            for d in record.directives.iter() {
+                prop_assert!(matches!(d.mechanism.kind(), SpfDirectiveKind::IPv4 | SpfDirectiveKind::Include));
+                if let Some(include) = d.mechanism.as_include() {
+                    prop_assert!(include.is_literal());
+                }
+            }
+        }
+
+        #[test]
+        fn malformed_macros_do_not_panic(text in "(%|\\{|\\}|[0-9]{1,24}|[sSlLoOdDiIpPvVhHcCrRtT]|[.+,/_=-]|\u{17c}|\u{1f600}){0,12}") {
+            let ctx = MacroContext::new("user@example.com", "example.com", IpAddr::from(Ipv4Addr::new(192, 0, 2, 3)), "mx.example.org")
+                .with_exp_variables("mail.example.net", 1_000_000_000);
+            match MacroString::parse(&text) {
+                Ok(m) => {
+                    let _ = m.evaluate(&ctx);
+                    let _ = m.evaluate_exp(&ctx);
+                }
+                Err(MacroEvaluationError::ParsingSyntaxError { offset }) => prop_assert!(text.is_char_boundary(offset)),
+                Err(e) => prop_assert!(false, "{:?} can't be parsed: {:?}", text, e),
+            }
+        }
+    }
+}
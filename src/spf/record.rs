@@ -0,0 +1,199 @@
+//! Module with iterator and collection helpers for `SpfRecord`.
+
+use std::iter::FromIterator;
+
+use crate::spf::{Directives, DomainSpec, SpfDirective, SpfRecord};
+
+impl<'a> SpfRecord<'a> {
+    /// mechanisms returns directives, which are mechanisms, in order they are evaluated.
+    /// Modifiers like `redirect`, `exp` and unknown ones are skipped.
+    pub fn mechanisms(&self) -> impl Iterator<Item=&SpfDirective<'a>> {
+        self.directives.iter().filter(|d| !d.mechanism.is_modifier())
+    }
+
+    /// modifiers returns directives, which are modifiers, including unknown ones, in order of record.
+    /// Unknown modifiers keep their name and value verbatim.
+    pub fn modifiers(&self) -> impl Iterator<Item=&SpfDirective<'a>> {
+        self.directives.iter().filter(|d| d.mechanism.is_modifier())
+    }
+
+    /// redirect returns target of `redirect` modifier of this record. When there are many of them,
+    /// which is invalid, first one is returned.
+    ///
+    /// `redirect` is returned even if record has `all` mechanism, which makes it ignored.
+    pub fn redirect(&self) -> Option<&DomainSpec<'a>> {
+        self.directives.iter().find_map(|d| d.mechanism.as_redirect())
+    }
+
+    /// exp returns domain-spec of `exp` modifier of this record. When there are many of them,
+    /// which is invalid, first one is returned.
+    pub fn exp(&self) -> Option<&DomainSpec<'a>> {
+        self.directives.iter().find_map(|d| d.mechanism.as_exp())
+    }
+
+    /// retain keeps only directives for which `f` returns true. Order of directives is preserved.
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&SpfDirective<'a>) -> bool
+    {
+        self.directives.retain(|d| f(d));
+    }
+
+    /// map_directives creates new record by applying `f` to each directive of this one.
+    pub fn map_directives<'b, F>(self, f: F) -> SpfRecord<'b>
+        where F: FnMut(SpfDirective<'a>) -> SpfDirective<'b>
+    {
+        self.directives.into_iter().map(f).collect()
+    }
+}
+
+impl<'a> IntoIterator for SpfRecord<'a> {
+    type Item = SpfDirective<'a>;
+    type IntoIter = <Directives<'a> as IntoIterator>::IntoIter;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.directives.into_iter()
+    }
+}
+
+impl<'r, 'a> IntoIterator for &'r SpfRecord<'a> {
+    type Item = &'r SpfDirective<'a>;
+    type IntoIter = std::slice::Iter<'r, SpfDirective<'a>>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.directives.iter()
+    }
+}
+
+/// Directives are collected in order they were yielded by iterator.
+///
+/// # Example
+/// ```
+/// use spf::{SpfAction, SpfDirective, SpfDirectiveKind, SpfMechanism, SpfRecord};
+///
+/// let record: SpfRecord = vec![
+///     SpfDirective::from_mechanism(SpfMechanism::mx().build().unwrap()),
+///     SpfDirective::from_mechanism(SpfMechanism::ptr(None::<&str>).unwrap()),
+///     SpfDirective::new(SpfAction::Fail, SpfMechanism::all()).unwrap(),
+/// ].into_iter().collect();
+///
+/// let stripped = record.into_iter()
+///     .filter(|d| d.mechanism.kind() != SpfDirectiveKind::Ptr)
+///     .collect::<SpfRecord>();
+/// assert_eq!(stripped.directives.len(), 2);
+/// ```
+impl<'a> From<Vec<SpfDirective<'a>>> for SpfRecord<'a> {
+    #[inline]
+    fn from(directives: Vec<SpfDirective<'a>>) -> Self {
+        Self {
+            directives: Directives::from(directives),
+        }
+    }
+}
+
+impl<'a> FromIterator<SpfDirective<'a>> for SpfRecord<'a> {
+    fn from_iter<T: IntoIterator<Item=SpfDirective<'a>>>(iter: T) -> Self {
+        Self {
+            directives: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a> Extend<SpfDirective<'a>> for SpfRecord<'a> {
+    fn extend<T: IntoIterator<Item=SpfDirective<'a>>>(&mut self, iter: T) {
+        self.directives.extend(iter);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::spf::{DomainSpec, DualCidr, SpfAction, SpfDirectiveKind, SpfMechanism};
+
+    use super::*;
+
+    fn directive(qualifier: SpfAction, mechanism: SpfMechanism<'static>) -> SpfDirective<'static> {
+        SpfDirective {
+            qualifier,
+            explicit_qualifier: qualifier != SpfAction::Pass,
+            mechanism,
+        }
+    }
+
+    fn record() -> SpfRecord<'static> {
+        vec![
+            directive(SpfAction::Pass, SpfMechanism::MX(None, DualCidr::default())),
+            directive(SpfAction::Pass, SpfMechanism::Ptr(Some(DomainSpec::new("example.com").unwrap()))),
+            directive(SpfAction::Pass, SpfMechanism::Include(DomainSpec::new("_spf.example.com").unwrap())),
+            directive(SpfAction::Fail, SpfMechanism::All),
+        ].into_iter().collect()
+    }
+
+    #[test]
+    fn test_filter_pipeline() {
+        let stripped = record().into_iter()
+            .filter(|d| d.mechanism.kind() != SpfDirectiveKind::Ptr)
+            .collect::<SpfRecord>();
+        assert_eq!(
+            stripped.directives.iter().map(|d| d.mechanism.kind()).collect::<Vec<_>>(),
+            vec![SpfDirectiveKind::MX, SpfDirectiveKind::Include, SpfDirectiveKind::All]
+        );
+
+        let mut retained = record();
+        retained.retain(|d| d.mechanism.kind() != SpfDirectiveKind::Ptr);
+        assert_eq!(retained, stripped);
+    }
+
+    #[test]
+    fn test_iterate_by_reference() {
+        let r = record();
+        let mut count = 0;
+        for d in &r {
+            assert_eq!(d, &r.directives[count]);
+            count += 1;
+        }
+        assert_eq!(count, r.directives.len());
+    }
+
+    #[test]
+    fn test_mechanisms_and_modifiers() {
+        let r = SpfRecord::parse_str("v=spf1 exp=explain.%{d} mx x-Ext=%{S}.Value redirect=_spf.example.com -all").unwrap();
+        assert_eq!(
+            r.mechanisms().map(|d| d.mechanism.kind()).collect::<Vec<_>>(),
+            vec![SpfDirectiveKind::MX, SpfDirectiveKind::All]
+        );
+        assert_eq!(
+            r.modifiers().map(|d| d.mechanism.kind()).collect::<Vec<_>>(),
+            vec![SpfDirectiveKind::Exp, SpfDirectiveKind::UnknownModifier, SpfDirectiveKind::Redirect]
+        );
+        assert_eq!(r.redirect().map(DomainSpec::as_str), Some("_spf.example.com"));
+        assert_eq!(r.exp().map(DomainSpec::as_str), Some("explain.%{d}"));
+
+        // unknown modifiers are kept verbatim, so they survive rewriting of record
+        let rewritten = r.into_iter().filter(|d| !d.mechanism.is_redirect()).collect::<SpfRecord>();
+        assert_eq!(rewritten.to_string(), "v=spf1 exp=explain.%{d} mx x-Ext=%{S}.Value -all");
+        assert_eq!(rewritten.redirect(), None);
+
+        let r = record();
+        assert_eq!(r.mechanisms().count(), r.directives.len());
+        assert_eq!(r.modifiers().count(), 0);
+        assert_eq!(r.exp(), None);
+    }
+
+    #[test]
+    fn test_extend_and_map() {
+        let mut r = record();
+        r.extend(vec![directive(SpfAction::Neutral, SpfMechanism::All)]);
+        assert_eq!(r.directives.len(), 5);
+        assert_eq!(r.directives[4].qualifier, SpfAction::Neutral);
+
+        let softened = r.map_directives(|mut d| {
+            if d.qualifier == SpfAction::Fail {
+                d.qualifier = SpfAction::SoftFail;
+            }
+            d
+        });
+        assert!(softened.directives.iter().all(|d| d.qualifier != SpfAction::Fail));
+        assert_eq!(softened.directives[3].qualifier, SpfAction::SoftFail);
+    }
+}
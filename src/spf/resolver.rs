@@ -0,0 +1,787 @@
+//! Module with `SpfResolver`, which lets evaluation fetch resources it needs on demand instead of
+//! requiring whole `ExternalResourceBag` to be filled up front.
+//!
+//! Driver evaluates record with resources fetched so far. Each time evaluation stops with
+//! `SpfEvaluationError::MissingResource` that resource is fetched and evaluation starts over, until it completes.
+//! Evaluation performs no DNS queries by itself, so starting over costs little compared to DNS round trips.
+//!
+//! Crate depends on no DNS library. `SpfResolver` is the integration point for any of them: implement it
+//! over client of your choice, converting its answers into strings and addresses.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::IpAddr;
+
+use crate::spf::{
+    evaluate_explanation, DomainInterner, EvaluationContext, EvaluationLimits, ExternalResourceBag, ExternalResourceIdentifier,
+    MacroContext, ScopedContext, SpfCheckResult, SpfEvaluationError, SpfEvaluationResult, SpfLookupError, SpfRecord,
+};
+
+/// MAX_PTR_NAMES is maximum number of names returned by PTR query, which are validated. Other names are ignored.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4.6.4) section `4.6.4`
+const MAX_PTR_NAMES: usize = 10;
+
+/// SpfResolver performs DNS queries required to evaluate SPF records.
+///
+/// Domains given to resolver have no trailing dot. When domain does not exist, lookups return no data
+/// rather than error. Errors are reserved for failed queries, which make check result in `TempError`.
+pub trait SpfResolver {
+    /// Error is returned when query fails, for instance because of timeout.
+    type Error;
+
+    /// lookup_spf returns texts of TXT records of given domain. Strings of each record are already joined.
+    /// Records other than SPF ones may be returned, they are ignored.
+    fn lookup_spf(&self, domain: &str) -> Result<Vec<String>, Self::Error>;
+
+    /// lookup_a returns addresses from A and AAAA records of given domain.
+    fn lookup_a(&self, domain: &str) -> Result<Vec<IpAddr>, Self::Error>;
+
+    /// lookup_mx returns hosts from MX records of given domain.
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<String>, Self::Error>;
+
+    /// domain_exists checks if given domain has A record, as `exists` mechanism requires.
+    fn domain_exists(&self, domain: &str) -> Result<bool, Self::Error>;
+
+    /// lookup_ptr returns names from PTR records of given address. They are validated by driver.
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, Self::Error>;
+}
+
+impl<R> SpfResolver for &R
+    where R: SpfResolver + ?Sized
+{
+    type Error = R::Error;
+
+    #[inline]
+    fn lookup_spf(&self, domain: &str) -> Result<Vec<String>, Self::Error> {
+        (**self).lookup_spf(domain)
+    }
+
+    #[inline]
+    fn lookup_a(&self, domain: &str) -> Result<Vec<IpAddr>, Self::Error> {
+        (**self).lookup_a(domain)
+    }
+
+    #[inline]
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<String>, Self::Error> {
+        (**self).lookup_mx(domain)
+    }
+
+    #[inline]
+    fn domain_exists(&self, domain: &str) -> Result<bool, Self::Error> {
+        (**self).domain_exists(domain)
+    }
+
+    #[inline]
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, Self::Error> {
+        (**self).lookup_ptr(ip)
+    }
+}
+
+/// InMemoryResolver is `SpfResolver` which answers queries from records stored in it, which is useful in tests.
+///
+/// Domains are case insensitive and trailing dot is ignored. Domain exists if it has A or AAAA record.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryResolver {
+    txt: HashMap<String, Vec<String>>,
+    addresses: HashMap<String, Vec<IpAddr>>,
+    mx: HashMap<String, Vec<String>>,
+    ptr: HashMap<IpAddr, Vec<String>>,
+}
+
+fn domain_key(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl InMemoryResolver {
+    /// new creates resolver without any records.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add_txt adds TXT record of given domain.
+    pub fn add_txt(&mut self, domain: &str, text: &str) -> &mut Self {
+        self.txt.entry(domain_key(domain)).or_default().push(text.to_string());
+        self
+    }
+
+    /// add_address adds A or AAAA record of given domain, depending on address family.
+    pub fn add_address(&mut self, domain: &str, addr: IpAddr) -> &mut Self {
+        self.addresses.entry(domain_key(domain)).or_default().push(addr);
+        self
+    }
+
+    /// add_mx adds MX record of given domain pointing at given host.
+    pub fn add_mx(&mut self, domain: &str, host: &str) -> &mut Self {
+        self.mx.entry(domain_key(domain)).or_default().push(host.to_string());
+        self
+    }
+
+    /// add_ptr adds PTR record of given address pointing at given name.
+    pub fn add_ptr(&mut self, ip: IpAddr, name: &str) -> &mut Self {
+        self.ptr.entry(ip).or_default().push(name.to_string());
+        self
+    }
+}
+
+impl SpfResolver for InMemoryResolver {
+    type Error = Infallible;
+
+    fn lookup_spf(&self, domain: &str) -> Result<Vec<String>, Self::Error> {
+        Ok(self.txt.get(&domain_key(domain)).cloned().unwrap_or_default())
+    }
+
+    fn lookup_a(&self, domain: &str) -> Result<Vec<IpAddr>, Self::Error> {
+        Ok(self.addresses.get(&domain_key(domain)).cloned().unwrap_or_default())
+    }
+
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<String>, Self::Error> {
+        Ok(self.mx.get(&domain_key(domain)).cloned().unwrap_or_default())
+    }
+
+    fn domain_exists(&self, domain: &str) -> Result<bool, Self::Error> {
+        Ok(self.addresses.contains_key(&domain_key(domain)))
+    }
+
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, Self::Error> {
+        Ok(self.ptr.get(&ip).cloned().unwrap_or_default())
+    }
+}
+
+/// spf_record selects SPF record from texts of TXT records of domain.
+/// When it can't be evaluated result of domain, which uses it, is returned.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4.5) section `4.5`
+fn spf_record(texts: &[String]) -> Result<SpfRecord<'static>, SpfEvaluationResult> {
+    match SpfRecord::find_and_parse(texts.iter().map(String::as_str)) {
+        Ok(record) => Ok(record.into_owned()),
+        Err(SpfLookupError::NoRecord) => Err(SpfEvaluationResult::None),
+        Err(SpfLookupError::MultipleRecords) | Err(SpfLookupError::InvalidRecord(_)) => Err(SpfEvaluationResult::PermError),
+    }
+}
+
+/// Driver holds resources fetched during single check.
+struct Driver {
+    bag: ExternalResourceBag<'static>,
+    interner: DomainInterner,
+    fetched: HashSet<ExternalResourceIdentifier<'static>>,
+
+    /// validated_names are names of source IP validated by their A and AAAA records, once they are fetched.
+    validated_names: Option<Vec<String>>,
+}
+
+impl Driver {
+    fn new() -> Self {
+        Self {
+            bag: ExternalResourceBag::default(),
+            interner: DomainInterner::new(),
+            fetched: HashSet::new(),
+            validated_names: None,
+        }
+    }
+
+    /// next_missing evaluates record and returns identifier of resource, which has to be fetched in order
+    /// to continue. Result of evaluation and domain of last record reached with `redirect` are returned
+    /// when there is no such resource.
+    ///
+    /// Resource requested again after it was fetched can't be provided, so it's returned as error.
+    fn next_missing<E>(&mut self, record: &SpfRecord, source_ip: IpAddr, ctx: E) -> Result<ExternalResourceIdentifier<'static>, Result<Evaluated, SpfEvaluationError>>
+        where E: EvaluationContext
+    {
+        match record.evaluate_tracking_redirects(&self.bag, source_ip, ctx, EvaluationLimits::default()) {
+            Err(SpfEvaluationError::MissingResource(id)) if self.fetched.insert(id.clone()) => Ok(id),
+            res => Err(res),
+        }
+    }
+
+    /// explanation_domain returns domain of TXT record with explanation of check, which ended with `Fail`.
+    /// Explanation comes from record reached with last `redirect`, if there was any.
+    fn explanation_domain<E>(&self, record: &SpfRecord, redirected_to: Option<&str>, ctx: E) -> Option<String>
+        where E: EvaluationContext
+    {
+        let ctx = ScopedContext {
+            inner: ctx,
+            domain: redirected_to,
+            validated_name: None,
+        };
+        // records have different lifetimes, so they can't be picked with single match
+        match redirected_to {
+            Some(domain) => self.bag.record(domain)?.explanation_domain(ctx)?.ok(),
+            None => record.explanation_domain(ctx)?.ok(),
+        }
+    }
+
+    fn insert_spf(&mut self, domain: &str, texts: &[String]) {
+        match spf_record(texts) {
+            Ok(record) => self.bag.insert_record(&self.interner, domain, record),
+            Err(res) => self.bag.insert_record_failure(&self.interner, domain, res),
+        }
+    }
+
+    fn insert_validated_names(&mut self, domain: &str) {
+        let names = self.validated_names.as_ref().expect("validated names are fetched");
+        self.bag.insert_validated_ptr_names(&self.interner, domain, names.iter().map(String::as_str));
+    }
+}
+
+/// Evaluated is result of check and domain of last record reached with `redirect`, if there was any.
+type Evaluated = (SpfEvaluationResult, Option<String>);
+
+/// explanation expands explanation string from TXT records of explanation domain. Explanation is dropped
+/// unless there is exactly one record, which can be expanded.
+fn explanation<E>(texts: &[String], redirected_to: Option<&str>, ctx: E) -> Option<String>
+    where E: EvaluationContext
+{
+    match texts {
+        [text] => evaluate_explanation(text, ScopedContext {
+            inner: ctx,
+            domain: redirected_to,
+            validated_name: None,
+        }).ok(),
+        _ => None,
+    }
+}
+
+/// exists_name joins parts of `DomainExists` identifier back into domain.
+fn exists_name(p1: &str, p2: &str) -> String {
+    if p2.is_empty() {
+        p1.to_string()
+    } else {
+        format!("{}.{}", p1, p2)
+    }
+}
+
+impl<'a> SpfRecord<'a> {
+    /// evaluate_with_resolver checks whether `source_ip` is authorized to send mail by this record, just like
+    /// `evaluate_with_context` does, but resources are fetched with `resolver` when they are needed,
+    /// including records of included domains.
+    ///
+    /// When resolver fails check results in `TempError`. Errors of PTR queries only make `ptr` not match,
+    /// as RFC 7208 requires.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// use spf::{InMemoryResolver, MacroVariable, SpfEvaluationResult, SpfRecord};
+    ///
+    /// let mut resolver = InMemoryResolver::new();
+    /// resolver.add_txt("_spf.example.org", "v=spf1 ip4:192.0.2.0/24 -all");
+    ///
+    /// let record = SpfRecord::parse_str("v=spf1 include:_spf.example.org -all").unwrap();
+    /// let ctx: HashMap<_, _> = vec![(MacroVariable::Domain, "example.com")].into_iter().collect();
+    /// let res = record.evaluate_with_resolver(&resolver, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), &ctx).unwrap();
+    /// assert_eq!(res, SpfEvaluationResult::Pass);
+    /// ```
+    pub fn evaluate_with_resolver<R, E>(&self, resolver: R, source_ip: IpAddr, ctx: E) -> Result<SpfEvaluationResult, SpfEvaluationError>
+        where
+            R: SpfResolver,
+            E: EvaluationContext
+    {
+        Driver::new().run(self, &resolver, source_ip, &ctx).map(|(res, _)| res)
+    }
+
+    /// evaluate_with_explanation works just like `evaluate_with_resolver`, but when check results in `Fail`
+    /// explanation pointed by `exp` modifier is fetched and expanded as well.
+    ///
+    /// Explanation of included records is never used. After `redirect` explanation of record, which was
+    /// redirected to, is used. Missing explanation or one which can't be expanded does not change result,
+    /// it's just not given. `%{p}` is expanded in explanation only if context provides it.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// use spf::{InMemoryResolver, MacroContext, SpfEvaluationResult, SpfRecord};
+    ///
+    /// let mut resolver = InMemoryResolver::new();
+    /// resolver.add_txt("explain.example.com", "%{i} is not one of %{d}'s designated mail servers.");
+    ///
+    /// let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    /// let ctx = MacroContext::new("user@example.com", "example.com", ip, "mx.example.org");
+    /// let record = SpfRecord::parse_str("v=spf1 -all exp=explain.%{d}").unwrap();
+    /// let res = record.evaluate_with_explanation(&resolver, ip, &ctx).unwrap();
+    /// assert_eq!(res.result, SpfEvaluationResult::Fail);
+    /// assert_eq!(res.explanation.unwrap(), "192.0.2.1 is not one of example.com's designated mail servers.");
+    /// ```
+    pub fn evaluate_with_explanation<R, E>(&self, resolver: R, source_ip: IpAddr, ctx: E) -> Result<SpfCheckResult, SpfEvaluationError>
+        where
+            R: SpfResolver,
+            E: EvaluationContext
+    {
+        let mut driver = Driver::new();
+        let (result, redirected_to) = driver.run(self, &resolver, source_ip, &ctx)?;
+        let explanation = match result {
+            SpfEvaluationResult::Fail => driver.explanation_domain(self, redirected_to.as_deref(), &ctx)
+                .and_then(|domain| resolver.lookup_spf(&domain).ok())
+                .and_then(|texts| explanation(&texts, redirected_to.as_deref(), &ctx)),
+            _ => None,
+        };
+        Ok(SpfCheckResult {
+            result,
+            explanation,
+        })
+    }
+}
+
+/// check_host finds SPF record of domain of `ctx` and evaluates it for address of `ctx` with `resolver`,
+/// fetching explanation when check results in `Fail`.
+///
+/// Failed query of record results in `TempError`, domain without SPF record results in `None` and
+/// domain with more than one SPF record or invalid one results in `PermError`.
+///
+/// Take a look at [RFC7208](https://tools.ietf.org/html/rfc7208#section-4) section `4`
+///
+/// # Example
+/// ```
+/// use std::net::{IpAddr, Ipv4Addr};
+/// use spf::{check_host, InMemoryResolver, MacroContext, SpfEvaluationResult};
+///
+/// let mut resolver = InMemoryResolver::new();
+/// resolver.add_txt("example.com", "v=spf1 ip4:192.0.2.0/24 -all");
+///
+/// let ctx = MacroContext::new("user@example.com", "example.com", IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), "mx.example.org");
+/// assert_eq!(check_host(&resolver, &ctx).unwrap().result, SpfEvaluationResult::Pass);
+///
+/// let ctx = MacroContext::new("user@example.org", "example.org", IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), "mx.example.org");
+/// assert_eq!(check_host(&resolver, &ctx).unwrap().result, SpfEvaluationResult::None);
+/// ```
+pub fn check_host<R>(resolver: R, ctx: &MacroContext) -> Result<SpfCheckResult, SpfEvaluationError>
+    where R: SpfResolver
+{
+    let texts = match resolver.lookup_spf(ctx.domain()) {
+        Ok(texts) => texts,
+        Err(_) => return Ok(SpfCheckResult::from(SpfEvaluationResult::TempError)),
+    };
+    let record = match SpfRecord::find_and_parse(texts.iter().map(String::as_str)) {
+        Ok(record) => record,
+        Err(SpfLookupError::NoRecord) => return Ok(SpfCheckResult::from(SpfEvaluationResult::None)),
+        Err(SpfLookupError::MultipleRecords) | Err(SpfLookupError::InvalidRecord(_)) => {
+            return Ok(SpfCheckResult::from(SpfEvaluationResult::PermError));
+        }
+    };
+    record.evaluate_with_explanation(resolver, ctx.ip(), ctx)
+}
+
+impl Driver {
+    /// run evaluates record, fetching resources with resolver until evaluation completes.
+    fn run<R, E>(&mut self, record: &SpfRecord, resolver: &R, source_ip: IpAddr, ctx: &E) -> Result<Evaluated, SpfEvaluationError>
+        where
+            R: SpfResolver,
+            E: EvaluationContext
+    {
+        loop {
+            let id = match self.next_missing(record, source_ip, ctx) {
+                Ok(id) => id,
+                Err(res) => return res,
+            };
+            let fetched = match &id {
+                ExternalResourceIdentifier::SPFFromDomain(d) => resolver.lookup_spf(d)
+                    .map(|texts| self.insert_spf(d, &texts)),
+                ExternalResourceIdentifier::DomainExists(p1, p2) => resolver.domain_exists(&exists_name(p1, p2))
+                    .map(|exists| self.bag.insert_existence(&self.interner, &exists_name(p1, p2), exists)),
+                ExternalResourceIdentifier::DomainAddresses(d) => resolver.lookup_a(d)
+                    .map(|addresses| self.bag.insert_addresses(&self.interner, d, addresses)),
+                ExternalResourceIdentifier::MxHosts(d) => resolver.lookup_mx(d)
+                    .map(|hosts| self.bag.insert_mx_hosts(&self.interner, d, hosts.iter().map(String::as_str))),
+                ExternalResourceIdentifier::ValidatedPtrDomain(d) => {
+                    if self.validated_names.is_none() {
+                        let names = resolver.lookup_ptr(source_ip).unwrap_or_default();
+                        let validated = names.into_iter()
+                            .take(MAX_PTR_NAMES)
+                            .filter(|name| resolver.lookup_a(name).is_ok_and(|addresses| addresses.contains(&source_ip)))
+                            .collect();
+                        self.validated_names = Some(validated);
+                    }
+                    self.insert_validated_names(d);
+                    Ok(())
+                }
+                _ => return Err(SpfEvaluationError::MissingResource(id)),
+            };
+            if fetched.is_err() {
+                return Ok((SpfEvaluationResult::TempError, None));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::asynchronous::AsyncSpfResolver;
+
+#[cfg(feature = "async")]
+mod asynchronous {
+    use std::convert::Infallible;
+    use std::future::{ready, Future};
+    use std::net::IpAddr;
+
+    use crate::spf::{EvaluationContext, ExternalResourceIdentifier, SpfCheckResult, SpfEvaluationError, SpfEvaluationResult, SpfRecord};
+
+    use super::{exists_name, Driver, Evaluated, InMemoryResolver, SpfResolver, MAX_PTR_NAMES};
+
+    /// AsyncSpfResolver is asynchronous version of `SpfResolver`. Take a look at it for details.
+    ///
+    /// Returned futures are `Send`, so evaluation may be spawned on multi-threaded executors, as long as
+    /// resolver and evaluation context are `Sync`. Methods return `impl Future`, so unlike `SpfResolver`
+    /// it's not object safe and has to be used through generics.
+    pub trait AsyncSpfResolver {
+        /// Error is returned when query fails, for instance because of timeout.
+        type Error;
+
+        /// lookup_spf returns texts of TXT records of given domain.
+        fn lookup_spf(&self, domain: &str) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send;
+
+        /// lookup_a returns addresses from A and AAAA records of given domain.
+        fn lookup_a(&self, domain: &str) -> impl Future<Output=Result<Vec<IpAddr>, Self::Error>> + Send;
+
+        /// lookup_mx returns hosts from MX records of given domain.
+        fn lookup_mx(&self, domain: &str) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send;
+
+        /// domain_exists checks if given domain has A record.
+        fn domain_exists(&self, domain: &str) -> impl Future<Output=Result<bool, Self::Error>> + Send;
+
+        /// lookup_ptr returns names from PTR records of given address.
+        fn lookup_ptr(&self, ip: IpAddr) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send;
+    }
+
+    impl<R> AsyncSpfResolver for &R
+        where R: AsyncSpfResolver + ?Sized
+    {
+        type Error = R::Error;
+
+        #[inline]
+        fn lookup_spf(&self, domain: &str) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send {
+            (**self).lookup_spf(domain)
+        }
+
+        #[inline]
+        fn lookup_a(&self, domain: &str) -> impl Future<Output=Result<Vec<IpAddr>, Self::Error>> + Send {
+            (**self).lookup_a(domain)
+        }
+
+        #[inline]
+        fn lookup_mx(&self, domain: &str) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send {
+            (**self).lookup_mx(domain)
+        }
+
+        #[inline]
+        fn domain_exists(&self, domain: &str) -> impl Future<Output=Result<bool, Self::Error>> + Send {
+            (**self).domain_exists(domain)
+        }
+
+        #[inline]
+        fn lookup_ptr(&self, ip: IpAddr) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send {
+            (**self).lookup_ptr(ip)
+        }
+    }
+
+    impl AsyncSpfResolver for InMemoryResolver {
+        type Error = Infallible;
+
+        fn lookup_spf(&self, domain: &str) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send {
+            ready(SpfResolver::lookup_spf(self, domain))
+        }
+
+        fn lookup_a(&self, domain: &str) -> impl Future<Output=Result<Vec<IpAddr>, Self::Error>> + Send {
+            ready(SpfResolver::lookup_a(self, domain))
+        }
+
+        fn lookup_mx(&self, domain: &str) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send {
+            ready(SpfResolver::lookup_mx(self, domain))
+        }
+
+        fn domain_exists(&self, domain: &str) -> impl Future<Output=Result<bool, Self::Error>> + Send {
+            ready(SpfResolver::domain_exists(self, domain))
+        }
+
+        fn lookup_ptr(&self, ip: IpAddr) -> impl Future<Output=Result<Vec<String>, Self::Error>> + Send {
+            ready(SpfResolver::lookup_ptr(self, ip))
+        }
+    }
+
+    impl<'a> SpfRecord<'a> {
+        /// evaluate_with_async_resolver works just like `evaluate_with_resolver`, but queries are performed
+        /// with asynchronous resolver.
+        pub async fn evaluate_with_async_resolver<R, E>(&self, resolver: R, source_ip: IpAddr, ctx: E) -> Result<SpfEvaluationResult, SpfEvaluationError>
+            where
+                R: AsyncSpfResolver,
+                E: EvaluationContext
+        {
+            Driver::new().run_async(self, &resolver, source_ip, &ctx).await.map(|(res, _)| res)
+        }
+
+        /// evaluate_with_async_explanation works just like `evaluate_with_explanation`, but queries are performed
+        /// with asynchronous resolver.
+        pub async fn evaluate_with_async_explanation<R, E>(&self, resolver: R, source_ip: IpAddr, ctx: E) -> Result<SpfCheckResult, SpfEvaluationError>
+            where
+                R: AsyncSpfResolver,
+                E: EvaluationContext
+        {
+            let mut driver = Driver::new();
+            let (result, redirected_to) = driver.run_async(self, &resolver, source_ip, &ctx).await?;
+            let mut explanation = None;
+            if result == SpfEvaluationResult::Fail {
+                if let Some(domain) = driver.explanation_domain(self, redirected_to.as_deref(), &ctx) {
+                    if let Ok(texts) = resolver.lookup_spf(&domain).await {
+                        explanation = super::explanation(&texts, redirected_to.as_deref(), &ctx);
+                    }
+                }
+            }
+            Ok(SpfCheckResult {
+                result,
+                explanation,
+            })
+        }
+    }
+
+    impl Driver {
+        /// run_async works just like `run`, but with asynchronous resolver.
+        async fn run_async<R, E>(&mut self, record: &SpfRecord<'_>, resolver: &R, source_ip: IpAddr, ctx: &E) -> Result<Evaluated, SpfEvaluationError>
+            where
+                R: AsyncSpfResolver,
+                E: EvaluationContext
+        {
+            loop {
+                let id = match self.next_missing(record, source_ip, ctx) {
+                    Ok(id) => id,
+                    Err(res) => return res,
+                };
+                let fetched = match &id {
+                    ExternalResourceIdentifier::SPFFromDomain(d) => resolver.lookup_spf(d).await
+                        .map(|texts| self.insert_spf(d, &texts)),
+                    ExternalResourceIdentifier::DomainExists(p1, p2) => resolver.domain_exists(&exists_name(p1, p2)).await
+                        .map(|exists| self.bag.insert_existence(&self.interner, &exists_name(p1, p2), exists)),
+                    ExternalResourceIdentifier::DomainAddresses(d) => resolver.lookup_a(d).await
+                        .map(|addresses| self.bag.insert_addresses(&self.interner, d, addresses)),
+                    ExternalResourceIdentifier::MxHosts(d) => resolver.lookup_mx(d).await
+                        .map(|hosts| self.bag.insert_mx_hosts(&self.interner, d, hosts.iter().map(String::as_str))),
+                    ExternalResourceIdentifier::ValidatedPtrDomain(d) => {
+                        if self.validated_names.is_none() {
+                            let names = resolver.lookup_ptr(source_ip).await.unwrap_or_default();
+                            let mut validated = Vec::new();
+                            for name in names.into_iter().take(MAX_PTR_NAMES) {
+                                if resolver.lookup_a(&name).await.is_ok_and(|addresses| addresses.contains(&source_ip)) {
+                                    validated.push(name);
+                                }
+                            }
+                            self.validated_names = Some(validated);
+                        }
+                        self.insert_validated_names(d);
+                        Ok(())
+                    }
+                    _ => return Err(SpfEvaluationError::MissingResource(id)),
+                };
+                if fetched.is_err() {
+                    return Ok((SpfEvaluationResult::TempError, None));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    use crate::spf::{MacroVariable, SpfDirective, SpfMechanism};
+
+    use super::*;
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    fn resolver() -> InMemoryResolver {
+        let mut r = InMemoryResolver::new();
+        r.add_txt("example.com", "google-site-verification=abc")
+            .add_txt("example.com", "v=spf1 mx include:_spf.example.org exists:%{i}.list.example.com -all")
+            .add_mx("example.com", "mail.example.com")
+            .add_address("mail.example.com", v4(192, 0, 2, 10))
+            .add_txt("_spf.example.org", "v=spf1 redirect=_spf2.Example.org.")
+            .add_txt("_spf2.example.org", "v=spf1 ip4:198.51.100.0/24 ptr:example.net")
+            .add_address("203.0.113.7.list.example.com", v4(127, 0, 0, 2))
+            .add_ptr(v4(203, 0, 113, 8), "host.example.net")
+            .add_ptr(v4(203, 0, 113, 9), "forged.example.net")
+            .add_address("host.example.net", v4(203, 0, 113, 8))
+            .add_txt("none.example.com", "v=spf10")
+            .add_txt("twice.example.com", "v=spf1 -all")
+            .add_txt("twice.example.com", "v=spf1 +all");
+        r
+    }
+
+    fn ctx(ip: IpAddr) -> HashMap<MacroVariable, String> {
+        vec![
+            (MacroVariable::Domain, "example.com".to_string()),
+            (MacroVariable::Ip, MacroVariable::format_ip(ip)),
+        ].into_iter().collect()
+    }
+
+    fn check(resolver: impl SpfResolver, record: &str, ip: IpAddr) -> Result<SpfEvaluationResult, SpfEvaluationError> {
+        SpfRecord::parse_str(record).unwrap().evaluate_with_resolver(resolver, ip, ctx(ip))
+    }
+
+    #[test]
+    fn test_check_host_with_resolver() {
+        let resolver = resolver();
+        let record = "v=spf1 include:example.com ~all";
+        assert_eq!(check(&resolver, record, v4(192, 0, 2, 10)).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(check(&resolver, record, v4(198, 51, 100, 1)).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(check(&resolver, record, v4(203, 0, 113, 7)).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(check(&resolver, record, v4(203, 0, 113, 8)).unwrap(), SpfEvaluationResult::Pass);
+        // name of PTR record does not resolve back to address, so it's not validated
+        assert_eq!(check(&resolver, record, v4(203, 0, 113, 9)).unwrap(), SpfEvaluationResult::SoftFail);
+
+        assert_eq!(check(&resolver, "v=spf1 include:none.example.com", v4(192, 0, 2, 10)).unwrap(), SpfEvaluationResult::PermError);
+        assert_eq!(check(&resolver, "v=spf1 include:twice.example.com", v4(192, 0, 2, 10)).unwrap(), SpfEvaluationResult::PermError);
+        assert_eq!(check(&resolver, "v=spf1 redirect=missing.example.com", v4(192, 0, 2, 10)).unwrap(), SpfEvaluationResult::PermError);
+    }
+
+    #[test]
+    fn test_check_host() {
+        let mut resolver = resolver();
+        resolver
+            .add_txt("exp.example.com", "v=spf1 -all exp=explain.%{d}")
+            .add_txt("explain.exp.example.com", "%{l} can't send from %{i}");
+        let host = |resolver: &dyn SpfResolver<Error=()>, sender: &str, ip: IpAddr| {
+            let domain = &sender[sender.find('@').unwrap() + 1..];
+            check_host(resolver, &MacroContext::new(sender, domain, ip, "mx.example.org")).unwrap()
+        };
+        let failing = FailingResolver(resolver, "timeout.example.com");
+        assert_eq!(host(&failing, "user@example.com", v4(192, 0, 2, 10)).result, SpfEvaluationResult::Pass);
+        assert_eq!(host(&failing, "user@example.com", v4(192, 0, 2, 11)).result, SpfEvaluationResult::Fail);
+        assert_eq!(host(&failing, "user@missing.example.com", v4(192, 0, 2, 10)).result, SpfEvaluationResult::None);
+        assert_eq!(host(&failing, "user@none.example.com", v4(192, 0, 2, 10)).result, SpfEvaluationResult::None);
+        assert_eq!(host(&failing, "user@twice.example.com", v4(192, 0, 2, 10)).result, SpfEvaluationResult::PermError);
+        assert_eq!(host(&failing, "user@timeout.example.com", v4(192, 0, 2, 10)).result, SpfEvaluationResult::TempError);
+
+        let res = host(&failing, "user@exp.example.com", v4(192, 0, 2, 10));
+        assert_eq!(res.result, SpfEvaluationResult::Fail);
+        assert_eq!(res.explanation.unwrap(), "user can't send from 192.0.2.10");
+    }
+
+    /// FailingResolver fails queries for domains of given one.
+    struct FailingResolver(InMemoryResolver, &'static str);
+
+    impl FailingResolver {
+        fn fail(&self, domain: &str) -> Result<(), ()> {
+            if domain.ends_with(self.1) { Err(()) } else { Ok(()) }
+        }
+    }
+
+    impl SpfResolver for FailingResolver {
+        type Error = ();
+
+        fn lookup_spf(&self, domain: &str) -> Result<Vec<String>, ()> {
+            self.fail(domain).map(|_| SpfResolver::lookup_spf(&self.0, domain).unwrap())
+        }
+
+        fn lookup_a(&self, domain: &str) -> Result<Vec<IpAddr>, ()> {
+            self.fail(domain).map(|_| SpfResolver::lookup_a(&self.0, domain).unwrap())
+        }
+
+        fn lookup_mx(&self, domain: &str) -> Result<Vec<String>, ()> {
+            self.fail(domain).map(|_| SpfResolver::lookup_mx(&self.0, domain).unwrap())
+        }
+
+        fn domain_exists(&self, domain: &str) -> Result<bool, ()> {
+            self.fail(domain).map(|_| SpfResolver::domain_exists(&self.0, domain).unwrap())
+        }
+
+        fn lookup_ptr(&self, _: IpAddr) -> Result<Vec<String>, ()> {
+            Err(())
+        }
+    }
+
+    #[test]
+    fn test_resolver_errors() {
+        let record = "v=spf1 include:example.com ~all";
+        let failing = FailingResolver(resolver(), "example.org");
+        // include is evaluated only if mx does not match
+        assert_eq!(check(&failing, record, v4(192, 0, 2, 10)).unwrap(), SpfEvaluationResult::Pass);
+        assert_eq!(check(&failing, record, v4(198, 51, 100, 1)).unwrap(), SpfEvaluationResult::TempError);
+
+        let failing = FailingResolver(resolver(), "mail.example.com");
+        assert_eq!(check(&failing, record, v4(192, 0, 2, 10)).unwrap(), SpfEvaluationResult::TempError);
+
+        // failed PTR query only makes ptr not match
+        let failing = FailingResolver(resolver(), "unused.example");
+        assert_eq!(check(&failing, record, v4(203, 0, 113, 8)).unwrap(), SpfEvaluationResult::SoftFail);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_resolver() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll, Waker};
+
+        let resolver = resolver();
+        let record = SpfRecord::parse_str("v=spf1 include:example.com ~all").unwrap();
+        for (ip, expected) in [(v4(198, 51, 100, 1), SpfEvaluationResult::Pass), (v4(203, 0, 113, 9), SpfEvaluationResult::SoftFail)].iter() {
+            // in-memory resolver never blocks, so future is ready once it's polled
+            let mut f = pin!(record.evaluate_with_async_resolver(&resolver, *ip, ctx(*ip)));
+            match f.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+                Poll::Ready(res) => assert_eq!(res.unwrap(), *expected),
+                Poll::Pending => panic!("in-memory resolver blocked"),
+            }
+        }
+
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&record.evaluate_with_async_explanation(&resolver, v4(192, 0, 2, 1), ctx(v4(192, 0, 2, 1))));
+
+        let mut resolver = resolver;
+        resolver.add_txt("explain.example.com", "%{i} is not allowed");
+        let record = SpfRecord::parse_str("v=spf1 -all exp=explain.%{d}").unwrap();
+        let ip = v4(192, 0, 2, 1);
+        let mut f = pin!(record.evaluate_with_async_explanation(&resolver, ip, ctx(ip)));
+        match f.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(res) => assert_eq!(res.unwrap().explanation.unwrap(), "192.0.2.1 is not allowed"),
+            Poll::Pending => panic!("in-memory resolver blocked"),
+        }
+    }
+
+    #[test]
+    fn test_explanation() {
+        let mut resolver = resolver();
+        resolver
+            .add_txt("explain.example.com", "%{i} is not one of %{d}'s designated mail servers.")
+            .add_txt("explain._spf.example.net", "Rejected by %{d}")
+            .add_txt("_spf.example.net", "v=spf1 -all exp=explain.%{d}")
+            .add_txt("noexp.example.net", "v=spf1 -all")
+            .add_txt("twice.example.com", "first")
+            .add_txt("twice.example.com", "second")
+            .add_txt("invalid.example.com", "%{q}")
+            .add_txt("exp-only.example.com", "%{c} rejected by %{r} at %{t}");
+        let ip = v4(192, 0, 2, 1);
+        let explain = |record: &str, ctx: &dyn EvaluationContext| {
+            SpfRecord::parse_str(record).unwrap().evaluate_with_explanation(&resolver, ip, ctx).unwrap()
+        };
+        let ctx = ctx(ip);
+
+        let res = explain("v=spf1 -all exp=explain.%{d}", &ctx);
+        assert_eq!(res.result, SpfEvaluationResult::Fail);
+        assert_eq!(res.explanation.unwrap(), "192.0.2.1 is not one of example.com's designated mail servers.");
+
+        // explanation is given only for fail
+        assert_eq!(explain("v=spf1 ~all exp=explain.%{d}", &ctx), SpfCheckResult::from(SpfEvaluationResult::SoftFail));
+        assert_eq!(explain("v=spf1 ip4:192.0.2.0/24 -all exp=explain.%{d}", &ctx), SpfCheckResult::from(SpfEvaluationResult::Pass));
+
+        // after redirect only explanation of target is used, with target as %{d}
+        assert_eq!(explain("v=spf1 redirect=_spf.example.net exp=explain.example.com", &ctx).explanation.unwrap(), "Rejected by _spf.example.net");
+        assert_eq!(explain("v=spf1 redirect=noexp.example.net exp=explain.example.com", &ctx), SpfCheckResult::from(SpfEvaluationResult::Fail));
+
+        // explanation of included record is never used
+        assert_eq!(explain("v=spf1 include:_spf.example.net -all", &ctx), SpfCheckResult::from(SpfEvaluationResult::Fail));
+
+        // missing, ambiguous or invalid explanation is dropped
+        for domain in ["missing.example.com", "twice.example.com", "invalid.example.com", "exp-only.example.com", "%{p}.example.com"].iter() {
+            assert_eq!(explain(&format!("v=spf1 -all exp={}", domain), &ctx), SpfCheckResult::from(SpfEvaluationResult::Fail), "{}", domain);
+        }
+        // parser rejects second exp, so invalid record is built by hand
+        let mut record = SpfRecord::parse_str("v=spf1 -all exp=a.example.com").unwrap();
+        record.directives.push(SpfDirective::from_mechanism(SpfMechanism::exp("b.example.com").unwrap()));
+        assert_eq!(record.evaluate_with_explanation(&resolver, ip, &ctx).unwrap(), SpfCheckResult::from(SpfEvaluationResult::PermError));
+
+        let ctx = MacroContext::new("user@example.com", "example.com", ip, "mx.example.org").with_exp_variables("mail.example.net", 1_000_000_000);
+        assert_eq!(explain("v=spf1 -all exp=exp-only.example.com", &ctx).explanation.unwrap(), "192.0.2.1 rejected by mail.example.net at 1000000000");
+    }
+}
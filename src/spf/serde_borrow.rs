@@ -0,0 +1,356 @@
+//! Module with zero-copy deserialization of SPF types.
+//!
+//! Plain `Deserialize` implementations of SPF types copy strings, so `SpfRecord<'static>` and friends implement
+//! `DeserializeOwned` and may be read with `serde_json::from_reader`. `Borrowed` deserializes the same format
+//! but borrows domain-specs and modifier values from input, when deserializer supports it,
+//! like `serde_json::from_str` does.
+//!
+//! Plain implementations allocate on purpose. With `#[serde(borrow)]` on their fields `SpfRecord<'a>` would
+//! implement `Deserialize<'de>` only for `'de: 'a`, so `SpfRecord<'static>` would not be `DeserializeOwned`.
+//!
+//! Borrowing is done by remote derives of private types, which mirror fields and variants of SPF types.
+//! `test_mirror_has_all_mechanisms` fails to compile or fails when `SpfMechanism` gains variant, which mirror lacks.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::spf::DomainSpec;
+use crate::spf::{DualCidr, Directives, Ipv4Net, Ipv6Net, SpfAction, SpfDirective, SpfMechanism, SpfRecord, UnknownModifier};
+
+/// Borrowed wraps SPF value deserialized with strings borrowed from input.
+///
+/// Strings which can't be borrowed, like JSON strings with escapes or all strings of `serde_json::from_reader`,
+/// are copied, so it accepts any input, which plain `Deserialize` accepts.
+/// It's implemented for `DomainSpec`, `SpfMechanism`, `SpfDirective` and `SpfRecord`.
+/// Use `deserialize_borrowed` for fields of own types.
+///
+/// # Example
+/// ```
+/// use std::borrow::Cow;
+///
+/// use spf::{Borrowed, SpfMechanism, SpfRecord};
+///
+/// let json = serde_json::to_string(&SpfRecord::parse_str("v=spf1 include:_spf.example.com -all").unwrap()).unwrap();
+/// let record: SpfRecord = serde_json::from_str::<Borrowed<SpfRecord>>(&json).unwrap().into_inner();
+/// match &record.directives[0].mechanism {
+///     SpfMechanism::Include(d) => assert!(matches!(d.clone().into_raw(), Cow::Borrowed("_spf.example.com"))),
+///     m => panic!("unexpected mechanism {:?}", m),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Borrowed<T>(pub T);
+
+impl<T> Borrowed<T> {
+    /// into_inner returns wrapped value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for Borrowed<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> serde::Serialize for Borrowed<T>
+    where T: serde::Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+/// deserialize_borrowed deserializes value through `Borrowed`, so it's meant for fields of SPF types:
+///
+/// ```
+/// use serde_derive::Deserialize;
+/// use spf::SpfRecord;
+///
+/// #[derive(Deserialize)]
+/// struct Config<'a> {
+///     #[serde(borrow, deserialize_with = "spf::deserialize_borrowed")]
+///     policy: SpfRecord<'a>,
+/// }
+/// ```
+pub fn deserialize_borrowed<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: Deserializer<'de>, Borrowed<T>: Deserialize<'de>
+{
+    Borrowed::<T>::deserialize(deserializer).map(Borrowed::into_inner)
+}
+
+/// CowStrVisitor visits string, which is borrowed from input when deserializer supports it.
+/// Given text is what it expects.
+pub(crate) struct CowStrVisitor(pub(crate) &'static str);
+
+impl<'de> Visitor<'de> for CowStrVisitor {
+    type Value = Cow<'de, str>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+
+    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+        where E: de::Error
+    {
+        Ok(Cow::Borrowed(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+        where E: de::Error
+    {
+        Ok(Cow::Owned(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+        where E: de::Error
+    {
+        Ok(Cow::Owned(v))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Borrowed<DomainSpec<'a>> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let raw = deserializer.deserialize_str(CowStrVisitor("domain-spec string"))?;
+        DomainSpec::new(raw).map(Borrowed).map_err(de::Error::custom)
+    }
+}
+
+// Types below mirror fields and variants of SPF types, which have to be kept in sync with them.
+// Mirrors are deserialized just like SPF types but with strings borrowed.
+
+fn borrow_domain_spec_option<'de: 'a, 'a, D>(deserializer: D) -> Result<Option<DomainSpec<'a>>, D::Error>
+    where D: Deserializer<'de>
+{
+    Option::<Borrowed<DomainSpec<'a>>>::deserialize(deserializer).map(|d| d.map(Borrowed::into_inner))
+}
+
+fn borrow_domain_spec<'de: 'a, 'a, D>(deserializer: D) -> Result<DomainSpec<'a>, D::Error>
+    where D: Deserializer<'de>
+{
+    deserialize_borrowed(deserializer)
+}
+
+fn borrow_unknown_modifier<'de: 'a, 'a, D>(deserializer: D) -> Result<Box<UnknownModifier<'a>>, D::Error>
+    where D: Deserializer<'de>
+{
+    UnknownModifierDef::deserialize(deserializer).map(Box::new)
+}
+
+fn borrow_directives<'de: 'a, 'a, D>(deserializer: D) -> Result<Directives<'a>, D::Error>
+    where D: Deserializer<'de>
+{
+    struct DirectivesVisitor<'a>(PhantomData<SpfDirective<'a>>);
+
+    impl<'de: 'a, 'a> Visitor<'de> for DirectivesVisitor<'a> {
+        type Value = Directives<'a>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("sequence of directives")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where A: de::SeqAccess<'de>
+        {
+            let mut directives = Directives::new();
+            while let Some(Borrowed(d)) = seq.next_element()? {
+                directives.push(d);
+            }
+            Ok(directives)
+        }
+    }
+
+    deserializer.deserialize_seq(DirectivesVisitor(PhantomData))
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "UnknownModifier")]
+struct UnknownModifierDef<'a> {
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+    #[serde(borrow)]
+    value: Cow<'a, str>,
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "SpfMechanism")]
+#[allow(clippy::upper_case_acronyms)] // mirrors variant names of SpfMechanism
+enum SpfMechanismDef<'a> {
+    A(#[serde(borrow, deserialize_with = "borrow_domain_spec_option")] Option<DomainSpec<'a>>, DualCidr),
+    AAAA(#[serde(borrow, deserialize_with = "borrow_domain_spec_option")] Option<DomainSpec<'a>>, DualCidr),
+    MX(#[serde(borrow, deserialize_with = "borrow_domain_spec_option")] Option<DomainSpec<'a>>, DualCidr),
+    Ipv4(Ipv4Net),
+    Ipv6(Ipv6Net),
+    Include(#[serde(borrow, deserialize_with = "borrow_domain_spec")] DomainSpec<'a>),
+    Exists(#[serde(borrow, deserialize_with = "borrow_domain_spec")] DomainSpec<'a>),
+    Redirect(#[serde(borrow, deserialize_with = "borrow_domain_spec")] DomainSpec<'a>),
+    UnknownModifier(#[serde(borrow, deserialize_with = "borrow_unknown_modifier")] Box<UnknownModifier<'a>>),
+    Exp(#[serde(borrow, deserialize_with = "borrow_domain_spec")] DomainSpec<'a>),
+    All,
+    Ptr(#[serde(borrow, deserialize_with = "borrow_domain_spec_option")] Option<DomainSpec<'a>>),
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "SpfDirective")]
+struct SpfDirectiveDef<'a> {
+    qualifier: SpfAction,
+    #[serde(default)]
+    explicit_qualifier: bool,
+    #[serde(borrow, deserialize_with = "SpfMechanismDef::deserialize")]
+    mechanism: SpfMechanism<'a>,
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "SpfRecord")]
+struct SpfRecordDef<'a> {
+    #[serde(borrow, deserialize_with = "borrow_directives")]
+    directives: Directives<'a>,
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Borrowed<SpfMechanism<'a>> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        SpfMechanismDef::deserialize(deserializer).map(Borrowed)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Borrowed<SpfDirective<'a>> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        SpfDirectiveDef::deserialize(deserializer).map(Borrowed)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Borrowed<SpfRecord<'a>> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        SpfRecordDef::deserialize(deserializer).map(Borrowed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::de::DeserializeOwned;
+
+    use crate::spf::{ExternalResourceBag, ExternalResourceIdentifier, SpfDirective, SpfMechanism, SpfRecord};
+
+    use super::*;
+
+    #[test]
+    fn test_deserialize_owned() {
+        fn assert_deserialize_owned<T: DeserializeOwned>() {}
+        assert_deserialize_owned::<DomainSpec<'static>>();
+        assert_deserialize_owned::<SpfMechanism<'static>>();
+        assert_deserialize_owned::<SpfRecord<'static>>();
+        assert_deserialize_owned::<ExternalResourceIdentifier<'static>>();
+        assert_deserialize_owned::<ExternalResourceBag<'static>>();
+
+        let record = SpfRecord::parse_str("v=spf1 include:_spf.example.com mx:%{d}/24 foo=bar -all").unwrap().into_owned();
+        let json = serde_json::to_vec(&record).unwrap();
+        let from_reader: SpfRecord<'static> = serde_json::from_reader(json.as_slice()).unwrap();
+        assert_eq!(from_reader, record);
+        let from_slice: SpfRecord<'static> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(from_slice, record);
+    }
+
+    #[test]
+    fn test_borrowed_is_deserialized_like_owned() {
+        let text = "v=spf1 a ?a:example.com/24 ptr include:_spf.example.com mx:%{d}/24//64 foo=bar -all";
+        // json has to outlive record, which borrows from it, when record is invariant(smallvec feature)
+        let json = serde_json::to_string(&SpfRecord::parse_str(text).unwrap()).unwrap();
+        let record = SpfRecord::parse_str(text).unwrap();
+        assert_eq!(serde_json::from_str::<Borrowed<SpfRecord>>(&json).unwrap().into_inner(), record);
+        assert_eq!(serde_json::from_str::<SpfRecord>(&json).unwrap(), record);
+        for d in record.directives.iter() {
+            let json = serde_json::to_string(&d.mechanism).unwrap();
+            assert_eq!(serde_json::from_str::<Borrowed<SpfMechanism>>(&json).unwrap().into_inner(), d.mechanism);
+        }
+        assert!(serde_json::from_str::<Borrowed<DomainSpec>>(r#""%{q}.example.com""#).is_err());
+    }
+
+        /// variant_name has no wildcard arm, so it stops compiling once `SpfMechanism` gains variant.
+    /// Add variant to `SpfMechanismDef` and its sample to `test_mirror_has_all_mechanisms` then.
+    fn variant_name(m: &SpfMechanism) -> &'static str {
+        match m {
+            SpfMechanism::A(..) => "A",
+            SpfMechanism::AAAA(..) => "AAAA",
+            SpfMechanism::MX(..) => "MX",
+            SpfMechanism::Ipv4(_) => "Ipv4",
+            SpfMechanism::Ipv6(_) => "Ipv6",
+            SpfMechanism::Include(_) => "Include",
+            SpfMechanism::Exists(_) => "Exists",
+            SpfMechanism::Redirect(_) => "Redirect",
+            SpfMechanism::UnknownModifier(_) => "UnknownModifier",
+            SpfMechanism::Exp(_) => "Exp",
+            SpfMechanism::All => "All",
+            SpfMechanism::Ptr(_) => "Ptr",
+        }
+    }
+
+    #[test]
+    fn test_mirror_has_all_mechanisms() {
+        // number of arms of variant_name
+        const VARIANTS: usize = 12;
+        let terms = [
+            "a:%{d}/24", "aaaa//64", "mx", "ip4:192.0.2.0/24", "ip6:2001:db8::/32", "include:_spf.example.com",
+            "exists:%{ir}.example.com", "redirect=example.com", "foo=bar", "exp=exp.example.com", "all", "ptr",
+        ];
+        let mut names = std::collections::HashSet::new();
+        for term in terms.iter() {
+            let m = SpfDirective::parse_str(term).unwrap().mechanism;
+            names.insert(variant_name(&m));
+            let json = serde_json::to_string(&m).unwrap();
+            let borrowed = serde_json::from_str::<Borrowed<SpfMechanism>>(&json);
+            assert_eq!(borrowed.map(Borrowed::into_inner).ok(), Some(m), "{} is not mirrored", term);
+        }
+        assert_eq!(names.len(), VARIANTS, "each variant needs a sample");
+    }
+
+    #[test]
+    fn test_deserialize_borrows_from_str() {
+        let json = r#"{"directives":[
+            {"qualifier":"Pass","mechanism":{"Include":"_spf.example.com"}},
+            {"qualifier":"Pass","mechanism":{"UnknownModifier":["foo","bar"]}}
+        ]}"#;
+        let record = serde_json::from_str::<Borrowed<SpfRecord>>(json).unwrap().into_inner();
+        match &record.directives[0].mechanism {
+            SpfMechanism::Include(d) => assert!(matches!(d.clone().into_raw(), Cow::Borrowed("_spf.example.com"))),
+            m => panic!("unexpected mechanism {:?}", m),
+        }
+        match &record.directives[1].mechanism {
+            SpfMechanism::UnknownModifier(m) => {
+                assert!(matches!(m.name, Cow::Borrowed("foo")));
+                assert!(matches!(m.value, Cow::Borrowed("bar")));
+            }
+            m => panic!("unexpected mechanism {:?}", m),
+        }
+
+        // plain deserialization and escaped strings can't borrow
+        let record: SpfRecord = serde_json::from_str(json).unwrap();
+        match &record.directives[0].mechanism {
+            SpfMechanism::Include(d) => assert!(matches!(d.clone().into_raw(), Cow::Owned(_))),
+            m => panic!("unexpected mechanism {:?}", m),
+        }
+        let json = r#"{"directives":[{"qualifier":"Pass","mechanism":{"Include":"_spf.example\u002ecom"}}]}"#;
+        let record = serde_json::from_str::<Borrowed<SpfRecord>>(json).unwrap().into_inner();
+        match &record.directives[0].mechanism {
+            SpfMechanism::Include(d) => assert!(matches!(d.clone().into_raw(), Cow::Owned(_))),
+            m => panic!("unexpected mechanism {:?}", m),
+        }
+    }
+}
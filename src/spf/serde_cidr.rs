@@ -0,0 +1,278 @@
+//! Module with serde implementations of `Ipv4Net` and `Ipv6Net`.
+//!
+//! Nets are serialized as strings in slash notation(`192.0.2.0/24`), since that's what humans
+//! and other tools expect in JSON or YAML. Fields serialized with `serde_cidr::structured` module are
+//! serialized as structure with `addr` and `prefix` fields instead:
+//!
+//! ```
+//! use serde_derive::Serialize;
+//! use spf::Ipv4Net;
+//!
+//! #[derive(Serialize)]
+//! struct Allowed {
+//!     #[serde(with = "spf::serde_cidr::structured")]
+//!     net: Ipv4Net,
+//! }
+//!
+//! let net = "192.0.2.0/24".parse().unwrap();
+//! assert_eq!(serde_json::to_string(&Allowed { net }).unwrap(), r#"{"net":{"addr":"192.0.2.0","prefix":24}}"#);
+//! ```
+//!
+//! Deserialization of human readable formats accepts both forms.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use serde::de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::spf::{CidrError, Ipv4Net, Ipv6Net};
+
+const FIELDS: &[&str] = &["addr", "prefix"];
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for crate::spf::Ipv4Net {}
+
+    impl Sealed for crate::spf::Ipv6Net {}
+}
+
+/// SerdeNet is implemented for nets, which may be serialized with this module: `Ipv4Net` and `Ipv6Net`.
+pub trait SerdeNet: Sized + fmt::Display + FromStr<Err=CidrError> + sealed::Sealed {
+    #[doc(hidden)]
+    type Addr: Serialize + DeserializeOwned;
+
+    #[doc(hidden)]
+    const NAME: &'static str;
+
+    #[doc(hidden)]
+    fn parts(&self) -> (Self::Addr, Option<u8>);
+
+    #[doc(hidden)]
+    fn from_parts(addr: Self::Addr, prefix: Option<u8>) -> Result<Self, CidrError>;
+}
+
+impl SerdeNet for Ipv4Net {
+    type Addr = Ipv4Addr;
+
+    const NAME: &'static str = "Ipv4Net";
+
+    fn parts(&self) -> (Ipv4Addr, Option<u8>) {
+        (self.addr(), self.prefix())
+    }
+
+    fn from_parts(addr: Ipv4Addr, prefix: Option<u8>) -> Result<Self, CidrError> {
+        Self::new(addr, prefix)
+    }
+}
+
+impl SerdeNet for Ipv6Net {
+    type Addr = Ipv6Addr;
+
+    const NAME: &'static str = "Ipv6Net";
+
+    fn parts(&self) -> (Ipv6Addr, Option<u8>) {
+        (self.addr(), self.prefix())
+    }
+
+    fn from_parts(addr: Ipv6Addr, prefix: Option<u8>) -> Result<Self, CidrError> {
+        Self::new(addr, prefix)
+    }
+}
+
+
+struct NetVisitor<N>(PhantomData<N>);
+
+impl<'de, N> Visitor<'de> for NetVisitor<N>
+    where N: SerdeNet
+{
+    type Value = N;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("network in slash notation or structure with addr and prefix fields")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<N, E>
+        where E: de::Error
+    {
+        N::from_str(v).map_err(E::custom)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<N, A::Error>
+        where A: SeqAccess<'de>
+    {
+        let addr = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let prefix = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        N::from_parts(addr, prefix).map_err(de::Error::custom)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<N, A::Error>
+        where A: MapAccess<'de>
+    {
+        let mut addr = None;
+        let mut prefix = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "addr" => addr = Some(map.next_value()?),
+                "prefix" => prefix = Some(map.next_value()?),
+                _ => return Err(de::Error::unknown_field(&key, FIELDS)),
+            }
+        }
+        let addr = addr.ok_or_else(|| de::Error::missing_field("addr"))?;
+        N::from_parts(addr, prefix.unwrap_or(None)).map_err(de::Error::custom)
+    }
+}
+
+fn deserialize_net<'de, N, D>(deserializer: D) -> Result<N, D::Error>
+    where N: SerdeNet, D: Deserializer<'de>
+{
+    let visitor = NetVisitor(PhantomData);
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(visitor)
+    } else {
+        deserializer.deserialize_str(visitor)
+    }
+}
+
+/// Module with serde helpers, which serialize net as structure with `addr` and `prefix` fields.
+pub mod structured {
+    use super::*;
+
+    /// serialize serializes net as structure with `addr` and `prefix` fields.
+    pub fn serialize<N, S>(net: &N, serializer: S) -> Result<S::Ok, S::Error>
+        where N: SerdeNet, S: Serializer
+    {
+        let (addr, prefix) = net.parts();
+        let mut s = serializer.serialize_struct(N::NAME, FIELDS.len())?;
+        s.serialize_field("addr", &addr)?;
+        s.serialize_field("prefix", &prefix)?;
+        s.end()
+    }
+
+    /// deserialize deserializes net from structure or, in human readable formats, from slash notation.
+    pub fn deserialize<'de, N, D>(deserializer: D) -> Result<N, D::Error>
+        where N: SerdeNet, D: Deserializer<'de>
+    {
+        let visitor = NetVisitor(PhantomData);
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(visitor)
+        } else {
+            // formats which are not self describing have to be told what to expect
+            deserializer.deserialize_struct(N::NAME, FIELDS, visitor)
+        }
+    }
+}
+
+impl Serialize for Ipv4Net {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ipv4Net {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserialize_net(deserializer)
+    }
+}
+
+impl Serialize for Ipv6Net {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ipv6Net {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserialize_net(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nets() -> (Ipv4Net, Ipv6Net) {
+        (
+            Ipv4Net::new(Ipv4Addr::new(203, 0, 113, 0), Some(24)).unwrap(),
+            Ipv6Net::new("2001:db8::".parse().unwrap(), None).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_both_forms_are_accepted() {
+        let (v4, v6) = nets();
+        assert_eq!(serde_json::from_str::<Ipv4Net>(r#""203.0.113.0/24""#).unwrap(), v4);
+        assert_eq!(serde_json::from_str::<Ipv4Net>(r#"{"addr":"203.0.113.0","prefix":24}"#).unwrap(), v4);
+        assert_eq!(serde_json::from_str::<Ipv6Net>(r#""2001:db8::""#).unwrap(), v6);
+        assert_eq!(serde_json::from_str::<Ipv6Net>(r#"{"addr":"2001:db8::","prefix":null}"#).unwrap(), v6);
+        assert_eq!(serde_json::from_str::<Ipv6Net>(r#"{"addr":"2001:db8::"}"#).unwrap(), v6);
+
+        for json in [r#""203.0.113.0/33""#, r#"{"addr":"203.0.113.0","prefix":33}"#, r#""203.0.113.0/""#, r#"{"prefix":24}"#].iter() {
+            assert!(serde_json::from_str::<Ipv4Net>(json).is_err(), "{} should be rejected", json);
+        }
+        assert!(serde_json::from_str::<Ipv6Net>(r#""::/129""#).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (v4, v6) = nets();
+        assert_eq!(serde_json::from_str::<Ipv4Net>(&serde_json::to_string(&v4).unwrap()).unwrap(), v4);
+        assert_eq!(serde_json::from_str::<Ipv6Net>(&serde_json::to_string(&v6).unwrap()).unwrap(), v6);
+
+        // Value is deserialized with deserialize_any, just like any other self describing format
+        let value = serde_json::to_value(v4).unwrap();
+        assert_eq!(serde_json::from_value::<Ipv4Net>(value).unwrap(), v4);
+    }
+
+    #[test]
+    fn test_slash_notation_is_default() {
+        use crate::spf::{SpfAction, SpfDirective, SpfMechanism, SpfRecord};
+
+        let (v4, v6) = nets();
+        let record = SpfRecord::from(vec![
+            SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism: SpfMechanism::Ipv4(v4),
+            },
+            SpfDirective {
+                qualifier: SpfAction::Pass,
+                explicit_qualifier: false,
+                mechanism: SpfMechanism::Ipv6(v6),
+            },
+        ]);
+        let json = serde_json::to_string_pretty(&record).unwrap();
+        assert_eq!(json, include_str!("testdata/nets.json").trim_end());
+        assert_eq!(serde_json::from_str::<SpfRecord>(&json).unwrap(), record);
+    }
+
+    #[test]
+    fn test_structured_form() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Nets {
+            #[serde(with = "crate::spf::serde_cidr::structured")]
+            v4: Ipv4Net,
+            #[serde(with = "crate::spf::serde_cidr::structured")]
+            v6: Ipv6Net,
+        }
+
+        let (v4, v6) = nets();
+        let json = serde_json::to_string(&Nets { v4, v6 }).unwrap();
+        assert_eq!(json, r#"{"v4":{"addr":"203.0.113.0","prefix":24},"v6":{"addr":"2001:db8::","prefix":null}}"#);
+        assert_eq!(serde_json::from_str::<Nets>(&json).unwrap(), Nets { v4, v6 });
+        assert_eq!(serde_json::from_str::<Nets>(r#"{"v4":"203.0.113.0/24","v6":"2001:db8::"}"#).unwrap(), Nets { v4, v6 });
+        assert!(serde_json::from_str::<Nets>(r#"{"v4":{"addr":"203.0.113.0","prefix":33},"v6":"2001:db8::"}"#).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Module with serde helpers, which serialize `SpfRecord`, `SpfDirective` and `SpfMechanism` as their text.
+//!
+//! Values are serialized as their RFC 7208 text, like `"v=spf1 ip4:192.0.2.0/24 -all"` or `"-ip4:192.0.2.0/24"`,
+//! and deserialized with parser, so they may be written by hand in JSON or YAML config files.
+//! Plain `Serialize` and `Deserialize` implementations of these types serialize them as structures, which mirror
+//! their fields, so text form is chosen per field:
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//! use spf::{SpfDirective, SpfRecord};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Config {
+//!     #[serde(with = "spf::serde_str")]
+//!     policy: SpfRecord<'static>,
+//!     #[serde(with = "spf::serde_str::vec")]
+//!     extra: Vec<SpfDirective<'static>>,
+//! }
+//!
+//! let config: Config = serde_json::from_str(r#"{"policy": "v=spf1 mx -all", "extra": ["-ip4:192.0.2.0/24"]}"#).unwrap();
+//! assert_eq!(config.policy.to_string(), "v=spf1 mx -all");
+//! assert_eq!(serde_json::to_string(&config).unwrap(), r#"{"policy":"v=spf1 mx -all","extra":["-ip4:192.0.2.0/24"]}"#);
+//! ```
+//!
+//! Deserialized values own their strings.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de;
+use serde::ser;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::spf::serde_borrow::CowStrVisitor;
+use crate::spf::{SpfDirective, SpfMechanism, SpfRecord};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// SpfText is implemented for types, which may be serialized with this module:
+/// `SpfRecord`, `SpfDirective` and `SpfMechanism`.
+pub trait SpfText: Sized + fmt::Display + sealed::Sealed {
+    #[doc(hidden)]
+    const EXPECTING: &'static str;
+
+    /// check_text returns error when text of value would not parse back.
+    #[doc(hidden)]
+    fn check_text(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    fn parse_text<E>(text: &str) -> Result<Self, E>
+        where E: de::Error;
+}
+
+impl<'a> sealed::Sealed for SpfRecord<'a> {}
+
+/// Invalid record is not serialized, since its text would not parse back.
+impl<'a> SpfText for SpfRecord<'a> {
+    const EXPECTING: &'static str = "SPF record text";
+
+    fn check_text(&self) -> Result<(), String> {
+        self.validate().map_err(|errors| errors[0].to_string())
+    }
+
+    fn parse_text<E>(text: &str) -> Result<Self, E>
+        where E: de::Error
+    {
+        // directives are moved, since owned record does not coerce to shorter lifetime with `smallvec` feature
+        SpfRecord::parse_str(text)
+            .map(|r| r.into_owned().map_directives(|d| d))
+            .map_err(E::custom)
+    }
+}
+
+impl<'a> sealed::Sealed for SpfDirective<'a> {}
+
+impl<'a> SpfText for SpfDirective<'a> {
+    const EXPECTING: &'static str = "SPF term";
+
+    fn parse_text<E>(text: &str) -> Result<Self, E>
+        where E: de::Error
+    {
+        SpfDirective::parse_str(text).map(SpfDirective::into_owned).map_err(E::custom)
+    }
+}
+
+impl<'a> sealed::Sealed for SpfMechanism<'a> {}
+
+/// Mechanism is deserialized from term without qualifier, like `ip4:192.0.2.0/24`.
+impl<'a> SpfText for SpfMechanism<'a> {
+    const EXPECTING: &'static str = "SPF term without qualifier";
+
+    fn parse_text<E>(text: &str) -> Result<Self, E>
+        where E: de::Error
+    {
+        let directive = SpfDirective::<'a>::parse_text::<E>(text)?;
+        if directive.explicit_qualifier {
+            return Err(E::custom("mechanism can't have qualifier"));
+        }
+        Ok(directive.mechanism)
+    }
+}
+
+/// serialize serializes value as its text.
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where T: SpfText, S: Serializer
+{
+    value.check_text().map_err(ser::Error::custom)?;
+    serializer.collect_str(value)
+}
+
+/// deserialize parses value from string.
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where T: SpfText, D: Deserializer<'de>
+{
+    let text = deserializer.deserialize_str(CowStrVisitor(T::EXPECTING))?;
+    T::parse_text(&text)
+}
+
+/// Text serializes wrapped value with this module, so it may be used as element of collections.
+struct Text<T>(T);
+
+impl<T> Serialize for Text<&T>
+    where T: SpfText
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serialize(self.0, serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Text<T>
+    where T: SpfText
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserialize(deserializer).map(Text)
+    }
+}
+
+/// Module with serde helpers for `Vec` of values, which are serialized as sequence of their texts.
+pub mod vec {
+    use super::*;
+
+    /// serialize serializes values as sequence of their texts.
+    pub fn serialize<T, S>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+        where T: SpfText, S: Serializer
+    {
+        serializer.collect_seq(values.iter().map(Text))
+    }
+
+    /// deserialize parses values from sequence of strings.
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+        where T: SpfText, D: Deserializer<'de>
+    {
+        struct TextsVisitor<T>(PhantomData<T>);
+
+        impl<'de, T> de::Visitor<'de> for TextsVisitor<T>
+            where T: SpfText
+        {
+            type Value = Vec<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "sequence of {}", T::EXPECTING)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+                where A: de::SeqAccess<'de>
+            {
+                let mut values = Vec::new();
+                while let Some(Text(v)) = seq.next_element()? {
+                    values.push(v);
+                }
+                Ok(values)
+            }
+        }
+
+        deserializer.deserialize_seq(TextsVisitor(PhantomData))
+    }
+}
+
+/// Module with serde helpers for `Option` of value, which is serialized as its text or none.
+pub mod option {
+    use super::*;
+
+    /// serialize serializes value as its text or none.
+    pub fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+        where T: SpfText, S: Serializer
+    {
+        match value {
+            Some(v) => serializer.serialize_some(&Text(v)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// deserialize parses value from string or none.
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+        where T: SpfText, D: Deserializer<'de>
+    {
+        Option::<Text<T>>::deserialize(deserializer).map(|v| v.map(|Text(v)| v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::spf::{SpfAction, UnknownModifier};
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Config<'a> {
+        #[serde(with = "crate::spf::serde_str")]
+        policy: SpfRecord<'a>,
+        #[serde(with = "crate::spf::serde_str::vec")]
+        extra: Vec<SpfDirective<'a>>,
+        #[serde(with = "crate::spf::serde_str::option", default)]
+        fallback: Option<SpfMechanism<'a>>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Text<T>(#[serde(with = "crate::spf::serde_str", bound = "T: SpfText")] T);
+
+    #[test]
+    fn test_record_round_trip() {
+        for text in [
+            "v=spf1 ip4:192.0.2.0/24 -all",
+            "v=spf1",
+            "v=spf1 +mx a:%{d}/24//64 exists:%{ir}.%{v}._spf.%{d2} ~all",
+            "v=spf1 include:_spf.example.com foo=%{S}.bar exp=explain._spf.%{d} redirect=_spf.example.org",
+        ].iter() {
+            let record = SpfRecord::parse_str(text).unwrap();
+            let json = serde_json::to_string(&Text(record.clone())).unwrap();
+            assert_eq!(json, format!("{:?}", text));
+            assert_eq!(serde_json::from_str::<Text<SpfRecord>>(&json).unwrap().0, record);
+        }
+    }
+
+    #[test]
+    fn test_config_file() {
+        let json = r#"{"policy": "v=spf1 mx unknown=extension -all", "extra": ["-ip4:192.0.2.0/24", "?exists:%{l}.example.com"]}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.policy.directives[1].mechanism, SpfMechanism::from(UnknownModifier::new("unknown", "extension")));
+        assert_eq!(config.extra[0].qualifier, SpfAction::Fail);
+        assert_eq!(config.extra[0].mechanism, SpfMechanism::ip4("192.0.2.0/24".parse().unwrap()));
+        assert_eq!(config.extra[1].to_string(), "?exists:%{l}.example.com");
+        assert_eq!(config.fallback, None);
+
+        let json = r#"{"policy":"v=spf1 -all","extra":[],"fallback":"a:%{d}"}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.fallback.as_ref().map(|m| m.to_string()).as_deref(), Some("a:%{d}"));
+        assert_eq!(serde_json::to_string(&config).unwrap(), json);
+
+        // escaped strings are parsed just like other ones
+        let json = r#""v=spf1 include:_spf.ex\u0061mple.com -all""#;
+        let record: SpfRecord<'static> = serde_json::from_reader::<_, Text<_>>(json.as_bytes()).unwrap().0;
+        assert_eq!(record.to_string(), "v=spf1 include:_spf.example.com -all");
+        match &record.directives[0].mechanism {
+            SpfMechanism::Include(d) => assert!(matches!(d.clone().into_raw(), Cow::Owned(_))),
+            m => panic!("unexpected mechanism {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_structured_form_is_default() {
+        let record = SpfRecord::parse_str("v=spf1 -all").unwrap();
+        assert_eq!(serde_json::to_string(&record).unwrap(), r#"{"directives":[{"qualifier":"Fail","explicit_qualifier":true,"mechanism":"All"}]}"#);
+        assert_eq!(serde_json::to_string(&Text(record.clone())).unwrap(), r#""v=spf1 -all""#);
+    }
+
+    #[test]
+    fn test_mechanism() {
+        let m = SpfMechanism::ip6("2001:db8::/32".parse().unwrap());
+        assert_eq!(serde_json::to_string(&Text(m.clone())).unwrap(), r#""ip6:2001:db8::/32""#);
+        assert_eq!(serde_json::from_str::<Text<SpfMechanism>>(r#""ip6:2001:db8::/32""#).unwrap().0, m);
+        assert_eq!(serde_json::from_str::<Text<SpfMechanism>>(r#""x=%{d}""#).unwrap().0, SpfMechanism::from(UnknownModifier::new("x", "%{d}")));
+        assert!(serde_json::from_str::<Text<SpfMechanism>>(r#""-ip6:2001:db8::/32""#).is_err());
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        for json in [r#""v=spf2 -all""#, r#""v=spf1 ip4:192.0.2.0/33""#, r#"{"directives":[]}"#, "1"].iter() {
+            assert!(serde_json::from_str::<Text<SpfRecord>>(json).is_err(), "{} should be rejected", json);
+        }
+        for json in [r#""?redirect=example.com""#, r#""mx -all""#, r#""""#].iter() {
+            assert!(serde_json::from_str::<Text<SpfDirective>>(json).is_err(), "{} should be rejected", json);
+        }
+        let e = serde_json::from_str::<Text<SpfRecord>>(r#""v=spf1 ip4:192.0.2.0/33""#).unwrap_err();
+        assert!(e.to_string().contains("invalid CIDR length"), "{}", e);
+
+        // invalid record is not serialized, rather than serialized to text, which does not parse back
+        let record = SpfRecord::from(vec![SpfDirective::from_mechanism(SpfMechanism::redirect("a.example.com").unwrap()); 2]);
+        assert!(serde_json::to_string(&Text(record.clone())).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Module with helpers shared by other modules of this crate.
+
+// flag_enum is copied from dnsie crate on purpose, this crate does not depend on any DNS library.
+/// flag_enum creates enum which may be either known or unknown(yet) flag.
+///
+/// Apart from known enum it generates `$any_name` enum, which is able to hold any value of `$val_ty`,
+/// conversions between both of them and `$val_ty` and list of all known variants.
+macro_rules! flag_enum {
+    (
+        $name:ident, $any_name:ident: $val_ty:ty {
+             $(
+                $variant_name:ident = $variant_val:tt
+             ),*
+        }
+
+    ) => {
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+        pub enum $name {
+            $(
+                $variant_name = ($variant_val) as isize
+            ),*
+        }
+
+        impl $name {
+            /// VARIANTS contains all known variants in order they were declared.
+            pub const VARIANTS: &'static [$name] = &[
+                $(
+                    $name::$variant_name
+                ),*
+            ];
+
+            /// VALUES contains numeric values of all known variants in order they were declared.
+            pub const VALUES: &'static [$val_ty] = &[
+                $(
+                    $variant_val
+                ),*
+            ];
+
+            /// all returns iterator over all known variants in order they were declared.
+            #[inline]
+            pub fn all() -> ::std::iter::Copied<::std::slice::Iter<'static, $name>> {
+                Self::VARIANTS.iter().copied()
+            }
+
+            /// name returns name of this variant, as it was declared.
+            #[inline]
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(
+                        $name::$variant_name => stringify!($variant_name)
+                    ),*
+                }
+            }
+
+            // deprecate this fn?
+            #[inline]
+            #[allow(clippy::result_unit_err)]
+            pub fn try_from_num(n: $val_ty) -> Result<Self, ()> {
+                <Self as ::std::convert::TryFrom<$val_ty>>::try_from(n)
+            }
+
+            #[inline]
+            pub fn into_num(self) -> $val_ty {
+                self.into()
+            }
+        }
+
+        impl From<$name> for $val_ty {
+            #[inline]
+            fn from(val: $name) -> $val_ty {
+                match val {
+                    $(
+                        $name::$variant_name => $variant_val
+                    ),*
+                }
+            }
+        }
+
+        impl ::std::convert::TryFrom<$val_ty> for $name {
+            type Error = ();
+
+            #[inline]
+            fn try_from(val: $val_ty) -> Result<Self, Self::Error> {
+                match val {
+                    $(
+                        $variant_val => Ok(Self::$variant_name),
+                    )*
+                    _ => Err(()),
+                }
+            }
+        }
+
+        impl From<$name> for $any_name {
+            fn from(data: $name) -> $any_name {
+                $any_name::Known(data)
+            }
+        }
+
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+        pub enum $any_name {
+            Known($name),
+            Unknown($val_ty)
+        }
+
+        impl $any_name {
+            pub fn into_canonical(self) -> Self {
+                match self {
+                    Self::Known(v) => Self::Known(v),
+                    Self::Unknown(v) => match <$name as ::std::convert::TryFrom<$val_ty>>::try_from(v) {
+                        Ok(new_v) => Self::Known(new_v),
+                        Err(_) => Self::Unknown(v),
+                    }
+                }
+            }
+        }
+
+        impl From<$any_name> for $val_ty {
+            #[inline]
+            fn from(val: $any_name) -> $val_ty {
+                match val {
+                    $any_name::Known(v) => v.into(),
+                    $any_name::Unknown(v) => v,
+                }
+            }
+        }
+
+        impl From<$val_ty> for $any_name {
+            #[inline]
+            fn from(val: $val_ty) -> Self {
+                Self::Unknown(val).into_canonical()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    flag_enum! {
+        TestFlag, AnyTestFlag: u16 {
+            First = 1,
+            Second = 2,
+            Tenth = 10
+        }
+    }
+
+    #[test]
+    fn test_variants_match_declaration() {
+        assert_eq!(TestFlag::VARIANTS, &[TestFlag::First, TestFlag::Second, TestFlag::Tenth]);
+        assert_eq!(TestFlag::VALUES, &[1, 2, 10]);
+        assert_eq!(TestFlag::all().collect::<Vec<_>>(), TestFlag::VARIANTS);
+        assert_eq!(TestFlag::all().map(TestFlag::name).collect::<Vec<_>>(), vec!["First", "Second", "Tenth"]);
+    }
+
+    #[test]
+    fn test_variants_round_trip_through_number() {
+        for v in TestFlag::all() {
+            let n: u16 = v.into();
+            assert_eq!(TestFlag::try_from(n), Ok(v));
+            assert_eq!(AnyTestFlag::from(n), AnyTestFlag::Known(v));
+            assert_eq!(u16::from(AnyTestFlag::from(v)), n);
+        }
+        assert_eq!(TestFlag::try_from(3), Err(()));
+        assert_eq!(AnyTestFlag::from(3), AnyTestFlag::Unknown(3));
+        assert_eq!(u16::from(AnyTestFlag::Unknown(3)), 3);
+    }
+
+    #[test]
+    fn test_macro_variables_round_trip() {
+        use crate::spf::{AnyMacroVariable, MacroVariable};
+
+        assert_eq!(MacroVariable::VARIANTS.len(), 11);
+        for v in MacroVariable::all() {
+            let n = v.into_num();
+            assert_eq!(MacroVariable::try_from_num(n), Ok(v));
+            assert_eq!(AnyMacroVariable::from(n), AnyMacroVariable::Known(v));
+            assert!(MacroVariable::get_valid_lowercase_symbols().contains(&n));
+        }
+        let mut symbols = MacroVariable::get_valid_lowercase_symbols().to_vec();
+        symbols.sort_unstable();
+        assert_eq!(&symbols[..], b"cdhiloprstv");
+    }
+}
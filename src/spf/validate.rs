@@ -0,0 +1,374 @@
+//! Module with `SpfRecord::validate`, which checks records built by hand, deserialized or mutated
+//! through public fields.
+//!
+//! CIDR lengths are not checked here: `DualCidr`, `Ipv4Net` and `Ipv6Net` can't hold out of range values,
+//! neither after parsing nor after deserialization.
+
+use std::fmt;
+
+use crate::spf::{
+    DomainSpecError, MacroEvaluationError, MacroString, SpfAction, SpfDirectiveKind, SpfMechanism, SpfRecord,
+};
+
+/// SpfValidationError describes single invariant violated by record.
+/// Each variant contains index of directive which violates it.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SpfValidationError {
+    /// InvalidDomain is returned when domain-spec does not match `domain-spec` grammar,
+    /// for instance when it's empty or contains non-ASCII chars.
+    InvalidDomain {
+        index: usize,
+        error: DomainSpecError,
+    },
+
+    /// QualifiedModifier is returned when modifier has qualifier. Modifiers have none.
+    QualifiedModifier {
+        index: usize,
+    },
+
+    /// ImplicitQualifier is returned when qualifier other than `Pass` is not marked as explicit.
+    /// Only `Pass` may be omitted from record text.
+    ImplicitQualifier {
+        index: usize,
+    },
+
+    /// DuplicateModifier is returned for second and every next `redirect` or `exp` modifier.
+    /// Each of them may appear at most once.
+    DuplicateModifier {
+        index: usize,
+        kind: SpfDirectiveKind,
+    },
+
+    /// InvalidModifierName is returned when name of unknown modifier does not match `name` grammar
+    /// or when it's name of known modifier, like `redirect`.
+    InvalidModifierName {
+        index: usize,
+    },
+
+    /// InvalidModifierValue is returned when value of unknown modifier is not valid macro string.
+    InvalidModifierValue {
+        index: usize,
+        error: MacroEvaluationError,
+    },
+}
+
+impl SpfValidationError {
+    /// index returns index of directive which caused this error.
+    pub fn index(&self) -> usize {
+        match self {
+            SpfValidationError::InvalidDomain { index, .. } |
+            SpfValidationError::QualifiedModifier { index } |
+            SpfValidationError::ImplicitQualifier { index } |
+            SpfValidationError::DuplicateModifier { index, .. } |
+            SpfValidationError::InvalidModifierName { index } |
+            SpfValidationError::InvalidModifierValue { index, .. } => *index,
+        }
+    }
+}
+
+impl fmt::Display for SpfValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfValidationError::InvalidDomain { index, error } => write!(f, "directive {}: {}", index, error),
+            SpfValidationError::QualifiedModifier { index } => write!(f, "directive {}: modifier has qualifier", index),
+            SpfValidationError::ImplicitQualifier { index } => {
+                write!(f, "directive {}: qualifier other than pass is not explicit", index)
+            }
+            SpfValidationError::DuplicateModifier { index, kind } => {
+                write!(f, "directive {}: modifier {:?} appears more than once", index, kind)
+            }
+            SpfValidationError::InvalidModifierName { index } => write!(f, "directive {}: invalid modifier name", index),
+            SpfValidationError::InvalidModifierValue { index, error } => {
+                write!(f, "directive {}: invalid modifier value: {}", index, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpfValidationError {}
+
+/// SpfValidationWarning describes part of record, which is valid but most likely is not what author meant.
+/// Each variant contains index of directive which caused it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SpfValidationWarning {
+    /// UnreachableMechanism is returned for mechanism after `all`, since it's never evaluated.
+    UnreachableMechanism {
+        index: usize,
+    },
+
+    /// IgnoredRedirect is returned for `redirect` in record with `all`, since it's ignored then.
+    IgnoredRedirect {
+        index: usize,
+    },
+}
+
+impl SpfValidationWarning {
+    /// index returns index of directive which caused this warning.
+    pub fn index(&self) -> usize {
+        match self {
+            SpfValidationWarning::UnreachableMechanism { index } |
+            SpfValidationWarning::IgnoredRedirect { index } => *index,
+        }
+    }
+}
+
+impl fmt::Display for SpfValidationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfValidationWarning::UnreachableMechanism { index } => write!(f, "directive {}: mechanism after all is never evaluated", index),
+            SpfValidationWarning::IgnoredRedirect { index } => write!(f, "directive {}: redirect is ignored, since record has all", index),
+        }
+    }
+}
+
+/// is_modifier_name checks `name` rule of RFC 7208: `ALPHA *( ALPHA / DIGIT / "-" / "_" / "." )`
+pub(crate) fn is_modifier_name(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    match bytes.next() {
+        Some(c) if c.is_ascii_alphabetic() => bytes.all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c == b'.'),
+        _ => false,
+    }
+}
+
+impl<'a> SpfRecord<'a> {
+    /// validate checks whether this record could be result of parsing valid record text.
+    /// All violations are reported, in order of directives.
+    ///
+    /// It's useful after building record by hand, deserializing it from untrusted source or mutating it's fields.
+    ///
+    /// Display doesn't call it, not even in debug builds, so that records can be logged or formatted while they are
+    /// being fixed. Call it before publishing record which was not produced by parser.
+    ///
+    /// # Example
+    /// ```
+    /// use spf::{SpfAction, SpfDirective, SpfMechanism, SpfRecord, SpfValidationError};
+    ///
+    /// let mut record: SpfRecord = vec![
+    ///     SpfDirective::from_mechanism(SpfMechanism::redirect("example.com").unwrap()),
+    ///     SpfDirective::from_mechanism(SpfMechanism::redirect("example.org").unwrap()),
+    /// ].into_iter().collect();
+    /// record.directives[0].qualifier = SpfAction::Fail;
+    ///
+    /// let errors = record.validate().unwrap_err();
+    /// assert!(matches!(errors[0], SpfValidationError::QualifiedModifier { index: 0 }));
+    /// assert_eq!(errors[1].index(), 1);
+    /// ```
+    pub fn validate(&self) -> Result<(), Vec<SpfValidationError>> {
+        let mut errors = Vec::new();
+        let mut seen_redirect = false;
+        let mut seen_exp = false;
+        for (index, d) in self.directives.iter().enumerate() {
+            if d.mechanism.is_modifier() {
+                if d.qualifier != SpfAction::Pass || d.explicit_qualifier {
+                    errors.push(SpfValidationError::QualifiedModifier { index });
+                }
+            } else if d.qualifier != SpfAction::Pass && !d.explicit_qualifier {
+                errors.push(SpfValidationError::ImplicitQualifier { index });
+            }
+
+            let seen = match &d.mechanism {
+                SpfMechanism::Redirect(_) => Some(&mut seen_redirect),
+                SpfMechanism::Exp(_) => Some(&mut seen_exp),
+                _ => None,
+            };
+            if let Some(seen) = seen {
+                if *seen {
+                    errors.push(SpfValidationError::DuplicateModifier {
+                        index,
+                        kind: d.mechanism.kind(),
+                    });
+                }
+                *seen = true;
+            }
+
+            if let Some(domain) = d.mechanism.target() {
+                if let Err(error) = domain.validate() {
+                    errors.push(SpfValidationError::InvalidDomain { index, error });
+                }
+            }
+
+            if let SpfMechanism::UnknownModifier(m) = &d.mechanism {
+                let known = m.name.eq_ignore_ascii_case("redirect") || m.name.eq_ignore_ascii_case("exp");
+                if known || !is_modifier_name(&m.name) {
+                    errors.push(SpfValidationError::InvalidModifierName { index });
+                }
+                if let Some(offset) = m.value.bytes().position(|c| !(0x21..=0x7e).contains(&c)) {
+                    errors.push(SpfValidationError::InvalidModifierValue {
+                        index,
+                        error: MacroEvaluationError::ParsingSyntaxError { offset },
+                    });
+                } else if let Err(error) = MacroString::parse(&m.value) {
+                    errors.push(SpfValidationError::InvalidModifierValue { index, error });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// warnings returns issues, which do not make record invalid, but are most likely mistakes.
+    /// Unlike errors returned by `validate` they do not change result of evaluation.
+    ///
+    /// # Example
+    /// ```
+    /// use spf::{SpfRecord, SpfValidationWarning};
+    ///
+    /// let record = SpfRecord::parse_str("v=spf1 -all mx redirect=example.com").unwrap();
+    /// assert_eq!(record.warnings(), vec![
+    ///     SpfValidationWarning::UnreachableMechanism { index: 1 },
+    ///     SpfValidationWarning::IgnoredRedirect { index: 2 },
+    /// ]);
+    /// ```
+    pub fn warnings(&self) -> Vec<SpfValidationWarning> {
+        let all = match self.directives.iter().position(|d| matches!(d.mechanism, SpfMechanism::All)) {
+            Some(all) => all,
+            None => return Vec::new(),
+        };
+        self.directives.iter()
+            .enumerate()
+            .filter_map(|(index, d)| match &d.mechanism {
+                SpfMechanism::Redirect(_) => Some(SpfValidationWarning::IgnoredRedirect { index }),
+                m if index > all && !m.is_modifier() => Some(SpfValidationWarning::UnreachableMechanism { index }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::net::Ipv4Addr;
+
+    use crate::spf::{DomainSpec, DualCidr, Ipv4Net, SpfDirective, UnknownModifier};
+
+    use super::*;
+
+    fn directive(qualifier: SpfAction, mechanism: SpfMechanism<'static>) -> SpfDirective<'static> {
+        SpfDirective {
+            qualifier,
+            explicit_qualifier: qualifier != SpfAction::Pass,
+            mechanism,
+        }
+    }
+
+    fn errors(directives: Vec<SpfDirective<'static>>) -> Vec<SpfValidationError> {
+        SpfRecord::from(directives).validate().unwrap_err()
+    }
+
+    #[test]
+    fn test_valid_record() {
+        let record = SpfRecord::from(vec![
+            directive(SpfAction::Pass, SpfMechanism::MX(None, DualCidr::new(Some(24), None).unwrap())),
+            directive(SpfAction::Fail, SpfMechanism::Ipv4(Ipv4Net::from(Ipv4Addr::LOCALHOST))),
+            directive(SpfAction::Pass, SpfMechanism::Exists(DomainSpec::new("%{ir}.%{v}._spf.%{d}").unwrap())),
+            directive(SpfAction::Pass, SpfMechanism::Redirect(DomainSpec::new("example.com").unwrap())),
+            directive(SpfAction::Pass, SpfMechanism::Exp(DomainSpec::new("exp.example.com").unwrap())),
+            directive(SpfAction::Pass, SpfMechanism::from(UnknownModifier::new("foo.bar-1", "%{d}"))),
+            directive(SpfAction::Pass, SpfMechanism::from(UnknownModifier::new("empty", ""))),
+        ]);
+        assert!(record.validate().is_ok());
+        assert!(SpfRecord::new().validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_domains() {
+        // DomainSpec::new rejects most of these, but records may be deserialized or built inside crate without it
+        let domain = |d: &'static str| DomainSpec::from_raw_unchecked(Cow::Borrowed(d));
+        let errs = errors(vec![
+            directive(SpfAction::Pass, SpfMechanism::Include(domain(""))),
+            directive(SpfAction::Pass, SpfMechanism::Exists(domain("exa mple.com"))),
+            directive(SpfAction::Pass, SpfMechanism::A(Some(domain("ex\u{e4}mple.com")), DualCidr::default())),
+            directive(SpfAction::Pass, SpfMechanism::Redirect(domain("%{q}.example.com"))),
+            directive(SpfAction::Pass, SpfMechanism::MX(Some(domain("example.123")), DualCidr::default())),
+            directive(SpfAction::Pass, SpfMechanism::MX(None, DualCidr::default())),
+        ]);
+        assert!(matches!(errs[..], [
+            SpfValidationError::InvalidDomain { index: 0, error: DomainSpecError::Empty },
+            SpfValidationError::InvalidDomain { index: 1, error: DomainSpecError::InvalidCharFound },
+            SpfValidationError::InvalidDomain { index: 2, error: DomainSpecError::InvalidCharFound },
+            SpfValidationError::InvalidDomain { index: 3, error: DomainSpecError::InvalidMacro(_) },
+            SpfValidationError::InvalidDomain { index: 4, error: DomainSpecError::InvalidDomainEnd },
+        ]), "{:?}", errs);
+    }
+
+    #[test]
+    fn test_invalid_qualifiers() {
+        let mut implicit_fail = directive(SpfAction::Fail, SpfMechanism::All);
+        implicit_fail.explicit_qualifier = false;
+        let mut explicit_modifier = directive(SpfAction::Pass, SpfMechanism::Redirect(DomainSpec::new("example.com").unwrap()));
+        explicit_modifier.explicit_qualifier = true;
+        let errs = errors(vec![
+            directive(SpfAction::Pass, SpfMechanism::All),
+            implicit_fail,
+            explicit_modifier,
+            directive(SpfAction::SoftFail, SpfMechanism::Exp(DomainSpec::new("exp.example.com").unwrap())),
+        ]);
+        assert!(matches!(errs[..], [
+            SpfValidationError::ImplicitQualifier { index: 1 },
+            SpfValidationError::QualifiedModifier { index: 2 },
+            SpfValidationError::QualifiedModifier { index: 3 },
+        ]), "{:?}", errs);
+    }
+
+    #[test]
+    fn test_duplicate_modifiers() {
+        let redirect = directive(SpfAction::Pass, SpfMechanism::Redirect(DomainSpec::new("example.com").unwrap()));
+        let exp = directive(SpfAction::Pass, SpfMechanism::Exp(DomainSpec::new("exp.example.com").unwrap()));
+        let errs = errors(vec![redirect.clone(), exp.clone(), redirect.clone(), exp, redirect]);
+        assert!(matches!(errs[..], [
+            SpfValidationError::DuplicateModifier { index: 2, kind: SpfDirectiveKind::Redirect },
+            SpfValidationError::DuplicateModifier { index: 3, kind: SpfDirectiveKind::Exp },
+            SpfValidationError::DuplicateModifier { index: 4, kind: SpfDirectiveKind::Redirect },
+        ]), "{:?}", errs);
+    }
+
+    #[test]
+    fn test_invalid_unknown_modifiers() {
+        let modifier = |name: &'static str, value: &'static str| {
+            directive(SpfAction::Pass, SpfMechanism::UnknownModifier(Box::new(UnknownModifier {
+                name: Cow::Borrowed(name),
+                value: Cow::Borrowed(value),
+            })))
+        };
+        let errs = errors(vec![
+            modifier("1foo", "bar"),
+            modifier("", "bar"),
+            modifier("Redirect", "example.com"),
+            modifier("foo", "b a r"),
+            modifier("foo", "%{q}"),
+            modifier("foo", "%"),
+        ]);
+        assert!(matches!(errs[..], [
+            SpfValidationError::InvalidModifierName { index: 0 },
+            SpfValidationError::InvalidModifierName { index: 1 },
+            SpfValidationError::InvalidModifierName { index: 2 },
+            SpfValidationError::InvalidModifierValue { index: 3, .. },
+            SpfValidationError::InvalidModifierValue { index: 4, .. },
+            SpfValidationError::InvalidModifierValue { index: 5, .. },
+        ]), "{:?}", errs);
+        assert_eq!(errs.iter().map(SpfValidationError::index).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let record = |text: &str| SpfRecord::parse_str(text).unwrap().warnings();
+        assert!(record("v=spf1 mx a -all exp=exp.example.com").is_empty());
+        assert!(record("v=spf1 mx redirect=example.com").is_empty());
+        assert!(record("v=spf1").is_empty());
+        assert_eq!(record("v=spf1 redirect=example.com ?all ip4:192.0.2.1 foo=bar ~all"), vec![
+            SpfValidationWarning::IgnoredRedirect { index: 0 },
+            SpfValidationWarning::UnreachableMechanism { index: 2 },
+            SpfValidationWarning::UnreachableMechanism { index: 4 },
+        ]);
+        assert_eq!(record("v=spf1 -all a").iter().map(SpfValidationWarning::to_string).collect::<Vec<_>>(), vec![
+            "directive 1: mechanism after all is never evaluated",
+        ]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Module with WASM bindings of parser, linter and macro expansion, which is enabled with `wasm` feature.
+//!
+//! Build it with `wasm-pack build --features wasm`. Exported functions are meant for tools like DNS management
+//! UIs, so they perform no DNS queries. Records cross the boundary in their structured serde form,
+//! with `null` in place of missing values, so `recordToString(parseSpf(text))` gives normalized text back.
+//!
+//! Errors are thrown as objects with `message` field. Panics are thrown as JS errors with panic message,
+//! rather than aborting module with `unreachable`.
+
+use std::collections::HashMap;
+use std::panic;
+use std::sync::Once;
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::spf::{evaluate_exp_macro, MacroVariable, SpfParseError, SpfRecord};
+
+/// Error is thrown by exported functions.
+#[derive(Debug, Serialize)]
+struct Error {
+    message: String,
+
+    /// offset is byte offset of invalid char or term of record, which can't be parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<usize>,
+}
+
+impl Error {
+    fn new(message: impl ToString) -> Self {
+        Error {
+            message: message.to_string(),
+            offset: None,
+        }
+    }
+}
+
+impl From<SpfParseError> for Error {
+    fn from(e: SpfParseError) -> Self {
+        Error {
+            message: e.to_string(),
+            offset: Some(e.offset()),
+        }
+    }
+}
+
+impl From<Error> for JsValue {
+    fn from(e: Error) -> Self {
+        to_js(&e).unwrap_or_else(|e| e)
+    }
+}
+
+/// Lint is single problem reported by `lintSpf`.
+#[derive(Debug, Serialize)]
+struct Lint {
+    /// severity is `error` for record, which can't be published, and `warning` for valid record, which most
+    /// likely does not do what its author meant.
+    severity: &'static str,
+
+    /// index is index of directive, which caused the problem.
+    index: usize,
+
+    message: String,
+}
+
+/// to_js converts value into plain JS value, like `JSON.parse` would create.
+fn to_js<T>(value: &T) -> Result<JsValue, JsValue>
+    where T: Serialize + ?Sized
+{
+    value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// set_panic_hook makes panics throw JS errors with their messages.
+fn set_panic_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| panic::set_hook(Box::new(|info| wasm_bindgen::throw_str(&info.to_string()))));
+}
+
+fn parse(text: &str) -> Result<SpfRecord<'_>, Error> {
+    SpfRecord::parse_str(text).map_err(Error::from)
+}
+
+/// parse_spf parses record and returns its structured form. Error thrown for invalid record has `offset`.
+#[wasm_bindgen(js_name = parseSpf)]
+pub fn parse_spf(text: &str) -> Result<JsValue, JsValue> {
+    set_panic_hook();
+    to_js(&parse(text)?)
+}
+
+/// lint_spf parses record and returns array of its errors and warnings. Errors are reported first.
+#[wasm_bindgen(js_name = lintSpf)]
+pub fn lint_spf(text: &str) -> Result<JsValue, JsValue> {
+    set_panic_hook();
+    let record = parse(text)?;
+    let errors = record.validate().err().unwrap_or_default().into_iter()
+        .map(|e| Lint { severity: "error", index: e.index(), message: e.to_string() });
+    let warnings = record.warnings().into_iter()
+        .map(|w| Lint { severity: "warning", index: w.index(), message: w.to_string() });
+    to_js(&errors.chain(warnings).collect::<Vec<_>>())
+}
+
+/// expand_macro expands macro string with variables given as object, whose keys are variable letters,
+/// like `{"s": "user@example.com", "d": "example.com"}`. `c`, `r` and `t` may be used, just like in `exp` text.
+#[wasm_bindgen(js_name = expandMacro)]
+pub fn expand_macro(text: &str, vars: JsValue) -> Result<String, JsValue> {
+    set_panic_hook();
+    let vars: HashMap<String, String> = serde_wasm_bindgen::from_value(vars).map_err(Error::new)?;
+    let mut ctx = HashMap::new();
+    for (name, value) in vars.into_iter() {
+        let variable = match name.as_bytes() {
+            [c] => MacroVariable::try_from_num(*c).ok(),
+            _ => None,
+        };
+        match variable {
+            Some(variable) => ctx.insert(variable, value),
+            None => return Err(Error::new(format!("unknown macro variable {:?}", name)).into()),
+        };
+    }
+    evaluate_exp_macro(&ctx, text).map_err(|e| Error::new(e).into())
+}
+
+/// record_to_string returns text of record given in structured form. Invalid record is rejected,
+/// since its text would not parse back.
+#[wasm_bindgen(js_name = recordToString)]
+pub fn record_to_string(record: JsValue) -> Result<String, JsValue> {
+    set_panic_hook();
+    let record: SpfRecord<'static> = serde_wasm_bindgen::from_value(record).map_err(Error::new)?;
+    match record.validate() {
+        Ok(()) => Ok(record.to_string()),
+        Err(errors) => Err(Error::new(&errors[0]).into()),
+    }
+}
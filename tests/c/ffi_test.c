@@ -0,0 +1,163 @@
+/*
+ * C program testing C API through include/spf.h. It's compiled, linked with the library and run by tests/ffi.rs.
+ * It exits with 0 on success or prints line of first failed check and exits with 1.
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "spf.h"
+
+#define CHECK(cond) do { if (!(cond)) return __LINE__; } while (0)
+
+/* Zone is single DNS record answered by test resolver. */
+typedef struct Zone {
+    const char *name;
+    char type;
+    const char *value;
+} Zone;
+
+static const Zone ZONE[] = {
+    {"example.com", 'T', "google-site-verification=abc"},
+    {"example.com", 'T', "v=spf1 mx include:_spf.example.org -all"},
+    {"example.com", 'M', "mail.example.com"},
+    {"mail.example.com", 'A', "192.0.2.10"},
+    {"_spf.example.org", 'T', "v=spf1 ip4:198.51.100.0/24 ip6:2001:db8::/32 ptr:example.net"},
+    {"203.0.113.8", 'P', "host.example.net"},
+    {"host.example.net", 'A', "203.0.113.8"},
+    {"two.example.com", 'T', "v=spf1 -all"},
+    {"two.example.com", 'T', "v=spf1 +all"},
+};
+
+static int lookup(char type, const char *name, SpfAnswer *answer) {
+    size_t i;
+    if (strcmp(name, "timeout.example.com") == 0) {
+        return 1;
+    }
+    for (i = 0; i < sizeof(ZONE) / sizeof(ZONE[0]); i++) {
+        if (ZONE[i].type == type && strcmp(ZONE[i].name, name) == 0) {
+            if (spf_answer_push(answer, ZONE[i].value) != SPF_ERROR_CODE_OK) {
+                return 1;
+            }
+        }
+    }
+    return 0;
+}
+
+static int lookup_txt(void *user_data, const char *name, SpfAnswer *answer) {
+    (*(int *) user_data)++;
+    return lookup('T', name, answer);
+}
+
+static int lookup_a(void *user_data, const char *name, SpfAnswer *answer) {
+    (*(int *) user_data)++;
+    return lookup('A', name, answer);
+}
+
+static int lookup_mx(void *user_data, const char *name, SpfAnswer *answer) {
+    (*(int *) user_data)++;
+    return lookup('M', name, answer);
+}
+
+static int lookup_ptr(void *user_data, const char *name, SpfAnswer *answer) {
+    (*(int *) user_data)++;
+    return lookup('P', name, answer);
+}
+
+/* Lints counts problems reported by spf_lint. */
+typedef struct Lints {
+    int errors;
+    int warnings;
+    size_t last_index;
+} Lints;
+
+static void count_lint(void *user_data, SpfLintSeverity severity, size_t index, const char *message) {
+    Lints *lints = user_data;
+    if (message == NULL || message[0] == '\0') {
+        return;
+    }
+    if (severity == SPF_LINT_SEVERITY_ERROR) {
+        lints->errors++;
+    } else {
+        lints->warnings++;
+    }
+    lints->last_index = index;
+}
+
+static int test_parse(void) {
+    SpfHandle *handle = NULL;
+    const char *text = NULL;
+    Lints lints = {0, 0, 0};
+
+    CHECK(spf_parse("v=spf1 +mx  -all a", &handle) == SPF_ERROR_CODE_OK);
+    CHECK(handle != NULL);
+    CHECK(spf_last_error_message(handle) == NULL);
+    CHECK(spf_record_to_string(handle, &text) == SPF_ERROR_CODE_OK);
+    CHECK(strcmp(text, "v=spf1 +mx -all a") == 0);
+    CHECK(spf_lint(handle, count_lint, &lints) == SPF_ERROR_CODE_OK);
+    CHECK(lints.errors == 0 && lints.warnings == 1 && lints.last_index == 2);
+    CHECK(spf_lint(handle, NULL, &lints) == SPF_ERROR_CODE_NULL_POINTER);
+    spf_record_free(handle);
+
+    CHECK(spf_parse("v=spf1 mx:", &handle) == SPF_ERROR_CODE_PARSE_FAILED);
+    CHECK(handle != NULL);
+    CHECK(spf_last_error_message(handle) != NULL);
+    CHECK(spf_record_to_string(handle, &text) == SPF_ERROR_CODE_NO_RECORD);
+    CHECK(text == NULL);
+    CHECK(spf_lint(handle, count_lint, &lints) == SPF_ERROR_CODE_NO_RECORD);
+    spf_record_free(handle);
+
+    CHECK(spf_parse("v=spf1 \xff", &handle) == SPF_ERROR_CODE_INVALID_UTF8);
+    CHECK(handle == NULL);
+    CHECK(spf_parse(NULL, &handle) == SPF_ERROR_CODE_NULL_POINTER);
+    CHECK(spf_parse("v=spf1", NULL) == SPF_ERROR_CODE_NULL_POINTER);
+    CHECK(spf_record_to_string(NULL, &text) == SPF_ERROR_CODE_NULL_POINTER);
+    spf_record_free(NULL);
+    return 0;
+}
+
+static int check(const char *domain, const char *ip, const char *sender, SpfResultCode expected) {
+    int queries = 0;
+    SpfResolverCallbacks resolver = {&queries, lookup_txt, lookup_a, lookup_mx, lookup_ptr};
+    SpfResultCode result = SPF_RESULT_CODE_NONE;
+    if (spf_check(domain, ip, sender, "mx.example.net", &resolver, &result) != SPF_ERROR_CODE_OK) {
+        return 0;
+    }
+    return result == expected && queries > 0;
+}
+
+static int test_check(void) {
+    int queries = 0;
+    SpfResolverCallbacks resolver = {&queries, lookup_txt, lookup_a, lookup_mx, lookup_ptr};
+    SpfResultCode result;
+
+    CHECK(check("example.com", "192.0.2.10", "user@example.com", SPF_RESULT_CODE_PASS));
+    CHECK(check("example.com", "198.51.100.1", "user@example.com", SPF_RESULT_CODE_PASS));
+    CHECK(check("example.com", "2001:db8::1", "user@example.com", SPF_RESULT_CODE_PASS));
+    CHECK(check("example.com", "203.0.113.8", "user@example.com", SPF_RESULT_CODE_PASS));
+    CHECK(check("example.com", "203.0.113.9", "user@example.com", SPF_RESULT_CODE_FAIL));
+    CHECK(check("example.com", "203.0.113.9", "", SPF_RESULT_CODE_FAIL));
+    CHECK(check("none.example.com", "192.0.2.10", "user@none.example.com", SPF_RESULT_CODE_NONE));
+    CHECK(check("two.example.com", "192.0.2.10", "user@two.example.com", SPF_RESULT_CODE_PERM_ERROR));
+    CHECK(check("timeout.example.com", "192.0.2.10", "user@timeout.example.com", SPF_RESULT_CODE_TEMP_ERROR));
+
+    CHECK(spf_check("example.com", "192.0.2.300", "", "", &resolver, &result) == SPF_ERROR_CODE_INVALID_IP);
+    CHECK(spf_check("example.com", "192.0.2.1", NULL, "", &resolver, &result) == SPF_ERROR_CODE_NULL_POINTER);
+    CHECK(spf_check("example.com", "192.0.2.1", "", "", NULL, &result) == SPF_ERROR_CODE_NULL_POINTER);
+    resolver.lookup_mx = NULL;
+    CHECK(spf_check("example.com", "192.0.2.1", "", "", &resolver, &result) == SPF_ERROR_CODE_NULL_POINTER);
+    CHECK(queries == 0);
+    return 0;
+}
+
+int main(void) {
+    int line = test_parse();
+    if (line == 0) {
+        line = test_check();
+    }
+    if (line != 0) {
+        fprintf(stderr, "check at line %d of tests/c/ffi_test.c failed\n", line);
+        return 1;
+    }
+    return 0;
+}
@@ -0,0 +1,51 @@
+//! Runs C program in `tests/c/ffi_test.c`, which uses C API through `include/spf.h`.
+//!
+//! Library is built as `cdylib` into temporary directory of tests and program is compiled with C compiler
+//! given in `CC` environment variable, `cc` by default. It needs Unix-like linker, so it's run on Unix only.
+
+use std::ptr;
+
+use spf::ffi::{spf_parse, SpfErrorCode};
+
+#[cfg(unix)]
+#[test]
+fn test_c_program() {
+    use std::env;
+    use std::path::Path;
+    use std::process::Command;
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let status = Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+        .args(["rustc", "--lib", "--features", "ffi", "--crate-type", "cdylib", "--manifest-path"])
+        .arg(root.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("cargo can't be run");
+    assert!(status.success(), "library can't be built");
+
+    let lib_dir = target_dir.join("debug");
+    let program = target_dir.join("ffi_test");
+    let status = Command::new(env::var_os("CC").unwrap_or_else(|| "cc".into()))
+        .args(["-Wall", "-Werror", "-o"])
+        .arg(&program)
+        .arg(root.join("tests/c/ffi_test.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lspf")
+        .status()
+        .expect("C compiler can't be run");
+    assert!(status.success(), "tests/c/ffi_test.c can't be compiled");
+
+    let status = Command::new(&program).status().unwrap();
+    assert!(status.success(), "tests/c/ffi_test.c failed");
+}
+
+#[test]
+fn test_null_pointers_are_rejected() {
+    assert_eq!(unsafe { spf_parse(ptr::null(), ptr::null_mut()) }, SpfErrorCode::NullPointer);
+}
@@ -0,0 +1,318 @@
+//! Runs scenarios written in format of the openspf/pyspf RFC 7208 test suite, stored as YAML files in `tests/data`.
+//!
+//! Each file contains one or more YAML documents:
+//! ```yaml
+//! description: what is tested
+//! tests:
+//!   test-name:
+//!     description: what is tested by this test
+//!     spec: 4.5/1
+//!     helo: mail.example.net
+//!     host: 192.0.2.1
+//!     mailfrom: user@example.com
+//!     result: pass              # or list of results, which are all accepted
+//!     explanation: optional expected explanation of fail
+//! zonedata:
+//!   example.com:
+//!     - TXT: v=spf1 ip4:192.0.2.0/24 -all
+//!     - TXT: ["v=spf1 ", "-all"]  # strings of single record
+//!     - MX: [10, mail.example.com]
+//!     - A: 192.0.2.1
+//!   timeout.example.com:
+//!     - TIMEOUT
+//! ```
+//! Zone data of document is loaded into `InMemoryResolver`, queries for domains marked with `TIMEOUT` fail.
+//! `SPF` records are ignored, since RFC 7208 uses TXT records only. Entries of other record types can't be loaded,
+//! so they are skipped. Tests, which depend on them, fail and their failures list skipped entries.
+//!
+//! Tests of features which are not implemented yet are listed in `SKIPPED`, so suite can be run as a whole.
+//! Skipped test, which passes, is reported as failure, so the list does not outlive reasons for skipping.
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryInto;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use serde_derive::Deserialize;
+use serde_yaml::Value;
+
+use spf::{check_host, InMemoryResolver, MacroContext, SpfCheckResult, SpfEvaluationResult, SpfResolver};
+
+/// SKIPPED lists names of tests of features, which are not implemented yet, together with reason.
+const SKIPPED: &[(&str, &str)] = &[
+    ("cname-chain", "CNAME records can't be loaded into InMemoryResolver"),
+];
+
+#[derive(Debug, Deserialize)]
+struct Suite {
+    description: String,
+    tests: BTreeMap<String, Scenario>,
+    #[serde(default)]
+    zonedata: BTreeMap<String, Vec<Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scenario {
+    #[serde(default)]
+    description: String,
+    helo: String,
+    host: IpAddr,
+    mailfrom: String,
+    result: Results,
+    explanation: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Results {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Results {
+    fn accepts(&self, result: SpfEvaluationResult) -> bool {
+        let name = result_name(result);
+        match self {
+            Results::One(r) => r == name,
+            Results::Many(rs) => rs.iter().any(|r| r == name),
+        }
+    }
+}
+
+/// result_name returns name of result used by suite.
+fn result_name(result: SpfEvaluationResult) -> &'static str {
+    match result {
+        SpfEvaluationResult::None => "none",
+        SpfEvaluationResult::Neutral => "neutral",
+        SpfEvaluationResult::Pass => "pass",
+        SpfEvaluationResult::Fail => "fail",
+        SpfEvaluationResult::SoftFail => "softfail",
+        SpfEvaluationResult::TempError => "temperror",
+        SpfEvaluationResult::PermError | SpfEvaluationResult::LimitExceeded(_) => "permerror",
+        _ => panic!("no suite name for {:?}", result),
+    }
+}
+
+/// ZoneResolver answers queries from zone data of single document.
+struct ZoneResolver {
+    records: InMemoryResolver,
+    timeouts: HashSet<String>,
+
+    /// skipped describes entries of zone data, which can't be loaded.
+    skipped: Vec<String>,
+}
+
+impl ZoneResolver {
+    fn timeout(&self, domain: &str) -> Result<(), ()> {
+        if self.timeouts.contains(&domain.trim_end_matches('.').to_ascii_lowercase()) { Err(()) } else { Ok(()) }
+    }
+}
+
+impl SpfResolver for ZoneResolver {
+    type Error = ();
+
+    fn lookup_spf(&self, domain: &str) -> Result<Vec<String>, ()> {
+        self.timeout(domain).map(|_| SpfResolver::lookup_spf(&self.records, domain).unwrap())
+    }
+
+    fn lookup_a(&self, domain: &str) -> Result<Vec<IpAddr>, ()> {
+        self.timeout(domain).map(|_| SpfResolver::lookup_a(&self.records, domain).unwrap())
+    }
+
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<String>, ()> {
+        self.timeout(domain).map(|_| SpfResolver::lookup_mx(&self.records, domain).unwrap())
+    }
+
+    fn domain_exists(&self, domain: &str) -> Result<bool, ()> {
+        self.timeout(domain).map(|_| SpfResolver::domain_exists(&self.records, domain).unwrap())
+    }
+
+    fn lookup_ptr(&self, ip: IpAddr) -> Result<Vec<String>, ()> {
+        self.timeout(&reverse_name(ip)).map(|_| SpfResolver::lookup_ptr(&self.records, ip).unwrap())
+    }
+}
+
+/// reverse_name returns name of PTR record of given address.
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::new();
+            for b in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", b & 0xf, b >> 4));
+            }
+            name + "ip6.arpa"
+        }
+    }
+}
+
+/// reverse_address returns address, which PTR record of given name belongs to.
+fn reverse_address(name: &str) -> Option<IpAddr> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if let Some(labels) = name.strip_suffix(".in-addr.arpa") {
+        let mut octets = labels.split('.').map(|l| l.parse::<u8>().ok()).collect::<Option<Vec<_>>>()?;
+        octets.reverse();
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        Some(IpAddr::V4(Ipv4Addr::from(octets)))
+    } else if let Some(labels) = name.strip_suffix(".ip6.arpa") {
+        let nibbles = labels.split('.').rev().map(|l| u8::from_str_radix(l, 16).ok().filter(|_| l.len() == 1)).collect::<Option<Vec<_>>>()?;
+        if nibbles.len() != 32 {
+            return None;
+        }
+        let mut octets = [0u8; 16];
+        for (o, pair) in octets.iter_mut().zip(nibbles.chunks(2)) {
+            *o = pair[0] << 4 | pair[1];
+        }
+        Some(IpAddr::V6(Ipv6Addr::from(octets)))
+    } else {
+        None
+    }
+}
+
+/// text returns text of scalar value, like TXT string or MX host.
+fn text(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        v => Err(format!("expected text, got {:?}", v)),
+    }
+}
+
+/// load_entry adds single entry of zone data of given domain to resolver.
+fn load_entry(zone: &mut ZoneResolver, domain: &str, entry: &Value) -> Result<(), String> {
+    let (kind, value) = match entry {
+        Value::String(s) if s == "TIMEOUT" => {
+            zone.timeouts.insert(domain.trim_end_matches('.').to_ascii_lowercase());
+            return Ok(());
+        }
+        Value::Mapping(m) if m.len() == 1 => m.iter().next().unwrap(),
+        e => return Err(format!("invalid entry {:?}", e)),
+    };
+    let parse_ip = |v: &Value| text(v)?.parse::<IpAddr>().map_err(|e| e.to_string());
+    match kind.as_str() {
+        Some("TXT") => {
+            let joined = match value {
+                Value::Sequence(strings) => strings.iter().map(text).collect::<Result<String, _>>()?,
+                v => text(v)?,
+            };
+            zone.records.add_txt(domain, &joined);
+        }
+        // RFC 7208 section 3.1: SPF records are published as TXT records only
+        Some("SPF") => {}
+        Some("A") | Some("AAAA") => {
+            zone.records.add_address(domain, parse_ip(value)?);
+        }
+        Some("MX") => match value {
+            Value::Sequence(mx) if mx.len() == 2 => {
+                zone.records.add_mx(domain, &text(&mx[1])?);
+            }
+            v => return Err(format!("invalid MX record {:?}", v)),
+        },
+        Some("PTR") => {
+            let ip = reverse_address(domain).ok_or_else(|| format!("{} is not reverse name", domain))?;
+            zone.records.add_ptr(ip, &text(value)?);
+        }
+        _ => return Err(format!("unsupported record type {:?}", kind)),
+    }
+    Ok(())
+}
+
+/// load_zone builds resolver answering queries from zone data of document.
+/// Entries, which can't be loaded, are skipped and reported by `skipped` of resolver.
+fn load_zone(zonedata: &BTreeMap<String, Vec<Value>>) -> ZoneResolver {
+    let mut zone = ZoneResolver {
+        records: InMemoryResolver::new(),
+        timeouts: HashSet::new(),
+        skipped: Vec::new(),
+    };
+    for (domain, entries) in zonedata.iter() {
+        for entry in entries.iter() {
+            if let Err(e) = load_entry(&mut zone, domain, entry) {
+                zone.skipped.push(format!("{}: {}", domain, e));
+            }
+        }
+    }
+    zone
+}
+
+/// check runs `check_host` for sender's domain, or HELO domain for empty sender.
+fn check(resolver: &ZoneResolver, scenario: &Scenario) -> Result<SpfCheckResult, String> {
+    let domain = match scenario.mailfrom.rfind('@') {
+        Some(i) => &scenario.mailfrom[i + 1..],
+        None if scenario.mailfrom.is_empty() => scenario.helo.as_str(),
+        None => scenario.mailfrom.as_str(),
+    };
+    let ctx = MacroContext::new(&scenario.mailfrom, domain, scenario.host, &scenario.helo);
+    check_host(resolver, &ctx).map_err(|e| format!("evaluation failed: {}", e))
+}
+
+fn run_scenario(resolver: &ZoneResolver, scenario: &Scenario) -> Result<(), String> {
+    let SpfCheckResult { result, explanation } = check(resolver, scenario)?;
+    if !scenario.result.accepts(result) {
+        return Err(format!("expected {:?}, got {}", scenario.result, result_name(result)));
+    }
+    match &scenario.explanation {
+        Some(expected) if result == SpfEvaluationResult::Fail && explanation.as_ref() != Some(expected) => {
+            Err(format!("expected explanation {:?}, got {:?}", expected, explanation))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// load_suites reads all documents of YAML files from given directory, sorted by file name.
+fn load_suites(dir: &Path) -> Vec<(PathBuf, Suite)> {
+    let mut paths = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", dir.display(), e))
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "yml" || e == "yaml"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    let mut suites = Vec::new();
+    for p in paths.into_iter() {
+        let text = fs::read_to_string(&p).unwrap();
+        for document in serde_yaml::Deserializer::from_str(&text) {
+            let suite = serde::Deserialize::deserialize(document)
+                .unwrap_or_else(|e| panic!("{} is not valid suite: {}", p.display(), e));
+            suites.push((p.clone(), suite));
+        }
+    }
+    suites
+}
+
+#[test]
+fn test_rfc7208_suite() {
+    let suites = load_suites(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data"));
+    assert!(!suites.is_empty(), "no suites found");
+
+    let mut failures = Vec::new();
+    let mut seen = HashSet::new();
+    for (path, suite) in suites.iter() {
+        let resolver = load_zone(&suite.zonedata);
+        for (name, scenario) in suite.tests.iter() {
+            seen.insert(name.as_str());
+            let res = run_scenario(&resolver, scenario).map_err(|e| if resolver.skipped.is_empty() {
+                e
+            } else {
+                format!("{}, zone data skipped: {}", e, resolver.skipped.join("; "))
+            });
+            let skipped = SKIPPED.iter().any(|(skipped, _)| skipped == name);
+            match res {
+                Err(e) if !skipped => {
+                    failures.push(format!("{} ({}): {} ({}): {}", path.display(), suite.description, name, scenario.description, e));
+                }
+                Ok(()) if skipped => {
+                    failures.push(format!("{}: {} passes, so it should be removed from skip list", path.display(), name));
+                }
+                _ => {}
+            }
+        }
+    }
+    for (name, _) in SKIPPED.iter().filter(|(name, _)| !seen.contains(name)) {
+        failures.push(format!("skipped test {} does not exist", name));
+    }
+    assert!(failures.is_empty(), "{} suite test(s) failed:\n{}", failures.len(), failures.join("\n"));
+}
@@ -0,0 +1,182 @@
+//! Runs known-answer test vectors stored as JSON files in `tests/vectors`.
+//!
+//! Each file contains single object:
+//! ```json
+//! {
+//!     "description": "what is tested and why",
+//!     "macros": [
+//!         {"macro": "%{d2}", "context": {"d": "mail.example.com"}, "expect": {"value": "example.com"}},
+//!         {"macro": "%{d", "expect": {"error": "syntax"}}
+//!     ],
+//!     "records": [
+//!         {"record": "v=spf1 +mx  -all", "expect": {"directives": ["+mx", "-all"]}},
+//!         {"record": "v=spf10", "expect": {"error": "invalid_record_kind"}}
+//!     ]
+//! }
+//! ```
+//! `context` maps macro letters to values of variables. Expected directives of record are compared with
+//! result of parsing each of them with `SpfDirective::parse_str`.
+//!
+//! Error codes are `syntax`, `unknown_variable` and `invalid_number` for macros and `invalid_record_kind`,
+//! `invalid_char` and `invalid_format` for records.
+//! Unknown fields are rejected, so vectors can't silently test less than they claim to.
+//!
+//! Adding regression test means adding new file there.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_derive::Deserialize;
+
+use spf::{evaluate_macro, MacroEvaluationError, MacroVariable, SpfDirective, SpfParseError, SpfRecord};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Vector {
+    description: String,
+    #[serde(default)]
+    macros: Vec<MacroCase>,
+    #[serde(default)]
+    records: Vec<RecordCase>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MacroCase {
+    #[serde(rename = "macro")]
+    text: String,
+    #[serde(default)]
+    context: HashMap<String, String>,
+    expect: Expectation,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Expectation {
+    Value(String),
+    Error(ErrorCode),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecordCase {
+    record: String,
+    expect: RecordExpectation,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum RecordExpectation {
+    Directives(Vec<String>),
+    Error(ErrorCode),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    Syntax,
+    UnknownVariable,
+    InvalidNumber,
+    InvalidRecordKind,
+    InvalidChar,
+    InvalidFormat,
+}
+
+impl From<&MacroEvaluationError> for ErrorCode {
+    fn from(e: &MacroEvaluationError) -> Self {
+        match e {
+            MacroEvaluationError::ParsingSyntaxError { .. } => ErrorCode::Syntax,
+            MacroEvaluationError::UnknownVariable(_) => ErrorCode::UnknownVariable,
+            MacroEvaluationError::ParseIntError(_) => ErrorCode::InvalidNumber,
+            _ => panic!("no error code for {:?}", e),
+        }
+    }
+}
+
+impl From<&SpfParseError> for ErrorCode {
+    fn from(e: &SpfParseError) -> Self {
+        match e {
+            SpfParseError::InvalidRecordKind => ErrorCode::InvalidRecordKind,
+            SpfParseError::InvalidCharFound { .. } => ErrorCode::InvalidChar,
+            SpfParseError::InvalidTerm { .. } => ErrorCode::InvalidFormat,
+            _ => panic!("no error code for {:?}", e),
+        }
+    }
+}
+
+/// load_vectors reads all vectors from given directory, sorted by file name.
+fn load_vectors(dir: &Path) -> Vec<(PathBuf, Vector)> {
+    let mut paths = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", dir.display(), e))
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths.into_iter()
+        .map(|p| {
+            let text = fs::read_to_string(&p).unwrap();
+            let v = serde_json::from_str(&text)
+                .unwrap_or_else(|e| panic!("{} is not valid vector: {}", p.display(), e));
+            (p, v)
+        })
+        .collect()
+}
+
+fn run_macro_case(case: &MacroCase) -> Result<(), String> {
+    let mut ctx = HashMap::new();
+    for (letter, value) in case.context.iter() {
+        let variable = match letter.as_bytes() {
+            [b] => MacroVariable::try_from(*b).map_err(|_| format!("unknown macro variable {:?}", letter))?,
+            _ => return Err(format!("context key {:?} is not single letter", letter)),
+        };
+        ctx.insert(variable, value.as_str());
+    }
+
+    let res = evaluate_macro(&ctx, &case.text);
+    match (&case.expect, &res) {
+        (Expectation::Value(expected), Ok(actual)) if expected == actual => Ok(()),
+        (Expectation::Error(expected), Err(e)) if *expected == ErrorCode::from(e) => Ok(()),
+        (expected, actual) => Err(format!("{:?}: expected {:?}, got {:?}", case.text, expected, actual)),
+    }
+}
+
+fn run_record_case(case: &RecordCase) -> Result<(), String> {
+    let res = SpfRecord::parse_str(&case.record);
+    match (&case.expect, &res) {
+        (RecordExpectation::Directives(expected), Ok(record)) => {
+            let expected = expected.iter()
+                .map(|d| SpfDirective::parse_str(d).map_err(|e| format!("expected directive {:?} is not valid: {:?}", d, e)))
+                .collect::<Result<Vec<_>, _>>()?;
+            if record.directives[..] == expected[..] {
+                Ok(())
+            } else {
+                Err(format!("{:?}: expected {:?}, got {:?}", case.record, expected, record.directives))
+            }
+        }
+        (RecordExpectation::Error(expected), Err(e)) if *expected == ErrorCode::from(e) => Ok(()),
+        (expected, actual) => Err(format!("{:?}: expected {:?}, got {:?}", case.record, expected, actual)),
+    }
+}
+
+#[test]
+fn test_vectors() {
+    let vectors = load_vectors(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vectors"));
+    assert!(!vectors.is_empty(), "no test vectors found");
+
+    let mut failures = Vec::new();
+    for (path, vector) in vectors.iter() {
+        for case in vector.macros.iter() {
+            if let Err(e) = run_macro_case(case) {
+                failures.push(format!("{} ({}): {}", path.display(), vector.description, e));
+            }
+        }
+        for case in vector.records.iter() {
+            if let Err(e) = run_record_case(case) {
+                failures.push(format!("{} ({}): {}", path.display(), vector.description, e));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{} vector case(s) failed:\n{}", failures.len(), failures.join("\n"));
+}
@@ -0,0 +1,70 @@
+//! Tests of WASM bindings, which are run in JS engine with `wasm-pack test --node --features wasm`
+//! or `wasm-pack test --headless --firefox --features wasm`. They are empty on other targets.
+
+#![cfg(target_arch = "wasm32")]
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use spf::wasm::{expand_macro, lint_spf, parse_spf, record_to_string};
+
+fn to_json(value: JsValue) -> Value {
+    serde_wasm_bindgen::from_value(value).unwrap()
+}
+
+fn to_js(value: &Value) -> JsValue {
+    value.serialize(&serde_wasm_bindgen::Serializer::json_compatible()).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn test_parse_spf() {
+    let record = to_json(parse_spf("v=spf1 -all").unwrap());
+    assert_eq!(record, json!({"directives": [{"qualifier": "Fail", "explicit_qualifier": true, "mechanism": "All"}]}));
+
+    let error = to_json(parse_spf("v=spf1 mx -al").unwrap_err());
+    assert_eq!(error["offset"], json!(10));
+    assert!(error["message"].as_str().unwrap().contains("-al"));
+
+    let error = to_json(parse_spf("v=spf2").unwrap_err());
+    assert!(error["message"].is_string());
+}
+
+#[wasm_bindgen_test]
+fn test_lint_spf() {
+    assert_eq!(to_json(lint_spf("v=spf1 mx -all").unwrap()), json!([]));
+
+    let lints = to_json(lint_spf("v=spf1 -all mx").unwrap());
+    let lints = lints.as_array().unwrap();
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0]["severity"], json!("warning"));
+    assert_eq!(lints[0]["index"], json!(1));
+
+    assert!(to_json(lint_spf("v=spf1 mx:").unwrap_err())["offset"].is_number());
+}
+
+#[wasm_bindgen_test]
+fn test_expand_macro() {
+    let vars = to_js(&json!({"s": "user@example.com", "d": "example.com", "i": "192.0.2.1"}));
+    assert_eq!(expand_macro("%{ir}._spf.%{d}", vars.clone()).unwrap(), "1.2.0.192._spf.example.com");
+
+    let error = expand_macro("%{x}", vars).unwrap_err();
+    assert!(to_json(error)["message"].is_string());
+
+    let error = expand_macro("%{d}", to_js(&json!({"dd": "example.com"}))).unwrap_err();
+    assert!(to_json(error)["message"].as_str().unwrap().contains("dd"));
+}
+
+#[wasm_bindgen_test]
+fn test_record_to_string() {
+    let record = parse_spf("v=spf1  +mx include:_spf.example.com  -all").unwrap();
+    assert_eq!(record_to_string(record).unwrap(), "v=spf1 +mx include:_spf.example.com -all");
+
+    let invalid = json!({"directives": [
+        {"qualifier": "Pass", "mechanism": {"Redirect": "a.example.com"}},
+        {"qualifier": "Pass", "mechanism": {"Redirect": "b.example.com"}},
+    ]});
+    assert!(to_json(record_to_string(to_js(&invalid)).unwrap_err())["message"].is_string());
+    assert!(record_to_string(JsValue::from_str("v=spf1 -all")).is_err());
+}